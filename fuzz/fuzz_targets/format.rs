@@ -0,0 +1,17 @@
+//! Formats metrics built from arbitrary input and checks they parse back into what was sent.
+//!
+//! ```text
+//! cargo +nightly fuzz run format
+//! ```
+
+#![no_main]
+
+use dogstatsd::fuzzing::FuzzMetric;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let metric = FuzzMetric::from_bytes(data);
+    if let Err(e) = metric.check_round_trip() {
+        panic!("{} for {:?}", e, metric);
+    }
+});
@@ -0,0 +1,42 @@
+/* C declarations for the dogstatsd crate's `ffi` feature. Build the library to link against
+ * with `cargo rustc --release --features ffi --crate-type cdylib`. */
+
+#ifndef DOGSTATSD_H
+#define DOGSTATSD_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct dogstatsd_client dogstatsd_client;
+
+/* Returns NULL on failure. Either argument may be NULL to use the default. */
+dogstatsd_client *dogstatsd_client_new(const char *to_addr, const char *namespace_);
+void dogstatsd_client_free(dogstatsd_client *client);
+
+/* Each returns 0 on success and -1 on failure. `tags` may be NULL when `tags_len` is 0. */
+int dogstatsd_incr(const dogstatsd_client *client, const char *stat,
+                   const char *const *tags, size_t tags_len);
+int dogstatsd_decr(const dogstatsd_client *client, const char *stat,
+                   const char *const *tags, size_t tags_len);
+int dogstatsd_count(const dogstatsd_client *client, const char *stat, int64_t count,
+                    const char *const *tags, size_t tags_len);
+int dogstatsd_timing(const dogstatsd_client *client, const char *stat, int64_t ms,
+                     const char *const *tags, size_t tags_len);
+int dogstatsd_gauge(const dogstatsd_client *client, const char *stat, double value,
+                    const char *const *tags, size_t tags_len);
+int dogstatsd_histogram(const dogstatsd_client *client, const char *stat, double value,
+                        const char *const *tags, size_t tags_len);
+int dogstatsd_distribution(const dogstatsd_client *client, const char *stat, double value,
+                           const char *const *tags, size_t tags_len);
+int dogstatsd_set(const dogstatsd_client *client, const char *stat, const char *value,
+                  const char *const *tags, size_t tags_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DOGSTATSD_H */
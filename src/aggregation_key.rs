@@ -0,0 +1,67 @@
+use std::fmt;
+use std::sync::Arc;
+
+type Generate = dyn Fn(&str, &str) -> Option<String> + Send + Sync;
+
+/// A callback that generates the aggregation key of events sent without one, from their title
+/// and text, such as to group events by the trace they were sent from so they show up next to
+/// it in APM.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::AggregationKeyGenerator;
+///
+///   let generator = AggregationKeyGenerator::new(|_title, _text| {
+///       Some(format!("trace:{}", 1234))
+///   });
+/// ```
+#[derive(Clone)]
+pub struct AggregationKeyGenerator(Arc<Generate>);
+
+impl AggregationKeyGenerator {
+    /// Create a new `AggregationKeyGenerator` from a closure, called with the event's title and
+    /// text. Returning `None` sends the event without an aggregation key.
+    pub fn new<F>(generator: F) -> Self
+    where
+        F: Fn(&str, &str) -> Option<String> + Send + Sync + 'static,
+    {
+        AggregationKeyGenerator(Arc::new(generator))
+    }
+
+    pub(crate) fn generate(&self, title: &str, text: &str) -> Option<String> {
+        (self.0)(title, text)
+    }
+}
+
+impl fmt::Debug for AggregationKeyGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AggregationKeyGenerator")
+    }
+}
+
+impl PartialEq for AggregationKeyGenerator {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AggregationKeyGenerator;
+
+    #[test]
+    fn test_generate() {
+        let generator = AggregationKeyGenerator::new(|title, text| {
+            (!text.is_empty()).then(|| format!("{}:{}", title, text.len()))
+        });
+
+        assert_eq!(
+            Some("deploy:4".into()),
+            generator.generate("deploy", "body")
+        );
+        assert_eq!(None, generator.generate("deploy", ""));
+        assert_eq!(generator, generator.clone());
+        assert_ne!(generator, AggregationKeyGenerator::new(|_, _| None));
+    }
+}
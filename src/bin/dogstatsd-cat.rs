@@ -0,0 +1,183 @@
+//! Reads metrics from stdin, one per line, and sends them through a client. Only built with the
+//! `cli` feature.
+//!
+//! Each line is a metric in the DogStatsD format, `<name>:<value>|<type>[|#<tag>,<tag>...]`,
+//! where the type is one of `c`, `g`, `ms`, `h`, `d` or `s`. Empty lines and lines starting
+//! with `#` are skipped.
+//!
+//! ```text
+//! echo 'deploys:1|c|#service:api' | dogstatsd-cat --to-addr 127.0.0.1:8125
+//! ```
+
+use std::env;
+use std::io::{self, BufRead};
+use std::process;
+
+use dogstatsd::{parse_metric, Client, DogstatsdResult, OptionsBuilder};
+
+const USAGE: &str = "usage: dogstatsd-cat [--to-addr <addr>] [--socket-path <path>] [--namespace <namespace>] [--tag <tag>]...";
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Count,
+    Gauge,
+    Timing,
+    Histogram,
+    Distribution,
+    Set,
+}
+
+#[derive(Debug, PartialEq)]
+struct Spec<'a> {
+    name: &'a str,
+    value: &'a str,
+    kind: Kind,
+    tags: Vec<&'a str>,
+}
+
+fn parse(line: &str) -> Result<Spec<'_>, String> {
+    let metric = parse_metric(line).map_err(|e| e.to_string())?;
+    let kind = match metric.metric_type() {
+        "c" => Kind::Count,
+        "g" => Kind::Gauge,
+        "ms" => Kind::Timing,
+        "h" => Kind::Histogram,
+        "d" => Kind::Distribution,
+        "s" => Kind::Set,
+        kind => return Err(format!("unknown metric type {:?} in {:?}", kind, line)),
+    };
+    if metric.sample_rate().is_some()
+        || metric.container_id().is_some()
+        || metric.timestamp().is_some()
+    {
+        return Err(format!(
+            "only tags can follow the metric type in {:?}",
+            line
+        ));
+    }
+
+    Ok(Spec {
+        name: metric.name(),
+        value: metric.value(),
+        kind,
+        tags: metric.tags().collect(),
+    })
+}
+
+fn send(client: &Client, spec: Spec) -> DogstatsdResult {
+    let parse_int = |value: &str| {
+        value
+            .parse::<i64>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "value isn't an integer"))
+    };
+
+    match spec.kind {
+        Kind::Count => client.count(spec.name, parse_int(spec.value)?, spec.tags),
+        Kind::Gauge => client.gauge(spec.name, spec.value, spec.tags),
+        Kind::Timing => client.timing(spec.name, parse_int(spec.value)?, spec.tags),
+        Kind::Histogram => client.histogram(spec.name, spec.value, spec.tags),
+        Kind::Distribution => client.distribution(spec.name, spec.value, spec.tags),
+        Kind::Set => client.set(spec.name, spec.value, spec.tags),
+    }
+}
+
+fn build_client(args: &[String]) -> Result<Client, String> {
+    let mut builder = OptionsBuilder::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--to-addr" => builder.to_addr(value()?),
+            "--socket-path" => builder.socket_path(Some(value()?)),
+            "--namespace" => builder.namespace(value()?),
+            "--tag" => builder.default_tag(value()?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => return Err(format!("unknown argument {:?}\n{}", arg, USAGE)),
+        };
+    }
+
+    Client::new(builder.build()).map_err(|e| format!("unable to create client: {}", e))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let client = build_client(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    let mut failed = false;
+    for (number, line) in io::stdin().lock().lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("unable to read stdin: {}", e);
+                process::exit(1);
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = parse(line).and_then(|spec| send(&client, spec).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            eprintln!("line {}: {}", number + 1, e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(Spec {
+                name: "deploys",
+                value: "1",
+                kind: Kind::Count,
+                tags: vec!["service:api", "env:prod"],
+            }),
+            parse("deploys:1|c|#service:api,env:prod")
+        );
+        assert_eq!(
+            Ok(Spec {
+                name: "queue.depth",
+                value: "12.5",
+                kind: Kind::Gauge,
+                tags: vec![],
+            }),
+            parse("queue.depth:12.5|g")
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse("deploys").is_err());
+        assert!(parse("deploys:1").is_err());
+        assert!(parse("deploys:1|x").is_err());
+        assert!(parse("deploys:1|c|@0.5").is_err());
+        assert!(parse(":1|c").is_err());
+    }
+
+    #[test]
+    fn test_build_client() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert!(build_client(&args(&["--namespace", "ns", "--tag", "env:prod"])).is_ok());
+        assert!(build_client(&args(&["--namespace"])).is_err());
+        assert!(build_client(&args(&["--unknown"])).is_err());
+    }
+}
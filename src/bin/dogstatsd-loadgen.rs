@@ -0,0 +1,100 @@
+//! Generates metrics through a client, for checking batching settings and agent capacity
+//! before a rollout. Only built with the `loadgen` feature.
+//!
+//! ```text
+//! dogstatsd-loadgen --to-addr 127.0.0.1:8125 --rate 50000 --duration-secs 60 --batch
+//! ```
+
+use std::env;
+use std::process;
+use std::time::Duration;
+
+use dogstatsd::{run_load, BatchingOptions, Client, LoadProfile, OptionsBuilder, Transport};
+
+const USAGE: &str = "usage: dogstatsd-loadgen [--to-addr <addr>] [--socket-path <path>] [--rate <metrics/s>] [--duration-secs <secs>] [--names <n>] [--cardinality <n>] [--batch]";
+
+fn parse_args(args: &[String]) -> Result<(Client, LoadProfile), String> {
+    let mut builder = OptionsBuilder::new();
+    let mut profile = LoadProfile::default();
+    let mut batch = false;
+    let mut uds = false;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{} needs a value\n{}", arg, USAGE))
+        };
+        let number = |value: String| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{} needs a number\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--to-addr" => {
+                builder.to_addr(value()?);
+            }
+            "--socket-path" => {
+                builder.socket_path(Some(value()?));
+                uds = true;
+            }
+            "--rate" => profile.rate = number(value()?)? as u32,
+            "--duration-secs" => profile.duration = Duration::from_secs(number(value()?)?),
+            "--names" => profile.metric_names = number(value()?)? as usize,
+            "--cardinality" => profile.tag_cardinality = number(value()?)? as usize,
+            "--batch" => batch = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => return Err(format!("unknown argument {:?}\n{}", arg, USAGE)),
+        }
+    }
+
+    if batch {
+        builder.batching_options(if uds {
+            BatchingOptions::for_transport(Transport::Uds)
+        } else {
+            BatchingOptions::default()
+        });
+    }
+
+    let client =
+        Client::new(builder.build()).map_err(|e| format!("unable to create client: {}", e))?;
+    Ok((client, profile))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (client, profile) = parse_args(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+
+    let report = run_load(&client, &profile);
+    let stats = client.stats();
+    println!(
+        "sent {} metrics ({} failed) in {:.1}s, {} dropped by the client, {} likely dropped by the kernel",
+        report.sent,
+        report.failed,
+        report.elapsed.as_secs_f64(),
+        stats.total_dropped(),
+        stats.likely_kernel_drops
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let (_, profile) =
+            parse_args(&args(&["--rate", "50", "--duration-secs", "2", "--batch"])).unwrap();
+        assert_eq!(50, profile.rate);
+        assert_eq!(Duration::from_secs(2), profile.duration);
+
+        assert!(parse_args(&args(&["--rate", "fast"])).is_err());
+        assert!(parse_args(&args(&["--unknown"])).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Compatibility with other StatsD crates, through `StatsdSink`, and with earlier versions of
+//! this one, in a module for each. Only available with the `compat` feature.
+
+use std::io;
+use std::ops::Deref;
+use std::panic::AssertUnwindSafe;
+
+use crate::{Client, DogstatsdError};
+
+pub mod v0_12;
+
+/// The shape of the sink traits in other StatsD crates, such as `cadence::MetricSink`: takes
+/// metrics that are already formatted, one per call. Only available with the `compat`
+/// feature.
+pub trait MetricSink {
+    /// Send a single formatted metric, returning the number of bytes written.
+    fn emit(&self, metric: &str) -> io::Result<usize>;
+
+    /// Send anything buffered.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A `MetricSink` that sends metrics formatted by another StatsD crate through a `Client`, so
+/// existing instrumentation gets this client's transports, batching, default tags and stats
+/// while it's migrated. Only available with the `compat` feature.
+///
+/// Metrics are sent as they are apart from adding the client's default tags, so the
+/// namespace and tag prefix aren't applied. Metrics larger than `Options::max_payload_size`
+/// are rejected.
+///
+/// The client can be anything that derefs to a `Client`, such as `&Client` or `Arc<Client>`.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, MetricSink, OptionsBuilder, StatsdSink};
+///   use std::sync::Arc;
+///
+///   let options = OptionsBuilder::new().default_tag(String::from("env:prod")).build();
+///   let sink = StatsdSink::new(Arc::new(Client::new(options).unwrap()));
+///   sink.emit("legacy.requests:1|c|#route:index").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct StatsdSink<C> {
+    // The client's state is only changed under locks, which a panic poisons, so it's never seen
+    // half changed after one, as `cadence::StatsdClient` requires of its sink.
+    client: AssertUnwindSafe<C>,
+}
+
+impl<C: Deref<Target = Client>> StatsdSink<C> {
+    /// Create a new `StatsdSink` sending through `client`.
+    pub fn new(client: C) -> Self {
+        StatsdSink {
+            client: AssertUnwindSafe(client),
+        }
+    }
+}
+
+impl<C: Clone> Clone for StatsdSink<C> {
+    fn clone(&self) -> Self {
+        StatsdSink {
+            client: AssertUnwindSafe(self.client.0.clone()),
+        }
+    }
+}
+
+impl<C: Deref<Target = Client>> MetricSink for StatsdSink<C> {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        for line in metric.lines().filter(|line| !line.is_empty()) {
+            let payload = with_default_tags(line, self.client.default_tag_bytes());
+            self.client.send_payload(payload).map_err(into_io_error)?;
+        }
+        Ok(metric.len())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.client.flush().map_err(into_io_error)
+    }
+}
+
+// Failures to send are passed on as they are, and anything else means the metric was bad.
+fn into_io_error(error: DogstatsdError) -> io::Error {
+    match error {
+        DogstatsdError::IoError(error) => error,
+        e => io::Error::new(io::ErrorKind::InvalidInput, e),
+    }
+}
+
+/// With the `cadence` feature, a `StatsdSink` can be given to `cadence::StatsdClient::from_sink`.
+/// Its stats only count packets dropped, from `ClientStats::total_dropped`.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, Options, StatsdSink};
+///   use cadence::Counted;
+///   use std::sync::Arc;
+///
+///   let client = Arc::new(Client::new(Options::default()).unwrap());
+///   let metrics = cadence::StatsdClient::from_sink("my.app", StatsdSink::new(client));
+///   metrics.count("requests", 1).unwrap();
+/// ```
+#[cfg(feature = "cadence")]
+impl<C: Deref<Target = Client>> cadence::MetricSink for StatsdSink<C> {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        MetricSink::emit(self, metric)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        MetricSink::flush(self)
+    }
+
+    fn stats(&self) -> cadence::SinkStats {
+        cadence::SinkStats {
+            packets_dropped: self.client.stats().total_dropped(),
+            ..Default::default()
+        }
+    }
+}
+
+// Adds `default_tags` to the end of the metric's tags, or as its tags if it has none.
+fn with_default_tags(metric: &str, default_tags: &[u8]) -> Vec<u8> {
+    let metric = metric.as_bytes();
+    if default_tags.is_empty() {
+        return metric.to_vec();
+    }
+
+    let mut payload = Vec::with_capacity(metric.len() + default_tags.len() + 2);
+    let tags_start = metric.windows(2).position(|field| field == b"|#");
+    match tags_start {
+        Some(start) => {
+            let end = metric[start + 2..]
+                .iter()
+                .position(|b| *b == b'|')
+                .map_or(metric.len(), |end| start + 2 + end);
+            payload.extend_from_slice(&metric[..end]);
+            if end > start + 2 {
+                payload.push(b',');
+            }
+            payload.extend_from_slice(default_tags);
+            payload.extend_from_slice(&metric[end..]);
+        }
+        None => {
+            payload.extend_from_slice(metric);
+            payload.extend_from_slice(b"|#");
+            payload.extend_from_slice(default_tags);
+        }
+    }
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BatchingOptions, CaptureSink, OptionsBuilder};
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    #[test]
+    fn test_with_default_tags() {
+        assert_eq!(
+            b"stat:1|c|#env:prod".to_vec(),
+            with_default_tags("stat:1|c", b"env:prod")
+        );
+        assert_eq!(
+            b"stat:1|c|@0.5|#a,env:prod".to_vec(),
+            with_default_tags("stat:1|c|@0.5|#a", b"env:prod")
+        );
+        assert_eq!(
+            b"stat:1|c|#a,b,env:prod|c:container".to_vec(),
+            with_default_tags("stat:1|c|#a,b|c:container", b"env:prod")
+        );
+        assert_eq!(
+            b"stat:1|c|#a".to_vec(),
+            with_default_tags("stat:1|c|#a", b"")
+        );
+    }
+
+    #[test]
+    fn test_statsd_sink() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace(String::from("ns"))
+                .default_tag(String::from("env:prod"))
+                .max_payload_size(48)
+                .build(),
+        );
+        let statsd_sink = StatsdSink::new(&client);
+
+        assert_eq!(15, statsd_sink.emit("legacy.hits:1|c").unwrap());
+        statsd_sink
+            .emit("legacy.latency:12|ms|#route:index")
+            .unwrap();
+        let error = statsd_sink
+            .emit("legacy.latency:12|ms|#route:a-very-long-route-name")
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+
+        assert_eq!(
+            vec![
+                "legacy.hits:1|c|#env:prod",
+                "legacy.latency:12|ms|#route:index,env:prod",
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_statsd_sink_flush() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .batching_options(BatchingOptions {
+                    max_time: Duration::from_secs(60),
+                    ..BatchingOptions::default()
+                })
+                .build(),
+        )
+        .unwrap();
+        let statsd_sink = StatsdSink::new(&client);
+
+        statsd_sink.emit("legacy.hits:1|c").unwrap();
+        MetricSink::flush(&statsd_sink).unwrap();
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"legacy.hits:1|c\n", &buf[..len]);
+    }
+
+    #[cfg(feature = "cadence")]
+    #[test]
+    fn test_cadence_sink() {
+        use cadence::prelude::*;
+        use std::sync::Arc;
+
+        let sink = CaptureSink::new();
+        let client = Arc::new(
+            sink.client(
+                OptionsBuilder::new()
+                    .default_tag(String::from("env:prod"))
+                    .build(),
+            ),
+        );
+        let metrics = cadence::StatsdClient::from_sink("legacy", StatsdSink::new(client));
+        metrics
+            .count_with_tags("hits", 2)
+            .with_tag("route", "index")
+            .send();
+        metrics.gauge("depth", 7).unwrap();
+        metrics.flush().unwrap();
+
+        assert_eq!(
+            vec![
+                "legacy.hits:2|c|#route:index,env:prod",
+                "legacy.depth:7|g|#env:prod",
+            ],
+            sink.payloads()
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! `Options`, `OptionsBuilder` and `Client` as they were in 0.12, for code written against
+//! them. `Options` can no longer be built from a struct literal, since it's gained fields and
+//! is `#[non_exhaustive]`, so this one can. Each converts to the current type, with every
+//! option added since left at its default, so code can be moved over a piece at a time.
+//!
+//! # Examples
+//!
+//! ```
+//!   use dogstatsd::compat::v0_12::{Client, OptionsBuilder};
+//!
+//!   let options = OptionsBuilder::new()
+//!       .namespace(String::from("app"))
+//!       .default_tag(String::from("env:prod"))
+//!       .build();
+//!   let client = Client::new(options).unwrap();
+//!   client.incr("requests", &["route:index"]).unwrap();
+//! ```
+
+use std::ops::Deref;
+
+use crate::{BatchingOptions, DogstatsdError, DEFAULT_FROM_ADDR, DEFAULT_TO_ADDR};
+
+/// The options of a 0.12 client.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::compat::v0_12::Options;
+///
+///   let options = Options {
+///       from_addr: "0.0.0.0:0".into(),
+///       to_addr: "127.0.0.1:8125".into(),
+///       namespace: String::new(),
+///       default_tags: vec![],
+///       socket_path: None,
+///       batching_options: None,
+///   };
+///   let options: dogstatsd::Options = options.into();
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct Options {
+    /// The address of the udp socket we'll bind to for sending.
+    pub from_addr: String,
+    /// The address of the udp socket we'll send metrics and events to.
+    pub to_addr: String,
+    /// A namespace to prefix all metrics with, joined with a '.'.
+    pub namespace: String,
+    /// Default tags to include with every request.
+    pub default_tags: Vec<String>,
+    /// OPTIONAL, if defined, will use UDS instead of UDP and will ignore UDP options
+    pub socket_path: Option<String>,
+    /// OPTIONAL, if defined, will utilize batching for sending metrics
+    pub batching_options: Option<BatchingOptions>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            from_addr: DEFAULT_FROM_ADDR.into(),
+            to_addr: DEFAULT_TO_ADDR.into(),
+            namespace: String::new(),
+            default_tags: vec![],
+            socket_path: None,
+            batching_options: None,
+        }
+    }
+}
+
+impl Options {
+    /// Create a new options struct by supplying values for all fields.
+    pub fn new(
+        from_addr: &str,
+        to_addr: &str,
+        namespace: &str,
+        default_tags: Vec<String>,
+        socket_path: Option<String>,
+        batching_options: Option<BatchingOptions>,
+    ) -> Self {
+        Options {
+            from_addr: from_addr.into(),
+            to_addr: to_addr.into(),
+            namespace: namespace.into(),
+            default_tags,
+            socket_path,
+            batching_options,
+        }
+    }
+}
+
+impl From<Options> for crate::Options {
+    fn from(options: Options) -> Self {
+        crate::Options {
+            from_addr: options.from_addr,
+            to_addr: options.to_addr,
+            namespace: options.namespace,
+            default_tags: options.default_tags,
+            socket_path: options.socket_path,
+            batching_options: options.batching_options,
+            ..crate::Options::default()
+        }
+    }
+}
+
+/// Builds 0.12 `Options`.
+#[derive(Debug, Default, Clone)]
+pub struct OptionsBuilder {
+    from_addr: Option<String>,
+    to_addr: Option<String>,
+    namespace: Option<String>,
+    default_tags: Vec<String>,
+    socket_path: Option<String>,
+    batching_options: Option<BatchingOptions>,
+}
+
+impl OptionsBuilder {
+    /// Create a new `OptionsBuilder` struct with default values.
+    pub fn new() -> Self {
+        OptionsBuilder::default()
+    }
+
+    /// Sets `from_addr`.
+    pub fn from_addr(&mut self, from_addr: String) -> &mut OptionsBuilder {
+        self.from_addr = Some(from_addr);
+        self
+    }
+
+    /// Sets `to_addr`.
+    pub fn to_addr(&mut self, to_addr: String) -> &mut OptionsBuilder {
+        self.to_addr = Some(to_addr);
+        self
+    }
+
+    /// Sets `namespace`.
+    pub fn namespace(&mut self, namespace: String) -> &mut OptionsBuilder {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Adds a default tag.
+    pub fn default_tag(&mut self, default_tag: String) -> &mut OptionsBuilder {
+        self.default_tags.push(default_tag);
+        self
+    }
+
+    /// Sets `socket_path`.
+    pub fn socket_path(&mut self, socket_path: Option<String>) -> &mut OptionsBuilder {
+        self.socket_path = socket_path;
+        self
+    }
+
+    /// Sets `batching_options`.
+    pub fn batching_options(&mut self, batching_options: BatchingOptions) -> &mut OptionsBuilder {
+        self.batching_options = Some(batching_options);
+        self
+    }
+
+    /// Build `Options`, with the defaults for anything that wasn't set.
+    pub fn build(&self) -> Options {
+        let defaults = Options::default();
+        Options {
+            from_addr: self.from_addr.clone().unwrap_or(defaults.from_addr),
+            to_addr: self.to_addr.clone().unwrap_or(defaults.to_addr),
+            namespace: self.namespace.clone().unwrap_or(defaults.namespace),
+            default_tags: self.default_tags.clone(),
+            socket_path: self.socket_path.clone(),
+            batching_options: self.batching_options,
+        }
+    }
+}
+
+/// A client created from 0.12 `Options`, which derefs to the current `Client` for sending.
+#[derive(Debug)]
+pub struct Client(crate::Client);
+
+impl Client {
+    /// Create a new client from 0.12 options.
+    pub fn new(options: Options) -> Result<Self, DogstatsdError> {
+        crate::Client::new(options.into()).map(Client)
+    }
+
+    /// The current `Client`, for code that's been moved over.
+    pub fn into_inner(self) -> crate::Client {
+        self.0
+    }
+}
+
+impl Deref for Client {
+    type Target = crate::Client;
+
+    fn deref(&self) -> &crate::Client {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v0_12_options() {
+        let options = OptionsBuilder::new()
+            .to_addr(String::from("127.0.0.1:9001"))
+            .default_tag(String::from("env:prod"))
+            .batching_options(BatchingOptions::default())
+            .build();
+        assert_eq!(
+            Options::new(
+                DEFAULT_FROM_ADDR,
+                "127.0.0.1:9001",
+                "",
+                vec!["env:prod".into()],
+                None,
+                Some(BatchingOptions::default())
+            ),
+            options
+        );
+
+        let current = crate::OptionsBuilder::new()
+            .to_addr(String::from("127.0.0.1:9001"))
+            .default_tag(String::from("env:prod"))
+            .batching_options(BatchingOptions::default())
+            .build();
+        assert_eq!(current, crate::Options::from(options));
+    }
+}
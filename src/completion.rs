@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::sync::lock;
+use crate::{DogstatsdError, DogstatsdResult};
+
+/// Resolved once the metrics a client sent before `Client::completion` was called have left
+/// the socket, with the first error that dropped any of them since the last completion. Can be awaited, or waited on from a thread
+/// that isn't running an executor.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{BatchingOptions, Client, OptionsBuilder};
+///
+///   let options = OptionsBuilder::new().batching_options(BatchingOptions::default()).build();
+///   let client = Client::new(options).unwrap();
+///   client.incr("payment.captured", &["provider:card"]).unwrap();
+///   client.completion().wait()
+///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+/// ```
+#[derive(Debug)]
+pub struct Completion {
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    inner: Mutex<Inner>,
+    resolved: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    result: Option<DogstatsdResult>,
+    waker: Option<Waker>,
+}
+
+// The batch processor's end of a `Completion`. Dropping it without resolving it, such as when
+// the batch processor has stopped, resolves the completion with
+// `DogstatsdError::CompletionDropped`.
+#[derive(Debug)]
+pub(crate) struct Completer {
+    state: Option<Arc<State>>,
+}
+
+impl Completion {
+    pub(crate) fn new() -> (Completer, Completion) {
+        let state = Arc::new(State::default());
+        (
+            Completer {
+                state: Some(state.clone()),
+            },
+            Completion { state },
+        )
+    }
+
+    // A completion that's already resolved.
+    pub(crate) fn resolved(result: DogstatsdResult) -> Completion {
+        let (completer, completion) = Completion::new();
+        completer.resolve(result);
+        completion
+    }
+
+    /// Whether the metrics have been sent, or failed to.
+    pub fn is_resolved(&self) -> bool {
+        lock(&self.state.inner).result.is_some()
+    }
+
+    /// Blocks until the metrics have been sent, or failed to.
+    pub fn wait(self) -> DogstatsdResult {
+        let mut inner = lock(&self.state.inner);
+        loop {
+            if let Some(result) = inner.result.take() {
+                return result;
+            }
+            inner = self
+                .state
+                .resolved
+                .wait(inner)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+}
+
+impl Future for Completion {
+    type Output = DogstatsdResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = lock(&self.state.inner);
+        match inner.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                inner.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl State {
+    fn resolve(&self, result: DogstatsdResult) {
+        let waker = {
+            let mut inner = lock(&self.inner);
+            inner.result = Some(result);
+            inner.waker.take()
+        };
+        self.resolved.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Completer {
+    pub(crate) fn resolve(mut self, result: DogstatsdResult) {
+        if let Some(state) = self.state.take() {
+            state.resolve(result);
+        }
+    }
+}
+
+impl Drop for Completer {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            state.resolve(Err(DogstatsdError::CompletionDropped));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_wait() {
+        let (completer, completion) = Completion::new();
+        assert!(!completion.is_resolved());
+
+        thread::spawn(move || completer.resolve(Err(std::io::Error::other("refused").into())));
+        match completion.wait() {
+            Err(DogstatsdError::IoError(error)) => assert_eq!("refused", error.to_string()),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        assert!(Completion::resolved(Ok(())).wait().is_ok());
+    }
+
+    #[test]
+    fn test_dropped_completer() {
+        let (completer, completion) = Completion::new();
+        drop(completer);
+        assert!(matches!(
+            completion.wait(),
+            Err(DogstatsdError::CompletionDropped)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_await() {
+        let (completer, completion) = Completion::new();
+        let resolver = tokio::spawn(async move { completer.resolve(Ok(())) });
+        assert!(completion.await.is_ok());
+        resolver.await.unwrap();
+    }
+}
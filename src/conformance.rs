@@ -0,0 +1,367 @@
+//! Checks payloads against the DogStatsD datagram format, as documented at
+//! https://docs.datadoghq.com/developers/dogstatsd/datagram_shell/. Only available with the
+//! `conformance` feature.
+//!
+//! Useful for checking what a client sends, such as payloads captured with `CaptureSink`, or
+//! payloads from anything else that formats DogStatsD messages itself.
+//!
+//! # Examples
+//!
+//! ```
+//!   use dogstatsd::conformance::validate_payload;
+//!
+//!   assert!(validate_payload("requests:1|c|#route:index\nlatency:12|ms\n").is_ok());
+//!   assert!(validate_payload("requests:one|c").is_err());
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+use crate::parse::{parse_metric, validate_tags};
+
+const METRIC_TYPES: [&str; 6] = ["c", "g", "ms", "h", "d", "s"];
+const EVENT_PRIORITIES: [&str; 2] = ["low", "normal"];
+const EVENT_ALERT_TYPES: [&str; 4] = ["error", "warning", "info", "success"];
+
+/// Why a message doesn't match the DogStatsD format.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ConformanceError {
+    message: String,
+    reason: String,
+}
+
+impl ConformanceError {
+    fn new<R: Into<String>>(message: &str, reason: R) -> Self {
+        ConformanceError {
+            message: message.to_owned(),
+            reason: reason.into(),
+        }
+    }
+
+    /// The message that didn't match.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// What's wrong with it.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for ConformanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid DogStatsD message {:?}: {}",
+            self.message, self.reason
+        )
+    }
+}
+
+impl Error for ConformanceError {}
+
+/// Check every message in a datagram, which holds one message per line. A batch's trailing
+/// newline is allowed.
+pub fn validate_payload(payload: &str) -> Result<(), ConformanceError> {
+    let payload = payload.strip_suffix('\n').unwrap_or(payload);
+    payload.split('\n').try_for_each(validate_message)
+}
+
+/// Check a single metric, event or service check message.
+pub fn validate_message(message: &str) -> Result<(), ConformanceError> {
+    if message.starts_with("_e{") {
+        validate_event(message)
+    } else if message.starts_with("_sc|") {
+        validate_service_check(message)
+    } else {
+        validate_metric(message)
+    }
+}
+
+// <name>:<value>[:<value>...]|<type>[|@<rate>][|#<tags>][|c:<container>][|T<timestamp>]
+fn validate_metric(message: &str) -> Result<(), ConformanceError> {
+    let error = |reason: &str| ConformanceError::new(message, reason);
+
+    let metric = parse_metric(message).map_err(|e| error(e.reason()))?;
+    validate_name(metric.name()).map_err(error)?;
+
+    let metric_type = metric.metric_type();
+    if !METRIC_TYPES.contains(&metric_type) {
+        return Err(error("unknown metric type"));
+    }
+    for value in metric.value().split(':') {
+        let valid = match metric_type {
+            "s" => !value.is_empty(),
+            _ => value.parse::<f64>().is_ok_and(f64::is_finite),
+        };
+        if !valid {
+            return Err(error("invalid value"));
+        }
+    }
+
+    if let Some(rate) = metric.sample_rate() {
+        if !rate
+            .parse::<f64>()
+            .is_ok_and(|rate| (0.0..=1.0).contains(&rate))
+        {
+            return Err(error("sample rate must be between 0 and 1"));
+        }
+    }
+    if metric.container_id() == Some("") {
+        return Err(error("empty container id"));
+    }
+    if let Some(timestamp) = metric.timestamp() {
+        validate_timestamp(timestamp).map_err(error)?;
+    }
+
+    Ok(())
+}
+
+// _e{<title length>,<text length>}:<title>|<text>[|d:..][|h:..][|k:..][|p:..][|s:..][|t:..][|#..]
+fn validate_event(message: &str) -> Result<(), ConformanceError> {
+    let error = |reason: &str| ConformanceError::new(message, reason);
+
+    let (lengths, rest) = message["_e{".len()..]
+        .split_once("}:")
+        .ok_or_else(|| error("missing '}:' after the lengths"))?;
+    let (title_len, text_len) = lengths
+        .split_once(',')
+        .and_then(|(title, text)| Some((title.parse::<usize>().ok()?, text.parse::<usize>().ok()?)))
+        .ok_or_else(|| error("invalid title or text length"))?;
+
+    let title = rest
+        .get(..title_len)
+        .ok_or_else(|| error("title is shorter than its length"))?;
+    if title.is_empty() {
+        return Err(error("empty title"));
+    }
+    let rest = rest[title_len..]
+        .strip_prefix('|')
+        .ok_or_else(|| error("title doesn't match its length"))?;
+    rest.get(..text_len)
+        .ok_or_else(|| error("text is shorter than its length"))?;
+    let rest = &rest[text_len..];
+    if !rest.is_empty() && !rest.starts_with('|') {
+        return Err(error("text doesn't match its length"));
+    }
+
+    for field in rest.split('|').skip(1) {
+        if let Some(timestamp) = field.strip_prefix("d:") {
+            validate_timestamp(timestamp).map_err(error)?;
+        } else if let Some(priority) = field.strip_prefix("p:") {
+            if !EVENT_PRIORITIES.contains(&priority) {
+                return Err(error("unknown priority"));
+            }
+        } else if let Some(alert_type) = field.strip_prefix("t:") {
+            if !EVENT_ALERT_TYPES.contains(&alert_type) {
+                return Err(error("unknown alert type"));
+            }
+        } else if let Some(tags) = field.strip_prefix('#') {
+            validate_tags(tags).map_err(error)?;
+        } else if !["h:", "k:", "s:"].iter().any(|key| field.starts_with(key)) {
+            return Err(error("unknown field"));
+        }
+    }
+
+    Ok(())
+}
+
+// _sc|<name>|<status>[|d:<timestamp>][|h:<hostname>][|#<tags>][|m:<message>]
+fn validate_service_check(message: &str) -> Result<(), ConformanceError> {
+    let error = |reason: &str| ConformanceError::new(message, reason);
+
+    let mut fields = message.split('|').skip(1);
+    let name = fields.next().unwrap_or_default();
+    validate_name(name).map_err(error)?;
+    match fields.next() {
+        Some("0" | "1" | "2" | "3") => {}
+        _ => return Err(error("status must be 0, 1, 2 or 3")),
+    }
+
+    for field in fields {
+        if let Some(timestamp) = field.strip_prefix("d:") {
+            validate_timestamp(timestamp).map_err(error)?;
+        } else if let Some(tags) = field.strip_prefix('#') {
+            validate_tags(tags).map_err(error)?;
+        } else if !["h:", "m:"].iter().any(|key| field.starts_with(key)) {
+            return Err(error("unknown field"));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("empty name");
+    }
+    if name.contains([':', '|', '@', '#', '\n']) {
+        return Err("name contains a reserved character");
+    }
+    Ok(())
+}
+
+fn validate_timestamp(timestamp: &str) -> Result<(), &'static str> {
+    if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("invalid timestamp");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BatchingOptions, CaptureSink, EventAlertType, EventOptions, EventPriority, OptionsBuilder,
+        ServiceCheckOptions, ServiceStatus,
+    };
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_validate_message() {
+        for message in [
+            "stat:1|c",
+            "ns.stat:-1.5|g|#tag1,env:prod",
+            "stat:1:2:3|d|@0.5|#tag1|c:abc|T1656581400",
+            "stat:value|s",
+            "_e{5,4}:title|text",
+            "_e{5,5}:ti|le|te|xt|d:1|h:host|k:key|p:low|s:src|t:error|#tag1",
+            "_sc|redis.can_connect|0",
+            "_sc|redis.can_connect|2|d:1|h:host|m:message|#tag1",
+        ] {
+            assert_eq!(Ok(()), validate_message(message), "{}", message);
+        }
+
+        for (message, reason) in [
+            ("stat", "missing ':' between the name and value"),
+            (":1|c", "empty name"),
+            ("st|at:1|c", "missing ':' between the name and value"),
+            ("stat:1", "missing metric type"),
+            ("stat:1|x", "unknown metric type"),
+            ("stat:one|c", "invalid value"),
+            ("stat:|s", "empty value"),
+            ("stat:1|c|@2", "sample rate must be between 0 and 1"),
+            ("stat:1|c|#tag1,", "empty tag"),
+            ("stat:1|c|Tsoon", "invalid timestamp"),
+            ("stat:1|c|x", "unknown field"),
+            ("_e{6,4}:title|text", "title doesn't match its length"),
+            ("_e{5,9}:title|text", "text is shorter than its length"),
+            ("_e{5,3}:title|text", "text doesn't match its length"),
+            ("_e{0,4}:|text", "empty title"),
+            ("_e{5,4}:title|text|p:high", "unknown priority"),
+            ("_sc|redis|4", "status must be 0, 1, 2 or 3"),
+            ("_sc||0", "empty name"),
+            ("_sc|redis|0|x:1", "unknown field"),
+        ] {
+            assert_eq!(
+                Err(reason.to_owned()),
+                validate_message(message).map_err(|e| e.reason().to_owned()),
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_payload() {
+        assert!(validate_payload("a:1|c\nb:2|g\n").is_ok());
+        assert!(validate_payload("a:1|c\n\nb:2|g").is_err());
+        assert_eq!(
+            "invalid DogStatsD message \"b:2\": missing metric type",
+            validate_payload("a:1|c\nb:2").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_client_conforms() {
+        let batching = BatchingOptions {
+            max_buffer_size: 64,
+            max_time: Duration::from_secs(60),
+            max_retry_attempts: 0,
+            initial_retry_delay: 0,
+        };
+
+        for namespace in ["", "ns"] {
+            for default_tags in [vec![], vec!["env:test", "region:eu"]] {
+                for tag_prefix in [None, Some("app")] {
+                    for batching_options in [None, Some(batching)] {
+                        let mut builder = OptionsBuilder::new();
+                        builder.namespace(namespace.into());
+                        for tag in &default_tags {
+                            builder.default_tag(tag.to_string());
+                        }
+                        if let Some(tag_prefix) = tag_prefix {
+                            builder.tag_prefix(tag_prefix.into());
+                        }
+                        if let Some(batching_options) = batching_options {
+                            builder.batching_options(batching_options);
+                        }
+
+                        let sink = CaptureSink::new();
+                        send_everything(&sink, builder.build());
+                        let payloads = sink.wait_for_payloads(1, Duration::from_secs(5));
+                        assert!(!payloads.is_empty());
+                        // Batching clients send an empty final batch when they shut down.
+                        for payload in payloads.into_iter().filter(|payload| !payload.is_empty()) {
+                            assert_eq!(Ok(()), validate_payload(&payload), "{:?}", payload);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn send_everything(sink: &CaptureSink, options: crate::Options) {
+        let client = sink.client(options);
+        for tags in [vec![], vec!["tag1", "key:value"]] {
+            client.incr("counter", &tags).unwrap();
+            client.decr("counter", &tags).unwrap();
+            client.count("counter", 42, &tags).unwrap();
+            client.incr_zero("counter", &tags).unwrap();
+            client.time("time", &tags, || {}).unwrap();
+            client.timing("timing", 12, &tags).unwrap();
+            client.gauge("gauge", "-1.5", &tags).unwrap();
+            client.histogram("histogram", "3", &tags).unwrap();
+            client.distribution("distribution", "4.25", &tags).unwrap();
+            client.set("set", "user-1", &tags).unwrap();
+            client
+                .service_check("redis.can_connect", ServiceStatus::Warning, &tags, None)
+                .unwrap();
+            client
+                .service_check(
+                    "redis.can_connect",
+                    ServiceStatus::Critical,
+                    &tags,
+                    Some(ServiceCheckOptions {
+                        timestamp: Some(1510326433),
+                        hostname: Some("localhost"),
+                        message: Some("down"),
+                    }),
+                )
+                .unwrap();
+            client.event("title", "text", &tags).unwrap();
+            client
+                .event_with_options(
+                    "ti|tle",
+                    "te|xt",
+                    &tags,
+                    Some(
+                        EventOptions::new()
+                            .with_timestamp(1638480000)
+                            .with_hostname("localhost")
+                            .with_aggregation_key("key")
+                            .with_priority(EventPriority::Low)
+                            .with_source_type_name("rust")
+                            .with_alert_type(EventAlertType::Success),
+                    ),
+                )
+                .unwrap();
+            client.deploy_event("api", "1.2.3", &tags).unwrap();
+            client
+                .error_event("failed", &io::Error::other("line 1\nline 2"), &tags)
+                .unwrap();
+        }
+    }
+}
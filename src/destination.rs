@@ -0,0 +1,409 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::error::report_error;
+use crate::sync::lock;
+use crate::transport::{resolve_to_addr, AddressFamily};
+use crate::ErrorHandler;
+
+/// The struct that represents the failover options available for the Dogstatsd client.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FailoverOptions {
+    /// The address of the secondary udp socket we'll send to while the primary is failing.
+    pub secondary_addr: String,
+    /// The number of consecutive failed sends to the primary before failing over.
+    pub max_consecutive_failures: usize,
+    /// While failed over, how often to probe the primary to see if we can fail back.
+    pub probe_interval: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct FailoverState {
+    consecutive_failures: usize,
+    // Set while we're sending to the secondary, with the time of the last probe of the primary.
+    last_probe: Option<Instant>,
+}
+
+/// Where udp payloads are sent. The client keeps it with its socket, and the batch processor
+/// has a copy, which the client updates.
+#[derive(Debug)]
+pub(crate) struct Destination {
+    // As configured, which can be a hostname.
+    primary_addr: String,
+    // What `primary_addr` resolved to, kept until the socket is opened again.
+    resolved: OnceLock<SocketAddr>,
+    address_family: Option<AddressFamily>,
+    failover_options: Option<FailoverOptions>,
+    state: Mutex<FailoverState>,
+    // Also sent every payload, without failover.
+    additional_addrs: Vec<String>,
+    // Whether the socket is connected to the primary, so it's sent to without an address.
+    connected: bool,
+    // Whether payloads are sent over udp, the only time the addresses are used.
+    udp: bool,
+    // Told when failing over.
+    error_handler: Option<ErrorHandler>,
+}
+
+impl Clone for Destination {
+    fn clone(&self) -> Self {
+        Destination {
+            primary_addr: self.primary_addr.clone(),
+            resolved: self.resolved.clone(),
+            address_family: self.address_family,
+            failover_options: self.failover_options.clone(),
+            state: Mutex::new(lock(&self.state).clone()),
+            additional_addrs: self.additional_addrs.clone(),
+            connected: self.connected,
+            udp: self.udp,
+            error_handler: self.error_handler.clone(),
+        }
+    }
+}
+
+impl Destination {
+    pub(crate) fn new(primary_addr: String, failover_options: Option<FailoverOptions>) -> Self {
+        Destination {
+            primary_addr,
+            resolved: OnceLock::new(),
+            address_family: None,
+            failover_options,
+            state: Mutex::new(FailoverState {
+                consecutive_failures: 0,
+                last_probe: None,
+            }),
+            additional_addrs: vec![],
+            connected: false,
+            udp: true,
+            error_handler: None,
+        }
+    }
+
+    pub(crate) fn with_udp(mut self, udp: bool) -> Self {
+        self.udp = udp;
+        self
+    }
+
+    pub(crate) fn with_address_family(mut self, address_family: Option<AddressFamily>) -> Self {
+        self.address_family = address_family;
+        self
+    }
+
+    pub(crate) fn with_connected(mut self, connected: bool) -> Self {
+        self.connected = connected;
+        self
+    }
+
+    pub(crate) fn with_error_handler(mut self, error_handler: Option<ErrorHandler>) -> Self {
+        self.error_handler = error_handler;
+        self
+    }
+
+    pub(crate) fn with_additional_addrs(mut self, additional_addrs: Vec<String>) -> Self {
+        self.additional_addrs = additional_addrs;
+        self
+    }
+
+    // Resolves `addr` to send to instead of the primary. It has to be the same IP version as
+    // the primary, which the socket was bound for.
+    pub(crate) fn resolve_new_primary(&self, addr: &str) -> io::Result<SocketAddr> {
+        if !self.udp || self.connected {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only clients sending over udp without connect_udp can change their to_addr",
+            ));
+        }
+
+        let current = self.primary_addr().ok();
+        let address_family = current.map(|current| match current {
+            SocketAddr::V4(_) => AddressFamily::Ipv4,
+            SocketAddr::V6(_) => AddressFamily::Ipv6,
+        });
+        let resolved = resolve_to_addr(addr, address_family)?;
+        if let Some(current) = current.filter(|current| current.is_ipv4() != resolved.is_ipv4()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't the same IP version as {}", resolved, current),
+            ));
+        }
+
+        Ok(resolved)
+    }
+
+    // Sends to `addr`, which resolved to `resolved`, instead of the primary from now on,
+    // starting over without failover.
+    pub(crate) fn set_primary_addr(&mut self, addr: &str, resolved: SocketAddr) {
+        self.primary_addr = addr.to_owned();
+        self.resolved = OnceLock::from(resolved);
+        let mut state = lock(&self.state);
+        state.consecutive_failures = 0;
+        state.last_probe = None;
+    }
+
+    // The address the primary resolves to, resolving it if it hasn't been since the socket was
+    // last opened.
+    pub(crate) fn primary_addr(&self) -> io::Result<SocketAddr> {
+        if let Some(resolved) = self.resolved.get() {
+            return Ok(*resolved);
+        }
+        let resolved = resolve_to_addr(&self.primary_addr, self.address_family)?;
+        Ok(*self.resolved.get_or_init(|| resolved))
+    }
+
+    // Resolves the primary again before the next send, once the socket's been opened again,
+    // so a hostname follows its DNS records.
+    pub(crate) fn reopened(&mut self) {
+        self.resolved = OnceLock::new();
+    }
+
+    // The only address payloads go to, for sending several at once, or `None` with failover or
+    // additional addresses. `Some(None)` when the socket is connected to it.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn single_addr(&self) -> Option<Option<SocketAddr>> {
+        if self.failover_options.is_some() || !self.additional_addrs.is_empty() {
+            return None;
+        }
+        if self.connected {
+            return Some(None);
+        }
+        self.primary_addr().ok().map(Some)
+    }
+
+    // The number of addresses each payload goes to, numbered from 0 for `send_to_target`.
+    pub(crate) fn targets(&self) -> usize {
+        1 + self.additional_addrs.len()
+    }
+
+    // Sends to every target, even after one fails, returning the first error.
+    pub(crate) fn send_to(&self, socket: &UdpSocket, data: &[u8]) -> io::Result<usize> {
+        let sent = self.send_to_target(socket, data, 0);
+        let mut first_error = None;
+        for target in 1..self.targets() {
+            if let Err(error) = self.send_to_target(socket, data, target) {
+                first_error.get_or_insert(error);
+            }
+        }
+        match first_error {
+            Some(error) if sent.is_ok() => Err(error),
+            _ => sent,
+        }
+    }
+
+    // Target 0 is the primary, with failover, and the rest are the additional addresses.
+    pub(crate) fn send_to_target(
+        &self,
+        socket: &UdpSocket,
+        data: &[u8],
+        target: usize,
+    ) -> io::Result<usize> {
+        if target > 0 {
+            let addr = &self.additional_addrs[target - 1];
+            return socket.send_to(data, addr).map_err(|error| {
+                io::Error::new(error.kind(), format!("sending to {}: {}", addr, error))
+            });
+        }
+
+        let failover_options = match &self.failover_options {
+            Some(failover_options) => failover_options,
+            None if self.connected => return socket.send(data),
+            None => return socket.send_to(data, self.primary_addr()?),
+        };
+
+        let mut state = lock(&self.state);
+
+        if let Some(last_probe) = state.last_probe {
+            if last_probe.elapsed() < failover_options.probe_interval {
+                return socket.send_to(data, &failover_options.secondary_addr);
+            }
+
+            // Probe the primary with real traffic, and fail back if it's healthy again.
+            if let Ok(sent) = self
+                .primary_addr()
+                .and_then(|addr| socket.send_to(data, addr))
+            {
+                state.last_probe = None;
+                state.consecutive_failures = 0;
+                return Ok(sent);
+            }
+            state.last_probe = Some(Instant::now());
+            return socket.send_to(data, &failover_options.secondary_addr);
+        }
+
+        match self
+            .primary_addr()
+            .and_then(|addr| socket.send_to(data, addr))
+        {
+            Ok(sent) => {
+                state.consecutive_failures = 0;
+                Ok(sent)
+            }
+            Err(error) => {
+                state.consecutive_failures += 1;
+                if state.consecutive_failures < failover_options.max_consecutive_failures {
+                    return Err(error);
+                }
+
+                let error = io::Error::new(
+                    error.kind(),
+                    format!(
+                        "failing over to {} after {} consecutive failures: {}",
+                        failover_options.secondary_addr, state.consecutive_failures, error
+                    ),
+                );
+                report_error(&self.error_handler, "Failing over", error.into());
+                state.last_probe = Some(Instant::now());
+                socket.send_to(data, &failover_options.secondary_addr)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // Sending to an ipv6 address from an ipv4 socket always fails.
+    const FAILING_ADDR: &str = "[::1]:8125";
+
+    fn listener() -> UdpSocket {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        listener
+    }
+
+    #[test]
+    fn test_no_failover() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let destination = Destination::new(FAILING_ADDR.into(), None);
+
+        assert!(destination.send_to(&socket, b"metric:1|c").is_err());
+        assert!(destination.send_to(&socket, b"metric:1|c").is_err());
+    }
+
+    #[test]
+    fn test_failover_after_consecutive_failures() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let secondary = listener();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let destination = Destination::new(
+            FAILING_ADDR.into(),
+            Some(FailoverOptions {
+                secondary_addr: secondary.local_addr().unwrap().to_string(),
+                max_consecutive_failures: 2,
+                probe_interval: Duration::from_secs(60),
+            }),
+        )
+        .with_error_handler(Some(ErrorHandler::new(move |error| {
+            errors_r.lock().unwrap().push(error.to_string())
+        })));
+
+        assert!(destination.send_to(&socket, b"first:1|c").is_err());
+        destination.send_to(&socket, b"second:1|c").unwrap();
+        destination.send_to(&socket, b"third:1|c").unwrap();
+
+        let mut buf = [0; 100];
+        let len = secondary.recv(&mut buf).unwrap();
+        assert_eq!(b"second:1|c", &buf[..len]);
+        let len = secondary.recv(&mut buf).unwrap();
+        assert_eq!(b"third:1|c", &buf[..len]);
+
+        let errors = errors.lock().unwrap();
+        assert_eq!(1, errors.len());
+        assert!(errors[0].contains(&format!(
+            "failing over to {} after 2 consecutive failures",
+            secondary.local_addr().unwrap()
+        )));
+    }
+
+    #[test]
+    fn test_fail_back_after_probe() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let primary = listener();
+        let destination = Destination::new(
+            primary.local_addr().unwrap().to_string(),
+            Some(FailoverOptions {
+                secondary_addr: FAILING_ADDR.into(),
+                max_consecutive_failures: 1,
+                probe_interval: Duration::ZERO,
+            }),
+        );
+        destination.state.lock().unwrap().last_probe = Some(Instant::now());
+
+        destination.send_to(&socket, b"probe:1|c").unwrap();
+        destination.send_to(&socket, b"after:1|c").unwrap();
+
+        let mut buf = [0; 100];
+        let len = primary.recv(&mut buf).unwrap();
+        assert_eq!(b"probe:1|c", &buf[..len]);
+        let len = primary.recv(&mut buf).unwrap();
+        assert_eq!(b"after:1|c", &buf[..len]);
+        assert!(destination.state.lock().unwrap().last_probe.is_none());
+    }
+
+    #[test]
+    fn test_connected() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let primary = listener();
+        socket.connect(primary.local_addr().unwrap()).unwrap();
+        // The address isn't used once connected.
+        let destination = Destination::new(FAILING_ADDR.into(), None).with_connected(true);
+
+        destination.send_to(&socket, b"metric:1|c").unwrap();
+
+        let mut buf = [0; 100];
+        let len = primary.recv(&mut buf).unwrap();
+        assert_eq!(b"metric:1|c", &buf[..len]);
+    }
+
+    #[test]
+    fn test_set_primary_addr() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let primary = listener();
+        let destination = Destination::new(FAILING_ADDR.into(), None);
+
+        // A client sending to an ipv6 primary binds an ipv6 socket, which can't send to ipv4.
+        let addr = primary.local_addr().unwrap().to_string();
+        assert!(destination.resolve_new_primary(&addr).is_err());
+
+        let mut destination = Destination::new("127.0.0.1:8125".into(), None);
+        let resolved = destination.resolve_new_primary(&addr).unwrap();
+        destination.set_primary_addr(&addr, resolved);
+        destination.send_to(&socket, b"metric:1|c").unwrap();
+
+        let mut buf = [0; 100];
+        let len = primary.recv(&mut buf).unwrap();
+        assert_eq!(b"metric:1|c", &buf[..len]);
+
+        let connected = Destination::new("127.0.0.1:8125".into(), None).with_connected(true);
+        assert!(connected.resolve_new_primary("127.0.0.1:8126").is_err());
+    }
+
+    #[test]
+    fn test_additional_addrs() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let primary = listener();
+        let additional = listener();
+        let destination = Destination::new(primary.local_addr().unwrap().to_string(), None)
+            .with_additional_addrs(vec![
+                FAILING_ADDR.into(),
+                additional.local_addr().unwrap().to_string(),
+            ]);
+        assert_eq!(3, destination.targets());
+
+        // The failing address doesn't stop the others from being sent to.
+        let error = destination.send_to(&socket, b"metric:1|c").unwrap_err();
+        assert!(error.to_string().starts_with("sending to [::1]:8125: "));
+
+        let mut buf = [0; 100];
+        let len = primary.recv(&mut buf).unwrap();
+        assert_eq!(b"metric:1|c", &buf[..len]);
+        let len = additional.recv(&mut buf).unwrap();
+        assert_eq!(b"metric:1|c", &buf[..len]);
+    }
+}
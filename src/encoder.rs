@@ -0,0 +1,209 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// A metric as the client formats it before its tags are added, given to an `Encoder`.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricParts<'a> {
+    payload: &'a str,
+    is_metric: bool,
+}
+
+impl<'a> MetricParts<'a> {
+    pub(crate) fn new(payload: &'a str, is_metric: bool) -> Self {
+        MetricParts { payload, is_metric }
+    }
+
+    /// The metric in the DogStatsD format without its tags, such as `app.page.views:1|c|@0.5`,
+    /// including the namespace.
+    pub fn payload(&self) -> &'a str {
+        self.payload
+    }
+
+    /// The metric's name, including the namespace, or `None` for events and service checks.
+    pub fn name(&self) -> Option<&'a str> {
+        self.split().map(|(name, _)| name)
+    }
+
+    /// Everything after the metric's name, such as `1|c|@0.5`, or `None` for events and
+    /// service checks.
+    pub fn value(&self) -> Option<&'a str> {
+        self.split().map(|(_, value)| value)
+    }
+
+    fn split(&self) -> Option<(&'a str, &'a str)> {
+        if !self.is_metric {
+            return None;
+        }
+        self.payload.split_once(':')
+    }
+}
+
+/// Writes metrics in the format of the server they're sent to. The client calls `begin` with
+/// the metric, `tag` with each of its tags followed by each default tag, then `end`, all with
+/// the same buffer.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Encoder, MetricParts, OptionsBuilder, SharedEncoder};
+///
+///   // Sends tags as `key=value`, the way some StatsD servers expect.
+///   #[derive(Debug)]
+///   struct EqualsTags;
+///
+///   impl Encoder for EqualsTags {
+///       fn begin(&self, metric: &MetricParts, buf: &mut Vec<u8>) {
+///           buf.extend_from_slice(metric.payload().as_bytes());
+///       }
+///
+///       fn tag(&self, _metric: &MetricParts, tag: &str, first: bool, buf: &mut Vec<u8>) {
+///           buf.extend_from_slice(if first { b"|#" } else { b"," });
+///           buf.extend_from_slice(tag.replacen(':', "=", 1).as_bytes());
+///       }
+///   }
+///
+///   let options = OptionsBuilder::new().encoder(SharedEncoder::new(EqualsTags)).build();
+/// ```
+pub trait Encoder: fmt::Debug + Send + Sync {
+    /// Write the start of the payload, before any tags.
+    fn begin(&self, metric: &MetricParts, buf: &mut Vec<u8>);
+
+    /// Write one tag, `first` being whether it's the metric's first.
+    fn tag(&self, metric: &MetricParts, tag: &str, first: bool, buf: &mut Vec<u8>);
+
+    /// Write the end of the payload, after every tag. Writes nothing by default.
+    fn end(&self, _metric: &MetricParts, _buf: &mut Vec<u8>) {}
+}
+
+/// The DogStatsD format the Datadog agent reads, used unless `Options::encoder` is set.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DogStatsdEncoder;
+
+impl Encoder for DogStatsdEncoder {
+    fn begin(&self, metric: &MetricParts, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(metric.payload().as_bytes());
+    }
+
+    fn tag(&self, _metric: &MetricParts, tag: &str, first: bool, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(if first { b"|#" } else { b"," });
+        buf.extend_from_slice(tag.as_bytes());
+    }
+}
+
+/// The plain StatsD format, which has no tags, so they're left out.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatsdEncoder;
+
+impl Encoder for StatsdEncoder {
+    fn begin(&self, metric: &MetricParts, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(metric.payload().as_bytes());
+    }
+
+    fn tag(&self, _metric: &MetricParts, _tag: &str, _first: bool, _buf: &mut Vec<u8>) {}
+}
+
+/// The format of Telegraf's StatsD input, with tags after the name as `key=value`, such as
+/// `page.views,env=prod:1|c`. Tags without a value are sent as `tag=true`. Events and service
+/// checks are sent in the DogStatsD format, which Telegraf reads with `datadog_extensions`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TelegrafEncoder;
+
+impl Encoder for TelegrafEncoder {
+    fn begin(&self, metric: &MetricParts, buf: &mut Vec<u8>) {
+        let start = metric.name().unwrap_or(metric.payload());
+        buf.extend_from_slice(start.as_bytes());
+    }
+
+    fn tag(&self, metric: &MetricParts, tag: &str, first: bool, buf: &mut Vec<u8>) {
+        if metric.name().is_none() {
+            return DogStatsdEncoder.tag(metric, tag, first, buf);
+        }
+        buf.push(b',');
+        let (key, value) = tag.split_once(':').unwrap_or((tag, "true"));
+        buf.extend_from_slice(key.as_bytes());
+        buf.push(b'=');
+        buf.extend_from_slice(value.as_bytes());
+    }
+
+    fn end(&self, metric: &MetricParts, buf: &mut Vec<u8>) {
+        if let Some(value) = metric.value() {
+            buf.push(b':');
+            buf.extend_from_slice(value.as_bytes());
+        }
+    }
+}
+
+/// An `Encoder` that can be shared between clients, for `Options::encoder`.
+#[derive(Clone)]
+pub struct SharedEncoder(Arc<dyn Encoder>);
+
+impl SharedEncoder {
+    /// Create a new `SharedEncoder` from an encoder.
+    pub fn new<E: Encoder + 'static>(encoder: E) -> Self {
+        SharedEncoder(Arc::new(encoder))
+    }
+
+    pub(crate) fn get(&self) -> &dyn Encoder {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for SharedEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl PartialEq for SharedEncoder {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(encoder: &dyn Encoder, payload: &str, is_metric: bool, tags: &[&str]) -> String {
+        let metric = MetricParts::new(payload, is_metric);
+        let mut buf = Vec::new();
+        encoder.begin(&metric, &mut buf);
+        for (i, tag) in tags.iter().enumerate() {
+            encoder.tag(&metric, tag, i == 0, &mut buf);
+        }
+        encoder.end(&metric, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_metric_parts() {
+        let metric = MetricParts::new("app.views:1|c|@0.5", true);
+        assert_eq!(Some("app.views"), metric.name());
+        assert_eq!(Some("1|c|@0.5"), metric.value());
+
+        let event = MetricParts::new("_e{5,4}:title|text", false);
+        assert_eq!(None, event.name());
+        assert_eq!(None, event.value());
+    }
+
+    #[test]
+    fn test_encoders() {
+        let tags = ["env:prod", "canary"];
+        assert_eq!(
+            "views:1|c|@0.5|#env:prod,canary",
+            encode(&DogStatsdEncoder, "views:1|c|@0.5", true, &tags)
+        );
+        assert_eq!(
+            "views:1|c|@0.5",
+            encode(&StatsdEncoder, "views:1|c|@0.5", true, &tags)
+        );
+        assert_eq!(
+            "views,env=prod,canary=true:1|c|@0.5",
+            encode(&TelegrafEncoder, "views:1|c|@0.5", true, &tags)
+        );
+        assert_eq!(
+            "_sc|web|0|#env:prod",
+            encode(&TelegrafEncoder, "_sc|web|0", false, &tags[..1])
+        );
+    }
+}
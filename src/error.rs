@@ -1,12 +1,68 @@
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use std::{fmt, io};
 
 /// This type represents the possible errors that can occur while
 /// sending DogstatsD metrics.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum DogstatsdError {
     /// Chained IO errors.
     IoError(io::Error),
+    /// The batch processor's channel closed before it was shut down, with this many metrics
+    /// still buffered.
+    ChannelClosed(u64),
+    /// A single metric's payload is larger than `Options::max_payload_size`, even after
+    /// applying `Options::oversized_metric_policy`.
+    PayloadTooLarge {
+        /// The name of the metric.
+        metric: String,
+        /// The size of the payload in bytes.
+        size: usize,
+        /// The largest payload allowed.
+        max_size: usize,
+    },
+    /// `Options::adaptive_sampling` changed the client's sample rate. Only reported to
+    /// `Options::error_handler`.
+    SampleRateAdjusted {
+        /// The previous sample rate.
+        from: f64,
+        /// The new sample rate.
+        to: f64,
+    },
+    /// `Options::watchdog` found the batch processor hasn't handled a message in a while. Only
+    /// reported to `Options::error_handler`.
+    BatchProcessorStalled {
+        /// How long it's been since the batch processor last handled a message.
+        stalled_for: Duration,
+        /// Whether it was replaced by a new batch processor.
+        restarted: bool,
+    },
+    /// `BatchingOptionsBuilder::build` was given settings that can't work with its transport.
+    InvalidBatchingOptions(String),
+    /// A metric's tags set a key reserved by Datadog, as checked by `Options::reserved_tags`.
+    ReservedTag(String),
+    /// A metric's tags were larger than `Options::max_tags_bytes`, so the last ones were
+    /// dropped. Only reported to `Options::error_handler`.
+    TagsTooLarge {
+        /// The name of the metric.
+        metric: String,
+        /// The size of the tags in bytes.
+        size: usize,
+        /// The largest size allowed.
+        max_size: usize,
+    },
+    /// A batch was too large for the socket to send, such as a UDP datagram over the path MTU,
+    /// so it was split in half and sent again. Only reported to `Options::error_handler`.
+    BatchTooLarge {
+        /// The size of the batch in bytes.
+        size: usize,
+    },
+    /// The batch processor stopped before resolving a `Completion`, so whether its metrics
+    /// were sent isn't known.
+    CompletionDropped,
 }
 
 use self::DogstatsdError::*;
@@ -15,6 +71,51 @@ impl fmt::Display for DogstatsdError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             IoError(ref error) => write!(f, "{}", error),
+            ChannelClosed(buffered_metrics) => write!(
+                f,
+                "batch processor channel closed with {} metrics buffered",
+                buffered_metrics
+            ),
+            PayloadTooLarge {
+                ref metric,
+                size,
+                max_size,
+            } => write!(
+                f,
+                "payload for metric {} is {} bytes, over the limit of {}",
+                metric, size, max_size
+            ),
+            SampleRateAdjusted { from, to } => {
+                write!(f, "sample rate adjusted from {} to {}", from, to)
+            }
+            BatchProcessorStalled {
+                stalled_for,
+                restarted,
+            } => write!(
+                f,
+                "batch processor hasn't handled a message in {:?}{}",
+                stalled_for,
+                if restarted { ", restarted it" } else { "" }
+            ),
+            InvalidBatchingOptions(ref reason) => {
+                write!(f, "invalid batching options: {}", reason)
+            }
+            ReservedTag(ref tag) => write!(f, "tag {} sets a key reserved by Datadog", tag),
+            TagsTooLarge {
+                ref metric,
+                size,
+                max_size,
+            } => write!(
+                f,
+                "tags for metric {} are {} bytes, over the limit of {}",
+                metric, size, max_size
+            ),
+            BatchTooLarge { size } => write!(
+                f,
+                "batch of {} bytes was too large for the socket, so it was split",
+                size
+            ),
+            CompletionDropped => write!(f, "batch processor stopped before the metrics were sent"),
         }
     }
 }
@@ -23,6 +124,15 @@ impl Error for DogstatsdError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             IoError(error) => Some(error),
+            ChannelClosed(_)
+            | PayloadTooLarge { .. }
+            | SampleRateAdjusted { .. }
+            | BatchProcessorStalled { .. }
+            | InvalidBatchingOptions(_)
+            | ReservedTag(_)
+            | TagsTooLarge { .. }
+            | BatchTooLarge { .. }
+            | CompletionDropped => None,
         }
     }
 }
@@ -33,14 +143,118 @@ impl From<io::Error> for DogstatsdError {
     }
 }
 
+/// A callback for errors that can't be returned to the caller, such as those that happen on
+/// the batch processor's thread.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::ErrorHandler;
+///
+///   let handler = ErrorHandler::new(|error| eprintln!("dogstatsd error: {}", error));
+/// ```
+#[derive(Clone)]
+pub struct ErrorHandler(Arc<dyn Fn(&DogstatsdError) + Send + Sync>);
+
+impl ErrorHandler {
+    /// Create a new `ErrorHandler` from a closure.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&DogstatsdError) + Send + Sync + 'static,
+    {
+        ErrorHandler(Arc::new(handler))
+    }
+
+    pub(crate) fn handle(&self, error: &DogstatsdError) {
+        (self.0)(error)
+    }
+}
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+impl PartialEq for ErrorHandler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// Hands the error to the handler if there is one, and prints it otherwise.
+pub(crate) fn report_error(handler: &Option<ErrorHandler>, context: &str, error: DogstatsdError) {
+    match handler {
+        Some(handler) => handler.handle(&error),
+        None => println!("{}: {:?}", context, error),
+    }
+}
+
+// Like `report_error`, for errors that can repeat for as long as something stays broken:
+// without a handler, only the first one is printed until `printed` is cleared again.
+pub(crate) fn report_error_once(
+    handler: &Option<ErrorHandler>,
+    printed: &AtomicBool,
+    context: &str,
+    error: &DogstatsdError,
+) {
+    match handler {
+        Some(handler) => handler.handle(error),
+        None if !printed.swap(true, Ordering::Relaxed) => {
+            eprintln!("{}: {:?} (repeats aren't printed)", context, error)
+        }
+        None => {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::DogstatsdError;
+    use super::{DogstatsdError, ErrorHandler};
     use std::io;
+    use std::sync::{Arc, Mutex};
 
     #[test]
+    #[allow(clippy::io_other_error)]
     fn test_error_display() {
         let err = DogstatsdError::from(io::Error::new(io::ErrorKind::Other, "oh no!"));
         assert_eq!(format!("{}", err), "oh no!".to_owned());
     }
+
+    #[test]
+    fn test_channel_closed_display() {
+        let err = DogstatsdError::ChannelClosed(3);
+        assert_eq!(
+            format!("{}", err),
+            "batch processor channel closed with 3 metrics buffered".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_payload_too_large_display() {
+        let err = DogstatsdError::PayloadTooLarge {
+            metric: "my_count".into(),
+            size: 70_000,
+            max_size: 65_467,
+        };
+        assert_eq!(
+            format!("{}", err),
+            "payload for metric my_count is 70000 bytes, over the limit of 65467".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_error_handler() {
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let handler = ErrorHandler::new(move |error| {
+            errors_r.lock().unwrap().push(format!("{}", error));
+        });
+
+        handler.handle(&DogstatsdError::ChannelClosed(1));
+        assert_eq!(
+            vec!["batch processor channel closed with 1 metrics buffered".to_owned()],
+            *errors.lock().unwrap()
+        );
+        assert_eq!(handler.clone(), handler);
+    }
 }
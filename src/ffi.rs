@@ -0,0 +1,378 @@
+//! A C ABI over `Client`, so code in other languages in the same process can share one client
+//! and one socket. Only available with the `ffi` feature; declarations are in
+//! `include/dogstatsd.h`, and `cargo rustc --release --features ffi --crate-type cdylib` builds
+//! the shared library to link, such as `libdogstatsd.so`.
+//!
+//! Every string is a NUL-terminated UTF-8 C string, and every function that sends returns `0`
+//! on success and `-1` on failure, including for invalid arguments.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::{Client, DogstatsdResult, Options};
+
+const OK: c_int = 0;
+const ERROR: c_int = -1;
+
+/// Create a client that sends to `to_addr` with the given `namespace`. Either may be null to
+/// use the default. Returns null if the client can't be created.
+///
+/// # Safety
+///
+/// `to_addr` and `namespace` must each be null or a valid C string. The returned client must
+/// be freed with `dogstatsd_client_free`.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_client_new(
+    to_addr: *const c_char,
+    namespace: *const c_char,
+) -> *mut Client {
+    catch_panic(ptr::null_mut(), || {
+        let mut options = Options::default();
+        match to_str(to_addr) {
+            Some(Some(to_addr)) => options.to_addr = to_addr.into(),
+            Some(None) => {}
+            None => return ptr::null_mut(),
+        }
+        match to_str(namespace) {
+            Some(Some(namespace)) => options.namespace = namespace.into(),
+            Some(None) => {}
+            None => return ptr::null_mut(),
+        }
+
+        match Client::new(options) {
+            Ok(client) => Box::into_raw(Box::new(client)),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Free a client created with `dogstatsd_client_new`. Does nothing if `client` is null.
+///
+/// # Safety
+///
+/// `client` must be null or a pointer returned by `dogstatsd_client_new` that hasn't been
+/// freed yet, and mustn't be used afterwards.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_client_free(client: *mut Client) {
+    if !client.is_null() {
+        catch_panic((), || drop(Box::from_raw(client)));
+    }
+}
+
+/// Increment a counter.
+///
+/// # Safety
+///
+/// `client` must be a live client, `stat` a valid C string, and `tags` null or an array of
+/// `tags_len` valid C strings.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_incr(
+    client: *const Client,
+    stat: *const c_char,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        client.incr(stat, tags)
+    })
+}
+
+/// Decrement a counter.
+///
+/// # Safety
+///
+/// Same as `dogstatsd_incr`.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_decr(
+    client: *const Client,
+    stat: *const c_char,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        client.decr(stat, tags)
+    })
+}
+
+/// Add `count` to a counter.
+///
+/// # Safety
+///
+/// Same as `dogstatsd_incr`.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_count(
+    client: *const Client,
+    stat: *const c_char,
+    count: i64,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        client.count(stat, count, tags)
+    })
+}
+
+/// Report a timing in ms.
+///
+/// # Safety
+///
+/// Same as `dogstatsd_incr`.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_timing(
+    client: *const Client,
+    stat: *const c_char,
+    ms: i64,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        client.timing(stat, ms, tags)
+    })
+}
+
+/// Report a gauge.
+///
+/// # Safety
+///
+/// Same as `dogstatsd_incr`.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_gauge(
+    client: *const Client,
+    stat: *const c_char,
+    value: f64,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        client.gauge(stat, client.format_float(value), tags)
+    })
+}
+
+/// Report a sample of a histogram.
+///
+/// # Safety
+///
+/// Same as `dogstatsd_incr`.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_histogram(
+    client: *const Client,
+    stat: *const c_char,
+    value: f64,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        client.histogram(stat, client.format_float(value), tags)
+    })
+}
+
+/// Report a sample of a distribution.
+///
+/// # Safety
+///
+/// Same as `dogstatsd_incr`.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_distribution(
+    client: *const Client,
+    stat: *const c_char,
+    value: f64,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        client.distribution(stat, client.format_float(value), tags)
+    })
+}
+
+/// Report a member of a set.
+///
+/// # Safety
+///
+/// Same as `dogstatsd_incr`, and `value` must be a valid C string.
+#[no_mangle]
+#[cfg_attr(all(test, not(debug_assertions)), no_panic::no_panic)]
+pub unsafe extern "C" fn dogstatsd_set(
+    client: *const Client,
+    stat: *const c_char,
+    value: *const c_char,
+    tags: *const *const c_char,
+    tags_len: usize,
+) -> c_int {
+    send(client, stat, tags, tags_len, |client, stat, tags| {
+        let value = match to_str(value) {
+            Some(Some(value)) => value,
+            _ => return Err(io::Error::from(io::ErrorKind::InvalidInput).into()),
+        };
+        client.set(stat, value, tags)
+    })
+}
+
+// Runs the body of an exported function, returning `on_panic` if it panics rather than
+// unwinding into the caller, which would abort the process. The panic's payload is leaked,
+// since dropping it could panic again.
+fn catch_panic<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            mem::forget(payload);
+            on_panic
+        }
+    }
+}
+
+// Checks and converts the arguments every send shares, then sends with `f`.
+unsafe fn send<F>(
+    client: *const Client,
+    stat: *const c_char,
+    tags: *const *const c_char,
+    tags_len: usize,
+    f: F,
+) -> c_int
+where
+    F: FnOnce(&Client, &str, Vec<&str>) -> DogstatsdResult,
+{
+    catch_panic(ERROR, || {
+        let client = match client.as_ref() {
+            Some(client) => client,
+            None => return ERROR,
+        };
+        let stat = match to_str(stat) {
+            Some(Some(stat)) => stat,
+            _ => return ERROR,
+        };
+        let tags = match to_tags(tags, tags_len) {
+            Some(tags) => tags,
+            None => return ERROR,
+        };
+
+        match f(client, stat, tags) {
+            Ok(()) => OK,
+            Err(_) => ERROR,
+        }
+    })
+}
+
+// `None` if the string isn't valid UTF-8, `Some(None)` if it's null.
+unsafe fn to_str<'a>(s: *const c_char) -> Option<Option<&'a str>> {
+    if s.is_null() {
+        return Some(None);
+    }
+    CStr::from_ptr(s).to_str().ok().map(Some)
+}
+
+unsafe fn to_tags<'a>(tags: *const *const c_char, tags_len: usize) -> Option<Vec<&'a str>> {
+    if tags.is_null() || tags_len == 0 {
+        return Some(vec![]);
+    }
+    slice::from_raw_parts(tags, tags_len)
+        .iter()
+        .map(|tag| to_str(*tag).flatten())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::net::UdpSocket;
+
+    fn recv(server: &UdpSocket) -> String {
+        let mut buf = [0; 256];
+        let len = server.recv(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
+
+    #[test]
+    fn test_send() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to_addr = CString::new(server.local_addr().unwrap().to_string()).unwrap();
+        let namespace = CString::new("ffi").unwrap();
+        let stat = CString::new("stat").unwrap();
+        let tag1 = CString::new("tag1").unwrap();
+        let tag2 = CString::new("tag2:val").unwrap();
+        let tags = [tag1.as_ptr(), tag2.as_ptr()];
+
+        unsafe {
+            let client = dogstatsd_client_new(to_addr.as_ptr(), namespace.as_ptr());
+            assert!(!client.is_null());
+
+            assert_eq!(OK, dogstatsd_incr(client, stat.as_ptr(), tags.as_ptr(), 2));
+            assert_eq!("ffi.stat:1|c|#tag1,tag2:val", recv(&server));
+
+            assert_eq!(
+                OK,
+                dogstatsd_gauge(client, stat.as_ptr(), 1.5, ptr::null(), 0)
+            );
+            assert_eq!("ffi.stat:1.5|g", recv(&server));
+
+            dogstatsd_client_free(client);
+        }
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let stat = CString::new("stat").unwrap();
+        let invalid = [0xff, 0];
+
+        unsafe {
+            assert_eq!(
+                ERROR,
+                dogstatsd_incr(ptr::null(), stat.as_ptr(), ptr::null(), 0)
+            );
+
+            let client = dogstatsd_client_new(ptr::null(), ptr::null());
+            assert!(!client.is_null());
+            assert_eq!(ERROR, dogstatsd_incr(client, ptr::null(), ptr::null(), 0));
+            assert_eq!(
+                ERROR,
+                dogstatsd_incr(client, invalid.as_ptr() as *const c_char, ptr::null(), 0)
+            );
+            dogstatsd_client_free(client);
+            dogstatsd_client_free(ptr::null_mut());
+        }
+    }
+
+    // Every exported function is checked by `no_panic` in release builds of the tests, so
+    // `cargo test --release --features ffi` only links if none of them can unwind into the
+    // caller. Each has to be called from here to be checked.
+    #[test]
+    fn test_panic_free() {
+        let stat = CString::new("stat").unwrap();
+        let stat = stat.as_ptr();
+
+        unsafe {
+            let client = dogstatsd_client_new(ptr::null(), ptr::null());
+            assert_eq!(OK, dogstatsd_incr(client, stat, ptr::null(), 0));
+            assert_eq!(OK, dogstatsd_decr(client, stat, ptr::null(), 0));
+            assert_eq!(OK, dogstatsd_count(client, stat, 3, ptr::null(), 0));
+            assert_eq!(OK, dogstatsd_timing(client, stat, 12, ptr::null(), 0));
+            assert_eq!(OK, dogstatsd_gauge(client, stat, 1.5, ptr::null(), 0));
+            assert_eq!(OK, dogstatsd_histogram(client, stat, 1.5, ptr::null(), 0));
+            assert_eq!(
+                OK,
+                dogstatsd_distribution(client, stat, 1.5, ptr::null(), 0)
+            );
+            assert_eq!(OK, dogstatsd_set(client, stat, stat, ptr::null(), 0));
+            assert_eq!(
+                ERROR,
+                dogstatsd_set(client, stat, ptr::null(), ptr::null(), 0)
+            );
+            dogstatsd_client_free(client);
+        }
+
+        assert_eq!(ERROR, catch_panic(ERROR, || panic!("panic in a send")));
+    }
+}
@@ -0,0 +1,295 @@
+//! Entry points into the formatter for fuzzing and property tests, checking that formatted
+//! metrics can be parsed back into what was sent. Only available with the `fuzzing` feature,
+//! and not part of the stable API.
+//!
+//! Inputs containing characters the DogStatsD format reserves, such as a `|` in a name or a
+//! `,` in a tag, are outside the formatter's contract and are skipped.
+
+use crate::conformance::validate_payload;
+use crate::metrics::*;
+use crate::parse::parse_metric;
+
+/// A metric value, with the method used to send it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FuzzValue {
+    /// `Client::count`.
+    Count(i64),
+    /// `Client::decr_by_value`.
+    Decr(i64),
+    /// `Client::timing`.
+    Timing(i64),
+    /// `Client::gauge`.
+    Gauge(f64),
+    /// `Client::histogram`.
+    Histogram(f64),
+    /// `Client::distribution`.
+    Distribution(f64),
+    /// `Client::set`.
+    Set(String),
+}
+
+/// Everything that goes into a formatted metric.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FuzzMetric {
+    /// `Options::namespace`.
+    pub namespace: String,
+    /// The metric's name.
+    pub name: String,
+    /// The metric's value.
+    pub value: FuzzValue,
+    /// The tags passed when sending.
+    pub tags: Vec<String>,
+    /// `Options::default_tags`.
+    pub default_tags: Vec<String>,
+}
+
+impl FuzzMetric {
+    /// Build a metric from arbitrary bytes, as given by a fuzzer. Fields are separated by
+    /// `0xff` bytes: namespace, name, value, then tags and default tags separated by `0xfe`.
+    /// The first byte chooses the metric type.
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let (selector, data) = data.split_first().unwrap_or((&0, &[]));
+        let string = |field: &[u8]| String::from_utf8_lossy(field).into_owned();
+        let mut fields = data.split(|b| *b == 0xff);
+        let namespace = string(fields.next().unwrap_or_default());
+        let name = string(fields.next().unwrap_or_default());
+        let value = string(fields.next().unwrap_or_default());
+        let mut tags = fields.next().unwrap_or_default().split(|b| *b == 0xfe);
+        let mut split = || -> Vec<String> {
+            tags.next()
+                .unwrap_or_default()
+                .split(|b| *b == 0xfd)
+                .filter(|tag| !tag.is_empty())
+                .map(string)
+                .collect()
+        };
+        let (tags, default_tags) = (split(), split());
+
+        let integer = value
+            .bytes()
+            .fold(0i64, |n, b| n.wrapping_mul(31).wrapping_add(i64::from(b)));
+        let float = f64::from_bits(integer as u64);
+        let float = if float.is_finite() {
+            float
+        } else {
+            integer as f64
+        };
+        let value = match selector % 7 {
+            0 => FuzzValue::Count(integer),
+            1 => FuzzValue::Decr(integer),
+            2 => FuzzValue::Timing(integer),
+            3 => FuzzValue::Gauge(float),
+            4 => FuzzValue::Histogram(float),
+            5 => FuzzValue::Distribution(float),
+            _ => FuzzValue::Set(value),
+        };
+
+        FuzzMetric {
+            namespace,
+            name,
+            value,
+            tags,
+            default_tags,
+        }
+    }
+
+    /// Whether the metric is within the formatter's contract, and so must round-trip.
+    pub fn is_well_formed(&self) -> bool {
+        let name_ok = |name: &str| !name.contains([':', '|', '@', '#', '\n']);
+        let tag_ok = |tag: &String| !tag.is_empty() && !tag.contains([',', '|', '\n']);
+        let value_ok = match &self.value {
+            FuzzValue::Set(value) => !value.is_empty() && !value.contains([':', '|', '\n']),
+            _ => true,
+        };
+
+        !self.name.is_empty()
+            && name_ok(&self.name)
+            && name_ok(&self.namespace)
+            && value_ok
+            && self.tags.iter().all(tag_ok)
+            && self.default_tags.iter().all(tag_ok)
+    }
+
+    /// Format the metric the way the client does.
+    pub fn format(&self) -> Vec<u8> {
+        let default_tags = self.default_tags.join(",");
+        let format = |metric: &dyn Fn() -> String| {
+            format_for_send(
+                &Formatted(metric()),
+                &self.namespace,
+                &self.tags,
+                default_tags.as_bytes(),
+            )
+        };
+        let name = self.name.as_str();
+
+        match &self.value {
+            FuzzValue::Count(n) => {
+                format(&|| CountMetric::Arbitrary(name, *n).metric_type_format())
+            }
+            FuzzValue::Decr(n) => format(&|| CountMetric::Decr(name, *n).metric_type_format()),
+            FuzzValue::Timing(n) => format(&|| TimingMetric::new(name, *n).metric_type_format()),
+            FuzzValue::Gauge(v) => {
+                format(&|| GaugeMetric::new(name, &v.to_string()).metric_type_format())
+            }
+            FuzzValue::Histogram(v) => {
+                format(&|| HistogramMetric::new(name, &v.to_string()).metric_type_format())
+            }
+            FuzzValue::Distribution(v) => {
+                format(&|| DistributionMetric::new(name, &v.to_string()).metric_type_format())
+            }
+            FuzzValue::Set(v) => format(&|| SetMetric::new(name, v).metric_type_format()),
+        }
+    }
+
+    /// Format the metric, then check that the payload is valid DogStatsD and parses back into
+    /// the same name, value, type and tags. Metrics that aren't well formed always pass.
+    pub fn check_round_trip(&self) -> Result<(), String> {
+        if !self.is_well_formed() {
+            return Ok(());
+        }
+
+        let payload = String::from_utf8(self.format()).map_err(|e| e.to_string())?;
+        validate_payload(&payload).map_err(|e| e.to_string())?;
+
+        let (expected_value, expected_type) = match &self.value {
+            FuzzValue::Count(n) => (n.to_string(), "c"),
+            FuzzValue::Decr(n) => ((-i128::from(*n)).to_string(), "c"),
+            FuzzValue::Timing(n) => (n.to_string(), "ms"),
+            FuzzValue::Gauge(v) => (v.to_string(), "g"),
+            FuzzValue::Histogram(v) => (v.to_string(), "h"),
+            FuzzValue::Distribution(v) => (v.to_string(), "d"),
+            FuzzValue::Set(v) => (v.clone(), "s"),
+        };
+        let expected_name = if self.namespace.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}.{}", self.namespace, self.name)
+        };
+        let expected_tags: Vec<&str> = self
+            .tags
+            .iter()
+            .chain(&self.default_tags)
+            .map(String::as_str)
+            .collect();
+
+        let mismatch = |what: &str| Err(format!("{} doesn't round-trip in {:?}", what, payload));
+        let metric = parse_metric(&payload).map_err(|e| e.to_string())?;
+        if metric.name() != expected_name {
+            return mismatch("name");
+        }
+        if metric.value() != expected_value {
+            return mismatch("value");
+        }
+        if metric.metric_type() != expected_type {
+            return mismatch("type");
+        }
+        if metric.tags().collect::<Vec<_>>() != expected_tags {
+            return mismatch("tags");
+        }
+        if metric.sample_rate().is_some()
+            || metric.container_id().is_some()
+            || metric.timestamp().is_some()
+        {
+            return mismatch("fields");
+        }
+        Ok(())
+    }
+}
+
+// An already formatted metric, so every metric type goes through the same `format_for_send`.
+struct Formatted(String);
+
+impl Metric for Formatted {
+    fn metric_type_format(&self) -> String {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reserved characters are overrepresented, so well formed and malformed inputs both come
+    // up often.
+    const ALPHABET: &[char] = &[
+        'a', 'b', 'z', '0', '9', '.', '_', '-', ' ', ':', '|', '@', '#', ',', '\n', 'é', '指',
+    ];
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn string(&mut self, max_len: u64) -> String {
+            let len = self.next() % (max_len + 1);
+            (0..len)
+                .map(|_| ALPHABET[(self.next() % ALPHABET.len() as u64) as usize])
+                .collect()
+        }
+
+        fn strings(&mut self, max: u64) -> Vec<String> {
+            (0..self.next() % (max + 1))
+                .map(|_| self.string(6))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        let mut well_formed = 0;
+
+        for _ in 0..20_000 {
+            let integer = match rng.next() % 4 {
+                0 => i64::MIN,
+                1 => i64::MAX,
+                _ => rng.next() as i64,
+            };
+            let float = f64::from_bits(rng.next());
+            let float = if float.is_finite() { float } else { 0.1 };
+            let value = match rng.next() % 7 {
+                0 => FuzzValue::Count(integer),
+                1 => FuzzValue::Decr(integer),
+                2 => FuzzValue::Timing(integer),
+                3 => FuzzValue::Gauge(float),
+                4 => FuzzValue::Histogram(float),
+                5 => FuzzValue::Distribution(float),
+                _ => FuzzValue::Set(rng.string(6)),
+            };
+            let metric = FuzzMetric {
+                namespace: rng.string(4),
+                name: rng.string(8),
+                value,
+                tags: rng.strings(3),
+                default_tags: rng.strings(2),
+            };
+
+            well_formed += usize::from(metric.is_well_formed());
+            assert_eq!(Ok(()), metric.check_round_trip(), "{:?}", metric);
+        }
+        assert!(well_formed > 100);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let metric = FuzzMetric::from_bytes(b"\x06ns\xffstat\xffvalue\xfftag1\xfdtag2");
+        assert_eq!(
+            FuzzMetric {
+                namespace: "ns".into(),
+                name: "stat".into(),
+                value: FuzzValue::Set("value".into()),
+                tags: vec!["tag1".into(), "tag2".into()],
+                default_tags: vec![],
+            },
+            metric
+        );
+        assert_eq!(Ok(()), metric.check_round_trip());
+        assert_eq!(Ok(()), FuzzMetric::from_bytes(b"").check_round_trip());
+    }
+}
@@ -0,0 +1,97 @@
+//! A client shared by the whole process, for libraries that want to send metrics only if the
+//! application using them has set one up. Only the application creates it, and shuts it down
+//! before exiting, since the client is never dropped. Libraries only send through it.
+//!
+//! # Examples
+//!
+//! ```
+//!   use dogstatsd::{global, try_init_global, Options};
+//!
+//!   // In the application, once at startup.
+//!   try_init_global(Options::default()).unwrap();
+//!
+//!   // In a library, which sends nothing if the application never set up a client.
+//!   global::client().incr("cache.miss", &["cache:users"]).unwrap();
+//!
+//!   // In the application, before exiting.
+//!   global::shutdown();
+//! ```
+
+use std::sync::OnceLock;
+
+use crate::{Client, DogstatsdError, Options, ShutdownReport};
+
+static GLOBAL: OnceLock<Client> = OnceLock::new();
+static NOOP: OnceLock<Client> = OnceLock::new();
+
+/// Create the global client from `options` if it hasn't been created yet, returning whether
+/// this call created it. Only the application should call it, once at startup; later calls
+/// leave the first client in place.
+pub fn try_init_global(options: Options) -> Result<bool, DogstatsdError> {
+    if GLOBAL.get().is_some() {
+        return Ok(false);
+    }
+    // If another thread gets there first, its client is kept and this one is dropped.
+    Ok(GLOBAL.set(Client::new(options)?).is_ok())
+}
+
+/// Whether the global client has been created.
+pub fn is_initialized() -> bool {
+    GLOBAL.get().is_some()
+}
+
+/// The global client, if it has been created.
+pub fn get() -> Option<&'static Client> {
+    GLOBAL.get()
+}
+
+/// The global client, or a client from `Client::noop` that sends nothing if it hasn't been
+/// created.
+pub fn client() -> &'static Client {
+    GLOBAL
+        .get()
+        .unwrap_or_else(|| NOOP.get_or_init(Client::noop))
+}
+
+/// Send anything the global client holds or has buffered for batching, and wait for it to be
+/// sent, as `Client::shutdown` does. Returns `None` if it was never created. The client can't
+/// be dropped, so anything sent through it afterwards needs another call to be flushed.
+pub fn shutdown() -> Option<ShutdownReport> {
+    GLOBAL.get().map(Client::flush_for_shutdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OptionsBuilder;
+
+    // The only test that touches the global client, since it can't be reset.
+    #[test]
+    fn test_try_init_global() {
+        assert!(!is_initialized());
+        assert!(get().is_none());
+        assert!(client().is_noop());
+        client().incr("counter", [] as [&str; 0]).unwrap();
+        assert_eq!(None, shutdown());
+
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let options = OptionsBuilder::new()
+            .namespace("first".into())
+            .to_addr(server.local_addr().unwrap().to_string())
+            .batching_options(crate::BatchingOptions::default())
+            .build();
+        assert!(try_init_global(options).unwrap());
+        let options = OptionsBuilder::new().namespace("second".into()).build();
+        assert!(!try_init_global(options).unwrap());
+
+        assert!(is_initialized());
+        assert!(!client().is_noop());
+        assert_eq!("first", get().unwrap().namespace);
+
+        client().incr("counter", [] as [&str; 0]).unwrap();
+        assert_eq!(1, shutdown().unwrap().flushed_metrics);
+        let mut buf = [0; 32];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"first.counter:1|c\n", &buf[..len]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::encoder::SharedEncoder;
+use crate::metrics::{format_with_encoder, DefaultTags, GaugeMetric};
+use crate::sync::lock;
+use crate::writer::Writer;
+use crate::{DogstatsdResult, DropReason};
+
+type Payload = Arc<Mutex<Vec<u8>>>;
+type Registry = Arc<Mutex<Vec<Weak<Mutex<Vec<u8>>>>>>;
+
+/// A handle to a metric that's re-sent every `Options::keep_alive_interval`, until the handle
+/// is dropped.
+#[derive(Debug)]
+pub struct KeepAlive {
+    _payload: Payload,
+}
+
+/// A handle to a gauge whose last value is re-sent every `Options::keep_alive_interval`, so
+/// the series doesn't go stale, until the handle is dropped.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, Options};
+///
+///   let client = Client::new(Options::default()).unwrap();
+///   let pool_size = client.persistent_gauge("pool.size", &["tag:pool"]);
+///   pool_size.set("12")
+///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+/// ```
+#[derive(Debug)]
+pub struct PersistentGauge {
+    payload: Payload,
+    writer: Arc<Writer>,
+    stat: String,
+    namespace: String,
+    tags: Vec<String>,
+    default_tags: DefaultTags,
+    encoder: Option<SharedEncoder>,
+}
+
+impl PersistentGauge {
+    /// Report a new value for the gauge now, and keep re-sending it until the next call.
+    pub fn set<'a, S>(&self, val: S) -> DogstatsdResult
+    where
+        S: Into<Cow<'a, str>>,
+    {
+        let formatted_metric = format_with_encoder(
+            &GaugeMetric::new(&self.stat, val.into().as_ref()),
+            &self.namespace,
+            &self.tags,
+            self.encoder.as_ref().map(SharedEncoder::get),
+            &self.default_tags,
+        );
+        *lock(&self.payload) = formatted_metric.clone();
+        self.writer.send(formatted_metric)
+    }
+}
+
+// The metrics registered for keep-alive, and the thread that re-sends them. The thread only
+// holds weak references, so it stops once the client and every handle are gone.
+#[derive(Debug)]
+pub(crate) struct KeepAlives {
+    interval: Duration,
+    registered: Registry,
+    started: Once,
+}
+
+impl KeepAlives {
+    pub(crate) fn new(interval: Duration) -> Self {
+        KeepAlives {
+            interval,
+            registered: Arc::new(Mutex::new(vec![])),
+            started: Once::new(),
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Re-sends the registered metrics now, as the thread does every interval.
+    #[cfg(test)]
+    pub(crate) fn resend(&self, writer: &Writer) {
+        resend(writer, &self.registered)
+    }
+
+    pub(crate) fn register(&self, writer: &Arc<Writer>, formatted_metric: Vec<u8>) -> Payload {
+        let payload = Arc::new(Mutex::new(formatted_metric));
+        lock(&self.registered).push(Arc::downgrade(&payload));
+
+        self.started.call_once(|| {
+            let interval = self.interval;
+            let weak = Arc::downgrade(writer);
+            let registered = self.registered.clone();
+            if let Err(error) = thread::Builder::new()
+                .name("dogstatsd-keep-alive".into())
+                .spawn(move || resend_every(interval, weak, registered))
+            {
+                writer.report_error("Failed to start the keep-alive thread", error.into());
+            }
+        });
+
+        payload
+    }
+
+    pub(crate) fn keep_alive(&self, writer: &Arc<Writer>, formatted_metric: Vec<u8>) -> KeepAlive {
+        KeepAlive {
+            _payload: self.register(writer, formatted_metric),
+        }
+    }
+
+    pub(crate) fn persistent_gauge(
+        &self,
+        writer: &Arc<Writer>,
+        stat: String,
+        namespace: String,
+        tags: Vec<String>,
+        default_tags: DefaultTags,
+        encoder: Option<SharedEncoder>,
+    ) -> PersistentGauge {
+        // Nothing is re-sent until the first value is set.
+        PersistentGauge {
+            payload: self.register(writer, vec![]),
+            writer: writer.clone(),
+            stat,
+            namespace,
+            tags,
+            default_tags,
+            encoder,
+        }
+    }
+}
+
+fn resend_every(interval: Duration, writer: Weak<Writer>, registered: Registry) {
+    loop {
+        thread::sleep(interval);
+
+        let writer = match writer.upgrade() {
+            Some(writer) => writer,
+            None => return,
+        };
+        resend(&writer, &registered);
+    }
+}
+
+// Re-sends every registered metric that still has a handle and a value.
+fn resend(writer: &Writer, registered: &Registry) {
+    let payloads: Vec<Vec<u8>> = {
+        let mut registered = lock(registered);
+        registered.retain(|payload| payload.strong_count() > 0);
+        registered
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|payload| lock(&payload).clone())
+            .filter(|payload| !payload.is_empty())
+            .collect()
+    };
+
+    for payload in payloads {
+        if let Err(error) = writer.send(payload) {
+            writer.stats.record_drop(DropReason::SendFailed, 1);
+            writer.report_error("Failed to re-send kept alive metric", error);
+        }
+    }
+}
@@ -92,26 +92,185 @@ extern crate chrono;
 
 use chrono::Utc;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::future::Future;
-use std::net::UdpSocket;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
 use std::os::unix::net::UnixDatagram;
 use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Mutex};
+use std::sync::{
+    mpsc, Arc, LockResult, Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-pub use self::error::DogstatsdError;
+pub use self::aggregation_key::AggregationKeyGenerator;
+use self::batch_processor::Clock;
+#[cfg(feature = "compat")]
+pub use self::compat::{MetricSink, StatsdSink};
+pub use self::completion::Completion;
+use self::destination::Destination;
+pub use self::destination::FailoverOptions;
+pub use self::encoder::{
+    DogStatsdEncoder, Encoder, MetricParts, SharedEncoder, StatsdEncoder, TelegrafEncoder,
+};
+pub use self::error::{DogstatsdError, ErrorHandler};
+pub use self::global::try_init_global;
+use self::keep_alive::KeepAlives;
+pub use self::keep_alive::{KeepAlive, PersistentGauge};
+#[cfg(feature = "loadgen")]
+pub use self::loadgen::{run_load, LoadMetricType, LoadProfile, LoadReport};
 use self::metrics::*;
 pub use self::metrics::{EventAlertType, EventPriority, ServiceCheckOptions, ServiceStatus};
+use self::mirror::Mirror;
+pub use self::mirror::{DebugMirror, MirrorHandler, MirrorSink};
+use self::normalize::normalize_formatted;
+pub use self::normalize::normalize_metric_name;
+pub use self::parse::{parse_metric, MetricLine, ParseError};
+pub use self::pool::{PoolMetrics, PoolStats};
+use self::probe::ProbeTarget;
+pub use self::probe::{AgentHealth, SelfTestReport};
+#[cfg(feature = "proxy")]
+pub use self::proxy::{Proxy, ProxyListen};
+pub use self::rate_limit::TagRateLimit;
+use self::rate_limit::TagRateLimiter;
+use self::reconnect::Reconnector;
+pub use self::reconnect::{ConnectRetry, ReconnectOptions};
+pub use self::reserved_tags::{ReservedTagAction, ReservedTags};
+pub use self::rollup::GaugeRollup;
+use self::rollup::Rollup;
+use self::route::Router;
+pub use self::route::{MetricDraft, RouteDecision, RouteHook};
+pub use self::sampling::AdaptiveSampling;
+use self::sampling::{correct_sample_rate, Sampler};
+pub use self::sender::{MetricPayload, MetricSender};
+pub use self::simple::SimpleClient;
+pub use self::startup::BufferedStartupClient;
+use self::stats::Stats;
+pub use self::stats::{ClientStats, DropReason, ShutdownReport};
+#[cfg(feature = "stream")]
+pub use self::stream::{CountItems, InstrumentStream, TimeItems};
+use self::sync::lock;
+pub use self::tag_source::TagSource;
+#[cfg(feature = "test-server")]
+pub use self::test_server::TestServer;
+#[cfg(any(test, feature = "testing"))]
+pub use self::testing::{CaptureSink, CapturedPayload, MockClock};
+pub use self::timed_lock::TimedGuard;
+#[cfg(feature = "trace-context")]
+pub use self::trace_context::{TraceContext, TraceContextGuard, TraceContextLayer};
+use self::transport::{
+    bind_udp, connect_uds, resolve_to_addr, set_uds_blocking, take_inherited, validate_compression,
+    FileSink, TcpConnection, UdsFallback,
+};
+pub use self::transport::{
+    recommended_max_payload, AddressFamily, BindMode, Compression, InheritedFd, Transport,
+    WouldBlockPolicy, DEFAULT_MTU, DEFAULT_UDS_PAYLOAD_SIZE, MAX_UDP_PAYLOAD_SIZE,
+    OPTIMAL_UDP_PAYLOAD_SIZE,
+};
+pub use self::unique::UniqueTracker;
+pub use self::validation::ConfigWarning;
+use self::warning_events::WarningEventSender;
+pub use self::warning_events::WarningEvents;
+use self::watchdog::Heartbeat;
+pub use self::watchdog::WatchdogOptions;
+use self::writer::{LazySocket, Reopen, SpawnProcessor, Writer};
 
+mod aggregation_key;
+#[cfg(feature = "compat")]
+pub mod compat;
+mod completion;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod destination;
+mod encoder;
 mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+pub mod global;
+mod keep_alive;
+#[cfg(feature = "loadgen")]
+mod loadgen;
 mod metrics;
+mod mirror;
+#[cfg(target_os = "linux")]
+mod mmsg;
+mod normalize;
+mod parse;
+mod pool;
+mod probe;
+#[cfg(feature = "proxy")]
+mod proxy;
+mod rate_limit;
+mod reconnect;
+mod reserved_tags;
+mod rollup;
+mod route;
+mod sampling;
+mod sender;
+mod simple;
+mod startup;
+mod stats;
+#[cfg(feature = "stream")]
+mod stream;
+mod sync;
+mod tag_source;
+#[cfg(feature = "test-server")]
+mod test_server;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+mod timed_lock;
+#[cfg(feature = "trace-context")]
+mod trace_context;
+mod transport;
+mod unique;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod validation;
+mod warning_events;
+mod watchdog;
+mod writer;
+
+// A path in the temp directory for this process, ending with `suffix`, for tests that need a
+// file or socket.
+#[cfg(test)]
+pub(crate) fn temp_path(suffix: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("dogstatsd-{}{}", std::process::id(), suffix))
+}
 
 /// A type alias for returning a unit type or an error
 pub type DogstatsdResult = Result<(), DogstatsdError>;
 
+/// The version of this crate, sent as the `client_version` tag on the client's own metrics.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 const DEFAULT_FROM_ADDR: &str = "0.0.0.0:0";
 const DEFAULT_TO_ADDR: &str = "127.0.0.1:8125";
+const DEFAULT_MAX_PAUSED_METRICS: usize = 10_000;
+const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_FLOAT_PRECISION: u32 = 6;
+const DEFAULT_BATCH_MAX_TIME: Duration = Duration::from_millis(100);
+const LOW_LATENCY_BATCH_MAX_TIME: Duration = Duration::from_millis(10);
+const HIGH_THROUGHPUT_BATCH_MAX_TIME: Duration = Duration::from_secs(1);
+// The longest event text Datadog accepts.
+const MAX_EVENT_TEXT_LEN: usize = 4000;
+
+/// What to do with a single metric whose payload is larger than `Options::max_payload_size`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OversizedMetricPolicy {
+    /// Drop the metric and return `DogstatsdError::PayloadTooLarge`.
+    Reject,
+    /// Drop per-call tags, starting from the last one, until the metric fits. Each such metric
+    /// is counted in `ClientStats::truncated_oversized`, and only reported as
+    /// `DogstatsdError::PayloadTooLarge` to an `error_handler`. If it doesn't fit even without
+    /// them, reject it.
+    #[default]
+    TruncateTags,
+}
 
 /// The struct that represents the options available for the Dogstatsd client.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -126,8 +285,165 @@ pub struct BatchingOptions {
     pub initial_retry_delay: u64,
 }
 
-/// The struct that represents the options available for the Dogstatsd client.
-#[derive(Debug, PartialEq)]
+impl Default for BatchingOptions {
+    /// Batching options for UDP on a network with the default MTU.
+    fn default() -> Self {
+        Self::for_transport(Transport::Udp { mtu: DEFAULT_MTU })
+    }
+}
+
+impl BatchingOptions {
+    /// Batching options whose buffer size is `recommended_max_payload(transport)`, flushed at
+    /// least every 100ms, without retries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, OptionsBuilder, Transport};
+    ///
+    ///   let options = OptionsBuilder::new()
+    ///       .socket_path(Some(String::from("/var/run/datadog/dsd.socket")))
+    ///       .batching_options(BatchingOptions::for_transport(Transport::Uds))
+    ///       .build();
+    /// ```
+    pub fn for_transport(transport: Transport) -> Self {
+        BatchingOptions {
+            max_buffer_size: recommended_max_payload(transport),
+            max_time: DEFAULT_BATCH_MAX_TIME,
+            max_retry_attempts: 0,
+            initial_retry_delay: 0,
+        }
+    }
+
+    /// A builder for batching options that are checked against the transport's limits.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, Transport};
+    ///
+    ///   let batching_options = BatchingOptions::builder()
+    ///       .transport(Transport::Uds)
+    ///       .low_latency()
+    ///       .build()
+    ///       .unwrap();
+    /// ```
+    pub fn builder() -> BatchingOptionsBuilder {
+        BatchingOptionsBuilder::default()
+    }
+}
+
+/// Builds `BatchingOptions`, starting from `BatchingOptions::default()`.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchingOptionsBuilder {
+    transport: Transport,
+    max_buffer_size: Option<usize>,
+    max_time: Duration,
+    max_retry_attempts: usize,
+    initial_retry_delay: u64,
+}
+
+impl Default for BatchingOptionsBuilder {
+    fn default() -> Self {
+        BatchingOptionsBuilder {
+            transport: Transport::Udp { mtu: DEFAULT_MTU },
+            max_buffer_size: None,
+            max_time: DEFAULT_BATCH_MAX_TIME,
+            max_retry_attempts: 0,
+            initial_retry_delay: 0,
+        }
+    }
+}
+
+impl BatchingOptionsBuilder {
+    /// The transport batches are sent over, which sets the default and largest
+    /// `max_buffer_size` to `recommended_max_payload(transport)`. UDP with the default MTU if
+    /// not set.
+    pub fn transport(&mut self, transport: Transport) -> &mut BatchingOptionsBuilder {
+        self.transport = transport;
+        self
+    }
+
+    /// Sets `BatchingOptions::max_buffer_size`.
+    pub fn max_buffer_size(&mut self, max_buffer_size: usize) -> &mut BatchingOptionsBuilder {
+        self.max_buffer_size = Some(max_buffer_size);
+        self
+    }
+
+    /// Sets `BatchingOptions::max_time`.
+    pub fn max_time(&mut self, max_time: Duration) -> &mut BatchingOptionsBuilder {
+        self.max_time = max_time;
+        self
+    }
+
+    /// Sets `BatchingOptions::max_retry_attempts`.
+    pub fn max_retry_attempts(&mut self, max_retry_attempts: usize) -> &mut BatchingOptionsBuilder {
+        self.max_retry_attempts = max_retry_attempts;
+        self
+    }
+
+    /// Sets `BatchingOptions::initial_retry_delay`, in milliseconds.
+    pub fn initial_retry_delay(&mut self, initial_retry_delay: u64) -> &mut BatchingOptionsBuilder {
+        self.initial_retry_delay = initial_retry_delay;
+        self
+    }
+
+    /// Flushes at least every 10ms and doesn't retry, so metrics reach the agent quickly and a
+    /// failing agent never holds up the batch processor.
+    pub fn low_latency(&mut self) -> &mut BatchingOptionsBuilder {
+        self.max_time = LOW_LATENCY_BATCH_MAX_TIME;
+        self.max_retry_attempts = 0;
+        self.initial_retry_delay = 0;
+        self
+    }
+
+    /// Flushes every second unless the buffer fills first, and retries failed sends 3 times,
+    /// for sending a lot of metrics in as few payloads as possible.
+    pub fn high_throughput(&mut self) -> &mut BatchingOptionsBuilder {
+        self.max_time = HIGH_THROUGHPUT_BATCH_MAX_TIME;
+        self.max_retry_attempts = 3;
+        self.initial_retry_delay = 10;
+        self
+    }
+
+    /// Builds the options, or returns `DogstatsdError::InvalidBatchingOptions` if
+    /// `max_buffer_size` is 0 or larger than the transport allows, or `max_time` is 0. TCP has
+    /// no largest buffer size.
+    pub fn build(&self) -> Result<BatchingOptions, DogstatsdError> {
+        let recommended = recommended_max_payload(self.transport);
+        let max_buffer_size = self.max_buffer_size.unwrap_or(recommended);
+
+        if max_buffer_size == 0 {
+            return Err(DogstatsdError::InvalidBatchingOptions(format!(
+                "max_buffer_size must be more than 0 for {:?}",
+                self.transport
+            )));
+        }
+        if self.transport != Transport::Tcp && max_buffer_size > recommended {
+            return Err(DogstatsdError::InvalidBatchingOptions(format!(
+                "max_buffer_size of {} is larger than the {} bytes that can be sent over {:?}",
+                max_buffer_size, recommended, self.transport
+            )));
+        }
+        if self.max_time.is_zero() {
+            return Err(DogstatsdError::InvalidBatchingOptions(
+                "max_time must be more than 0".into(),
+            ));
+        }
+
+        Ok(BatchingOptions {
+            max_buffer_size,
+            max_time: self.max_time,
+            max_retry_attempts: self.max_retry_attempts,
+            initial_retry_delay: self.initial_retry_delay,
+        })
+    }
+}
+
+/// The struct that represents the options available for the Dogstatsd client. Create it with
+/// `OptionsBuilder`, `Options::default` or `Options::new`, since fields are added over time.
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
 pub struct Options {
     /// The address of the udp socket we'll bind to for sending.
     pub from_addr: String,
@@ -141,6 +457,136 @@ pub struct Options {
     pub socket_path: Option<String>,
     /// OPTIONAL, if defined, will utilize batching for sending metrics
     pub batching_options: Option<BatchingOptions>,
+    /// OPTIONAL, if defined, will report the client's drop counters as metrics at most this often, tagged with the client's version and transport
+    pub stats_report_interval: Option<Duration>,
+    /// OPTIONAL, if defined, will fail over to a secondary udp address when sends to `to_addr` keep failing
+    pub failover_options: Option<FailoverOptions>,
+    /// The maximum number of metrics held in memory while the client is paused.
+    pub max_paused_metrics: usize,
+    /// How often kept alive counts and persistent gauges are re-sent.
+    pub keep_alive_interval: Duration,
+    /// OPTIONAL, if defined, will be called with errors that can't be returned to the caller, instead of printing them
+    pub error_handler: Option<ErrorHandler>,
+    /// OPTIONAL, if defined, will prefix the name of every tag except `env`, `service`, `version` and trace context tags, joined with a '.'
+    pub tag_prefix: Option<String>,
+    /// The largest payload a single metric can have.
+    pub max_payload_size: usize,
+    /// What to do with a metric whose payload is larger than `max_payload_size`.
+    pub oversized_metric_policy: OversizedMetricPolicy,
+    /// OPTIONAL, if defined, will be called with every metric to decide where it's sent
+    pub route_hook: Option<RouteHook>,
+    /// Upper bounds of SLO buckets. If any are set, every timing that's sent also increments
+    /// `<stat>.slo_bucket`, tagged `le:<bound>` with the smallest bound it fits in.
+    pub slo_buckets: Vec<Duration>,
+    /// If true, adds a `host:<hostname>` default tag, for when the agent can't tag metrics
+    /// with the host itself, such as when sending through a proxy
+    pub host_tag: bool,
+    /// If true, adds a `pid:<pid>` default tag
+    pub pid_tag: bool,
+    /// OPTIONAL, if defined, limits the metrics sent for each value of a tag
+    pub tag_rate_limit: Option<TagRateLimit>,
+    /// OPTIONAL, if defined, samples metrics at a lower rate while sends are failing
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    /// OPTIONAL, if defined, how to get the socket to send from, instead of binding `from_addr`
+    pub bind_mode: Option<BindMode>,
+    /// OPTIONAL, if defined, will be called to generate the aggregation key of events sent without one
+    pub aggregation_key_generator: Option<AggregationKeyGenerator>,
+    /// OPTIONAL, if defined, reports and optionally restarts a stalled batch processor
+    pub watchdog: Option<WatchdogOptions>,
+    /// If true, sampled counters are sent with their values scaled up by their sample rate,
+    /// instead of with the rate, for StatsD servers that ignore it
+    pub sample_rate_correction: bool,
+    /// If true, metric names are sent as `normalize_metric_name` returns them, the way Datadog
+    /// would show them
+    pub normalize_metric_names: bool,
+    /// OPTIONAL, if set to `Transport::Tcp`, metrics are sent to `to_addr` over TCP, one per
+    /// line, and if set to `Transport::UdsSeqPacket`, to `socket_path` over a `SOCK_SEQPACKET`
+    /// socket. Otherwise UDS is used if `socket_path` is set, and UDP if not
+    pub transport: Option<Transport>,
+    /// OPTIONAL, if true, the time each socket send takes is measured, and the percentiles of
+    /// recent sends are included in `Client::stats`
+    pub measure_send_latency: bool,
+    /// OPTIONAL, if set, problems inside the client that lose metrics, such as a stalled batch
+    /// processor, are also sent as Datadog events
+    pub warning_events: Option<WarningEvents>,
+    /// The most significant digits float values are formatted with, up to 17. Digits before
+    /// the decimal point are never rounded away, so whole numbers are formatted exactly
+    pub float_precision: u32,
+    /// OPTIONAL, if defined, metrics are appended to this file, one per line, instead of sent
+    /// to the agent, for replaying later with `Client::replay_file`
+    pub file_path: Option<String>,
+    /// OPTIONAL, if defined, what to do with metrics whose tags set a key reserved by
+    /// Datadog, such as `host`
+    pub reserved_tags: Option<ReservedTags>,
+    /// More udp addresses every datagram is also sent to, such as a local listener for
+    /// debugging next to the agent. A failure to send to one doesn't stop the others.
+    pub additional_to_addrs: Vec<String>,
+    /// If set, the udp socket is connected to `to_addr` once when the client is created, so
+    /// the address isn't resolved on every send and ICMP unreachable errors are returned by
+    /// later sends. Can't be used with `failover_options` or `additional_to_addrs`.
+    pub connect_udp: bool,
+    /// OPTIONAL, if defined, what sends over UDS do when the socket's buffer is full, instead
+    /// of failing
+    pub would_block_policy: Option<WouldBlockPolicy>,
+    /// OPTIONAL, if defined, the IP version preferred when `to_addr` is a hostname that
+    /// resolves to both. It's resolved when the socket is opened, and again after the socket
+    /// is re-created by `Client::rebind` or a reconnect
+    pub address_family: Option<AddressFamily>,
+    /// OPTIONAL, if defined, the format metrics are sent in, for StatsD servers other than the
+    /// Datadog agent. `OversizedMetricPolicy::TruncateTags` only applies to the default
+    /// DogStatsD format, oversized metrics are rejected in any other
+    pub encoder: Option<SharedEncoder>,
+    /// OPTIONAL, if defined, re-creates the UDP or UDS socket after consecutive failed sends,
+    /// with backoff between attempts
+    pub reconnect_options: Option<ReconnectOptions>,
+    /// OPTIONAL, if defined with `socket_path`, metrics are sent to `to_addr` over UDP while
+    /// the socket is missing or refuses connections, and the socket is tried again this often
+    pub udp_fallback: Option<Duration>,
+    /// OPTIONAL, if defined, the most bytes of tags a metric can have, including default tags
+    /// and the commas between them. Per-call tags are dropped from the end, whole, until the
+    /// rest fit. Each such metric is counted in `ClientStats::truncated_tags`, and only
+    /// reported as `DogstatsdError::TagsTooLarge` to an `error_handler`.
+    pub max_tags_bytes: Option<usize>,
+    /// Whether to open the UDP or UDS socket on the first send instead of in `Client::new`, so
+    /// a client can be created before the agent's socket exists. The UDS socket is only
+    /// connected to, never bound. If it can't be opened, the metric is dropped and it's tried
+    /// again on a later send, backing off between attempts as `connect_retry` says to, or from
+    /// 100ms to 2s. Ignored with `BindMode::Inherit`.
+    pub lazy_socket: bool,
+    /// OPTIONAL, if defined, `Client::new` tries again to open the socket or connect over TCP
+    /// when it fails, backing off between attempts, before returning the error
+    pub connect_retry: Option<ConnectRetry>,
+    /// OPTIONAL, if defined, `socket_path` is connected without blocking, failing with
+    /// `ErrorKind::TimedOut` if the agent hasn't accepted the connection after this long. Sends
+    /// over UDS never wait, except with `WouldBlockPolicy::Block`, whose deadline bounds them.
+    pub uds_connect_timeout: Option<Duration>,
+    /// OPTIONAL, if defined, gauges matching one of its prefixes are rolled up over each
+    /// window, and their last value is sent along with `.min`, `.max` and `.avg` gauges
+    pub gauge_rollup: Option<GaugeRollup>,
+    /// OPTIONAL, if defined, a fraction of the payloads sent are also copied to its sink, for
+    /// debugging what's sent
+    pub debug_mirror: Option<DebugMirror>,
+    /// If true, adds a `thread:<name>` tag to each metric with the name of the thread it was
+    /// sent from, if the thread has one. Metrics sent with `Client::sender` are tagged with the
+    /// sender's thread.
+    pub thread_tag: bool,
+    /// OPTIONAL, if defined, with batching, up to this many batches that failed to send are
+    /// kept, and sent again before the next batch, instead of being dropped. The oldest is
+    /// dropped to make room
+    pub max_retained_batches: Option<usize>,
+    /// If true, sends the gauge `datadog.dogstatsd.client.config` once when the client is
+    /// created, tagged `config_hash:<hash>` with a short hash of these options, to spot
+    /// instances of the same service running with different configuration
+    pub config_banner: bool,
+    /// If true, with batching, full batches for a single UDP address are queued with io_uring
+    /// instead of sent together with `sendmmsg`, so the batch processor keeps batching while
+    /// the kernel sends. Failed sends are retried, split and kept as they would be otherwise.
+    /// Only has an effect when built with the `io-uring` feature on Linux, and batches are
+    /// sent as usual if io_uring can't be set up
+    pub io_uring: bool,
+    /// OPTIONAL, if defined, metrics sent over `Transport::Tcp` are compressed this way. Only
+    /// available with the `compression` feature, and only for `Transport::Tcp`
+    pub compression: Option<Compression>,
 }
 
 impl Default for Options {
@@ -149,21 +595,22 @@ impl Default for Options {
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::Options;
+    ///   use dogstatsd::{Options, OversizedMetricPolicy};
+    ///   use std::time::Duration;
     ///
     ///   let options = Options::default();
     ///
-    ///   assert_eq!(
-    ///       Options {
-    ///           from_addr: "0.0.0.0:0".into(),
-    ///           to_addr: "127.0.0.1:8125".into(),
-    ///           namespace: String::new(),
-    ///           default_tags: vec!(),
-    ///           socket_path: None,
-    ///           batching_options: None,
-    ///       },
-    ///       options
-    ///   )
+    ///   assert_eq!("0.0.0.0:0", options.from_addr);
+    ///   assert_eq!("127.0.0.1:8125", options.to_addr);
+    ///   assert_eq!("", options.namespace);
+    ///   assert!(options.default_tags.is_empty());
+    ///   assert_eq!(None, options.socket_path);
+    ///   assert_eq!(None, options.batching_options);
+    ///   assert_eq!(10_000, options.max_paused_metrics);
+    ///   assert_eq!(Duration::from_secs(10), options.keep_alive_interval);
+    ///   assert_eq!(65_467, options.max_payload_size);
+    ///   assert_eq!(OversizedMetricPolicy::TruncateTags, options.oversized_metric_policy);
+    ///   assert_eq!(6, options.float_precision);
     /// ```
     fn default() -> Self {
         Options {
@@ -173,6 +620,49 @@ impl Default for Options {
             default_tags: vec![],
             socket_path: None,
             batching_options: None,
+            stats_report_interval: None,
+            failover_options: None,
+            max_paused_metrics: DEFAULT_MAX_PAUSED_METRICS,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            error_handler: None,
+            tag_prefix: None,
+            max_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            oversized_metric_policy: OversizedMetricPolicy::default(),
+            route_hook: None,
+            slo_buckets: vec![],
+            host_tag: false,
+            pid_tag: false,
+            tag_rate_limit: None,
+            adaptive_sampling: None,
+            bind_mode: None,
+            aggregation_key_generator: None,
+            watchdog: None,
+            sample_rate_correction: false,
+            normalize_metric_names: false,
+            transport: None,
+            measure_send_latency: false,
+            warning_events: None,
+            float_precision: DEFAULT_FLOAT_PRECISION,
+            file_path: None,
+            reserved_tags: None,
+            additional_to_addrs: vec![],
+            connect_udp: false,
+            would_block_policy: None,
+            address_family: None,
+            encoder: None,
+            reconnect_options: None,
+            udp_fallback: None,
+            max_tags_bytes: None,
+            lazy_socket: false,
+            connect_retry: None,
+            uds_connect_timeout: None,
+            gauge_rollup: None,
+            debug_mirror: None,
+            thread_tag: false,
+            max_retained_batches: None,
+            config_banner: false,
+            io_uring: false,
+            compression: None,
         }
     }
 }
@@ -202,9 +692,69 @@ impl Options {
             default_tags,
             socket_path,
             batching_options,
+            stats_report_interval: None,
+            failover_options: None,
+            max_paused_metrics: DEFAULT_MAX_PAUSED_METRICS,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            error_handler: None,
+            tag_prefix: None,
+            max_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            oversized_metric_policy: OversizedMetricPolicy::default(),
+            route_hook: None,
+            slo_buckets: vec![],
+            host_tag: false,
+            pid_tag: false,
+            tag_rate_limit: None,
+            adaptive_sampling: None,
+            bind_mode: None,
+            aggregation_key_generator: None,
+            watchdog: None,
+            sample_rate_correction: false,
+            normalize_metric_names: false,
+            transport: None,
+            measure_send_latency: false,
+            warning_events: None,
+            float_precision: DEFAULT_FLOAT_PRECISION,
+            file_path: None,
+            reserved_tags: None,
+            additional_to_addrs: vec![],
+            connect_udp: false,
+            would_block_policy: None,
+            address_family: None,
+            encoder: None,
+            reconnect_options: None,
+            udp_fallback: None,
+            max_tags_bytes: None,
+            lazy_socket: false,
+            connect_retry: None,
+            uds_connect_timeout: None,
+            gauge_rollup: None,
+            debug_mirror: None,
+            thread_tag: false,
+            max_retained_batches: None,
+            config_banner: false,
+            io_uring: false,
+            compression: None,
         }
     }
 
+    /// Check for settings that are likely to lose metrics without any error, such as malformed
+    /// default tags or batches too large for the agent to read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{ConfigWarning, OptionsBuilder};
+    ///
+    ///   let options = OptionsBuilder::new().default_tag(String::from("env:")).build();
+    ///   for warning in options.validate() {
+    ///       println!("dogstatsd misconfigured: {}", warning);
+    ///   }
+    /// ```
+    pub fn validate(&self) -> Vec<ConfigWarning> {
+        validation::validate(self)
+    }
+
     fn merge_with_system_tags(default_tags: Vec<String>) -> Vec<String> {
         let mut merged_tags = default_tags;
 
@@ -240,6 +790,40 @@ impl Options {
     }
 }
 
+// The machine's hostname, as reported by the OS.
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    std::str::from_utf8(&buf[..len])
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .map(str::to_owned)
+}
+
+// The `thread:<name>` tag for the current thread, if it has a name. Commas and pipes would end
+// the tag, so they're replaced.
+fn thread_tag() -> Option<String> {
+    let thread = thread::current();
+    let name = thread.name()?;
+    Some(format!("thread:{}", name.replace([',', '|'], "_")))
+}
+
+// A short hash of the options, for `Options::config_banner`, the same across processes and
+// builds for the same options.
+fn config_hash(options: &Options) -> String {
+    // 32-bit FNV-1a, over the options as they're printed for debugging.
+    let hash = format!("{:?}", options)
+        .bytes()
+        .fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        });
+    format!("{:08x}", hash)
+}
+
 /// Struct that allows build an `Options` for available for the Dogstatsd client.
 #[derive(Default, Debug)]
 pub struct OptionsBuilder {
@@ -249,12 +833,98 @@ pub struct OptionsBuilder {
     to_addr: Option<String>,
     /// A namespace to prefix all metrics with, joined with a '.'.
     namespace: Option<String>,
-    /// Default tags to include with every request.
-    default_tags: Vec<String>,
+    /// Default tags to include with every request, and where each came from.
+    default_tags: Vec<(TagSource, String)>,
     /// OPTIONAL, if defined, will use UDS instead of UDP and will ignore UDP options
     socket_path: Option<String>,
     /// OPTIONAL, if defined, will utilize batching for sending metrics
     batching_options: Option<BatchingOptions>,
+    /// OPTIONAL, if defined, will report the client's drop counters as metrics at most this often, tagged with the client's version and transport
+    stats_report_interval: Option<Duration>,
+    /// OPTIONAL, if defined, will fail over to a secondary udp address when sends to `to_addr` keep failing
+    failover_options: Option<FailoverOptions>,
+    /// The maximum number of metrics held in memory while the client is paused.
+    max_paused_metrics: Option<usize>,
+    /// How often kept alive counts and persistent gauges are re-sent.
+    keep_alive_interval: Option<Duration>,
+    /// OPTIONAL, if defined, will be called with errors that can't be returned to the caller, instead of printing them
+    error_handler: Option<ErrorHandler>,
+    /// OPTIONAL, if defined, will prefix the name of every tag except `env`, `service`, `version` and trace context tags, joined with a '.'
+    tag_prefix: Option<String>,
+    /// The largest payload a single metric can have.
+    max_payload_size: Option<usize>,
+    /// What to do with a metric whose payload is larger than `max_payload_size`.
+    oversized_metric_policy: Option<OversizedMetricPolicy>,
+    /// OPTIONAL, if defined, will be called with every metric to decide where it's sent
+    route_hook: Option<RouteHook>,
+    /// Upper bounds of SLO buckets for timings.
+    slo_buckets: Vec<Duration>,
+    /// Whether to add a `host:<hostname>` default tag.
+    host_tag: bool,
+    /// Whether to add a `pid:<pid>` default tag.
+    pid_tag: bool,
+    /// OPTIONAL, if defined, limits the metrics sent for each value of a tag.
+    tag_rate_limit: Option<TagRateLimit>,
+    /// OPTIONAL, if defined, samples metrics at a lower rate while sends are failing.
+    adaptive_sampling: Option<AdaptiveSampling>,
+    /// OPTIONAL, if defined, how to get the socket to send from.
+    bind_mode: Option<BindMode>,
+    /// OPTIONAL, if defined, generates the aggregation key of events sent without one.
+    aggregation_key_generator: Option<AggregationKeyGenerator>,
+    /// OPTIONAL, if defined, watches the batch processor for stalls.
+    watchdog: Option<WatchdogOptions>,
+    /// Whether to scale sampled counters up instead of sending their sample rate.
+    sample_rate_correction: bool,
+    /// Whether to normalize metric names the way Datadog does.
+    normalize_metric_names: bool,
+    /// OPTIONAL, if defined, the transport to send over.
+    transport: Option<Transport>,
+    /// OPTIONAL, if defined, whether to measure socket send latency.
+    measure_send_latency: bool,
+    /// OPTIONAL, if defined, will send internal problems as events.
+    warning_events: Option<WarningEvents>,
+    /// OPTIONAL, if defined, the significant digits of formatted floats.
+    float_precision: Option<u32>,
+    /// OPTIONAL, if defined, the file to append metrics to.
+    file_path: Option<String>,
+    /// OPTIONAL, if defined, how to check tags for reserved keys.
+    reserved_tags: Option<ReservedTags>,
+    /// More udp addresses to send every datagram to.
+    additional_to_addrs: Vec<String>,
+    /// OPTIONAL, if defined, whether to connect the udp socket to `to_addr`.
+    connect_udp: Option<bool>,
+    /// OPTIONAL, if defined, what to do when a UDS send would block.
+    would_block_policy: Option<WouldBlockPolicy>,
+    /// OPTIONAL, if defined, the IP version to prefer for `to_addr`.
+    address_family: Option<AddressFamily>,
+    /// OPTIONAL, if defined, the format to send metrics in.
+    encoder: Option<SharedEncoder>,
+    /// OPTIONAL, if defined, re-creates the socket after consecutive failed sends.
+    reconnect_options: Option<ReconnectOptions>,
+    /// OPTIONAL, if defined, sends over UDP while `socket_path` is unavailable, trying it again this often.
+    udp_fallback: Option<Duration>,
+    /// OPTIONAL, if defined, the most bytes of tags a metric can have.
+    max_tags_bytes: Option<usize>,
+    /// OPTIONAL, if defined, whether to open the socket on the first send instead of in `Client::new`.
+    lazy_socket: Option<bool>,
+    /// OPTIONAL, if defined, how to retry opening the socket in `Client::new`.
+    connect_retry: Option<ConnectRetry>,
+    /// OPTIONAL, if defined, how long to wait for `socket_path` to accept the connection.
+    uds_connect_timeout: Option<Duration>,
+    /// OPTIONAL, if defined, which gauges to roll up, and over what window.
+    gauge_rollup: Option<GaugeRollup>,
+    /// OPTIONAL, if defined, where to copy a fraction of the payloads sent.
+    debug_mirror: Option<DebugMirror>,
+    /// Whether to add a `thread:<name>` tag with the sending thread's name.
+    thread_tag: bool,
+    /// OPTIONAL, if defined, how many failed batches to keep and send again.
+    max_retained_batches: Option<usize>,
+    /// Whether to send a gauge with a hash of the options when the client is created.
+    config_banner: bool,
+    /// Whether to send batches with io_uring.
+    io_uring: bool,
+    /// OPTIONAL, if defined, how to compress metrics sent over TCP.
+    compression: Option<Compression>,
 }
 
 impl OptionsBuilder {
@@ -323,10 +993,60 @@ impl OptionsBuilder {
     ///   let options_builder = OptionsBuilder::new().default_tag(String::from("tag1:tav1val")).default_tag(String::from("tag2:tag2val"));
     /// ```
     pub fn default_tag(&mut self, default_tag: String) -> &mut OptionsBuilder {
-        self.default_tags.push(default_tag);
+        self.default_tags.push((TagSource::Code, default_tag));
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value. Can be called multiple times to add `default_tags` from several sources, merged by `TagSource` when the `Options` are built.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, TagSource};
+    ///
+    ///   let options_builder = OptionsBuilder::new().tag_source(TagSource::File, vec![String::from("team:platform")]);
+    /// ```
+    pub fn tag_source(&mut self, source: TagSource, tags: Vec<String>) -> &mut OptionsBuilder {
+        self.default_tags
+            .extend(tags.into_iter().map(|tag| (source, tag)));
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the `TagSource::Env` tags in the environment variable `var`, such as `DD_TAGS`, separated by commas or whitespace. Nothing is added if it isn't set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().env_tags("DD_TAGS");
+    /// ```
+    pub fn env_tags(&mut self, var: &str) -> &mut OptionsBuilder {
+        if let Ok(value) = std::env::var(var) {
+            self.default_tags
+                .extend(tag_source::parse_env(&value).map(|tag| (TagSource::Env, tag)));
+        }
         self
     }
 
+    /// The default tags the `Options` will be built with, after merging every `TagSource`, and
+    /// where each came from.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, TagSource};
+    ///
+    ///   let merged_tags = OptionsBuilder::new()
+    ///       .tag_source(TagSource::Env, vec![String::from("env:dev")])
+    ///       .default_tag(String::from("env:prod"))
+    ///       .merged_tags();
+    ///   assert_eq!(vec![(String::from("env:prod"), TagSource::Code)], merged_tags);
+    /// ```
+    pub fn merged_tags(&self) -> Vec<(String, TagSource)> {
+        tag_source::merge(&self.default_tags)
+    }
+
     /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
@@ -356,554 +1076,2849 @@ impl OptionsBuilder {
         self
     }
 
-    /// Will construct an `Options` with all of the provided values and fall back to the default values if they aren't provided.
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
     ///   use dogstatsd::OptionsBuilder;
-    ///   use dogstatsd::Options;
-    ///
-    ///   let options = OptionsBuilder::new().namespace(String::from("mynamespace")).default_tag(String::from("tag1:tav1val")).build();
+    ///   use std::time::Duration;
     ///
-    ///   assert_eq!(
-    ///       Options {
-    ///           from_addr: "0.0.0.0:0".into(),
-    ///           to_addr: "127.0.0.1:8125".into(),
-    ///           namespace: String::from("mynamespace"),
-    ///           default_tags: vec!(String::from("tag1:tav1val")),
-    ///           socket_path: None,
-    ///           batching_options: None,
-    ///       },
-    ///       options
-    ///   )
+    ///   let options_builder = OptionsBuilder::new().stats_report_interval(Duration::from_secs(10));
     /// ```
-    pub fn build(&self) -> Options {
-        Options::new(
-            self.from_addr
-                .as_ref()
-                .unwrap_or(&String::from(DEFAULT_FROM_ADDR)),
-            self.to_addr
-                .as_ref()
-                .unwrap_or(&String::from(DEFAULT_TO_ADDR)),
-            self.namespace.as_ref().unwrap_or(&String::default()),
-            self.default_tags.to_vec(),
-            self.socket_path.clone(),
-            self.batching_options,
-        )
-    }
-}
-
-#[derive(Debug)]
-enum SocketType {
-    Udp(UdpSocket),
-    Uds(UnixDatagram),
-    BatchableUdp(Mutex<Sender<batch_processor::Message>>),
-    BatchableUds(Mutex<Sender<batch_processor::Message>>),
-}
-
-/// The client struct that handles sending metrics to the Dogstatsd server.
-#[derive(Debug)]
-pub struct Client {
-    socket: SocketType,
-    from_addr: String,
-    to_addr: String,
-    namespace: String,
-    default_tags: Vec<u8>,
-}
-
-impl PartialEq for Client {
-    fn eq(&self, other: &Self) -> bool {
-        // Ignore `socket`, which will never be the same
-        self.from_addr == other.from_addr
-            && self.to_addr == other.to_addr
-            && self.namespace == other.namespace
-            && self.default_tags == other.default_tags
+    pub fn stats_report_interval(
+        &mut self,
+        stats_report_interval: Duration,
+    ) -> &mut OptionsBuilder {
+        self.stats_report_interval = Some(stats_report_interval);
+        self
     }
-}
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        match &self.socket {
-            SocketType::BatchableUdp(tx_channel) | SocketType::BatchableUds(tx_channel) => {
-                // Destructing Client... If fails, ignore and keep going...
-                let _ = tx_channel
-                    .lock()
-                    .unwrap()
-                    .send(batch_processor::Message::Shutdown);
-            }
-            _ => {}
-        }
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{ OptionsBuilder, FailoverOptions };
+    ///   use std::time::Duration;
+    ///
+    ///   let options_builder = OptionsBuilder::new().failover_options(FailoverOptions { secondary_addr: String::from("127.0.0.1:8126"), max_consecutive_failures: 3, probe_interval: Duration::from_secs(30) });
+    /// ```
+    pub fn failover_options(&mut self, failover_options: FailoverOptions) -> &mut OptionsBuilder {
+        self.failover_options = Some(failover_options);
+        self
     }
-}
 
-impl Client {
-    /// Create a new client from an options struct.
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::OptionsBuilder;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let options_builder = OptionsBuilder::new().max_paused_metrics(500);
     /// ```
-    pub fn new(options: Options) -> Result<Self, DogstatsdError> {
-        let fn_create_tx_channel = |socket: SocketType,
-                                    batching_options: BatchingOptions,
-                                    to_addr: String,
-                                    socket_path: Option<String>|
-         -> Mutex<Sender<batch_processor::Message>> {
-            let (tx, rx) = mpsc::channel();
-            thread::spawn(move || {
-                batch_processor::process_events(batching_options, to_addr, socket, socket_path, rx);
-            });
-            Mutex::from(tx)
-        };
-
-        let socket = match options.socket_path {
-            Some(socket_path) => {
-                // The follow scenarios can occur:
-                // - socket does not exist yet: We will call .bind(...) to create one
-                // - socket exists, but no listener: We will retry attempting to connect
-                //   however, if no listener subscribes to the socket within retries, we will
-                //   failt to initialize
-                // - socket exists, with a listener: Calling .connect(...) will work successfully
-                let mut uds_socket = UnixDatagram::unbound()?;
-                match uds_socket.connect(socket_path.clone()) {
-                    Ok(socket) => socket,
-                    Err(e) => {
-                        println!(
-                            "Couldn't connect to uds socket.. attempting to re-create by binding directly: {e:?}"
-                        );
-                        uds_socket = UnixDatagram::bind(socket_path.clone())?;
-                    }
-                };
-                uds_socket.set_nonblocking(true)?;
-
-                let wrapped_socket = SocketType::Uds(uds_socket);
-                if let Some(batching_options) = options.batching_options {
-                    SocketType::BatchableUds(fn_create_tx_channel(
-                        wrapped_socket,
-                        batching_options,
-                        options.to_addr.clone(),
-                        Some(socket_path),
-                    ))
-                } else {
-                    wrapped_socket
-                }
-            }
-            None => {
-                let wrapped_socket = SocketType::Udp(UdpSocket::bind(&options.from_addr)?);
-                if let Some(batching_options) = options.batching_options {
-                    SocketType::BatchableUdp(fn_create_tx_channel(
-                        wrapped_socket,
-                        batching_options,
-                        options.to_addr.clone(),
-                        None,
-                    ))
-                } else {
-                    wrapped_socket
-                }
-            }
-        };
-
-        let default_tags = Options::merge_with_system_tags(options.default_tags);
-
-        Ok(Client {
-            socket,
-            from_addr: options.from_addr,
-            to_addr: options.to_addr,
-            namespace: options.namespace,
-            default_tags: default_tags.join(",").into_bytes(),
-        })
+    pub fn max_paused_metrics(&mut self, max_paused_metrics: usize) -> &mut OptionsBuilder {
+        self.max_paused_metrics = Some(max_paused_metrics);
+        self
     }
 
-    /// Increment a StatsD counter
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::OptionsBuilder;
+    ///   use std::time::Duration;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.incr("counter", &["tag:counter"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().keep_alive_interval(Duration::from_secs(15));
     /// ```
-    pub fn incr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(&CountMetric::Incr(stat.into().as_ref(), 1), tags)
+    pub fn keep_alive_interval(&mut self, keep_alive_interval: Duration) -> &mut OptionsBuilder {
+        self.keep_alive_interval = Some(keep_alive_interval);
+        self
     }
 
-    /// Increment a StatsD counter by the provided amount
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::{ OptionsBuilder, ErrorHandler };
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.incr_by_value("counter", 123, &["tag:counter"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().error_handler(ErrorHandler::new(|error| eprintln!("{}", error)));
     /// ```
-    pub fn incr_by_value<'a, I, S, T>(&self, stat: S, value: i64, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(&CountMetric::Incr(stat.into().as_ref(), value), tags)
+    pub fn error_handler(&mut self, error_handler: ErrorHandler) -> &mut OptionsBuilder {
+        self.error_handler = Some(error_handler);
+        self
     }
 
-    /// Decrement a StatsD counter
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::OptionsBuilder;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.decr("counter", &["tag:counter"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().tag_prefix(String::from("custom"));
     /// ```
-    pub fn decr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(&CountMetric::Decr(stat.into().as_ref(), 1), tags)
+    pub fn tag_prefix(&mut self, tag_prefix: String) -> &mut OptionsBuilder {
+        self.tag_prefix = Some(tag_prefix);
+        self
     }
 
-    /// Decrement a StatsD counter by the provided amount
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::OptionsBuilder;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.decr_by_value("counter", 23, &["tag:counter"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().max_payload_size(8192);
     /// ```
-    pub fn decr_by_value<'a, I, S, T>(&self, stat: S, value: i64, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(&CountMetric::Decr(stat.into().as_ref(), value), tags)
+    pub fn max_payload_size(&mut self, max_payload_size: usize) -> &mut OptionsBuilder {
+        self.max_payload_size = Some(max_payload_size);
+        self
     }
 
-    /// Make an arbitrary change to a StatsD counter
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::{OptionsBuilder, OversizedMetricPolicy};
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.count("counter", 42, &["tag:counter"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().oversized_metric_policy(OversizedMetricPolicy::Reject);
     /// ```
-    pub fn count<'a, I, S, T>(&self, stat: S, count: i64, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(&CountMetric::Arbitrary(stat.into().as_ref(), count), tags)
+    pub fn oversized_metric_policy(
+        &mut self,
+        oversized_metric_policy: OversizedMetricPolicy,
+    ) -> &mut OptionsBuilder {
+        self.oversized_metric_policy = Some(oversized_metric_policy);
+        self
     }
 
-    /// Time how long it takes for a block of code to execute.
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
-    ///   use std::thread;
-    ///   use std::time::Duration;
+    ///   use dogstatsd::{OptionsBuilder, RouteDecision, RouteHook};
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.time("timer", &["tag:time"], || {
-    ///       thread::sleep(Duration::from_millis(200))
-    ///   }).unwrap_or_else(|(_, e)| println!("Encountered error: {}", e))
+    ///   let options_builder = OptionsBuilder::new().route_hook(RouteHook::new(|_| RouteDecision::Send));
     /// ```
-    pub fn time<'a, F, O, I, S, T>(
-        &self,
-        stat: S,
-        tags: I,
-        block: F,
-    ) -> Result<O, (O, DogstatsdError)>
-    where
-        F: FnOnce() -> O,
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        let start_time = Utc::now();
-        let output = block();
-        let end_time = Utc::now();
-        let stat = stat.into();
-        let metric = TimeMetric::new(stat.as_ref(), &start_time, &end_time);
-        match self.send(&metric, tags) {
-            Ok(()) => Ok(output),
-            Err(error) => Err((output, error)),
-        }
+    pub fn route_hook(&mut self, route_hook: RouteHook) -> &mut OptionsBuilder {
+        self.route_hook = Some(route_hook);
+        self
     }
 
-    /// Time how long it takes for an async block of code to execute.
+    /// Will allow the builder to generate an `Options` struct with the provided value. Can be called multiple times to add multiple `slo_buckets` to the `Options`.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
-    ///   use std::thread;
+    ///   use dogstatsd::OptionsBuilder;
     ///   use std::time::Duration;
     ///
-    /// # async fn do_work() {}
-    ///   async fn timer() {
-    ///       let client = Client::new(Options::default()).unwrap();
-    ///       client.async_time("timer", &["tag:time"], do_work)
-    ///       .await
-    ///       .unwrap_or_else(|(_, e)| println!("Encountered error: {}", e))
-    ///   }
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .slo_bucket(Duration::from_millis(100))
+    ///       .slo_bucket(Duration::from_millis(500));
     /// ```
-    pub async fn async_time<'a, Fn, Fut, O, I, S, T>(
-        &self,
-        stat: S,
-        tags: I,
-        block: Fn,
-    ) -> Result<O, (O, DogstatsdError)>
-    where
-        Fn: FnOnce() -> Fut,
-        Fut: Future<Output = O>,
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        let start_time = Utc::now();
-        let output = block().await;
-        let end_time = Utc::now();
-        let stat = stat.into();
-        match self.send(
-            &TimeMetric::new(stat.as_ref(), &start_time, &end_time),
-            tags,
-        ) {
-            Ok(()) => Ok(output),
-            Err(error) => Err((output, error)),
-        }
+    pub fn slo_bucket(&mut self, slo_bucket: Duration) -> &mut OptionsBuilder {
+        self.slo_buckets.push(slo_bucket);
+        self
     }
 
-    /// Send your own timing metric in milliseconds
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::OptionsBuilder;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.timing("timing", 350, &["tag:timing"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().host_tag(true);
     /// ```
-    pub fn timing<'a, I, S, T>(&self, stat: S, ms: i64, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(&TimingMetric::new(stat.into().as_ref(), ms), tags)
+    pub fn host_tag(&mut self, host_tag: bool) -> &mut OptionsBuilder {
+        self.host_tag = host_tag;
+        self
     }
 
-    /// Report an arbitrary value as a gauge
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::OptionsBuilder;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.gauge("gauge", "12345", &["tag:gauge"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().pid_tag(true);
     /// ```
-    pub fn gauge<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        SS: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(
-            &GaugeMetric::new(stat.into().as_ref(), val.into().as_ref()),
-            tags,
-        )
+    pub fn pid_tag(&mut self, pid_tag: bool) -> &mut OptionsBuilder {
+        self.pid_tag = pid_tag;
+        self
     }
 
-    /// Report a value in a histogram
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::{OptionsBuilder, TagRateLimit};
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.histogram("histogram", "67890", &["tag:histogram"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().tag_rate_limit(TagRateLimit::new("customer_id", 100, 500));
     /// ```
-    pub fn histogram<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        SS: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(
-            &HistogramMetric::new(stat.into().as_ref(), val.into().as_ref()),
-            tags,
-        )
+    pub fn tag_rate_limit(&mut self, tag_rate_limit: TagRateLimit) -> &mut OptionsBuilder {
+        self.tag_rate_limit = Some(tag_rate_limit);
+        self
     }
 
-    /// Report a value in a distribution
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::{AdaptiveSampling, OptionsBuilder};
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.distribution("distribution", "67890", &["tag:distribution"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().adaptive_sampling(AdaptiveSampling::default());
     /// ```
-    pub fn distribution<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        SS: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(
-            &DistributionMetric::new(stat.into().as_ref(), val.into().as_ref()),
-            tags,
-        )
+    pub fn adaptive_sampling(
+        &mut self,
+        adaptive_sampling: AdaptiveSampling,
+    ) -> &mut OptionsBuilder {
+        self.adaptive_sampling = Some(adaptive_sampling);
+        self
     }
 
-    /// Report a value in a set
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::{BindMode, OptionsBuilder};
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.set("set", "13579", &["tag:set"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().bind_mode(BindMode::Fixed(String::from("0.0.0.0:9000")));
     /// ```
-    pub fn set<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        SS: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(
-            &SetMetric::new(stat.into().as_ref(), val.into().as_ref()),
-            tags,
-        )
+    pub fn bind_mode(&mut self, bind_mode: BindMode) -> &mut OptionsBuilder {
+        self.bind_mode = Some(bind_mode);
+        self
     }
 
-    /// Report the status of a service
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options, ServiceStatus, ServiceCheckOptions};
+    ///   use dogstatsd::{AggregationKeyGenerator, OptionsBuilder};
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.service_check("redis.can_connect", ServiceStatus::OK, &["tag:service"], None)
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .aggregation_key_generator(AggregationKeyGenerator::new(|title, _| Some(title.to_owned())));
+    /// ```
+    pub fn aggregation_key_generator(
+        &mut self,
+        aggregation_key_generator: AggregationKeyGenerator,
+    ) -> &mut OptionsBuilder {
+        self.aggregation_key_generator = Some(aggregation_key_generator);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
-    ///   let options = ServiceCheckOptions {
-    ///     hostname: Some("my-host.localhost"),
-    ///     ..Default::default()
-    ///   };
-    ///   client.service_check("redis.can_connect", ServiceStatus::OK, &["tag:service"], Some(options))
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// # Examples
     ///
-    ///   let all_options = ServiceCheckOptions {
-    ///     hostname: Some("my-host.localhost"),
-    ///     timestamp: Some(1510326433),
-    ///     message: Some("Message about the check or service")
-    ///   };
-    ///   client.service_check("redis.can_connect", ServiceStatus::OK, &["tag:service"], Some(all_options))
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
     /// ```
-    pub fn service_check<'a, I, S, T>(
-        &self,
-        stat: S,
-        val: ServiceStatus,
-        tags: I,
-        options: Option<ServiceCheckOptions>,
-    ) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        let unwrapped_options = options.unwrap_or_default();
-        self.send(
-            &ServiceCheck::new(stat.into().as_ref(), val, unwrapped_options),
-            tags,
-        )
+    ///   use dogstatsd::{OptionsBuilder, WatchdogOptions};
+    ///
+    ///   let options_builder = OptionsBuilder::new().watchdog(WatchdogOptions::default());
+    /// ```
+    pub fn watchdog(&mut self, watchdog: WatchdogOptions) -> &mut OptionsBuilder {
+        self.watchdog = Some(watchdog);
+        self
     }
 
-    /// Send a custom event as a title and a body
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options};
+    ///   use dogstatsd::OptionsBuilder;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   client.event("Event Title", "Event Body", &["tag:event"])
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().sample_rate_correction(true);
+    /// ```
+    pub fn sample_rate_correction(&mut self, sample_rate_correction: bool) -> &mut OptionsBuilder {
+        self.sample_rate_correction = sample_rate_correction;
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
     ///
+    ///   let options_builder = OptionsBuilder::new().normalize_metric_names(true);
     /// ```
+    pub fn normalize_metric_names(&mut self, normalize_metric_names: bool) -> &mut OptionsBuilder {
+        self.normalize_metric_names = normalize_metric_names;
+        self
+    }
 
-    pub fn event<'a, I, S, SS, T>(&self, title: S, text: SS, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = T>,
-        S: Into<Cow<'a, str>>,
-        SS: Into<Cow<'a, str>>,
-        T: AsRef<str>,
-    {
-        self.send(
-            &Event::new(title.into().as_ref(), text.into().as_ref()),
-            tags,
-        )
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, Transport};
+    ///
+    ///   let options_builder = OptionsBuilder::new().transport(Transport::Tcp);
+    /// ```
+    pub fn transport(&mut self, transport: Transport) -> &mut OptionsBuilder {
+        self.transport = Some(transport);
+        self
     }
 
-    /// Send a custom event as a title and a body
+    /// Will allow the builder to generate an `Options` struct with the provided value.
     ///
     /// # Examples
     ///
     /// ```
-    ///   use dogstatsd::{Client, Options, EventOptions, EventAlertType, EventPriority};
+    ///   use dogstatsd::OptionsBuilder;
     ///
-    ///   let client = Client::new(Options::default()).unwrap();
-    ///   let event_options = EventOptions::new()
-    ///     .with_timestamp(1638480000)
-    ///     .with_hostname("localhost")
-    ///     .with_priority(EventPriority::Normal)
-    ///     .with_alert_type(EventAlertType::Error);
-    ///   client.event_with_options("My Custom Event Title", "My Custom Event Body", &["tag:event"], Some(event_options))
-    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   let options_builder = OptionsBuilder::new().measure_send_latency(true);
+    /// ```
+    pub fn measure_send_latency(&mut self, measure_send_latency: bool) -> &mut OptionsBuilder {
+        self.measure_send_latency = measure_send_latency;
+        self
+    }
 
-    pub fn event_with_options<'a, I, S, SS, T>(
-        &self,
-        title: S,
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, WarningEvents};
+    ///
+    ///   let options_builder = OptionsBuilder::new().warning_events(WarningEvents::default());
+    /// ```
+    pub fn warning_events(&mut self, warning_events: WarningEvents) -> &mut OptionsBuilder {
+        self.warning_events = Some(warning_events);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().float_precision(3);
+    /// ```
+    pub fn float_precision(&mut self, float_precision: u32) -> &mut OptionsBuilder {
+        self.float_precision = Some(float_precision);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().file_path(String::from("/tmp/metrics.dsd"));
+    /// ```
+    pub fn file_path(&mut self, file_path: String) -> &mut OptionsBuilder {
+        self.file_path = Some(file_path);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, ReservedTagAction, ReservedTags};
+    ///
+    ///   let options_builder = OptionsBuilder::new().reserved_tags(ReservedTags::new(ReservedTagAction::Warn));
+    /// ```
+    pub fn reserved_tags(&mut self, reserved_tags: ReservedTags) -> &mut OptionsBuilder {
+        self.reserved_tags = Some(reserved_tags);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value. Can be called multiple times to add multiple `additional_to_addrs` to the `Options`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().additional_to_addr(String::from("127.0.0.1:9125"));
+    /// ```
+    pub fn additional_to_addr(&mut self, additional_to_addr: String) -> &mut OptionsBuilder {
+        self.additional_to_addrs.push(additional_to_addr);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().connect_udp(true);
+    /// ```
+    pub fn connect_udp(&mut self, connect_udp: bool) -> &mut OptionsBuilder {
+        self.connect_udp = Some(connect_udp);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, WouldBlockPolicy};
+    ///   use std::time::Duration;
+    ///
+    ///   let options_builder = OptionsBuilder::new().would_block_policy(WouldBlockPolicy::Retry {
+    ///       attempts: 3,
+    ///       backoff: Duration::from_micros(100),
+    ///   });
+    /// ```
+    pub fn would_block_policy(
+        &mut self,
+        would_block_policy: WouldBlockPolicy,
+    ) -> &mut OptionsBuilder {
+        self.would_block_policy = Some(would_block_policy);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{AddressFamily, OptionsBuilder};
+    ///
+    ///   let options_builder = OptionsBuilder::new().address_family(AddressFamily::Ipv6);
+    /// ```
+    pub fn address_family(&mut self, address_family: AddressFamily) -> &mut OptionsBuilder {
+        self.address_family = Some(address_family);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, SharedEncoder, TelegrafEncoder};
+    ///
+    ///   let options_builder = OptionsBuilder::new().encoder(SharedEncoder::new(TelegrafEncoder));
+    /// ```
+    pub fn encoder(&mut self, encoder: SharedEncoder) -> &mut OptionsBuilder {
+        self.encoder = Some(encoder);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{OptionsBuilder, ReconnectOptions};
+    ///
+    ///   let options_builder = OptionsBuilder::new().reconnect_options(ReconnectOptions::default());
+    /// ```
+    pub fn reconnect_options(
+        &mut self,
+        reconnect_options: ReconnectOptions,
+    ) -> &mut OptionsBuilder {
+        self.reconnect_options = Some(reconnect_options);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///   use std::time::Duration;
+    ///
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .socket_path(Some(String::from("/var/run/datadog/dsd.socket")))
+    ///       .udp_fallback(Duration::from_secs(30));
+    /// ```
+    pub fn udp_fallback(&mut self, retry_interval: Duration) -> &mut OptionsBuilder {
+        self.udp_fallback = Some(retry_interval);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().max_tags_bytes(4096);
+    /// ```
+    pub fn max_tags_bytes(&mut self, max_tags_bytes: usize) -> &mut OptionsBuilder {
+        self.max_tags_bytes = Some(max_tags_bytes);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .socket_path(Some(String::from("/var/run/datadog/dsd.socket")))
+    ///       .lazy_socket(true);
+    /// ```
+    pub fn lazy_socket(&mut self, lazy_socket: bool) -> &mut OptionsBuilder {
+        self.lazy_socket = Some(lazy_socket);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{ConnectRetry, OptionsBuilder};
+    ///
+    ///   let options_builder = OptionsBuilder::new().connect_retry(ConnectRetry::default());
+    /// ```
+    pub fn connect_retry(&mut self, connect_retry: ConnectRetry) -> &mut OptionsBuilder {
+        self.connect_retry = Some(connect_retry);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///   use std::time::Duration;
+    ///
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .socket_path(Some(String::from("/var/run/datadog/dsd.socket")))
+    ///       .uds_connect_timeout(Duration::from_secs(1));
+    /// ```
+    pub fn uds_connect_timeout(&mut self, uds_connect_timeout: Duration) -> &mut OptionsBuilder {
+        self.uds_connect_timeout = Some(uds_connect_timeout);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{GaugeRollup, OptionsBuilder};
+    ///   use std::time::Duration;
+    ///
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .gauge_rollup(GaugeRollup {
+    ///           prefixes: vec![String::from("queue.")],
+    ///           interval: Duration::from_secs(10),
+    ///       });
+    /// ```
+    pub fn gauge_rollup(&mut self, gauge_rollup: GaugeRollup) -> &mut OptionsBuilder {
+        self.gauge_rollup = Some(gauge_rollup);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{DebugMirror, MirrorSink, OptionsBuilder};
+    ///
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .debug_mirror(DebugMirror {
+    ///           fraction: 0.001,
+    ///           sink: MirrorSink::File(String::from("/tmp/dogstatsd-mirror.dsd")),
+    ///       });
+    /// ```
+    pub fn debug_mirror(&mut self, debug_mirror: DebugMirror) -> &mut OptionsBuilder {
+        self.debug_mirror = Some(debug_mirror);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().thread_tag(true);
+    /// ```
+    pub fn thread_tag(&mut self, thread_tag: bool) -> &mut OptionsBuilder {
+        self.thread_tag = thread_tag;
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, OptionsBuilder};
+    ///
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .batching_options(BatchingOptions::default())
+    ///       .max_retained_batches(16);
+    /// ```
+    pub fn max_retained_batches(&mut self, max_retained_batches: usize) -> &mut OptionsBuilder {
+        self.max_retained_batches = Some(max_retained_batches);
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().config_banner(true);
+    /// ```
+    pub fn config_banner(&mut self, config_banner: bool) -> &mut OptionsBuilder {
+        self.config_banner = config_banner;
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::OptionsBuilder;
+    ///
+    ///   let options_builder = OptionsBuilder::new().io_uring(true);
+    /// ```
+    pub fn io_uring(&mut self, io_uring: bool) -> &mut OptionsBuilder {
+        self.io_uring = io_uring;
+        self
+    }
+
+    /// Will allow the builder to generate an `Options` struct with the provided value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Compression, OptionsBuilder, Transport};
+    ///
+    ///   let options_builder = OptionsBuilder::new()
+    ///       .transport(Transport::Tcp)
+    ///       .compression(Compression::Zstd);
+    /// ```
+    pub fn compression(&mut self, compression: Compression) -> &mut OptionsBuilder {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Will construct an `Options` with all of the provided values and fall back to the default values if they aren't provided.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Options, OptionsBuilder};
+    ///
+    ///   let options = OptionsBuilder::new().namespace(String::from("mynamespace")).default_tag(String::from("tag1:tav1val")).build();
+    ///
+    ///   let mut expected = Options::default();
+    ///   expected.namespace = String::from("mynamespace");
+    ///   expected.default_tags = vec![String::from("tag1:tav1val")];
+    ///   assert_eq!(expected, options);
+    /// ```
+    pub fn build(&self) -> Options {
+        let mut options = Options::new(
+            self.from_addr
+                .as_ref()
+                .unwrap_or(&String::from(DEFAULT_FROM_ADDR)),
+            self.to_addr
+                .as_ref()
+                .unwrap_or(&String::from(DEFAULT_TO_ADDR)),
+            self.namespace.as_ref().unwrap_or(&String::default()),
+            self.merged_tags().into_iter().map(|(tag, _)| tag).collect(),
+            self.socket_path.clone(),
+            self.batching_options,
+        );
+        options.stats_report_interval = self.stats_report_interval;
+        options.failover_options = self.failover_options.clone();
+        options.max_paused_metrics = self
+            .max_paused_metrics
+            .unwrap_or(DEFAULT_MAX_PAUSED_METRICS);
+        options.keep_alive_interval = self
+            .keep_alive_interval
+            .unwrap_or(DEFAULT_KEEP_ALIVE_INTERVAL);
+        options.error_handler = self.error_handler.clone();
+        options.tag_prefix = self.tag_prefix.clone();
+        options.max_payload_size = self.max_payload_size.unwrap_or(MAX_UDP_PAYLOAD_SIZE);
+        options.oversized_metric_policy = self.oversized_metric_policy.unwrap_or_default();
+        options.route_hook = self.route_hook.clone();
+        options.slo_buckets = self.slo_buckets.clone();
+        options.host_tag = self.host_tag;
+        options.pid_tag = self.pid_tag;
+        options.tag_rate_limit = self.tag_rate_limit.clone();
+        options.adaptive_sampling = self.adaptive_sampling;
+        options.bind_mode = self.bind_mode.clone();
+        options.aggregation_key_generator = self.aggregation_key_generator.clone();
+        options.watchdog = self.watchdog;
+        options.sample_rate_correction = self.sample_rate_correction;
+        options.normalize_metric_names = self.normalize_metric_names;
+        options.transport = self.transport;
+        options.measure_send_latency = self.measure_send_latency;
+        options.warning_events = self.warning_events;
+        options.float_precision = self.float_precision.unwrap_or(DEFAULT_FLOAT_PRECISION);
+        options.file_path = self.file_path.clone();
+        options.reserved_tags = self.reserved_tags.clone();
+        options.additional_to_addrs = self.additional_to_addrs.clone();
+        options.connect_udp = self.connect_udp.unwrap_or(false);
+        options.would_block_policy = self.would_block_policy;
+        options.address_family = self.address_family;
+        options.encoder = self.encoder.clone();
+        options.reconnect_options = self.reconnect_options;
+        options.udp_fallback = self.udp_fallback;
+        options.max_tags_bytes = self.max_tags_bytes;
+        options.lazy_socket = self.lazy_socket.unwrap_or(false);
+        options.connect_retry = self.connect_retry;
+        options.uds_connect_timeout = self.uds_connect_timeout;
+        options.gauge_rollup = self.gauge_rollup.clone();
+        options.debug_mirror = self.debug_mirror.clone();
+        options.thread_tag = self.thread_tag;
+        options.max_retained_batches = self.max_retained_batches;
+        options.config_banner = self.config_banner;
+        options.io_uring = self.io_uring;
+        options.compression = self.compression;
+        options
+    }
+}
+
+#[derive(Debug)]
+enum SocketType {
+    Udp(UdpSocket),
+    Uds(UnixDatagram),
+    // UDS, sent over UDP while the socket is missing, for `Options::udp_fallback`.
+    UdsFallback(UdsFallback),
+    Tcp(TcpConnection),
+    File(FileSink),
+    // Discards everything, for `Client::noop`.
+    Null,
+    // Not opened until the first send, for `Options::lazy_socket`.
+    Unopened(LazySocket),
+    #[cfg(any(test, feature = "testing"))]
+    Capture(CaptureSink),
+    Batched(Mutex<Sender<batch_processor::Message>>),
+}
+
+impl SocketType {
+    // Another handle to the same socket, for restarting the batch processor.
+    fn try_clone(&self) -> io::Result<SocketType> {
+        match self {
+            SocketType::Udp(socket) => socket.try_clone().map(SocketType::Udp),
+            SocketType::Uds(socket) => socket.try_clone().map(SocketType::Uds),
+            SocketType::UdsFallback(socket) => socket.try_clone().map(SocketType::UdsFallback),
+            SocketType::Tcp(connection) => connection.try_clone().map(SocketType::Tcp),
+            SocketType::File(sink) => sink.try_clone().map(SocketType::File),
+            SocketType::Null => Ok(SocketType::Null),
+            SocketType::Unopened(lazy) => Ok(SocketType::Unopened(lazy.clone())),
+            #[cfg(any(test, feature = "testing"))]
+            SocketType::Capture(sink) => Ok(SocketType::Capture(sink.clone())),
+            SocketType::Batched(_) => Err(io::Error::other("a batched socket can't be cloned")),
+        }
+    }
+
+    // Opens the socket if it's waiting for the first send. If it can't be, it's tried again
+    // on the next one.
+    fn open_lazily(&mut self) -> io::Result<()> {
+        if let SocketType::Unopened(lazy) = self {
+            *self = lazy.open()?;
+        }
+        Ok(())
+    }
+}
+
+// Opens the UDS socket if `socket_path` is set, and the UDP socket otherwise.
+fn open_datagram_socket(options: &Options) -> io::Result<SocketType> {
+    Ok(match (&options.socket_path, &options.bind_mode) {
+        (_, Some(BindMode::Inherit(fd))) => open_inherited(options, &take_inherited(fd))?,
+        (Some(socket_path), bind_mode) if options.udp_fallback.is_some() => {
+            let to_addr = resolve_to_addr(&options.to_addr, options.address_family)?;
+            let udp = bind_udp(bind_mode.as_ref(), udp_from_addr(options, to_addr))?;
+            SocketType::UdsFallback(UdsFallback::open(
+                socket_path,
+                udp,
+                to_addr,
+                options.udp_fallback.unwrap_or_default(),
+                options.uds_connect_timeout,
+                options.transport == Some(Transport::UdsSeqPacket),
+                options.would_block_policy,
+            ))
+        }
+        (Some(socket_path), _) => {
+            // The follow scenarios can occur:
+            // - socket does not exist yet: We will call .bind(...) to create one
+            // - socket exists, but no listener: We will retry attempting to connect
+            //   however, if no listener subscribes to the socket within retries, we will
+            //   failt to initialize
+            // - socket exists, with a listener: Calling .connect(...) will work successfully
+            // - connecting takes longer than `uds_connect_timeout`: binding would hang too, so
+            //   we fail to initialize
+            // - the socket is a `SOCK_SEQPACKET` one: only a listener can bind it, so we fail
+            //   to initialize
+            // - the socket is opened lazily: binding would stop it waiting for the agent, so we
+            //   fail until the agent creates it
+            let seqpacket = options.transport == Some(Transport::UdsSeqPacket);
+            let uds_socket = match connect_uds(socket_path, options.uds_connect_timeout, seqpacket)
+            {
+                Ok(socket) => socket,
+                Err(e)
+                    if seqpacket || options.lazy_socket || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Err(e)
+                }
+                Err(e) => {
+                    println!(
+                        "Couldn't connect to uds socket.. attempting to re-create by binding directly: {e:?}"
+                    );
+                    UnixDatagram::bind(socket_path.clone())?
+                }
+            };
+            set_uds_blocking(&uds_socket, options.would_block_policy)?;
+            SocketType::Uds(uds_socket)
+        }
+        (None, bind_mode) => {
+            // Resolved each time the socket is opened, so a hostname that doesn't resolve yet
+            // is retried with `connect_retry` or `lazy_socket`.
+            let to_addr = resolve_to_addr(&options.to_addr, options.address_family)?;
+            let socket = bind_udp(bind_mode.as_ref(), udp_from_addr(options, to_addr))?;
+            connect_udp(&socket, options, to_addr)?;
+            SocketType::Udp(socket)
+        }
+    })
+}
+
+// Sets up a copy of the socket for `BindMode::Inherit`, so an attempt that fails doesn't close
+// the inherited one, which is closed once it's no longer needed.
+fn open_inherited(options: &Options, fd: &OwnedFd) -> io::Result<SocketType> {
+    let fd = fd.try_clone()?;
+    Ok(match options.socket_path {
+        Some(_) => {
+            // The inherited socket should already be connected to the agent.
+            let uds_socket = UnixDatagram::from(fd);
+            set_uds_blocking(&uds_socket, options.would_block_policy)?;
+            SocketType::Uds(uds_socket)
+        }
+        None => {
+            let socket = UdpSocket::from(fd);
+            let to_addr = resolve_to_addr(&options.to_addr, options.address_family)?;
+            connect_udp(&socket, options, to_addr)?;
+            SocketType::Udp(socket)
+        }
+    })
+}
+
+// Calls `connect`, retrying as `Options::connect_retry` says to.
+fn connect_with_retry<T>(
+    options: &Options,
+    mut connect: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    match &options.connect_retry {
+        Some(connect_retry) => connect_retry.run(connect),
+        None => connect(),
+    }
+}
+
+// The address to bind the UDP socket to. The default `from_addr` can't send to IPv6 addresses.
+fn udp_from_addr(options: &Options, to_addr: SocketAddr) -> &str {
+    match to_addr {
+        SocketAddr::V6(_) if options.from_addr == DEFAULT_FROM_ADDR => "[::]:0",
+        _ => &options.from_addr,
+    }
+}
+
+// Opens the socket again the way `open_datagram_socket` did, for `Client::rebind`. An inherited
+// socket can't be opened again.
+fn reopen_datagram_socket(options: &Options) -> Option<Reopen> {
+    if let Some(BindMode::Inherit(_)) = options.bind_mode {
+        return None;
+    }
+    let options = options.clone();
+    Some(Arc::new(move || open_datagram_socket(&options)))
+}
+
+// Checks options that can't be used together for a UDP or UDS client.
+fn validate_options(options: &Options) -> io::Result<()> {
+    validate_compression(options.compression, options.transport)?;
+    if options.transport == Some(Transport::UdsSeqPacket) && options.socket_path.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Transport::UdsSeqPacket needs socket_path",
+        ));
+    }
+    Ok(())
+}
+
+// Connects the udp socket to `to_addr`, as resolved, if `connect_udp` is set.
+fn connect_udp(socket: &UdpSocket, options: &Options, to_addr: SocketAddr) -> io::Result<()> {
+    if !options.connect_udp {
+        return Ok(());
+    }
+    if options.failover_options.is_some() || !options.additional_to_addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "connect_udp can't be used with failover_options or additional_to_addrs",
+        ));
+    }
+    socket.connect(to_addr)
+}
+
+/// The client struct that handles sending metrics to the Dogstatsd server.
+#[derive(Debug)]
+pub struct Client {
+    writer: Arc<Writer>,
+    from_addr: String,
+    to_addr: String,
+    probe_target: ProbeTarget,
+    namespace: String,
+    default_tags: DefaultTags,
+    tag_prefix: Option<String>,
+    max_payload_size: usize,
+    oversized_metric_policy: OversizedMetricPolicy,
+    max_tags_bytes: Option<usize>,
+    thread_tag: bool,
+    rollup: Option<Arc<Rollup>>,
+    router: Option<Router>,
+    slo_buckets: Vec<Duration>,
+    telemetry_tags: [String; 3],
+    rate_limiter: Option<Arc<TagRateLimiter>>,
+    sampler: Option<Arc<Sampler>>,
+    aggregation_key_generator: Option<AggregationKeyGenerator>,
+    metric_sender: OnceLock<MetricSender>,
+    sample_rate_correction: bool,
+    normalize_metric_names: bool,
+    float_precision: u32,
+    reserved_tags: Option<ReservedTags>,
+    encoder: Option<SharedEncoder>,
+    stats_report_interval: Option<Duration>,
+    last_stats_report: Mutex<(Instant, ClientStats)>,
+    keep_alives: KeepAlives,
+}
+
+impl PartialEq for Client {
+    fn eq(&self, other: &Self) -> bool {
+        // Ignore `writer`, whose socket will never be the same
+        self.from_addr == other.from_addr
+            && self.to_addr == other.to_addr
+            && self.namespace == other.namespace
+            && self.default_tags == other.default_tags
+    }
+}
+
+impl Client {
+    /// Create a new client from an options struct.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    /// ```
+    pub fn new(options: Options) -> Result<Self, DogstatsdError> {
+        validate_compression(options.compression, options.transport)?;
+        if let Some(file_path) = &options.file_path {
+            if options.socket_path.is_some() || options.transport == Some(Transport::Tcp) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "file_path can't be used with socket_path or Transport::Tcp",
+                )
+                .into());
+            }
+            let socket = SocketType::File(FileSink::open(file_path)?);
+            let file_path = file_path.clone();
+            let client = Self::with_socket(options, socket, Clock::System)?;
+            client.writer.set_reopen(Arc::new(move || {
+                Ok(SocketType::File(FileSink::open(&file_path)?))
+            }));
+            return Ok(client);
+        }
+
+        if options.transport == Some(Transport::Tcp) {
+            if options.socket_path.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "socket_path can't be used with Transport::Tcp",
+                )
+                .into());
+            }
+            let socket = SocketType::Tcp(connect_with_retry(&options, || {
+                TcpConnection::connect(&options.to_addr, options.compression)
+            })?);
+            let to_addr = options.to_addr.clone();
+            let compression = options.compression;
+            let client = Self::with_socket(options, socket, Clock::System)?;
+            client.writer.set_reopen(Arc::new(move || {
+                Ok(SocketType::Tcp(TcpConnection::connect(
+                    &to_addr,
+                    compression,
+                )?))
+            }));
+            return Ok(client);
+        }
+
+        validate_options(&options)?;
+        let reopen = reopen_datagram_socket(&options);
+        let socket = match (&options.bind_mode, reopen.as_ref()) {
+            (Some(BindMode::Inherit(fd)), _) => {
+                // Taken over once, outside the retries.
+                let fd = take_inherited(fd);
+                connect_with_retry(&options, || open_inherited(&options, &fd))?
+            }
+            (_, Some(open)) if options.lazy_socket => SocketType::Unopened(LazySocket::new(
+                open.clone(),
+                options.connect_retry.unwrap_or_default(),
+            )),
+            _ => connect_with_retry(&options, || open_datagram_socket(&options))?,
+        };
+        let reconnector = options.reconnect_options.and_then(|reconnect_options| {
+            let reopen = reopen.clone()?;
+            Some(Arc::new(Reconnector::new(reconnect_options, reopen)))
+        });
+
+        let client = Self::with_reconnector(options, socket, Clock::System, reconnector)?;
+        if let Some(reopen) = reopen {
+            client.writer.set_reopen(reopen);
+        }
+        Ok(client)
+    }
+
+    /// Create a client that accepts every metric and sends nothing, without formatting it or
+    /// opening a socket, for turning metrics off by configuration while keeping the same
+    /// `Client` type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let metrics_enabled = false;
+    ///   let client = if metrics_enabled {
+    ///       Client::new(Options::default()).unwrap()
+    ///   } else {
+    ///       Client::noop()
+    ///   };
+    ///   client.incr("counter", &["tag:counter"]).unwrap();
+    /// ```
+    pub fn noop() -> Self {
+        Self::with_socket(Options::default(), SocketType::Null, Clock::System)
+            .expect("the default options start no threads")
+    }
+
+    /// Whether this client was created with `Client::noop`, and so sends nothing.
+    pub fn is_noop(&self) -> bool {
+        self.writer.is_null()
+    }
+
+    /// Create a new client that sends from a UDP socket that's already open, such as one
+    /// passed down by a parent process, instead of binding one. `from_addr` and `bind_mode`
+    /// are ignored, and batching works as usual.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::net::UdpSocket;
+    ///
+    ///   let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    ///   let client = Client::from_socket(socket, Options::default()).unwrap();
+    /// ```
+    pub fn from_socket(socket: UdpSocket, options: Options) -> Result<Self, DogstatsdError> {
+        if options.socket_path.is_some() || options.transport == Some(Transport::Tcp) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "from_socket needs a UDP transport, without socket_path",
+            )
+            .into());
+        }
+        validate_options(&options)?;
+        let to_addr = resolve_to_addr(&options.to_addr, options.address_family)?;
+        connect_udp(&socket, &options, to_addr)?;
+
+        Self::with_socket(options, SocketType::Udp(socket), Clock::System)
+    }
+
+    /// Create a new client that sends from a socket that's already open, such as one from
+    /// `sd_listen_fds` or inherited across `exec`, so a process that can't open sockets itself
+    /// can still send metrics. It's a Unix datagram socket, which should already be connected
+    /// to the agent, if `Options::socket_path` is set, and a UDP socket otherwise, as for
+    /// `from_socket`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::net::UdpSocket;
+    ///   use std::os::unix::io::OwnedFd;
+    ///
+    ///   let fd = OwnedFd::from(UdpSocket::bind("127.0.0.1:0").unwrap());
+    ///   let client = Client::from_owned_fd(fd, Options::default()).unwrap();
+    /// ```
+    pub fn from_owned_fd(fd: OwnedFd, options: Options) -> Result<Self, DogstatsdError> {
+        if options.socket_path.is_none() {
+            return Self::from_socket(UdpSocket::from(fd), options);
+        }
+        if options.transport == Some(Transport::Tcp) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket_path can't be used with Transport::Tcp",
+            )
+            .into());
+        }
+        let socket = UnixDatagram::from(fd);
+        set_uds_blocking(&socket, options.would_block_policy)?;
+        Self::with_socket(options, SocketType::Uds(socket), Clock::System)
+    }
+
+    /// Like `from_owned_fd`, with a raw file descriptor.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open datagram socket that nothing else will use or close. The client
+    /// takes ownership and closes it when dropped.
+    pub unsafe fn from_raw_fd(fd: RawFd, options: Options) -> Result<Self, DogstatsdError> {
+        Self::from_owned_fd(OwnedFd::from_raw_fd(fd), options)
+    }
+
+    // Wraps an already set up socket in the batch processor if batching is enabled, and
+    // builds the client around it, failing only if its threads can't be started.
+    fn with_socket(
+        options: Options,
+        socket: SocketType,
+        clock: Clock,
+    ) -> Result<Self, DogstatsdError> {
+        Self::with_reconnector(options, socket, clock, None)
+    }
+
+    // Like `with_socket`, re-creating the socket with `reconnector` when sends keep failing,
+    // from the batch processor if there is one.
+    fn with_reconnector(
+        options: Options,
+        socket: SocketType,
+        clock: Clock,
+        reconnector: Option<Arc<Reconnector>>,
+    ) -> Result<Self, DogstatsdError> {
+        let probe_target = ProbeTarget::new(&socket, &options);
+        let config_hash = options.config_banner.then(|| config_hash(&options));
+        let stats = Arc::new(Stats::new(options.measure_send_latency));
+        let warning_events = options
+            .warning_events
+            .map(|warning_events| Arc::new(WarningEventSender::new(warning_events)));
+        let error_handler = match &warning_events {
+            Some(warning_events) => Some(warning_events.wrap(options.error_handler)),
+            None => options.error_handler,
+        };
+        let destination = Destination::new(options.to_addr.clone(), options.failover_options)
+            .with_address_family(options.address_family)
+            .with_additional_addrs(options.additional_to_addrs)
+            .with_connected(options.connect_udp)
+            .with_udp(matches!(socket, SocketType::Udp(_)))
+            .with_error_handler(error_handler.clone());
+
+        let mut watchdog = None;
+        let mut batch_processor = None;
+        let socket = match options.batching_options {
+            Some(batching_options) => {
+                let heartbeat = options.watchdog.map(|_| Arc::new(Heartbeat::new()));
+                // Kept before the socket is handed to the batch processor, for restarting it.
+                let spare_socket = match options.watchdog {
+                    Some(WatchdogOptions { restart: true, .. }) => socket.try_clone().ok(),
+                    _ => None,
+                };
+                let spawn_processor: SpawnProcessor = Arc::new({
+                    let stats = stats.clone();
+                    let socket_path = options.socket_path.clone();
+                    let error_handler = error_handler.clone();
+                    let heartbeat = heartbeat.clone();
+                    let reconnector = reconnector.clone();
+                    move |socket: SocketType, destination: Destination| {
+                        let (tx, rx) = mpsc::channel();
+                        let stats = stats.clone();
+                        let socket_path = socket_path.clone();
+                        let error_handler = error_handler.clone();
+                        let heartbeat = heartbeat.clone();
+                        let reconnector = reconnector.clone();
+                        let processor = thread::Builder::new()
+                            .name("dogstatsd-batch".into())
+                            .spawn(move || {
+                                batch_processor::process_events(
+                                    batching_options,
+                                    destination,
+                                    socket,
+                                    socket_path,
+                                    options.would_block_policy,
+                                    stats,
+                                    error_handler,
+                                    heartbeat,
+                                    reconnector,
+                                    options.max_retained_batches.unwrap_or(0),
+                                    options.io_uring,
+                                    clock,
+                                    rx,
+                                );
+                            })?;
+                        Ok((tx, processor))
+                    }
+                });
+
+                let (tx, processor) = spawn_processor(socket, destination.clone())?;
+                batch_processor = Some((processor, spawn_processor, spare_socket));
+                if let (Some(watchdog_options), Some(heartbeat)) = (options.watchdog, heartbeat) {
+                    watchdog = Some((watchdog_options, batching_options.max_time, heartbeat));
+                }
+                SocketType::Batched(Mutex::from(tx))
+            }
+            None => socket,
+        };
+
+        let tag_prefix = options.tag_prefix.filter(|prefix| !prefix.is_empty());
+        let mut default_tags = Options::merge_with_system_tags(options.default_tags);
+        if options.host_tag && !default_tags.iter().any(|tag| tag.starts_with("host:")) {
+            if let Some(hostname) = hostname() {
+                default_tags.push(format!("host:{}", hostname));
+            }
+        }
+        if options.pid_tag {
+            default_tags.push(format!("pid:{}", std::process::id()));
+        }
+        let default_tags: Vec<String> = default_tags
+            .iter()
+            .map(|tag| prefix_tag(tag_prefix.as_deref(), tag).into_owned())
+            .collect();
+
+        let writer = Arc::new(Writer::new(
+            socket,
+            destination,
+            stats,
+            options.max_paused_metrics,
+            options.would_block_policy,
+            error_handler,
+        ));
+        match batch_processor {
+            Some((batch_processor, spawn_processor, spare_socket)) => {
+                writer.set_processor(batch_processor);
+                writer.set_spawn_processor(spawn_processor);
+                if let Some(spare_socket) = spare_socket {
+                    writer.set_spare_socket(spare_socket);
+                }
+            }
+            None => {
+                if let Some(reconnector) = reconnector {
+                    writer.set_reconnector(reconnector);
+                }
+            }
+        }
+        if let Some(debug_mirror) = options.debug_mirror {
+            match Mirror::open(debug_mirror) {
+                Ok(mirror) => writer.set_mirror(mirror),
+                Err(error) => writer.report_error("Failed to open debug mirror", error.into()),
+            }
+        }
+        let default_tags = DefaultTags::new(default_tags);
+        if let Some(warning_events) = warning_events {
+            warning_events.attach(
+                Arc::downgrade(&writer),
+                default_tags.clone(),
+                options.encoder.clone(),
+            );
+        }
+        if let Some((watchdog_options, interval, heartbeat)) = watchdog {
+            watchdog::spawn(
+                watchdog_options,
+                interval,
+                heartbeat,
+                Arc::downgrade(&writer),
+            )?;
+        }
+        let rollup = options
+            .gauge_rollup
+            .map(|rollup| Rollup::new(rollup, writer.clone(), options.float_precision))
+            .transpose()?;
+
+        let client = Client {
+            writer,
+            from_addr: options.from_addr,
+            to_addr: options.to_addr,
+            probe_target,
+            namespace: options.namespace,
+            default_tags,
+            tag_prefix,
+            max_payload_size: options.max_payload_size,
+            oversized_metric_policy: options.oversized_metric_policy,
+            max_tags_bytes: options.max_tags_bytes,
+            thread_tag: options.thread_tag,
+            rollup,
+            router: options.route_hook.map(Router::new),
+            slo_buckets: {
+                let mut slo_buckets = options.slo_buckets;
+                slo_buckets.sort();
+                slo_buckets
+            },
+            telemetry_tags: [
+                String::from("client:rust"),
+                format!("client_version:{}", VERSION),
+                format!(
+                    "client_transport:{}",
+                    if options.file_path.is_some() {
+                        "file"
+                    } else if options.transport == Some(Transport::Tcp) {
+                        "tcp"
+                    } else if options.socket_path.is_some() {
+                        "uds"
+                    } else {
+                        "udp"
+                    }
+                ),
+            ],
+            rate_limiter: options
+                .tag_rate_limit
+                .map(|limit| Arc::new(TagRateLimiter::new(limit))),
+            sampler: options
+                .adaptive_sampling
+                .map(|sampling| Arc::new(Sampler::new(sampling))),
+            aggregation_key_generator: options.aggregation_key_generator,
+            metric_sender: OnceLock::new(),
+            sample_rate_correction: options.sample_rate_correction,
+            normalize_metric_names: options.normalize_metric_names,
+            float_precision: options.float_precision,
+            reserved_tags: options.reserved_tags,
+            encoder: options.encoder,
+            stats_report_interval: options.stats_report_interval,
+            last_stats_report: Mutex::new((Instant::now(), ClientStats::default())),
+            keep_alives: KeepAlives::new(options.keep_alive_interval),
+        };
+        if let Some(config_hash) = config_hash {
+            if let Err(error) = client.send_config_banner(&config_hash) {
+                client
+                    .writer
+                    .report_error("Failed to send config banner", error);
+            }
+        }
+        Ok(client)
+    }
+
+    /// Stop sending metrics, and hold them in memory until `resume` is called. At most
+    /// `Options::max_paused_metrics` are held, anything beyond that is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.pause();
+    ///   client.incr("counter", &["tag:counter"]).unwrap();
+    ///   client.resume().unwrap();
+    /// ```
+    pub fn pause(&self) {
+        self.writer.pause()
+    }
+
+    /// Send every metric held since `pause` was called, in order, and go back to sending
+    /// metrics immediately. Metrics sent from other threads while this runs wait until all of
+    /// the held metrics have been released.
+    ///
+    /// If any of the held metrics fail to send, the rest are still attempted and the first
+    /// error is returned.
+    pub fn resume(&self) -> DogstatsdResult {
+        self.writer.resume()
+    }
+
+    /// Send anything held by `pause` or buffered for batching, wait for it to be sent, and
+    /// shut the client down.
+    ///
+    /// Handles from `for_namespace` share the batch processor, which keeps running until the
+    /// last of them is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, Client, OptionsBuilder};
+    ///
+    ///   let options = OptionsBuilder::new().batching_options(BatchingOptions::default()).build();
+    ///   let client = Client::new(options).unwrap();
+    ///   client.incr("counter", &["tag:counter"]).unwrap();
+    ///
+    ///   let report = client.shutdown();
+    ///   println!("flushed {} metrics in {:?}", report.flushed_metrics, report.duration);
+    /// ```
+    pub fn shutdown(self) -> ShutdownReport {
+        self.flush_for_shutdown()
+    }
+
+    // Everything `shutdown` does but dropping the client, for the global one, which can't be.
+    pub(crate) fn flush_for_shutdown(&self) -> ShutdownReport {
+        let start = Instant::now();
+        let dropped_before = self.writer.stats.snapshot().total_dropped();
+
+        // Failures are counted in the stats, so there's nothing to do with the error.
+        let _ = self.writer.resume();
+        let (flushed_bytes, flushed_metrics) = self.writer.flush();
+
+        ShutdownReport {
+            flushed_bytes,
+            flushed_metrics,
+            dropped_metrics: self.writer.stats.snapshot().total_dropped() - dropped_before,
+            duration: start.elapsed(),
+        }
+    }
+
+    /// Check whether the agent can be reached, by sending it a canary metric,
+    /// `datadog.dogstatsd.client.probe`, from a new socket, after checking the UDS socket path
+    /// exists. It's sent right away even with batching, for deployment tooling to check
+    /// before serving traffic. Over UDP and TCP it goes to the address metrics are sent to,
+    /// including after `set_destination`, and a TCP probe gives up connecting after a second.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{AgentHealth, Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   if client.probe() != AgentHealth::Reachable {
+    ///       println!("The agent isn't reachable yet");
+    ///   }
+    /// ```
+    pub fn probe(&self) -> AgentHealth {
+        let canary = format!(
+            "datadog.dogstatsd.client.probe:1|c|#{}",
+            self.telemetry_tags.join(",")
+        );
+        self.probe_target
+            .probe(canary.as_bytes(), || self.writer.primary_addr())
+    }
+
+    /// Send one metric of each type, `datadog.dogstatsd.client.self_test.<type>`, then an
+    /// event and a service check, all tagged with a marker unique to this run, and wait for
+    /// them to be sent. Operators can then look for the marker in the agent's
+    /// `dogstatsd-stats` to check metrics make it all the way through.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let report = client.self_test();
+    ///   for (kind, result) in &report.results {
+    ///       println!("{} {}: {:?}", report.marker, kind, result);
+    ///   }
+    /// ```
+    pub fn self_test(&self) -> SelfTestReport {
+        let started = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let marker = format!("self_test:{}-{:x}", std::process::id(), started.as_micros());
+        let tags = [marker.as_str()];
+        let stat = |kind| format!("datadog.dogstatsd.client.self_test.{}", kind);
+
+        let results = vec![
+            ("count", self.incr(stat("count"), tags)),
+            ("gauge", self.gauge(stat("gauge"), "1", tags)),
+            ("histogram", self.histogram(stat("histogram"), "1", tags)),
+            (
+                "distribution",
+                self.distribution(stat("distribution"), "1", tags),
+            ),
+            ("set", self.set(stat("set"), "1", tags)),
+            ("timing", self.timing(stat("timing"), 1, tags)),
+            (
+                "event",
+                self.event(
+                    "DogStatsD client self-test",
+                    format!("Sent by the DogStatsD client with {}", marker),
+                    tags,
+                ),
+            ),
+            (
+                "service_check",
+                self.service_check(
+                    "datadog.dogstatsd.client.self_test",
+                    ServiceStatus::OK,
+                    tags,
+                    None,
+                ),
+            ),
+        ];
+        SelfTestReport {
+            flushed: self.completion().wait(),
+            marker,
+            results,
+        }
+    }
+
+    /// Open a new socket with the same options and send from it from now on, such as after a
+    /// network namespace change or VPN reconnect, without creating a new client. Every client
+    /// sharing this one's socket switches to the new one. With batching, a new batch processor
+    /// takes over and the old one sends what it has buffered first. Clients created with
+    /// `from_socket`, `noop` or `BindMode::Inherit` can't be rebound, and return an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.rebind().unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn rebind(&self) -> DogstatsdResult {
+        Ok(self.writer.rebind()?)
+    }
+
+    /// Send to `addr` instead of `to_addr` from now on, such as after a config reload moves the
+    /// agent, without creating a new client. Metrics already batched are sent to `addr` too,
+    /// and every client sharing this one's socket switches. It's resolved now, and has to be
+    /// the same IP version as `to_addr`. Only clients sending over UDP without `connect_udp`
+    /// can switch, others return an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.set_destination("127.0.0.1:8126")
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn set_destination(&self, addr: &str) -> DogstatsdResult {
+        Ok(self.writer.set_destination(addr)?)
+    }
+
+    /// A handle that's resolved once every metric this client has sent so far has left the
+    /// socket. With batching, the batch processor sends what it's buffered now instead of
+    /// waiting for the batch to fill. Without it, metrics are sent before each call returns,
+    /// so the handle is already resolved. Metrics held while paused aren't waited for.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, Client, OptionsBuilder};
+    ///
+    ///   # #[tokio::main(flavor = "current_thread")]
+    ///   # async fn main() {
+    ///   let options = OptionsBuilder::new().batching_options(BatchingOptions::default()).build();
+    ///   let client = Client::new(options).unwrap();
+    ///   client.incr("payment.captured", &["provider:card"]).unwrap();
+    ///   client.completion().await
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///   # }
+    /// ```
+    pub fn completion(&self) -> Completion {
+        self.writer.completion()
+    }
+
+    /// Send everything this client has buffered now, and wait for it to leave the socket,
+    /// returning the error if sending it failed. This is `completion().wait()`, for callers
+    /// that don't want a handle.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, Client, OptionsBuilder};
+    ///
+    ///   let options = OptionsBuilder::new().batching_options(BatchingOptions::default()).build();
+    ///   let client = Client::new(options).unwrap();
+    ///   client.incr("payment.captured", &["provider:card"]).unwrap();
+    ///   client.flush()
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn flush(&self) -> DogstatsdResult {
+        self.completion().wait()
+    }
+
+    /// Whether dropping the last client sharing this one's batch processor waits for it to send
+    /// its final batch and stop. Off by default, so dropping never blocks, but then metrics
+    /// buffered when the process exits are lost. A stalled batch processor makes the drop
+    /// wait forever, and `std::process::exit` skips destructors, so drop the client before
+    /// calling it. Does nothing without batching.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, Client, OptionsBuilder};
+    ///
+    ///   let options = OptionsBuilder::new().batching_options(BatchingOptions::default()).build();
+    ///   let client = Client::new(options).unwrap();
+    ///   client.join_on_drop(true);
+    ///   client.incr("counter", &["tag:counter"]).unwrap();
+    ///   drop(client);
+    /// ```
+    pub fn join_on_drop(&self, join: bool) {
+        self.writer.join_on_drop(join);
+    }
+
+    /// Let the batch processor's thread run on its own, so dropping the client never waits for
+    /// it, even if `join_on_drop` is turned on again later.
+    pub fn detach(&self) {
+        self.writer.detach();
+    }
+
+    /// Whether the client is currently paused.
+    pub fn is_paused(&self) -> bool {
+        self.writer.is_paused()
+    }
+
+    /// Get a snapshot of the client's internal counters, such as how many metrics have been
+    /// dropped and why.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, DropReason, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let stats = client.stats();
+    ///   assert_eq!(0, stats.dropped(DropReason::Sampled));
+    /// ```
+    pub fn stats(&self) -> ClientStats {
+        self.writer.stats.snapshot()
+    }
+
+    /// The rate metrics are currently sampled at, which is only below 1 while
+    /// `Options::adaptive_sampling` is lowering it.
+    pub fn sample_rate(&self) -> f64 {
+        self.sampler.as_ref().map_or(1.0, |sampler| sampler.rate())
+    }
+
+    /// The number of metrics dropped by `Options::tag_rate_limit`, for each value of the tag.
+    /// Only the first 10,000 values to be rate limited are counted here, so that a tag with
+    /// unbounded values can't grow the counts without bound; drops for later values are still
+    /// counted as `DropReason::RateLimited`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, OptionsBuilder, TagRateLimit};
+    ///
+    ///   let options = OptionsBuilder::new().tag_rate_limit(TagRateLimit::new("customer_id", 1, 1)).build();
+    ///   let client = Client::new(options).unwrap();
+    ///   client.incr("requests", &["customer_id:42"]).unwrap();
+    ///   client.incr("requests", &["customer_id:42"]).unwrap();
+    ///   assert_eq!(Some(&1), client.rate_limited_by_tag().get("42"));
+    /// ```
+    pub fn rate_limited_by_tag(&self) -> HashMap<String, u64> {
+        self.rate_limiter
+            .as_ref()
+            .map(|rate_limiter| rate_limiter.dropped())
+            .unwrap_or_default()
+    }
+
+    /// The tags added to every metric, including those picked up from `DD_ENV`, `DD_SERVICE`
+    /// and `DD_VERSION`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, OptionsBuilder};
+    ///
+    ///   let options = OptionsBuilder::new().default_tag(String::from("region:東京")).build();
+    ///   let client = Client::new(options).unwrap();
+    ///   assert!(client.default_tags().contains(&"region:東京"));
+    /// ```
+    pub fn default_tags(&self) -> Vec<&str> {
+        self.default_tags
+            .iter()
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+
+    /// The default tags exactly as they're appended to every payload, joined with `,`.
+    pub fn default_tag_bytes(&self) -> &[u8] {
+        self.default_tags.as_bytes()
+    }
+
+    /// A client that sends through this client's socket, batch processor and error handler,
+    /// but prefixes metrics with `namespace` instead, for processes hosting several services
+    /// with their own metric prefixes. Everything else is configured as for this client,
+    /// except that client stats are only reported by this one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let billing = client.for_namespace("billing");
+    ///   billing.incr("invoices", &["tag:invoices"]).unwrap();
+    /// ```
+    pub fn for_namespace(&self, namespace: &str) -> Client {
+        Client {
+            writer: self.writer.clone(),
+            from_addr: self.from_addr.clone(),
+            to_addr: self.to_addr.clone(),
+            probe_target: self.probe_target.clone(),
+            namespace: namespace.to_owned(),
+            default_tags: self.default_tags.clone(),
+            tag_prefix: self.tag_prefix.clone(),
+            max_payload_size: self.max_payload_size,
+            oversized_metric_policy: self.oversized_metric_policy,
+            max_tags_bytes: self.max_tags_bytes,
+            thread_tag: self.thread_tag,
+            rollup: self.rollup.clone(),
+            router: self
+                .router
+                .as_ref()
+                .map(|router| Router::new(router.hook().clone())),
+            slo_buckets: self.slo_buckets.clone(),
+            telemetry_tags: self.telemetry_tags.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            sampler: self.sampler.clone(),
+            aggregation_key_generator: self.aggregation_key_generator.clone(),
+            metric_sender: OnceLock::new(),
+            sample_rate_correction: self.sample_rate_correction,
+            normalize_metric_names: self.normalize_metric_names,
+            float_precision: self.float_precision,
+            reserved_tags: self.reserved_tags.clone(),
+            encoder: self.encoder.clone(),
+            stats_report_interval: None,
+            last_stats_report: Mutex::new((Instant::now(), ClientStats::default())),
+            keep_alives: KeepAlives::new(self.keep_alives.interval()),
+        }
+    }
+
+    /// Send the metrics in a file written with `Options::file_path` through this client, packed
+    /// as by `send_batch_raw`, returning how many were sent. The file is read a line at a time,
+    /// so it can be larger than memory. Metrics are sent as they were captured, so this
+    /// client's namespace and default tags aren't added.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let replayed = client.replay_file("/tmp/metrics.dsd").unwrap();
+    ///   println!("replayed {} metrics", replayed);
+    /// ```
+    pub fn replay_file<P: AsRef<std::path::Path>>(&self, path: P) -> Result<u64, DogstatsdError> {
+        use std::io::BufRead;
+
+        // Read a line at a time, so the file is never held in memory.
+        let mut packer = RawPacker::new(self);
+        let mut replayed = 0;
+        for line in io::BufReader::new(std::fs::File::open(path)?).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => {
+                    packer.finish()?;
+                    return Err(error.into());
+                }
+            };
+            if !line.is_empty() {
+                replayed += 1;
+            }
+            packer.push(line.as_bytes());
+        }
+        packer.finish()?;
+        Ok(replayed)
+    }
+
+    /// A client that sends through this client's socket and batch processor with `options`
+    /// applied, for the metrics that need them. Like `for_namespace`, it's cheap enough to
+    /// create per call, but can also be kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, MetricOptions, OptionsBuilder};
+    ///
+    ///   let options = OptionsBuilder::new().default_tag(String::from("host_group:web")).build();
+    ///   let client = Client::new(options).unwrap();
+    ///   client
+    ///       .with_metric_options(MetricOptions::new().with_skip_default_tags(true))
+    ///       .incr("signups", &["plan:free"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn with_metric_options(&self, options: MetricOptions) -> Client {
+        let mut client = self.for_namespace(&self.namespace);
+        if options.skip_default_tags {
+            client.default_tags = DefaultTags::default();
+        }
+        if let Some(host) = options.host {
+            let mut default_tags: Vec<String> = client
+                .default_tags
+                .iter()
+                .filter(|tag| !tag.is_empty() && !tag.starts_with("host:"))
+                .map(str::to_owned)
+                .collect();
+            default_tags.push(format!("host:{}", host));
+            client.default_tags = DefaultTags::new(default_tags);
+        }
+        client
+    }
+
+    /// A handle for sending owned metrics from any thread through a channel, without
+    /// borrowing this client. Every call returns a clone of the same handle, and fails only if
+    /// its thread can't be started.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, MetricPayload, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let sender = client.sender().unwrap();
+    ///   sender.send(MetricPayload::Timing { stat: "render".into(), ms: 12, tags: vec![] })
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn sender(&self) -> Result<MetricSender, DogstatsdError> {
+        if let Some(sender) = self.metric_sender.get() {
+            return Ok(sender.clone());
+        }
+        // If another thread got there first, its handle is kept and this one's thread stops.
+        let sender = sender::spawn(self.for_namespace(&self.namespace))?;
+        Ok(self.metric_sender.get_or_init(|| sender).clone())
+    }
+
+    /// Send metrics that are already formatted, one per line without the trailing newline,
+    /// such as those relayed from other processes. Without batching they're packed into
+    /// payloads of up to `Options::max_payload_size`, and with it they're handed to the batch
+    /// processor. They skip sampling, rate limits and the route hook. A line larger than
+    /// `max_payload_size` is dropped, and the first error is returned once the rest are sent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let relayed = "requests:1|c\nqueue.depth:12|g";
+    ///   client.send_batch_raw(relayed.lines().map(str::as_bytes))
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn send_batch_raw<'a, I>(&self, lines: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut packer = RawPacker::new(self);
+        for line in lines {
+            packer.push(line);
+        }
+        packer.finish()
+    }
+
+    /// Format a float value with at most `Options::float_precision` significant digits, for
+    /// sending with the methods that take values as strings. Digits before the decimal point
+    /// are never rounded away, so whole numbers are formatted exactly.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   assert_eq!("0.3", client.format_float(0.1 + 0.2));
+    ///   assert_eq!("123456789", client.format_float(123456789.0));
+    ///   assert_eq!("0.000123457", client.format_float(0.000123456789));
+    ///   client.gauge("ratio", client.format_float(0.1 + 0.2), &["tag:gauge"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn format_float(&self, value: f64) -> String {
+        metrics::format_float(value, self.float_precision)
+    }
+
+    /// Increment a StatsD counter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.incr("counter", &["tag:counter"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn incr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Incr(stat.into().as_ref(), 1), tags)
+    }
+
+    /// Increment a StatsD counter by the provided amount. A value of 0 is still sent, as `:0|c`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.incr_by_value("counter", 123, &["tag:counter"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn incr_by_value<'a, I, S, T>(&self, stat: S, value: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Incr(stat.into().as_ref(), value), tags)
+    }
+
+    /// Submit an explicit zero to a StatsD counter, to keep the series alive without changing
+    /// its value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.incr_zero("counter", &["tag:counter"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn incr_zero<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Arbitrary(stat.into().as_ref(), 0), tags)
+    }
+
+    /// Submit an explicit zero to a StatsD counter now, and again every
+    /// `Options::keep_alive_interval` until the returned handle is dropped
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let keep_alive = client.keep_alive_count("counter", &["tag:counter"]).unwrap();
+    ///   // ...
+    ///   drop(keep_alive);
+    /// ```
+    pub fn keep_alive_count<'a, I, S, T>(
+        &self,
+        stat: S,
+        tags: I,
+    ) -> Result<KeepAlive, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let formatted_metric =
+            self.format(&CountMetric::Arbitrary(stat.into().as_ref(), 0), tags)?;
+        self.send_formatted(formatted_metric.clone())?;
+        Ok(self.keep_alives.keep_alive(&self.writer, formatted_metric))
+    }
+
+    /// Decrement a StatsD counter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.decr("counter", &["tag:counter"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn decr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Decr(stat.into().as_ref(), 1), tags)
+    }
+
+    /// Decrement a StatsD counter by the provided amount
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.decr_by_value("counter", 23, &["tag:counter"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn decr_by_value<'a, I, S, T>(&self, stat: S, value: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Decr(stat.into().as_ref(), value), tags)
+    }
+
+    /// Make an arbitrary change to a StatsD counter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.count("counter", 42, &["tag:counter"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn count<'a, I, S, T>(&self, stat: S, count: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.count_with_outcome(stat, count, tags).map(|_| ())
+    }
+
+    /// Like `count`, but also returns whether the metric was sent, or was left out by sampling
+    /// or dropped, for skipping work that only goes with metrics that were sent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options, SendOutcome};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   if let Ok(SendOutcome::Sent) = client.count_with_outcome("cache.miss", 1, &["cache:users"]) {
+    ///       println!("cache miss for users");
+    ///   }
+    /// ```
+    pub fn count_with_outcome<'a, I, S, T>(
+        &self,
+        stat: S,
+        count: i64,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send_with_outcome(&CountMetric::Arbitrary(stat.into().as_ref(), count), tags)
+    }
+
+    /// Make an arbitrary change to a StatsD counter with a count that was already sampled
+    /// upstream, where each counted event stands for `weight` events. It's sent with a sample
+    /// rate of `1 / weight`, so Datadog scales it back up. `weight` has to be at least 1.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   // 12 requests seen in a trace sample that kept 1 in 10.
+    ///   client.count_weighted("requests", 12, 10.0, &["tag:counter"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn count_weighted<'a, I, S, T>(
+        &self,
+        stat: S,
+        count: i64,
+        weight: f64,
+        tags: I,
+    ) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        if !(weight >= 1.0 && weight.is_finite()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "weight must be a finite number of at least 1, got {}",
+                    weight
+                ),
+            )
+            .into());
+        }
+        self.send(
+            &CountMetric::Weighted(stat.into().as_ref(), count, weight),
+            tags,
+        )
+    }
+
+    /// Time how long it takes for a block of code to execute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::thread;
+    ///   use std::time::Duration;
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.time("timer", &["tag:time"], || {
+    ///       thread::sleep(Duration::from_millis(200))
+    ///   }).unwrap_or_else(|(_, e)| println!("Encountered error: {}", e))
+    /// ```
+    pub fn time<'a, F, O, I, S, T>(
+        &self,
+        stat: S,
+        tags: I,
+        block: F,
+    ) -> Result<O, (O, DogstatsdError)>
+    where
+        F: FnOnce() -> O,
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let start_time = Utc::now();
+        let output = block();
+        let end_time = Utc::now();
+        let stat = stat.into();
+        let metric = TimeMetric::new(stat.as_ref(), &start_time, &end_time);
+        let ms = (end_time - start_time).num_milliseconds() as f64;
+        match self.send_timed(&metric, stat.as_ref(), ms, tags) {
+            Ok(_) => Ok(output),
+            Err(error) => Err((output, error)),
+        }
+    }
+
+    /// Like `time`, but returns the block's output either way, and hands any error sending the
+    /// timing to the error handler, so metrics never change what the caller does next.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let sum = client.time_infallible("timer", &["tag:time"], || 1 + 1);
+    ///   assert_eq!(2, sum);
+    /// ```
+    pub fn time_infallible<'a, F, O, I, S, T>(&self, stat: S, tags: I, block: F) -> O
+    where
+        F: FnOnce() -> O,
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.time(stat, tags, block)
+            .unwrap_or_else(|(output, error)| {
+                self.writer.report_error("Failed to send timing", error);
+                output
+            })
+    }
+
+    /// Time how long it takes for an async block of code to execute.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::thread;
+    ///   use std::time::Duration;
+    ///
+    /// # async fn do_work() {}
+    ///   async fn timer() {
+    ///       let client = Client::new(Options::default()).unwrap();
+    ///       client.async_time("timer", &["tag:time"], do_work)
+    ///       .await
+    ///       .unwrap_or_else(|(_, e)| println!("Encountered error: {}", e))
+    ///   }
+    /// ```
+    pub async fn async_time<'a, Fn, Fut, O, I, S, T>(
+        &self,
+        stat: S,
+        tags: I,
+        block: Fn,
+    ) -> Result<O, (O, DogstatsdError)>
+    where
+        Fn: FnOnce() -> Fut,
+        Fut: Future<Output = O>,
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let start_time = Utc::now();
+        let output = block().await;
+        let end_time = Utc::now();
+        let stat = stat.into();
+        let metric = TimeMetric::new(stat.as_ref(), &start_time, &end_time);
+        let ms = (end_time - start_time).num_milliseconds() as f64;
+        match self.send_timed(&metric, stat.as_ref(), ms, tags) {
+            Ok(_) => Ok(output),
+            Err(error) => Err((output, error)),
+        }
+    }
+
+    /// Send your own timing metric in milliseconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.timing("timing", 350, &["tag:timing"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn timing<'a, I, S, T>(&self, stat: S, ms: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.timing_with_outcome(stat, ms, tags).map(|_| ())
+    }
+
+    /// Like `timing`, but also returns whether the metric was sent, or was left out by
+    /// sampling or dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.timing_with_outcome("timing", 350, &["tag:timing"])
+    ///       .unwrap_or_else(|e| panic!("Encountered error: {}", e));
+    /// ```
+    pub fn timing_with_outcome<'a, I, S, T>(
+        &self,
+        stat: S,
+        ms: i64,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let stat = stat.into();
+        self.send_timed(
+            &TimingMetric::new(stat.as_ref(), ms),
+            stat.as_ref(),
+            ms as f64,
+            tags,
+        )
+    }
+
+    /// Report an arbitrary value as a gauge
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.gauge("gauge", "12345", &["tag:gauge"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn gauge<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.gauge_with_outcome(stat, val, tags).map(|_| ())
+    }
+
+    /// Like `gauge`, but also returns whether the metric was sent, or was left out by sampling,
+    /// dropped or rolled up.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.gauge_with_outcome("gauge", "12345", &["tag:gauge"])
+    ///       .unwrap_or_else(|e| panic!("Encountered error: {}", e));
+    /// ```
+    pub fn gauge_with_outcome<'a, I, S, SS, T>(
+        &self,
+        stat: S,
+        val: SS,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let (stat, val) = (stat.into(), val.into());
+        let metric = GaugeMetric::new(stat.as_ref(), val.as_ref());
+        match &self.rollup {
+            Some(rollup) if rollup.matches(&stat) && !self.writer.is_null() => {
+                match rollup.record(self.format(&metric, tags)?) {
+                    Some(formatted_metric) => self.dispatch(formatted_metric),
+                    None => Ok(SendOutcome::RolledUp),
+                }
+            }
+            _ => self.send_with_outcome(&metric, tags),
+        }
+    }
+
+    /// Create a gauge whose last value is re-sent every `Options::keep_alive_interval` until
+    /// the returned handle is dropped. Nothing is sent until the first call to
+    /// `PersistentGauge::set`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let gauge = client.persistent_gauge("pool.size", &["tag:gauge"]);
+    ///   gauge.set("12345")
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn persistent_gauge<'a, I, S, T>(&self, stat: S, tags: I) -> PersistentGauge
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.keep_alives.persistent_gauge(
+            &self.writer,
+            stat.into().into_owned(),
+            self.namespace.clone(),
+            tags.into_iter()
+                .map(|tag| prefix_tag(self.tag_prefix.as_deref(), tag.as_ref()).into_owned())
+                .collect(),
+            self.default_tags.clone(),
+            self.encoder.clone(),
+        )
+    }
+
+    /// Create a set whose members are sent once each every `interval`, however often they're
+    /// inserted, the first time they're inserted in each.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::time::Duration;
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let visitors = client.unique_tracker("visitors", Duration::from_secs(10), &["tag:set"]);
+    ///   visitors.insert("203.0.113.7").unwrap();
+    /// ```
+    pub fn unique_tracker<'a, I, S, T>(&self, stat: S, interval: Duration, tags: I) -> UniqueTracker
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        UniqueTracker::new(
+            self.for_namespace(&self.namespace),
+            stat.into().into_owned(),
+            interval,
+            tags.into_iter()
+                .map(|tag| tag.as_ref().to_owned())
+                .collect(),
+        )
+    }
+
+    /// Report the state of the connection pool `name` every `interval`, from a thread that
+    /// stops when the returned `PoolMetrics` is dropped, which also times waits for
+    /// connections. Fails only if the thread can't be started.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options, PoolStats};
+    ///   use std::sync::Arc;
+    ///   use std::time::Duration;
+    ///
+    ///   struct Pool;
+    ///
+    ///   impl PoolStats for Pool {
+    ///       fn size(&self) -> u64 { 4 }
+    ///       fn idle(&self) -> u64 { 1 }
+    ///   }
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let metrics = client.pool_metrics("db", Arc::new(Pool), Duration::from_secs(10), &["tag:pool"]).unwrap();
+    /// ```
+    pub fn pool_metrics<I, T>(
+        &self,
+        name: &str,
+        pool: Arc<dyn PoolStats>,
+        interval: Duration,
+        tags: I,
+    ) -> Result<PoolMetrics, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        PoolMetrics::new(
+            self.for_namespace(&self.namespace),
+            name,
+            pool,
+            interval,
+            tags.into_iter()
+                .map(|tag| tag.as_ref().to_owned())
+                .collect(),
+        )
+        .map_err(DogstatsdError::from)
+    }
+
+    /// Lock `mutex`, reporting how long it took to acquire as the distribution `<stat>.wait`,
+    /// and how long it was held as `<stat>.hold`, in ms, when the guard is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::sync::Mutex;
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let connections = Mutex::new(vec![1, 2]);
+    ///   let mut guard = client.timed_lock(&connections, "db.pool.lock", &["tag:lock"]).unwrap();
+    ///   guard.push(3);
+    /// ```
+    pub fn timed_lock<'a, T, I, S>(
+        &'a self,
+        mutex: &'a Mutex<T>,
+        stat: &str,
+        tags: I,
+    ) -> LockResult<TimedGuard<'a, MutexGuard<'a, T>>>
+    where
+        T: ?Sized,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        timed_lock::timed(self, stat, tags, || mutex.lock())
+    }
+
+    /// Like `timed_lock`, but takes a read lock on `lock`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::sync::RwLock;
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let config = RwLock::new(String::from("config"));
+    ///   let guard = client.timed_read(&config, "config.lock", &["tag:lock"]).unwrap();
+    ///   assert_eq!("config", *guard);
+    /// ```
+    pub fn timed_read<'a, T, I, S>(
+        &'a self,
+        lock: &'a RwLock<T>,
+        stat: &str,
+        tags: I,
+    ) -> LockResult<TimedGuard<'a, RwLockReadGuard<'a, T>>>
+    where
+        T: ?Sized,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        timed_lock::timed(self, stat, tags, || lock.read())
+    }
+
+    /// Like `timed_lock`, but takes a write lock on `lock`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///   use std::sync::RwLock;
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let config = RwLock::new(String::from("config"));
+    ///   *client.timed_write(&config, "config.lock", &["tag:lock"]).unwrap() = String::from("new");
+    /// ```
+    pub fn timed_write<'a, T, I, S>(
+        &'a self,
+        lock: &'a RwLock<T>,
+        stat: &str,
+        tags: I,
+    ) -> LockResult<TimedGuard<'a, RwLockWriteGuard<'a, T>>>
+    where
+        T: ?Sized,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        timed_lock::timed(self, stat, tags, || lock.write())
+    }
+
+    /// Report a value in a histogram
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.histogram("histogram", "67890", &["tag:histogram"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn histogram<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.histogram_with_outcome(stat, val, tags).map(|_| ())
+    }
+
+    /// Like `histogram`, but also returns whether the metric was sent, or was left out by
+    /// sampling or dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.histogram_with_outcome("histogram", "67890", &["tag:histogram"])
+    ///       .unwrap_or_else(|e| panic!("Encountered error: {}", e));
+    /// ```
+    pub fn histogram_with_outcome<'a, I, S, SS, T>(
+        &self,
+        stat: S,
+        val: SS,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send_with_outcome(
+            &HistogramMetric::new(stat.into().as_ref(), val.into().as_ref()),
+            tags,
+        )
+    }
+
+    /// Report a value in a distribution
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.distribution("distribution", "67890", &["tag:distribution"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn distribution<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.distribution_with_outcome(stat, val, tags).map(|_| ())
+    }
+
+    /// Like `distribution`, but also returns whether the metric was sent, or was left out by
+    /// sampling or dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.distribution_with_outcome("distribution", "67890", &["tag:distribution"])
+    ///       .unwrap_or_else(|e| panic!("Encountered error: {}", e));
+    /// ```
+    pub fn distribution_with_outcome<'a, I, S, SS, T>(
+        &self,
+        stat: S,
+        val: SS,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let stat = stat.into();
+        let val = val.into();
+        self.send_with_outcome(&DistributionMetric::new(stat.as_ref(), val.as_ref()), tags)
+    }
+
+    /// Report pre-aggregated values of a distribution, each as a `(value, count)` pair. Values
+    /// with the same count are packed into as few messages as fit in
+    /// `Options::max_payload_size`, sent with a sample rate of `1 / count` so the agent counts
+    /// each of them `count` times. Pairs with a count of 0 are skipped. Messages are only ever
+    /// split between values, counted in `ClientStats::packed_splits`, and if one fails to send
+    /// the rest are still sent before its error is returned.
+    ///
+    /// Messages with several values need version 7.25 or later of the agent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.distribution_sketch("latency", &[(12.5, 40), (30.0, 3), (85.0, 3)], &["tag:sketch"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn distribution_sketch<'a, I, S, T>(
+        &self,
+        stat: S,
+        samples: &[(f64, u32)],
+        tags: I,
+    ) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let stat = stat.into();
+        let tags: Vec<String> = tags
+            .into_iter()
+            .map(|tag| tag.as_ref().to_owned())
+            .collect();
+
+        let mut counts: Vec<u32> = samples
+            .iter()
+            .map(|(_, count)| *count)
+            .filter(|count| *count > 0)
+            .collect();
+        counts.sort_unstable();
+        counts.dedup();
+
+        let mut result = Ok(());
+        let mut keep_first_error = |sent: DogstatsdResult| {
+            if result.is_ok() {
+                result = sent;
+            }
+        };
+
+        for count in counts {
+            let sample_rate = 1.0 / f64::from(count);
+            // Without room for even one value, each is sent on its own and rejected or
+            // truncated like any other oversized metric.
+            let max_vals_len = match self.format(
+                &PackedDistributionMetric::new(&stat, "", sample_rate),
+                &tags,
+            ) {
+                Ok(overhead) => self.max_payload_size.saturating_sub(overhead.len()),
+                Err(_) => 0,
+            };
+
+            let mut vals = String::new();
+            for (value, _) in samples.iter().filter(|(_, c)| *c == count) {
+                let value = self.format_float(*value);
+                if !vals.is_empty() && vals.len() + 1 + value.len() > max_vals_len {
+                    self.writer.stats.record_packed_split();
+                    keep_first_error(self.send(
+                        &PackedDistributionMetric::new(&stat, &vals, sample_rate),
+                        &tags,
+                    ));
+                    vals.clear();
+                }
+                if !vals.is_empty() {
+                    vals.push(':');
+                }
+                vals.push_str(&value);
+            }
+            keep_first_error(self.send(
+                &PackedDistributionMetric::new(&stat, &vals, sample_rate),
+                &tags,
+            ));
+        }
+        result
+    }
+
+    /// Report a value in a set
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.set("set", "13579", &["tag:set"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn set<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.set_with_outcome(stat, val, tags).map(|_| ())
+    }
+
+    /// Like `set`, but also returns whether the metric was sent, or was left out by sampling
+    /// or dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.set_with_outcome("set", "13579", &["tag:set"])
+    ///       .unwrap_or_else(|e| panic!("Encountered error: {}", e));
+    /// ```
+    pub fn set_with_outcome<'a, I, S, SS, T>(
+        &self,
+        stat: S,
+        val: SS,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send_with_outcome(
+            &SetMetric::new(stat.into().as_ref(), val.into().as_ref()),
+            tags,
+        )
+    }
+
+    /// Report the status of a service
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options, ServiceStatus, ServiceCheckOptions};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.service_check("redis.can_connect", ServiceStatus::OK, &["tag:service"], None)
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///
+    ///   let options = ServiceCheckOptions {
+    ///     hostname: Some("my-host.localhost"),
+    ///     ..Default::default()
+    ///   };
+    ///   client.service_check("redis.can_connect", ServiceStatus::OK, &["tag:service"], Some(options))
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///
+    ///   let all_options = ServiceCheckOptions {
+    ///     hostname: Some("my-host.localhost"),
+    ///     timestamp: Some(1510326433),
+    ///     message: Some("Message about the check or service")
+    ///   };
+    ///   client.service_check("redis.can_connect", ServiceStatus::OK, &["tag:service"], Some(all_options))
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn service_check<'a, I, S, T>(
+        &self,
+        stat: S,
+        val: ServiceStatus,
+        tags: I,
+        options: Option<ServiceCheckOptions>,
+    ) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let unwrapped_options = options.unwrap_or_default();
+        self.send(
+            &ServiceCheck::new(stat.into().as_ref(), val, unwrapped_options),
+            tags,
+        )
+    }
+
+    /// Send a custom event as a title and a body
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.event("Event Title", "Event Body", &["tag:event"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    ///
+    /// ```
+    pub fn event<'a, I, S, SS, T>(&self, title: S, text: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.event_with_outcome(title, text, tags).map(|_| ())
+    }
+
+    /// Like `event`, but also returns whether the event was sent, or was left out by sampling
+    /// or dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.event_with_outcome("Event Title", "Event Body", &["tag:event"])
+    ///       .unwrap_or_else(|e| panic!("Encountered error: {}", e));
+    /// ```
+    pub fn event_with_outcome<'a, I, S, SS, T>(
+        &self,
+        title: S,
+        text: SS,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        let title = title.into();
+        let text = text.into();
+        let aggregation_key = self.generate_aggregation_key(&title, &text);
+        let mut event = Event::new(title.as_ref(), text.as_ref());
+        if let Some(aggregation_key) = &aggregation_key {
+            event = event.with_aggregation_key(aggregation_key);
+        }
+
+        self.send_with_outcome(&event, tags)
+    }
+
+    /// Send a custom event as a title and a body
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options, EventOptions, EventAlertType, EventPriority};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let event_options = EventOptions::new()
+    ///     .with_timestamp(1638480000)
+    ///     .with_hostname("localhost")
+    ///     .with_priority(EventPriority::Normal)
+    ///     .with_alert_type(EventAlertType::Error);
+    ///   client.event_with_options("My Custom Event Title", "My Custom Event Body", &["tag:event"], Some(event_options))
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn event_with_options<'a, I, S, SS, T>(
+        &self,
+        title: S,
         text: SS,
         tags: I,
         options: Option<EventOptions<'a>>,
@@ -917,385 +3932,4401 @@ impl Client {
         let title_owned = title.into();
         let text_owned = text.into();
         let mut event = Event::new(title_owned.as_ref(), text_owned.as_ref());
+        let generated_aggregation_key = match options.as_ref().and_then(|o| o.aggregation_key) {
+            Some(_) => None,
+            None => self.generate_aggregation_key(&title_owned, &text_owned),
+        };
+        if let Some(aggregation_key) = &generated_aggregation_key {
+            event = event.with_aggregation_key(aggregation_key);
+        }
+
+        // Apply additional options if provided
+        if let Some(options) = options {
+            if let Some(timestamp) = options.timestamp {
+                event = event.with_timestamp(timestamp);
+            }
+            if let Some(hostname) = options.hostname {
+                event = event.with_hostname(hostname);
+            }
+            if let Some(aggregation_key) = options.aggregation_key {
+                event = event.with_aggregation_key(aggregation_key);
+            }
+            if let Some(priority) = options.priority {
+                event = event.with_priority(priority);
+            }
+            if let Some(source_type_name) = options.source_type_name {
+                event = event.with_source_type_name(source_type_name);
+            }
+            if let Some(alert_type) = options.alert_type {
+                event = event.with_alert_type(alert_type);
+            }
+        }
+
+        self.send(&event, tags)
+    }
+
+    /// Like `event`, but with a title and body that are already encoded, such as log excerpts,
+    /// which are copied into the payload as they are, without checking they're valid UTF-8.
+    /// With `Options::encoder` they're checked, since only the DogStatsD format can be written
+    /// from bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let excerpt: &[u8] = b"connection reset by peer";
+    ///   client.event_bytes(b"Worker crashed", excerpt, &["tag:event"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn event_bytes<I, T>(&self, title: &[u8], text: &[u8], tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        if self.encoder.is_some() {
+            return match (std::str::from_utf8(title), std::str::from_utf8(text)) {
+                (Ok(title), Ok(text)) => self.event(title, text, tags),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "events sent with a custom encoder must be valid UTF-8",
+                )
+                .into()),
+            };
+        }
+        if self.writer.is_null() {
+            return Ok(());
+        }
+
+        let aggregation_key = match &self.aggregation_key_generator {
+            Some(_) => self.generate_aggregation_key(
+                &String::from_utf8_lossy(title),
+                &String::from_utf8_lossy(text),
+            ),
+            None => None,
+        };
+        let mut event = Event::new("", "");
+        if let Some(aggregation_key) = &aggregation_key {
+            event = event.with_aggregation_key(aggregation_key);
+        }
+        // Formatted with an empty title and body, which the real ones are written in place of,
+        // so tags are handled like any other event's.
+        let empty = self.format(&event, tags)?;
+        let empty_header = format_untagged(&Event::new("", ""), "");
+        let header = format!("_e{{{},{}}}:", title.len(), text.len());
+        let mut formatted =
+            Vec::with_capacity(header.len() + title.len() + 1 + text.len() + empty.len());
+        formatted.extend_from_slice(header.as_bytes());
+        formatted.extend_from_slice(title);
+        formatted.push(b'|');
+        formatted.extend_from_slice(text);
+        formatted.extend_from_slice(&empty[empty_header.len()..]);
+
+        if formatted.len() > self.max_payload_size {
+            let untagged_size = formatted.len() - empty.len() + format_untagged(&event, "").len();
+            formatted = self.shrink_oversized(formatted, untagged_size)?;
+        }
+        self.dispatch(formatted).map(|_| ())
+    }
+
+    /// Send an error event whose text is `error` and its chain of sources, tagged with the
+    /// error's type name without its module path, such as `error_type:ParseIntError`, and
+    /// increment the `errors` counter with the same tags.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let error = std::io::Error::other("connection reset");
+    ///   client.error_event("payment failed", &error, &["env:production"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn error_event<E, I, T>(&self, title: &str, error: &E, tags: I) -> DogstatsdResult
+    where
+        E: std::error::Error + ?Sized,
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let text = error_event_text(error);
+        let tags: Vec<String> = tags
+            .into_iter()
+            .map(|tag| tag.as_ref().to_owned())
+            .chain([format!(
+                "error_type:{}",
+                error_type_name(std::any::type_name::<E>())
+            )])
+            .collect();
+
+        let aggregation_key = self.generate_aggregation_key(title, &text);
+        let mut event = Event::new(title, &text).with_alert_type(EventAlertType::Error);
+        if let Some(aggregation_key) = &aggregation_key {
+            event = event.with_aggregation_key(aggregation_key);
+        }
+
+        self.send(&event, &tags)?;
+        self.send(&CountMetric::Incr("errors", 1), &tags)
+    }
+
+    /// Send a standard deployment event for `service` at `version`, tagged with `service` and
+    /// `version` and aggregated per service, so every deploy marker looks the same.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, Options};
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   client.deploy_event("checkout", "v1.4.2", &["env:production"])
+    ///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+    /// ```
+    pub fn deploy_event<I, T>(&self, service: &str, version: &str, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let title = format!("Deployed {} {}", service, version);
+        let text = format!("{} was deployed at version {}", service, version);
+        let aggregation_key = format!("deploy:{}", service);
+        let event = Event::new(&title, &text)
+            .with_aggregation_key(&aggregation_key)
+            .with_source_type_name("deployment")
+            .with_priority(EventPriority::Normal)
+            .with_alert_type(EventAlertType::Info);
+
+        let deploy_tags = [
+            format!("service:{}", service),
+            format!("version:{}", version),
+        ];
+        self.send(
+            &event,
+            tags.into_iter()
+                .map(|tag| tag.as_ref().to_owned())
+                .chain(deploy_tags),
+        )
+    }
+
+    fn send<I, M, S>(&self, metric: &M, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = S>,
+        M: Metric,
+        S: AsRef<str>,
+    {
+        self.send_with_outcome(metric, tags).map(|_| ())
+    }
+
+    fn send_with_outcome<I, M, S>(&self, metric: &M, tags: I) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = S>,
+        M: Metric,
+        S: AsRef<str>,
+    {
+        if self.writer.is_null() {
+            return Ok(SendOutcome::Dropped);
+        }
+        let formatted_metric = self.format(metric, tags)?;
+        self.dispatch(formatted_metric)
+    }
+
+    // Applies sampling, the rate limit and route hook to a formatted metric, and sends it.
+    // The drop counters are reported whatever happened to the metric, so drops are reported
+    // even when every metric is being sampled or rate limited.
+    fn dispatch(&self, formatted_metric: Vec<u8>) -> Result<SendOutcome, DogstatsdError> {
+        let outcome = self.dispatch_metric(formatted_metric);
+        self.report_stats_if_due();
+        outcome
+    }
+
+    fn dispatch_metric(&self, formatted_metric: Vec<u8>) -> Result<SendOutcome, DogstatsdError> {
+        let formatted_metric = match &self.sampler {
+            Some(sampler) => {
+                if let Some(adjustment) = sampler.adjust_if_due(&self.writer.stats) {
+                    self.writer.report_error("Adjusted sample rate", adjustment);
+                }
+                match sampler.sample(formatted_metric) {
+                    Some(formatted_metric) => formatted_metric,
+                    None => {
+                        self.writer.stats.record_drop(DropReason::Sampled, 1);
+                        return Ok(SendOutcome::Sampled);
+                    }
+                }
+            }
+            None => formatted_metric,
+        };
+        let formatted_metric = if self.sample_rate_correction {
+            correct_sample_rate(formatted_metric)
+        } else {
+            formatted_metric
+        };
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if !rate_limiter.allow(&formatted_metric) {
+                self.writer.stats.record_drop(DropReason::RateLimited, 1);
+                return Ok(SendOutcome::Dropped);
+            }
+        }
+
+        match &self.router {
+            Some(router) => self.send_routed(router, formatted_metric),
+            None => {
+                self.send_formatted(formatted_metric)?;
+                Ok(SendOutcome::Sent)
+            }
+        }
+    }
+
+    // Sends a metric whose value is `ms`, and its SLO bucket counter if there are buckets and
+    // the metric was sent, returning the metric's outcome.
+    fn send_timed<I, M, S>(
+        &self,
+        metric: &M,
+        stat: &str,
+        ms: f64,
+        tags: I,
+    ) -> Result<SendOutcome, DogstatsdError>
+    where
+        I: IntoIterator<Item = S>,
+        M: Metric,
+        S: AsRef<str>,
+    {
+        if self.slo_buckets.is_empty() {
+            return self.send_with_outcome(metric, tags);
+        }
+
+        let tags: Vec<String> = tags
+            .into_iter()
+            .map(|tag| tag.as_ref().to_owned())
+            .collect();
+        let outcome = self.send_with_outcome(metric, &tags)?;
+        if outcome != SendOutcome::Sent {
+            return Ok(outcome);
+        }
+
+        let bucket = self
+            .slo_buckets
+            .iter()
+            .find(|bucket| ms <= bucket.as_secs_f64() * 1000.0)
+            .map_or_else(
+                || String::from("le:inf"),
+                |bucket| format!("le:{:?}", bucket),
+            );
+        self.send(
+            &CountMetric::Incr(&format!("{}.slo_bucket", stat), 1),
+            tags.iter().map(String::as_str).chain([bucket.as_str()]),
+        )?;
+        Ok(outcome)
+    }
+
+    fn send_routed(
+        &self,
+        router: &Router,
+        formatted_metric: Vec<u8>,
+    ) -> Result<SendOutcome, DogstatsdError> {
+        match router.route(&formatted_metric) {
+            RouteDecision::Send => self.send_formatted(formatted_metric)?,
+            RouteDecision::Drop => {
+                self.writer.stats.record_drop(DropReason::Filtered, 1);
+                return Ok(SendOutcome::Dropped);
+            }
+            RouteDecision::Redirect(addr) => router.send_to(&addr, &formatted_metric)?,
+            RouteDecision::Duplicate(addr) => {
+                // The copy is best effort, it mustn't fail the real send.
+                if let Err(error) = router.send_to(&addr, &formatted_metric) {
+                    self.writer
+                        .report_error("Failed to send duplicated metric", error.into());
+                }
+                self.send_formatted(formatted_metric)?
+            }
+        }
+        Ok(SendOutcome::Sent)
+    }
+
+    fn format<I, M, S>(&self, metric: &M, tags: I) -> Result<Vec<u8>, DogstatsdError>
+    where
+        I: IntoIterator<Item = S>,
+        M: Metric,
+        S: AsRef<str>,
+    {
+        if let Some(reserved_tags) = &self.reserved_tags {
+            // Checked as the tags are formatted, only copying a reserved one for the error. A
+            // rejected metric's payload is thrown away.
+            let mut reserved = None;
+            let tags = tags.into_iter().inspect(|tag| {
+                if reserved.is_none() && reserved_tags.is_reserved(tag.as_ref()) {
+                    reserved = Some(tag.as_ref().to_owned());
+                }
+            });
+            let formatted_metric = self.format_traced(metric, tags);
+            if let Some(tag) = reserved {
+                let error = DogstatsdError::ReservedTag(tag);
+                match reserved_tags.action {
+                    ReservedTagAction::Reject => {
+                        self.writer.stats.record_drop(DropReason::Invalid, 1);
+                        return Err(error);
+                    }
+                    ReservedTagAction::Warn => self
+                        .writer
+                        .report_error("Sent a metric with a reserved tag", error),
+                }
+            }
+            return formatted_metric;
+        }
+
+        self.format_traced(metric, tags)
+    }
+
+    fn format_traced<I, M, S>(&self, metric: &M, tags: I) -> Result<Vec<u8>, DogstatsdError>
+    where
+        I: IntoIterator<Item = S>,
+        M: Metric,
+        S: AsRef<str>,
+    {
+        let thread_tag = self.thread_tag.then(thread_tag).flatten();
+        #[cfg(feature = "trace-context")]
+        let trace_tags = TraceContext::current().map(|context| context.tags());
+        #[cfg(not(feature = "trace-context"))]
+        let trace_tags: Option<[String; 2]> = None;
+
+        let tags = tags
+            .into_iter()
+            .map(Tag::Given)
+            .chain(trace_tags.into_iter().flatten().map(Tag::Added))
+            .chain(thread_tag.map(Tag::Added));
+        self.format_tagged(metric, tags)
+    }
+
+    fn format_tagged<I, M, S>(&self, metric: &M, tags: I) -> Result<Vec<u8>, DogstatsdError>
+    where
+        I: IntoIterator<Item = S>,
+        M: Metric,
+        S: AsRef<str>,
+    {
+        let untagged = self.format_untagged(metric);
+        let untagged_size = untagged.len();
+        // Events and service checks are the only kinds that don't use the namespace.
+        let is_metric = metric.uses_namespace();
+        // Drops whole tags from the end once they'd take more than `max_tags_bytes` along with
+        // the default tags, measuring them as prefixed without building them.
+        let mut budget = self
+            .max_tags_bytes
+            .map(|max_tags_bytes| TagBudget::new(self.default_tags.as_bytes(), max_tags_bytes));
+        let prefix = self.tag_prefix.as_deref();
+        let tags = tags.into_iter().filter(|tag| {
+            budget
+                .as_mut()
+                .is_none_or(|budget| budget.fits(prefixed_len(prefix, tag.as_ref())))
+        });
+        let formatted_metric = match prefix {
+            Some(prefix) => encode_for_send(
+                self.encoder(),
+                untagged,
+                is_metric,
+                tags.map(|tag| prefix_tag(Some(prefix), tag.as_ref()).into_owned()),
+                &self.default_tags,
+            ),
+            None => encode_for_send(
+                self.encoder(),
+                untagged,
+                is_metric,
+                tags,
+                &self.default_tags,
+            ),
+        };
+        if let Some((size, max_size)) =
+            budget.and_then(|budget| budget.exceeded().zip(self.max_tags_bytes))
+        {
+            self.writer.stats.record_tag_truncation();
+            self.writer
+                .report_to_handler(|| DogstatsdError::TagsTooLarge {
+                    metric: metric_name(self.format_untagged(metric).as_bytes()),
+                    size,
+                    max_size,
+                });
+        }
+
+        if formatted_metric.len() <= self.max_payload_size {
+            return Ok(formatted_metric);
+        }
+        self.shrink_oversized(formatted_metric, untagged_size)
+    }
+
+    // The metric in the DogStatsD format without its tags, with the namespace.
+    fn format_untagged<M: Metric>(&self, metric: &M) -> String {
+        let untagged = format_untagged(metric, &self.namespace);
+        if self.normalize_metric_names {
+            normalize_formatted(untagged)
+        } else {
+            untagged
+        }
+    }
+
+    fn encoder(&self) -> Option<&dyn Encoder> {
+        self.encoder.as_ref().map(SharedEncoder::get)
+    }
+
+    // Tags can only be truncated in the DogStatsD format, where `untagged_size` is the size of
+    // the payload before them.
+    fn shrink_oversized(
+        &self,
+        formatted_metric: Vec<u8>,
+        untagged_size: usize,
+    ) -> Result<Vec<u8>, DogstatsdError> {
+        let error = || DogstatsdError::PayloadTooLarge {
+            metric: metric_name(&formatted_metric),
+            size: formatted_metric.len(),
+            max_size: self.max_payload_size,
+        };
+
+        if self.oversized_metric_policy == OversizedMetricPolicy::TruncateTags
+            && self.encoder.is_none()
+        {
+            if let Some(truncated) = truncate_tags(
+                &formatted_metric,
+                untagged_size,
+                self.default_tags.as_bytes(),
+                self.max_payload_size,
+            ) {
+                self.writer.stats.record_oversized_truncation();
+                self.writer.report_to_handler(error);
+                return Ok(truncated);
+            }
+        }
+
+        self.writer.stats.record_drop(DropReason::Invalid, 1);
+        Err(error())
+    }
+
+    // Sends a metric formatted elsewhere, which can only be rejected if it's too large.
+    #[cfg(feature = "compat")]
+    pub(crate) fn send_payload(&self, payload: Vec<u8>) -> DogstatsdResult {
+        if payload.len() > self.max_payload_size {
+            self.writer.stats.record_drop(DropReason::Invalid, 1);
+            return Err(DogstatsdError::PayloadTooLarge {
+                metric: metric_name(&payload),
+                size: payload.len(),
+                max_size: self.max_payload_size,
+            });
+        }
+
+        self.dispatch(payload).map(|_| ())
+    }
+
+    fn send_formatted(&self, formatted_metric: Vec<u8>) -> DogstatsdResult {
+        self.writer.send(formatted_metric)
+    }
+
+    fn generate_aggregation_key(&self, title: &str, text: &str) -> Option<String> {
+        self.aggregation_key_generator
+            .as_ref()
+            .and_then(|generator| generator.generate(title, text))
+    }
+
+    // Drop counters are reported as deltas on the first send after the interval has passed,
+    // the same way the batch processor flushes on the first message after `max_time`. A
+    // failed report goes to the error handler rather than failing the send, and the counts it
+    // didn't get to are reported with the next one.
+    fn report_stats_if_due(&self) {
+        let interval = match self.stats_report_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let mut last_report = lock(&self.last_stats_report);
+        if last_report.0.elapsed() < interval {
+            return;
+        }
+        last_report.0 = Instant::now();
+        let current = self.writer.stats.snapshot();
+
+        for (reason, count) in current.dropped_since(&last_report.1) {
+            let tag = format!("reason:{}", reason.as_str());
+            let metric =
+                CountMetric::Arbitrary("datadog.dogstatsd.client.metrics_dropped", count as i64);
+            let sent = self.send_formatted(format_with_encoder(
+                &metric,
+                "",
+                [tag.as_str()]
+                    .into_iter()
+                    .chain(self.telemetry_tags.iter().map(String::as_str)),
+                self.encoder(),
+                &self.default_tags,
+            ));
+            if let Err(error) = sent {
+                self.writer
+                    .report_error("Failed to report client stats", error);
+                return;
+            }
+            *last_report.1.dropped_mut(reason) = current.dropped(reason);
+        }
+    }
+
+    // Sends `Options::config_banner`'s gauge, without the namespace, like the client's other
+    // telemetry.
+    fn send_config_banner(&self, config_hash: &str) -> DogstatsdResult {
+        let tag = format!("config_hash:{}", config_hash);
+        let metric = GaugeMetric::new("datadog.dogstatsd.client.config", "1");
+        self.send_formatted(format_with_encoder(
+            &metric,
+            "",
+            [tag.as_str()]
+                .into_iter()
+                .chain(self.telemetry_tags.iter().map(String::as_str)),
+            self.encoder(),
+            &self.default_tags,
+        ))
+    }
+}
+
+// The last segment of an error's type name, without generic parameters or trait object
+// bounds, and with the characters that would split a tag replaced.
+fn error_type_name(type_name: &str) -> String {
+    let name = type_name
+        .split(['<', '+'])
+        .next()
+        .unwrap_or_default()
+        .trim();
+    let name = name.rsplit("::").next().unwrap_or_default();
+    name.replace([',', '|', '#'], "_")
+}
+
+// The error and each of its sources on their own line, with newlines escaped the way events
+// expect, and truncated to what Datadog accepts.
+fn error_event_text<E: std::error::Error + ?Sized>(error: &E) -> String {
+    let mut text = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        text.push_str("\ncaused by: ");
+        text.push_str(&error.to_string());
+        source = error.source();
+    }
+    let mut text = text.replace('\n', "\\n");
+
+    if text.len() > MAX_EVENT_TEXT_LEN {
+        let mut end = MAX_EVENT_TEXT_LEN - 3;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...");
+    }
+    text
+}
+
+/// Configuration options for an `Event`.
+///
+/// `EventOptions` provides additional optional metadata that can be attached
+/// to an event, enabling greater flexibility and contextual information
+/// for event handling and monitoring systems.
+///
+/// # Example
+///
+/// ```rust
+/// use dogstatsd::{EventOptions, EventAlertType, EventPriority};
+///
+/// let options = EventOptions {
+///     timestamp: Some(1638480000),
+///     hostname: Some("localhost"),
+///     aggregation_key: Some("service_down"),
+///     priority: Some(EventPriority::Normal),
+///     source_type_name: Some("monitoring"),
+///     alert_type: Some(EventAlertType::Error),
+/// };
+/// ```
+///
+#[derive(Default, Clone, Copy, Debug)]
+pub struct EventOptions<'a> {
+    /// Optional Unix timestamp representing the event time. The default is the current Unix epoch timestamp.
+    pub timestamp: Option<u64>,
+    /// Optional hostname associated with the event.
+    pub hostname: Option<&'a str>,
+    /// Optional key for grouping related events.
+    pub aggregation_key: Option<&'a str>,
+    /// Optional priority level of the event, e.g., `"low"` or `"normal"`.
+    pub priority: Option<EventPriority>,
+    /// Optional source type name of the event, e.g., `"monitoring"`.
+    pub source_type_name: Option<&'a str>,
+    /// Optional alert type for the event, e.g., `"error"`, `"warning"`,  `"info"`, `"success"`. Default `"info"`.
+    pub alert_type: Option<EventAlertType>,
+}
+
+impl<'a> EventOptions<'a> {
+    /// Creates a new `EventOptions` instance with all fields set to `None`.
+    pub fn new() -> Self {
+        EventOptions {
+            timestamp: None,
+            hostname: None,
+            aggregation_key: None,
+            priority: None,
+            source_type_name: None,
+            alert_type: None,
+        }
+    }
+    /// Sets the `hostname` for the event.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the `hostname` for the event.
+    pub fn with_hostname(mut self, hostname: &'a str) -> Self {
+        self.hostname = Some(hostname);
+        self
+    }
+
+    /// Sets the `aggregation_key` for the event.
+    pub fn with_aggregation_key(mut self, aggregation_key: &'a str) -> Self {
+        self.aggregation_key = Some(aggregation_key);
+        self
+    }
+
+    /// Sets the `priority` for the event.
+    pub fn with_priority(mut self, priority: EventPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Sets the `source_type_name` for the event.
+    pub fn with_source_type_name(mut self, source_type_name: &'a str) -> Self {
+        self.source_type_name = Some(source_type_name);
+        self
+    }
+
+    /// Sets the `alert_type` for the event.
+    pub fn with_alert_type(mut self, alert_type: EventAlertType) -> Self {
+        self.alert_type = Some(alert_type);
+        self
+    }
+}
+
+/// Overrides of the client's settings for some metrics, applied through
+/// `Client::with_metric_options`.
+///
+/// # Example
+///
+/// ```rust
+/// use dogstatsd::MetricOptions;
+///
+/// let options = MetricOptions::new().with_skip_default_tags(true);
+/// let remote = MetricOptions::new().with_host("switch-01.dc1");
+/// ```
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct MetricOptions {
+    /// Send the metrics without the client's default tags, including those from `DD_ENV`,
+    /// `DD_SERVICE` and `DD_VERSION`, such as for counters aggregated across every host.
+    pub skip_default_tags: bool,
+    /// Send the metrics on behalf of this host, such as for a collector reporting a remote
+    /// device's metrics, with a `host:` tag that replaces any in the default tags.
+    pub host: Option<String>,
+}
+
+impl MetricOptions {
+    /// Creates a new `MetricOptions` that changes nothing.
+    pub fn new() -> Self {
+        MetricOptions::default()
+    }
+
+    /// Sets `skip_default_tags`.
+    pub fn with_skip_default_tags(mut self, skip_default_tags: bool) -> Self {
+        self.skip_default_tags = skip_default_tags;
+        self
+    }
+
+    /// Sets `host`.
+    pub fn with_host<T: Into<String>>(mut self, host: T) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+}
+
+// Packs already formatted lines into payloads for `Client::send_batch_raw`, sending each as it
+// fills up, or hands them to the batch processor one at a time with batching. Keeps the first
+// error for `finish`.
+struct RawPacker<'c> {
+    client: &'c Client,
+    batched: bool,
+    packed: Vec<u8>,
+    result: DogstatsdResult,
+}
+
+impl<'c> RawPacker<'c> {
+    fn new(client: &'c Client) -> Self {
+        RawPacker {
+            client,
+            batched: client.writer.is_batched(),
+            packed: Vec::with_capacity(client.max_payload_size),
+            result: Ok(()),
+        }
+    }
+
+    fn push(&mut self, line: &[u8]) {
+        let max_payload_size = self.client.max_payload_size;
+        if line.is_empty() {
+            return;
+        }
+        if line.len() > max_payload_size {
+            self.client.writer.stats.record_drop(DropReason::Invalid, 1);
+            self.keep_first_error(Err(DogstatsdError::PayloadTooLarge {
+                metric: metric_name(line),
+                size: line.len(),
+                max_size: max_payload_size,
+            }));
+            return;
+        }
+        if self.batched {
+            let sent = self.client.writer.send(line.to_vec());
+            self.keep_first_error(sent);
+            return;
+        }
+
+        if !self.packed.is_empty() && self.packed.len() + 1 + line.len() > max_payload_size {
+            let sent = self.client.writer.send(std::mem::take(&mut self.packed));
+            self.keep_first_error(sent);
+        }
+        if !self.packed.is_empty() {
+            self.packed.push(b'\n');
+        }
+        self.packed.extend_from_slice(line);
+    }
+
+    // Sends what's left, returning the first error sending any of the lines.
+    fn finish(mut self) -> DogstatsdResult {
+        if !self.packed.is_empty() {
+            let sent = self.client.writer.send(std::mem::take(&mut self.packed));
+            self.keep_first_error(sent);
+        }
+        self.result
+    }
+
+    fn keep_first_error(&mut self, sent: DogstatsdResult) {
+        if self.result.is_ok() {
+            self.result = sent;
+        }
+    }
+}
+
+/// What happened to a metric sent with one of the `_with_outcome` methods, such as
+/// `Client::count_with_outcome`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SendOutcome {
+    /// The metric was written to the socket, or handed to the batch processor.
+    Sent,
+    /// The metric wasn't selected by adaptive sampling.
+    Sampled,
+    /// The metric was dropped by the tag rate limit or the route hook, or by a client from
+    /// `Client::noop`.
+    Dropped,
+    /// The gauge was added to its series' `Options::gauge_rollup` window, to be sent with the
+    /// rest of the window.
+    RolledUp,
+}
+
+mod batch_processor {
+    use std::collections::VecDeque;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::mpsc::{Receiver, Sender};
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use retry::{delay::jitter, delay::Exponential, retry, OperationResult};
+
+    use crate::completion::Completer;
+    use crate::destination::Destination;
+    use crate::error::report_error;
+    #[cfg(target_os = "linux")]
+    use crate::mmsg::{send_many, MAX_DATAGRAMS_PER_SEND};
+    use crate::reconnect::Reconnector;
+    use crate::stats::Stats;
+    use crate::transport::{drops_would_block, is_message_too_long, send_uds};
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    use crate::uring::UringSender;
+    use crate::watchdog::Heartbeat;
+    use crate::{
+        BatchingOptions, DogstatsdError, DropReason, ErrorHandler, SocketType, WouldBlockPolicy,
+    };
+
+    #[derive(Debug)]
+    pub(crate) enum Message {
+        Data(Vec<u8>),
+        // Moves a `Clock::Manual` forward, in order with the metrics around it.
+        #[cfg(any(test, feature = "testing"))]
+        Advance(std::time::Duration),
+        // Sends the buffer now, replying with the number of bytes and metrics sent.
+        Flush(Sender<(usize, u64)>),
+        // Sends the buffer now, resolving the completion once it's sent.
+        Completion(Completer),
+        // Sends to the new primary address, as resolved, from now on.
+        SetDestination(String, SocketAddr),
+        // Only updates the heartbeat.
+        Ping,
+        Shutdown,
+    }
+
+    // Where the batch processor gets the time from when deciding whether `max_time` has passed.
+    #[derive(Clone, Copy)]
+    pub(crate) enum Clock {
+        System,
+        #[cfg(any(test, feature = "testing"))]
+        Manual(SystemTime),
+    }
+
+    impl Clock {
+        fn now(&self) -> SystemTime {
+            match self {
+                Clock::System => SystemTime::now(),
+                #[cfg(any(test, feature = "testing"))]
+                Clock::Manual(now) => *now,
+            }
+        }
+    }
+
+    fn send_to_socket_with_retries(
+        batching_options: &BatchingOptions,
+        socket: &SocketType,
+        data: &[u8],
+        destination: &Destination,
+        socket_path: &Option<String>,
+        would_block_policy: Option<WouldBlockPolicy>,
+        stats: &Stats,
+    ) -> io::Result<()> {
+        // Each udp target is retried on its own, so only the ones that failed are sent to again.
+        let mut pending: Vec<usize> = (0..destination.targets()).collect();
+        retry(
+            Exponential::from_millis(batching_options.initial_retry_delay)
+                .map(jitter)
+                .take(batching_options.max_retry_attempts),
+            || {
+                let result = stats.time_send(|| {
+                    match socket {
+                        SocketType::Udp(socket) => {
+                            let mut last_error = None;
+                            pending.retain(|&target| {
+                                match destination.send_to_target(socket, data, target) {
+                                    Ok(_) => false,
+                                    Err(error) => {
+                                        last_error = Some(error);
+                                        true
+                                    }
+                                }
+                            });
+                            if let Some(error) = last_error {
+                                return Err(error);
+                            }
+                        }
+                        SocketType::Uds(socket) => {
+                            if let Err(error) = send_uds(socket, data, would_block_policy) {
+                                // Retrying would only send what the policy says to drop.
+                                if drops_would_block(&error, would_block_policy) {
+                                    return Err(error);
+                                }
+                                // Per https://doc.rust-lang.org/stable/std/os/unix/net/struct.UnixDatagram.html#method.send
+                                // If send fails, it is due to a connection issue, so just attempt
+                                // to reconnect
+                                if let Some(socket_path) = socket_path {
+                                    socket.connect(socket_path)?;
+                                }
+
+                                return Err(error);
+                            }
+                        }
+                        // Batches already have a newline after every metric.
+                        SocketType::UdsFallback(socket) => socket.send(data)?,
+                        SocketType::Tcp(connection) => connection.send(data)?,
+                        SocketType::File(sink) => sink.send(data)?,
+                        SocketType::Null => {}
+                        // Opened before each flush.
+                        SocketType::Unopened(_) => {
+                            return Err(io::Error::from(io::ErrorKind::NotConnected))
+                        }
+                        #[cfg(any(test, feature = "testing"))]
+                        SocketType::Capture(sink) => {
+                            sink.capture(data.to_vec());
+                        }
+                        SocketType::Batched(_tx_channel) => {
+                            return Err(io::Error::other(
+                                "Logic Error - socket type should not be batchable.",
+                            ));
+                        }
+                    }
+
+                    Ok(())
+                });
+                match result {
+                    // Too large is too large however many times it's tried.
+                    Err(error)
+                        if drops_would_block(&error, would_block_policy)
+                            || is_message_too_long(&error) =>
+                    {
+                        OperationResult::Err(error)
+                    }
+                    result => result.into(),
+                }
+            },
+        )
+        .map_err(|error| error.error)
+    }
+
+    // Where to split a batch in two, after the metric nearest the middle. Every metric in a
+    // batch ends with a newline, and a single metric can't be split.
+    fn split_point(data: &[u8]) -> Option<usize> {
+        let middle = data.len() / 2;
+        match data[..middle].iter().rposition(|b| *b == b'\n') {
+            Some(newline) => Some(newline + 1),
+            None => data[middle..data.len().saturating_sub(1)]
+                .iter()
+                .position(|b| *b == b'\n')
+                .map(|newline| middle + newline + 1),
+        }
+    }
+
+    fn count_metrics(data: &[u8]) -> u64 {
+        data.iter().filter(|b| **b == b'\n').count() as u64
+    }
+
+    // Errors are handed to more than one place, but `io::Error` can't be cloned.
+    fn copy_error(error: &io::Error) -> io::Error {
+        match error.raw_os_error() {
+            Some(code) => io::Error::from_raw_os_error(code),
+            None => io::Error::new(error.kind(), error.to_string()),
+        }
+    }
+
+    // Full buffers held back to be sent together while more metrics are already waiting, each
+    // with the number of metrics in it. Emptied buffers are kept for reuse.
+    #[derive(Default)]
+    struct HeldBuffers {
+        buffers: Vec<(Vec<u8>, u64)>,
+        spare: Vec<Vec<u8>>,
+    }
+
+    impl HeldBuffers {
+        // Holds `buffer`, replacing it with an empty one.
+        #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+        fn hold(&mut self, buffer: &mut Vec<u8>, buffered_metrics: &mut u64) {
+            let empty = self
+                .spare
+                .pop()
+                .unwrap_or_else(|| Vec::with_capacity(buffer.capacity()));
+            self.buffers.push((
+                std::mem::replace(buffer, empty),
+                std::mem::take(buffered_metrics),
+            ));
+        }
+
+        fn clear(&mut self) {
+            for (mut buffer, _) in self.buffers.drain(..) {
+                buffer.clear();
+                self.spare.push(buffer);
+            }
+        }
+    }
+
+    // Sends held buffers to the destination's only address with as few `sendmmsg` calls as it
+    // takes, retrying the ones that weren't sent. Returns the error and the index of the
+    // buffer that wasn't sent if one wasn't, after which none were.
+    #[cfg(target_os = "linux")]
+    fn send_many_with_retries(
+        batching_options: &BatchingOptions,
+        socket: &SocketType,
+        buffers: &[(Vec<u8>, u64)],
+        destination: &Destination,
+        stats: &Stats,
+    ) -> Result<(), (io::Error, usize)> {
+        let (SocketType::Udp(socket), Some(addr)) = (socket, destination.single_addr()) else {
+            return Err((
+                io::Error::other("Logic Error - only udp to one address is sent with sendmmsg."),
+                0,
+            ));
+        };
+        let datagrams: Vec<&[u8]> = buffers.iter().map(|(buffer, _)| &buffer[..]).collect();
+        let mut next = 0;
+        retry(
+            Exponential::from_millis(batching_options.initial_retry_delay)
+                .map(jitter)
+                .take(batching_options.max_retry_attempts),
+            || {
+                let result = stats.time_send(|| {
+                    while next < datagrams.len() {
+                        next += send_many(socket, &datagrams[next..], addr)?;
+                    }
+                    Ok(())
+                });
+                match result {
+                    Err(error) if is_message_too_long(&error) => OperationResult::Err(error),
+                    result => result.into(),
+                }
+            },
+        )
+        .map_err(|error| (error.error, next))
+    }
+
+    // Buffers are only held on Linux, so elsewhere this is never called, but it would send them
+    // one at a time.
+    #[cfg(not(target_os = "linux"))]
+    fn send_many_with_retries(
+        batching_options: &BatchingOptions,
+        socket: &SocketType,
+        buffers: &[(Vec<u8>, u64)],
+        destination: &Destination,
+        stats: &Stats,
+    ) -> Result<(), (io::Error, usize)> {
+        for (i, (buffer, _)) in buffers.iter().enumerate() {
+            send_to_socket_with_retries(
+                batching_options,
+                socket,
+                buffer,
+                destination,
+                &None,
+                None,
+                stats,
+            )
+            .map_err(|error| (error, i))?;
+        }
+        Ok(())
+    }
+
+    // Batches that failed to send, each with the number of metrics in it, kept for
+    // `Options::max_retained_batches` to send again before the next one.
+    struct RetainedBatches {
+        max: usize,
+        batches: VecDeque<(Vec<u8>, u64)>,
+    }
+
+    impl RetainedBatches {
+        // Keeps a batch that failed to send, returning the number of metrics dropped to make
+        // room, or in it if none are kept.
+        fn retain(&mut self, batch: &[u8], metrics: u64) -> u64 {
+            if self.max == 0 {
+                return metrics;
+            }
+            let dropped = match self.batches.len() >= self.max {
+                true => self.batches.pop_front().map_or(0, |(_, metrics)| metrics),
+                false => 0,
+            };
+            self.batches.push_back((batch.to_vec(), metrics));
+            dropped
+        }
+
+        fn metrics(&self) -> u64 {
+            self.batches.iter().map(|(_, metrics)| metrics).sum()
+        }
+    }
+
+    // Sends batches from the batch processor, whichever way they're sent, and handles the
+    // ones that weren't: splitting those the socket refused as too large, keeping those that
+    // failed for `Options::max_retained_batches`, and counting and reporting the rest.
+    struct Delivery {
+        batching_options: BatchingOptions,
+        destination: Destination,
+        // Replaced when the reconnector re-creates it.
+        socket: SocketType,
+        socket_path: Option<String>,
+        would_block_policy: Option<WouldBlockPolicy>,
+        stats: Arc<Stats>,
+        error_handler: Option<ErrorHandler>,
+        reconnector: Option<Arc<Reconnector>>,
+        retained: RetainedBatches,
+        // The first error that dropped metrics since the last completion, which it's resolved
+        // with.
+        first_failure: Option<io::Error>,
+    }
+
+    impl Delivery {
+        fn send(&self, data: &[u8]) -> io::Result<()> {
+            send_to_socket_with_retries(
+                &self.batching_options,
+                &self.socket,
+                data,
+                &self.destination,
+                &self.socket_path,
+                self.would_block_policy,
+                &self.stats,
+            )
+        }
+
+        fn succeeded(&self) {
+            if let Some(reconnector) = &self.reconnector {
+                reconnector.succeeded();
+            }
+        }
+
+        // Counts and reports metrics that won't be sent.
+        fn dropped(&mut self, error: io::Error, metrics: u64) {
+            self.stats.record_drop(DropReason::SendFailed, metrics);
+            self.stats.record_send_error(&error, metrics);
+            if self.first_failure.is_none() {
+                self.first_failure = Some(copy_error(&error));
+            }
+            if !drops_would_block(&error, self.would_block_policy) {
+                if let Some(reconnector) = &self.reconnector {
+                    reconnector.failed();
+                }
+                report_error(
+                    &self.error_handler,
+                    "Failed to send within retry policy... Dropping metrics",
+                    DogstatsdError::IoError(error),
+                );
+            }
+        }
+
+        // Sends `batch`, with `metrics` in it, after the batches kept from earlier failures,
+        // returning a copy of the error if it wasn't all sent. While the kept batches still
+        // fail, this one waits behind them.
+        fn deliver(&mut self, batch: &[u8], metrics: u64) -> io::Result<()> {
+            let sent = self
+                .socket
+                .open_lazily()
+                .and_then(|()| self.resend_retained())
+                .and_then(|()| self.send(batch));
+            match sent {
+                Ok(()) => {
+                    self.succeeded();
+                    Ok(())
+                }
+                Err(error) => self.failed(batch, metrics, error),
+            }
+        }
+
+        // Sends the kept batches in order, stopping at the first that fails again.
+        fn resend_retained(&mut self) -> io::Result<()> {
+            while let Some((batch, metrics)) = self.retained.batches.pop_front() {
+                match self.send(&batch) {
+                    Ok(()) => {}
+                    Err(error) if is_message_too_long(&error) => {
+                        let _ = self.failed(&batch, metrics, error);
+                    }
+                    Err(error) => {
+                        self.retained.batches.push_front((batch, metrics));
+                        return Err(error);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        // Handles `batch`, with `metrics` in it, that failed to send with `error`. Returns `Ok`
+        // if it was too large and every part of it was sent once it was split, and a copy of
+        // the error otherwise.
+        fn failed(&mut self, batch: &[u8], metrics: u64, error: io::Error) -> io::Result<()> {
+            if is_message_too_long(&error) {
+                self.stats.record_batch_split();
+                report_error(
+                    &self.error_handler,
+                    "Batch too large for the socket, splitting it",
+                    DogstatsdError::BatchTooLarge { size: batch.len() },
+                );
+                return self.send_in_halves(batch, error);
+            }
+
+            let copy = copy_error(&error);
+            if drops_would_block(&error, self.would_block_policy) {
+                self.dropped(error, metrics);
+                return Err(copy);
+            }
+            match self.retained.retain(batch, metrics) {
+                // Kept to send again, without dropping any.
+                0 => {
+                    if let Some(reconnector) = &self.reconnector {
+                        reconnector.failed();
+                    }
+                }
+                dropped => self.dropped(error, dropped),
+            }
+            Err(copy)
+        }
+
+        // Handles `batch`, with `metrics` in it, that io_uring failed to send once with `error`,
+        // sending it again with what's left of the retry policy before handling it as failed.
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        fn retry(&mut self, batch: &[u8], metrics: u64, error: io::Error) -> io::Result<()> {
+            let attempts = self.batching_options.max_retry_attempts;
+            if attempts == 0
+                || is_message_too_long(&error)
+                || drops_would_block(&error, self.would_block_policy)
+            {
+                return self.failed(batch, metrics, error);
+            }
+            let batching_options = BatchingOptions {
+                max_retry_attempts: attempts - 1,
+                ..self.batching_options
+            };
+            let sent = send_to_socket_with_retries(
+                &batching_options,
+                &self.socket,
+                batch,
+                &self.destination,
+                &self.socket_path,
+                self.would_block_policy,
+                &self.stats,
+            );
+            match sent {
+                Ok(()) => Ok(()),
+                Err(error) => self.failed(batch, metrics, error),
+            }
+        }
+
+        // Sends a batch the socket refused as too large, with `error`, in two halves,
+        // splitting each again if it's still too large. A single metric that's too large is
+        // dropped.
+        fn send_in_halves(&mut self, data: &[u8], error: io::Error) -> io::Result<()> {
+            let Some(split) = split_point(data) else {
+                let copy = copy_error(&error);
+                self.dropped(error, 1);
+                return Err(copy);
+            };
+
+            let mut result = Ok(());
+            for half in [&data[..split], &data[split..]] {
+                let sent = match self.send(half) {
+                    Ok(()) => Ok(()),
+                    Err(error) if is_message_too_long(&error) => self.send_in_halves(half, error),
+                    Err(error) => self.failed(half, count_metrics(half), error),
+                };
+                result = result.and(sent);
+            }
+            if result.is_ok() {
+                self.succeeded();
+            }
+            result
+        }
+
+        // Sends held buffers together. Those the socket refuses as too large are split, and
+        // sending carries on after them. Once one fails otherwise, it and the rest are handled
+        // as failed. While there are batches kept from earlier failures, the held buffers are
+        // sent one at a time after them instead.
+        fn send_held(&mut self, held: &mut HeldBuffers) {
+            if !self.retained.batches.is_empty() {
+                for (buffer, metrics) in &held.buffers {
+                    let _ = self.deliver(buffer, *metrics);
+                }
+                held.clear();
+                return;
+            }
+            let mut next = 0;
+            while next < held.buffers.len() {
+                match send_many_with_retries(
+                    &self.batching_options,
+                    &self.socket,
+                    &held.buffers[next..],
+                    &self.destination,
+                    &self.stats,
+                ) {
+                    Ok(()) => {
+                        self.succeeded();
+                        break;
+                    }
+                    Err((error, failed)) if is_message_too_long(&error) => {
+                        let (buffer, metrics) = &held.buffers[next + failed];
+                        let _ = self.failed(buffer, *metrics, error);
+                        next += failed + 1;
+                    }
+                    Err((error, failed)) => {
+                        for (buffer, metrics) in &held.buffers[next + failed..] {
+                            let _ = self.failed(buffer, *metrics, copy_error(&error));
+                        }
+                        break;
+                    }
+                }
+            }
+            held.clear();
+        }
+    }
+
+    // Queues the buffer with io_uring, if the socket is UDP to a single address, otherwise
+    // returning `None` for it to be sent some other way. `wait` is whether to wait for every
+    // queued buffer to be sent too. Returns a copy of an error if any buffer wasn't sent.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn flush_with_uring(
+        delivery: &mut Delivery,
+        uring: &mut UringSender,
+        buffer: &mut Vec<u8>,
+        buffered_metrics: &mut u64,
+        wait: bool,
+    ) -> Option<io::Result<()>> {
+        let addr = delivery.destination.single_addr()?;
+        let SocketType::Udp(socket) = &delivery.socket else {
+            return None;
+        };
+
+        let mut result = Ok(());
+        if *buffered_metrics > 0 {
+            // Batches kept from earlier failures go first, so while there are any this one is
+            // sent after them rather than queued.
+            if delivery.retained.batches.is_empty() {
+                result = uring.submit(socket, buffer, *buffered_metrics, addr);
+            }
+            // The buffer is only replaced once it's queued.
+            if !buffer.is_empty() {
+                result = delivery.deliver(buffer, *buffered_metrics);
+                buffer.clear();
+            }
+            *buffered_metrics = 0;
+        }
+        if wait {
+            result = result.and(uring.wait_all());
+        }
+        for (batch, metrics, error) in uring.take_failed() {
+            result = result.and(delivery.retry(&batch, metrics, error));
+        }
+        if result.is_ok() {
+            delivery.succeeded();
+        }
+        Some(result)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn process_events(
+        batching_options: BatchingOptions,
+        destination: Destination,
+        socket: SocketType,
+        socket_path: Option<String>,
+        would_block_policy: Option<WouldBlockPolicy>,
+        stats: Arc<Stats>,
+        error_handler: Option<ErrorHandler>,
+        heartbeat: Option<Arc<Heartbeat>>,
+        reconnector: Option<Arc<Reconnector>>,
+        max_retained_batches: usize,
+        #[cfg_attr(
+            not(all(feature = "io-uring", target_os = "linux")),
+            allow(unused_variables)
+        )]
+        io_uring: bool,
+        clock: Clock,
+        rx: Receiver<Message>,
+    ) {
+        #[cfg(any(test, feature = "testing"))]
+        let mut clock = clock;
+        let mut last_updated = clock.now();
+        // The buffer keeps its allocation between flushes, so once it's grown to
+        // `max_buffer_size` batching doesn't allocate again.
+        let mut buffer: Vec<u8> = Vec::with_capacity(batching_options.max_buffer_size);
+        let mut buffered_metrics: u64 = 0;
+        // On Linux, full buffers for a single UDP address are held while more metrics are
+        // already waiting, and sent together with `sendmmsg`.
+        #[cfg(target_os = "linux")]
+        let sends_many =
+            matches!(socket, SocketType::Udp(_)) && destination.single_addr().is_some();
+        // With `Options::io_uring` and the `io-uring` feature those buffers are queued with
+        // io_uring instead, so the batch processor doesn't wait for each send.
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let mut uring = match sends_many && io_uring {
+            true => UringSender::new()
+                .map_err(|error| {
+                    report_error(
+                        &error_handler,
+                        "Failed to set up io_uring, sending batches with sendmmsg",
+                        DogstatsdError::IoError(error),
+                    )
+                })
+                .ok(),
+            false => None,
+        };
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        let sends_many = sends_many && uring.is_none();
+        let mut held = HeldBuffers::default();
+        let mut delivery = Delivery {
+            batching_options,
+            destination,
+            socket,
+            socket_path,
+            would_block_policy,
+            stats,
+            error_handler,
+            reconnector,
+            retained: RetainedBatches {
+                max: max_retained_batches,
+                batches: VecDeque::new(),
+            },
+            first_failure: None,
+        };
+        let mut next = None;
+
+        // Returns a copy of the error if the buffer wasn't sent. With io_uring, `wait` is
+        // whether to wait for every queued buffer to be sent too.
+        #[cfg_attr(
+            not(all(feature = "io-uring", target_os = "linux")),
+            allow(unused_variables, unused_mut)
+        )]
+        let mut flush = |delivery: &mut Delivery,
+                         buffer: &mut Vec<u8>,
+                         buffered_metrics: &mut u64,
+                         wait: bool| {
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            if let Some(uring) = &mut uring {
+                if let Some(result) =
+                    flush_with_uring(delivery, uring, buffer, buffered_metrics, wait)
+                {
+                    return result;
+                }
+            }
+            if *buffered_metrics == 0 {
+                return Ok(());
+            }
+            let result = delivery.deliver(buffer, *buffered_metrics);
+            buffer.clear();
+            *buffered_metrics = 0;
+            result
+        };
+
+        let beat = || {
+            if let Some(heartbeat) = &heartbeat {
+                heartbeat.beat();
+            }
+        };
+        beat();
+
+        loop {
+            if let Some(reconnector) = &delivery.reconnector {
+                match reconnector.reconnect() {
+                    Some(Ok(reconnected)) => {
+                        delivery.destination.reopened();
+                        delivery.socket = reconnected;
+                    }
+                    Some(Err(error)) => reconnector.report_failure(&delivery.error_handler, error),
+                    None => {}
+                }
+            }
+            let message = match next.take() {
+                Some(message) => Ok(message),
+                None => {
+                    // Nothing else is waiting, so what's held is sent before blocking.
+                    if !held.buffers.is_empty() {
+                        delivery.send_held(&mut held);
+                    }
+                    rx.recv()
+                }
+            };
+            beat();
+            match message {
+                Ok(Message::Data(data)) => {
+                    buffer.extend_from_slice(&data);
+                    buffer.push(b'\n');
+                    buffered_metrics += 1;
+
+                    let current_time = clock.now();
+                    let full = buffer.len() >= batching_options.max_buffer_size;
+                    #[cfg(target_os = "linux")]
+                    if full && sends_many {
+                        held.hold(&mut buffer, &mut buffered_metrics);
+                        if held.buffers.len() < MAX_DATAGRAMS_PER_SEND {
+                            match rx.try_recv() {
+                                Ok(message @ Message::Data(_)) => {
+                                    next = Some(message);
+                                    continue;
+                                }
+                                Ok(message) => next = Some(message),
+                                Err(_) => {}
+                            }
+                        }
+                        delivery.send_held(&mut held);
+                        last_updated = current_time;
+                        continue;
+                    }
+                    if full || last_updated + batching_options.max_time < current_time {
+                        if !held.buffers.is_empty() {
+                            delivery.send_held(&mut held);
+                        }
+                        let _ = flush(&mut delivery, &mut buffer, &mut buffered_metrics, false);
+                        last_updated = current_time;
+                    }
+                }
+                #[cfg(any(test, feature = "testing"))]
+                Ok(Message::Advance(duration)) => {
+                    if let Clock::Manual(now) = &mut clock {
+                        *now += duration;
+                    }
+                }
+                Ok(Message::Flush(reply)) => {
+                    let flushed = (buffer.len(), buffered_metrics);
+                    // Flushed even when empty, to wait for sends queued with io_uring.
+                    let sent = flush(&mut delivery, &mut buffer, &mut buffered_metrics, true)
+                        .is_ok()
+                        && flushed.1 > 0;
+                    let _ = reply.send(if sent { flushed } else { (0, 0) });
+                }
+                Ok(Message::Completion(completer)) => {
+                    let result = flush(&mut delivery, &mut buffer, &mut buffered_metrics, true);
+                    // Metrics dropped by an earlier flush were sent before the completion too.
+                    completer.resolve(match delivery.first_failure.take() {
+                        Some(error) => Err(error.into()),
+                        None => result.map_err(DogstatsdError::from),
+                    });
+                }
+                Ok(Message::SetDestination(addr, resolved)) => {
+                    delivery.destination.set_primary_addr(&addr, resolved);
+                }
+                Ok(Message::Ping) => {}
+                Ok(Message::Shutdown) => {
+                    let was_empty = buffered_metrics == 0;
+                    let _ = flush(&mut delivery, &mut buffer, &mut buffered_metrics, true);
+                    // The final batch is sent even when it's empty.
+                    if was_empty {
+                        let _ = delivery.deliver(&buffer, 0);
+                    }
+                    break;
+                }
+                Err(_) => {
+                    // The client normally shuts us down before the channel closes, so anything
+                    // still buffered here would otherwise be lost. Nothing is printed without
+                    // a handler, since the metrics are still sent, or counted if they can't be.
+                    if buffered_metrics > 0 {
+                        let error = DogstatsdError::ChannelClosed(buffered_metrics);
+                        let _ = flush(&mut delivery, &mut buffer, &mut buffered_metrics, true);
+                        if let Some(handler) = &delivery.error_handler {
+                            handler.handle(&error);
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+
+        let unsent = delivery.retained.metrics();
+        if unsent > 0 {
+            delivery.stats.record_drop(DropReason::SendFailed, unsent);
+            report_error(
+                &delivery.error_handler,
+                "Failed to send retained batches before shutting down... Dropping metrics",
+                DogstatsdError::IoError(io::Error::other(format!(
+                    "{} retained batches were never sent",
+                    delivery.retained.batches.len()
+                ))),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics::GaugeMetric;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::{fmt, io};
+
+    use super::*;
+
+    #[test]
+    fn test_options_default() {
+        let options = Options::default();
+        let expected_options = Options {
+            from_addr: DEFAULT_FROM_ADDR.into(),
+            to_addr: DEFAULT_TO_ADDR.into(),
+            namespace: String::new(),
+            ..Default::default()
+        };
+
+        assert_eq!(expected_options, options)
+    }
+
+    #[test]
+    fn test_options_builder_none() {
+        let options = OptionsBuilder::new().build();
+        let expected_options = Options {
+            from_addr: DEFAULT_FROM_ADDR.into(),
+            to_addr: DEFAULT_TO_ADDR.into(),
+            namespace: String::new(),
+            ..Default::default()
+        };
+
+        assert_eq!(expected_options, options);
+    }
+
+    #[test]
+    #[allow(clippy::useless_vec)]
+    fn teset_options_builder_all() {
+        let options = OptionsBuilder::new()
+            .from_addr("127.0.0.2:0".into())
+            .to_addr("127.0.0.2:8125".into())
+            .namespace("mynamespace".into())
+            .default_tag(String::from("tag1:tag1val"))
+            .build();
+        let expected_options = Options {
+            from_addr: "127.0.0.2:0".into(),
+            to_addr: "127.0.0.2:8125".into(),
+            namespace: "mynamespace".into(),
+            default_tags: vec!["tag1:tag1val".into()].to_vec(),
+            socket_path: None,
+            batching_options: None,
+            stats_report_interval: None,
+            failover_options: None,
+            max_paused_metrics: DEFAULT_MAX_PAUSED_METRICS,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            error_handler: None,
+            tag_prefix: None,
+            max_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            oversized_metric_policy: OversizedMetricPolicy::default(),
+            route_hook: None,
+            slo_buckets: vec![],
+            host_tag: false,
+            pid_tag: false,
+            tag_rate_limit: None,
+            adaptive_sampling: None,
+            bind_mode: None,
+            aggregation_key_generator: None,
+            watchdog: None,
+            sample_rate_correction: false,
+            normalize_metric_names: false,
+            transport: None,
+            measure_send_latency: false,
+            warning_events: None,
+            float_precision: DEFAULT_FLOAT_PRECISION,
+            file_path: None,
+            reserved_tags: None,
+            additional_to_addrs: vec![],
+            connect_udp: false,
+            would_block_policy: None,
+            address_family: None,
+            encoder: None,
+            reconnect_options: None,
+            udp_fallback: None,
+            max_tags_bytes: None,
+            lazy_socket: false,
+            connect_retry: None,
+            uds_connect_timeout: None,
+            gauge_rollup: None,
+            debug_mirror: None,
+            thread_tag: false,
+            max_retained_batches: None,
+            config_banner: false,
+            io_uring: false,
+            compression: None,
+        };
+
+        assert_eq!(expected_options, options);
+    }
+
+    #[test]
+    fn test_batching_options_default() {
+        assert_eq!(
+            BatchingOptions {
+                max_buffer_size: 1432,
+                max_time: Duration::from_millis(100),
+                max_retry_attempts: 0,
+                initial_retry_delay: 0,
+            },
+            BatchingOptions::default()
+        );
+        assert_eq!(
+            8192,
+            BatchingOptions::for_transport(Transport::Uds).max_buffer_size
+        );
+    }
+
+    #[test]
+    fn test_batching_options_builder() {
+        assert_eq!(
+            BatchingOptions::default(),
+            BatchingOptions::builder().build().unwrap()
+        );
+        assert_eq!(
+            BatchingOptions {
+                max_buffer_size: 8192,
+                max_time: Duration::from_millis(10),
+                max_retry_attempts: 0,
+                initial_retry_delay: 0,
+            },
+            BatchingOptions::builder()
+                .transport(Transport::Uds)
+                .low_latency()
+                .build()
+                .unwrap()
+        );
+        assert_eq!(
+            BatchingOptions {
+                max_buffer_size: 1000,
+                max_time: Duration::from_secs(1),
+                max_retry_attempts: 3,
+                initial_retry_delay: 10,
+            },
+            BatchingOptions::builder()
+                .high_throughput()
+                .max_buffer_size(1000)
+                .build()
+                .unwrap()
+        );
+        assert_eq!(
+            100_000,
+            BatchingOptions::builder()
+                .transport(Transport::Tcp)
+                .max_buffer_size(100_000)
+                .build()
+                .unwrap()
+                .max_buffer_size
+        );
+    }
+
+    #[test]
+    fn test_batching_options_builder_invalid() {
+        let error = BatchingOptions::builder()
+            .max_buffer_size(8192)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            "invalid batching options: max_buffer_size of 8192 is larger than the 1432 bytes that can be sent over Udp { mtu: 1500 }",
+            error.to_string()
+        );
+        assert!(BatchingOptions::builder()
+            .transport(Transport::Udp { mtu: 40 })
+            .build()
+            .is_err());
+        assert!(BatchingOptions::builder()
+            .max_buffer_size(0)
+            .build()
+            .is_err());
+        assert!(BatchingOptions::builder()
+            .max_time(Duration::ZERO)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_new() {
+        let client = Client::new(Options::default()).unwrap();
+        let expected_client = Client {
+            writer: default_writer(),
+            from_addr: DEFAULT_FROM_ADDR.into(),
+            to_addr: DEFAULT_TO_ADDR.into(),
+            probe_target: ProbeTarget::Udp,
+            namespace: String::new(),
+            default_tags: String::new().into_bytes().into(),
+            tag_prefix: None,
+            max_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            oversized_metric_policy: OversizedMetricPolicy::TruncateTags,
+            max_tags_bytes: None,
+            thread_tag: false,
+            rollup: None,
+            router: None,
+            slo_buckets: vec![],
+            telemetry_tags: Default::default(),
+            rate_limiter: None,
+            sampler: None,
+            aggregation_key_generator: None,
+            metric_sender: OnceLock::new(),
+            sample_rate_correction: false,
+            normalize_metric_names: false,
+            float_precision: DEFAULT_FLOAT_PRECISION,
+            reserved_tags: None,
+            encoder: None,
+            stats_report_interval: None,
+            last_stats_report: Mutex::new((Instant::now(), ClientStats::default())),
+            keep_alives: KeepAlives::new(DEFAULT_KEEP_ALIVE_INTERVAL),
+        };
+
+        assert_eq!(expected_client, client)
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let lines = |options: Options| {
+            let client = Client::new(options).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            client.incr("first", [] as [&str; 0]).unwrap();
+            client.gauge("second", "2", ["tag"]).unwrap();
+            client.writer.flush();
+            BufReader::new(stream)
+                .lines()
+                .take(2)
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap()
+        };
+
+        assert_eq!(
+            vec!["first:1|c", "second:2|g|#tag"],
+            lines(
+                OptionsBuilder::new()
+                    .to_addr(addr.clone())
+                    .transport(Transport::Tcp)
+                    .build()
+            )
+        );
+        assert_eq!(
+            vec!["first:1|c", "second:2|g|#tag"],
+            lines(
+                OptionsBuilder::new()
+                    .to_addr(addr.clone())
+                    .transport(Transport::Tcp)
+                    .batching_options(BatchingOptions::for_transport(Transport::Tcp))
+                    .build()
+            )
+        );
+
+        let error = Client::new(
+            OptionsBuilder::new()
+                .to_addr(addr)
+                .transport(Transport::Tcp)
+                .socket_path(Some("/tmp/dogstatsd.sock".into()))
+                .build(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("socket_path"));
+
+        // Compression is only for TCP, never the agent's sockets.
+        let error =
+            Client::new(OptionsBuilder::new().compression(Compression::Zlib).build()).unwrap_err();
+        assert!(error.to_string().contains("Transport::Tcp"));
+    }
+
+    #[test]
+    fn test_from_socket() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to_addr = server.local_addr().unwrap().to_string();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let from_addr = socket.local_addr().unwrap();
+
+        let client = Client::from_socket(
+            socket,
+            OptionsBuilder::new()
+                .to_addr(to_addr)
+                .batching_options(BatchingOptions::default())
+                .build(),
+        )
+        .unwrap();
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        client.writer.flush();
+
+        let mut buf = [0; 64];
+        let (len, addr) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(b"counter:1|c\n", &buf[..len]);
+        assert_eq!(from_addr, addr);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let options = OptionsBuilder::new()
+            .socket_path(Some("/tmp/dogstatsd.sock".into()))
+            .build();
+        assert!(Client::from_socket(socket, options).is_err());
+    }
+
+    #[test]
+    fn test_from_owned_fd() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::from_owned_fd(
+            OwnedFd::from(socket),
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .build(),
+        )
+        .unwrap();
+        client.incr("udp", [] as [&str; 0]).unwrap();
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"udp:1|c", &buf[..len]);
+
+        let path = temp_path(".fd");
+        let _ = std::fs::remove_file(&path);
+        let agent = UnixDatagram::bind(&path).unwrap();
+        let socket = UnixDatagram::unbound().unwrap();
+        socket.connect(&path).unwrap();
+        // SAFETY: the socket is open, and the client is its only owner.
+        let client = unsafe {
+            Client::from_raw_fd(
+                std::os::unix::io::IntoRawFd::into_raw_fd(socket),
+                OptionsBuilder::new()
+                    .socket_path(Some(path.to_str().unwrap().into()))
+                    .build(),
+            )
+        }
+        .unwrap();
+        client.incr("uds", [] as [&str; 0]).unwrap();
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"uds:1|c", &buf[..len]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_send_batch_raw() {
+        let sink = CaptureSink::new();
+        let client = sink.client(OptionsBuilder::new().max_payload_size(24).build());
+        let lines = [
+            &b"first:1|c"[..],
+            b"second:2|g",
+            b"",
+            b"third:3|ms",
+            b"a_much_longer_metric_name:1|c",
+            b"fourth:4|h",
+        ];
+
+        match client.send_batch_raw(lines) {
+            Err(DogstatsdError::PayloadTooLarge { metric, .. }) => {
+                assert_eq!("a_much_longer_metric_name", metric)
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(
+            vec!["first:1|c\nsecond:2|g", "third:3|ms\nfourth:4|h"],
+            sink.payloads()
+        );
+        assert_eq!(1, client.stats().dropped(DropReason::Invalid));
+    }
+
+    #[test]
+    fn test_send_batch_raw_batched() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .batching_options(BatchingOptions::default())
+                .build(),
+        )
+        .unwrap();
+
+        client
+            .send_batch_raw("first:1|c\nsecond:2|g".lines().map(str::as_bytes))
+            .unwrap();
+        client.writer.flush();
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"first:1|c\nsecond:2|g\n", &buf[..len]);
+    }
+
+    #[test]
+    fn test_additional_to_addrs_batched() {
+        let primary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let additional = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(primary.local_addr().unwrap().to_string())
+                // Sending to an ipv6 address from an ipv4 socket always fails.
+                .additional_to_addr("[::1]:8125".into())
+                .additional_to_addr(additional.local_addr().unwrap().to_string())
+                .batching_options(
+                    BatchingOptions::builder()
+                        .max_retry_attempts(2)
+                        .initial_retry_delay(1)
+                        .build()
+                        .unwrap(),
+                )
+                .error_handler(ErrorHandler::new(move |error| {
+                    errors_r.lock().unwrap().push(error.to_string())
+                }))
+                .build(),
+        )
+        .unwrap();
+
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        client.writer.flush();
+
+        // The failing address is retried alone, so the others get the batch once.
+        let mut buf = [0; 64];
+        for server in [&primary, &additional] {
+            let len = server.recv(&mut buf).unwrap();
+            assert_eq!(b"counter:1|c\n", &buf[..len]);
+            server.set_nonblocking(true).unwrap();
+            assert!(server.recv(&mut buf).is_err());
+        }
+        assert_eq!(1, errors.lock().unwrap().len());
+        assert!(errors.lock().unwrap()[0].contains("sending to [::1]:8125"));
+    }
+
+    #[test]
+    fn test_resolve_to_addr() {
+        let server = UdpSocket::bind("[::1]:0").unwrap();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .build(),
+        )
+        .unwrap();
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"counter:1|c", &buf[..len]);
+
+        let options = OptionsBuilder::new().to_addr("localhost".into()).build();
+        match Client::new(options) {
+            Err(DogstatsdError::IoError(error)) => {
+                assert!(error.to_string().contains("\"localhost\""))
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        // Resolved when the socket is opened, so a lazy client doesn't need it to resolve yet.
+        let options = OptionsBuilder::new()
+            .to_addr("localhost".into())
+            .lazy_socket(true)
+            .build();
+        let client = Client::new(options).unwrap();
+        assert!(client.incr("counter", [] as [&str; 0]).is_err());
+
+        // A hostname is kept, and sent to at its address of the preferred family.
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to_addr = format!("localhost:{}", server.local_addr().unwrap().port());
+        let options = OptionsBuilder::new()
+            .to_addr(to_addr.clone())
+            .address_family(AddressFamily::Ipv4)
+            .build();
+        let client = Client::new(options).unwrap();
+        assert_eq!(to_addr, client.to_addr);
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"counter:1|c", &buf[..len]);
+    }
+
+    #[test]
+    fn test_connect_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to_addr = server.local_addr().unwrap().to_string();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(to_addr.clone())
+                .connect_udp(true)
+                .build(),
+        )
+        .unwrap();
+
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"counter:1|c", &buf[..len]);
+
+        // Once nothing listens on the address, the ICMP unreachable fails a later send.
+        drop(server);
+        let refused = (0..10).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            matches!(
+                client.incr("counter", [] as [&str; 0]),
+                Err(DogstatsdError::IoError(error)) if error.kind() == io::ErrorKind::ConnectionRefused
+            )
+        });
+        assert!(refused);
+
+        let options = OptionsBuilder::new()
+            .to_addr(to_addr)
+            .additional_to_addr("127.0.0.1:9125".into())
+            .connect_udp(true)
+            .build();
+        assert!(Client::new(options).is_err());
+    }
+
+    #[test]
+    fn test_completion() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .batching_options(BatchingOptions::default())
+                .build(),
+        )
+        .unwrap();
+
+        // Nothing is buffered yet.
+        client.completion().wait().unwrap();
+
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        client.completion().wait().unwrap();
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"counter:1|c\n", &buf[..len]);
+
+        assert!(Client::new(Options::default())
+            .unwrap()
+            .completion()
+            .is_resolved());
+    }
+
+    #[test]
+    fn test_completion_after_failed_flush() {
+        let (socket, peer) = UnixDatagram::pair().unwrap();
+        drop(peer);
+        let options = OptionsBuilder::new()
+            .batching_options(BatchingOptions {
+                max_buffer_size: 1,
+                max_time: Duration::from_secs(60),
+                max_retry_attempts: 0,
+                initial_retry_delay: 0,
+            })
+            .build();
+        let client = Client::with_socket(options, SocketType::Uds(socket), Clock::System).unwrap();
+
+        // Sent, and dropped, as soon as it's buffered, leaving nothing for the completion.
+        client.incr("dropped", [] as [&str; 0]).unwrap();
+        assert!(matches!(
+            client.completion().wait(),
+            Err(DogstatsdError::IoError(_))
+        ));
+        // Only failures since the last completion count.
+        client.completion().wait().unwrap();
+    }
+
+    #[test]
+    fn test_would_block_policy_drop() {
+        let path = temp_path(".sock");
+        let _ = std::fs::remove_file(&path);
+        // Never read from, so its buffer fills up.
+        let _agent = UnixDatagram::bind(&path).unwrap();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .socket_path(Some(path.to_str().unwrap().into()))
+                .would_block_policy(WouldBlockPolicy::Drop)
+                .build(),
+        )
+        .unwrap();
+
+        for _ in 0..10_000 {
+            client.incr("counter", [] as [&str; 0]).unwrap();
+            if client.stats().dropped(DropReason::SendFailed) > 0 {
+                break;
+            }
+        }
+        assert!(client.stats().dropped(DropReason::SendFailed) > 0);
+        assert!(client.stats().likely_kernel_drops > 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_rebind() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to_addr = server.local_addr().unwrap().to_string();
+        let recv_from = || {
+            let mut buf = [0; 64];
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            (String::from_utf8_lossy(&buf[..len]).into_owned(), from)
+        };
+
+        let client = Client::new(OptionsBuilder::new().to_addr(to_addr.clone()).build()).unwrap();
+        let namespaced = client.for_namespace("app");
+        client.incr("before", [] as [&str; 0]).unwrap();
+        let (payload, before) = recv_from();
+        assert_eq!("before:1|c", payload);
+        client.rebind().unwrap();
+        namespaced.incr("after", [] as [&str; 0]).unwrap();
+        let (payload, after) = recv_from();
+        assert_eq!("app.after:1|c", payload);
+        assert_ne!(before, after);
+
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(to_addr)
+                .batching_options(BatchingOptions::default())
+                .build(),
+        )
+        .unwrap();
+        client.incr("before", [] as [&str; 0]).unwrap();
+        client.rebind().unwrap();
+        client.incr("after", [] as [&str; 0]).unwrap();
+        client.writer.flush();
+        // The old batch processor sends its last batch on its own thread, in any order.
+        let mut received = [recv_from(), recv_from()];
+        received.sort();
+        assert_eq!("after:1|c\n", received[0].0);
+        assert_eq!("before:1|c\n", received[1].0);
+        assert_ne!(received[0].1, received[1].1);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::from_socket(socket, Options::default()).unwrap();
+        assert!(client.rebind().is_err());
+        assert!(Client::noop().rebind().is_err());
+    }
+
+    #[test]
+    fn test_reconnect_options() {
+        let path = temp_path(".rec");
+        let _ = std::fs::remove_file(&path);
+        let agent = UnixDatagram::bind(&path).unwrap();
+        let reconnect_options = ReconnectOptions {
+            max_consecutive_failures: 1,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        };
+        let client = Client::new(
+            OptionsBuilder::new()
+                .socket_path(Some(path.to_str().unwrap().into()))
+                .reconnect_options(reconnect_options)
+                .build(),
+        )
+        .unwrap();
+        client.incr("before", [] as [&str; 0]).unwrap();
+
+        // The agent restarts, replacing its socket.
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+        let agent = UnixDatagram::bind(&path).unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        assert!(client.incr("lost", [] as [&str; 0]).is_err());
+        client.incr("after", [] as [&str; 0]).unwrap();
+
+        let mut buf = [0; 64];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"after:1|c", &buf[..len]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_lazy_socket() {
+        let dir = temp_path("-lazy");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("dsd.socket");
+        let options = |lazy_socket, backoff| {
+            OptionsBuilder::new()
+                .socket_path(Some(path.to_str().unwrap().into()))
+                .lazy_socket(lazy_socket)
+                .connect_retry(ConnectRetry {
+                    max_retries: 0,
+                    initial_backoff: backoff,
+                    max_backoff: backoff,
+                })
+                .build()
+        };
+        assert!(Client::new(options(false, Duration::ZERO)).is_err());
+
+        // The agent hasn't created its socket yet, and the client doesn't create it either.
+        std::fs::create_dir(&dir).unwrap();
+        let client = Client::new(options(true, Duration::ZERO)).unwrap();
+        let backing_off = Client::new(options(true, Duration::from_secs(60))).unwrap();
+        assert!(client.incr("lost", [] as [&str; 0]).is_err());
+        assert!(backing_off.incr("lost", [] as [&str; 0]).is_err());
+        assert!(!path.exists());
+
+        let agent = UnixDatagram::bind(&path).unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client.incr("sent", [] as [&str; 0]).unwrap();
+        // Not tried again until the backoff has passed.
+        assert!(backing_off.incr("lost", [] as [&str; 0]).is_err());
+
+        let mut buf = [0; 64];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"sent:1|c", &buf[..len]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_inherited_socket_connect_retry() {
+        use std::os::unix::io::IntoRawFd;
+
+        let fd = UdpSocket::bind("127.0.0.1:0").unwrap().into_raw_fd();
+        let options = OptionsBuilder::new()
+            // SAFETY: the socket was just opened, and nothing else uses it.
+            .bind_mode(BindMode::Inherit(unsafe { InheritedFd::new(fd) }))
+            .connect_udp(true)
+            .additional_to_addr("127.0.0.1:8126".into())
+            .connect_retry(ConnectRetry {
+                max_retries: 2,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+            })
+            .build();
+        // Every attempt fails after setting up the socket, which is still only closed once.
+        assert!(Client::new(options).is_err());
+    }
+
+    #[test]
+    fn test_connect_retry() {
+        let dir = temp_path("-retry");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("dsd.socket");
+
+        // The agent's socket is mounted a little after the client starts. It's bound elsewhere
+        // first, so it appears with its directory all at once.
+        let staging = dir.with_extension("staging");
+        let _ = std::fs::remove_dir_all(&staging);
+        let agent = thread::spawn({
+            let (dir, staging) = (dir.clone(), staging.clone());
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                std::fs::create_dir(&staging).unwrap();
+                let agent = UnixDatagram::bind(staging.join("dsd.socket")).unwrap();
+                std::fs::rename(&staging, &dir).unwrap();
+                agent
+            }
+        });
+        let client = Client::new(
+            OptionsBuilder::new()
+                .socket_path(Some(path.to_str().unwrap().into()))
+                .connect_retry(ConnectRetry {
+                    max_retries: 100,
+                    initial_backoff: Duration::from_millis(10),
+                    max_backoff: Duration::from_millis(10),
+                })
+                .build(),
+        )
+        .unwrap();
+        let agent = agent.join().unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client.incr("started", [] as [&str; 0]).unwrap();
+
+        let mut buf = [0; 64];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"started:1|c", &buf[..len]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_self_test() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .batching_options(BatchingOptions::default())
+                .build(),
+        );
+        let report = client.self_test();
+        assert!(report.passed());
+        assert_eq!(
+            vec![
+                "count",
+                "gauge",
+                "histogram",
+                "distribution",
+                "set",
+                "timing",
+                "event",
+                "service_check"
+            ],
+            report
+                .results
+                .iter()
+                .map(|(kind, _)| *kind)
+                .collect::<Vec<_>>()
+        );
+
+        // Already sent, without waiting for the batch to fill.
+        let payloads = sink.payloads().concat();
+        assert_eq!(8, payloads.lines().count());
+        assert!(payloads
+            .lines()
+            .all(|payload| payload.ends_with(&format!("|#{}", report.marker))));
+        assert!(payloads.starts_with("datadog.dogstatsd.client.self_test.count:1|c|#self_test:"));
+    }
+
+    #[test]
+    fn test_count_weighted() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+
+        client
+            .count_weighted("requests", 12, 10.0, ["route:index"])
+            .unwrap();
+        client
+            .count_weighted("requests", 3, 1.0, [] as [&str; 0])
+            .unwrap();
+        assert!(client
+            .count_weighted("requests", 3, 0.5, [] as [&str; 0])
+            .is_err());
+        assert!(client
+            .count_weighted("requests", 3, f64::NAN, [] as [&str; 0])
+            .is_err());
+
+        assert_eq!(
+            vec!["requests:12|c|@0.1|#route:index", "requests:3|c"],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_set_destination() {
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv = |server: &UdpSocket| {
+            let mut buf = [0; 64];
+            let len = server.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        };
+
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(first.local_addr().unwrap().to_string())
+                .build(),
+        )
+        .unwrap();
+        client.incr("before", [] as [&str; 0]).unwrap();
+        client
+            .set_destination(&second.local_addr().unwrap().to_string())
+            .unwrap();
+        client.incr("after", [] as [&str; 0]).unwrap();
+        assert_eq!("before:1|c", recv(&first));
+        assert_eq!("after:1|c", recv(&second));
+        // The probe follows it too.
+        assert_eq!(AgentHealth::Reachable, client.probe());
+        assert!(recv(&second).starts_with("datadog.dogstatsd.client.probe:1|c"));
+
+        // What's already batched goes to the new address too.
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(first.local_addr().unwrap().to_string())
+                .batching_options(BatchingOptions::default())
+                .build(),
+        )
+        .unwrap();
+        client.incr("before", [] as [&str; 0]).unwrap();
+        client
+            .set_destination(&second.local_addr().unwrap().to_string())
+            .unwrap();
+        client.incr("after", [] as [&str; 0]).unwrap();
+        client.writer.flush();
+        assert_eq!("before:1|c\nafter:1|c\n", recv(&second));
+
+        assert!(client.set_destination("[::1]:8125").is_err());
+        let sink = CaptureSink::new();
+        assert!(sink
+            .client(Options::default())
+            .set_destination("127.0.0.1:8126")
+            .is_err());
+    }
+
+    #[test]
+    fn test_noop() {
+        let client = Client::noop();
+        assert!(client.is_noop());
+        assert!(!Client::new(Options::default()).unwrap().is_noop());
+
+        client.incr("counter", ["tag"]).unwrap();
+        client
+            .gauge("gauge", "not a number", [] as [&str; 0])
+            .unwrap();
+        client
+            .send_batch_raw("first:1|c".lines().map(str::as_bytes))
+            .unwrap();
+        client
+            .for_namespace("billing")
+            .event("title", "text", [] as [&str; 0])
+            .unwrap();
+        assert_eq!(ClientStats::default(), client.stats());
+    }
+
+    #[test]
+    fn test_join_on_drop() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_nonblocking(true).unwrap();
+        let options = || {
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .batching_options(BatchingOptions {
+                    max_time: Duration::from_secs(60),
+                    ..Default::default()
+                })
+                .build()
+        };
+
+        let client = Client::new(options()).unwrap();
+        client.join_on_drop(true);
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        drop(client);
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"counter:1|c\n", &buf[..len]);
+
+        // Detached, the final batch is still sent, just not waited for.
+        let client = Client::new(options()).unwrap();
+        client.detach();
+        client.join_on_drop(true);
+        client.incr("detached", [] as [&str; 0]).unwrap();
+        drop(client);
+        server.set_nonblocking(false).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"detached:1|c\n", &buf[..len]);
+    }
+
+    #[test]
+    fn test_with_metric_options() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace("app".into())
+                .default_tag("host_group:web".into())
+                .build(),
+        );
+
+        client
+            .with_metric_options(MetricOptions::new().with_skip_default_tags(true))
+            .incr("signups", ["plan:free"])
+            .unwrap();
+        client
+            .with_metric_options(MetricOptions::new())
+            .incr("signups", ["plan:free"])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "app.signups:1|c|#plan:free",
+                "app.signups:1|c|#plan:free,host_group:web",
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_with_metric_options_host() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .default_tag("host:collector".into())
+                .default_tag("env:prod".into())
+                .build(),
+        );
+
+        client
+            .with_metric_options(MetricOptions::new().with_host("switch-01"))
+            .gauge("port.errors", "3", ["port:1"])
+            .unwrap();
+        client
+            .with_metric_options(
+                MetricOptions::new()
+                    .with_skip_default_tags(true)
+                    .with_host("switch-02"),
+            )
+            .gauge("port.errors", "5", ["port:1"])
+            .unwrap();
+        client.incr("polls", [] as [&str; 0]).unwrap();
+
+        assert_eq!(
+            vec![
+                "port.errors:3|g|#port:1,env:prod,host:switch-01",
+                "port.errors:5|g|#port:1,host:switch-02",
+                "polls:1|c|#host:collector,env:prod",
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_encoder() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace("app".into())
+                .default_tag("env:prod".into())
+                .encoder(SharedEncoder::new(TelegrafEncoder))
+                .build(),
+        );
+
+        client.count("requests", 3, ["route:index"]).unwrap();
+        client
+            .service_check("web", ServiceStatus::OK, [] as [&str; 0], None)
+            .unwrap();
+        let pool_size = client.persistent_gauge("pool.size", ["pool:db"]);
+        pool_size.set("12").unwrap();
+
+        assert_eq!(
+            vec![
+                "app.requests,route=index,env=prod:3|c",
+                "_sc|web|0|#env:prod",
+                "app.pool.size,pool=db,env=prod:12|g",
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_file_transport() {
+        let path = temp_path(".dsd");
+        let _ = std::fs::remove_file(&path);
+        let file_path = path.to_str().unwrap().to_owned();
+
+        let client = Client::new(
+            OptionsBuilder::new()
+                .file_path(file_path.clone())
+                .default_tag("env:prod".into())
+                .build(),
+        )
+        .unwrap();
+        client.incr("first", [] as [&str; 0]).unwrap();
+        drop(client);
+        let client = Client::new(
+            OptionsBuilder::new()
+                .file_path(file_path.clone())
+                .batching_options(BatchingOptions::default())
+                .build(),
+        )
+        .unwrap();
+        client.gauge("second", "2", ["tag"]).unwrap();
+        client.writer.flush();
+        assert_eq!(
+            "first:1|c|#env:prod\nsecond:2|g|#tag\n",
+            std::fs::read_to_string(&path).unwrap()
+        );
+
+        let sink = CaptureSink::new();
+        let client = sink.client(OptionsBuilder::new().namespace("ignored".into()).build());
+        assert_eq!(2, client.replay_file(&path).unwrap());
+        assert_eq!(
+            vec!["first:1|c|#env:prod\nsecond:2|g|#tag"],
+            sink.payloads()
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(client.replay_file(&path).is_err());
+
+        assert!(Client::new(
+            OptionsBuilder::new()
+                .file_path(file_path)
+                .transport(Transport::Tcp)
+                .build()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_reserved_tags() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .reserved_tags(ReservedTags::new(ReservedTagAction::Reject).allow("device"))
+                .build(),
+        );
+
+        match client.incr("requests", ["host:web-1"]) {
+            Err(DogstatsdError::ReservedTag(tag)) => assert_eq!("host:web-1", tag),
+            result => panic!("unexpected result {:?}", result),
+        }
+        client.incr("requests", ["device:sda1"]).unwrap();
+        assert_eq!(vec!["requests:1|c|#device:sda1"], sink.payloads());
+        assert_eq!(1, client.stats().dropped(DropReason::Invalid));
+
+        let warnings = Arc::new(Mutex::new(vec![]));
+        let warnings_r = warnings.clone();
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .reserved_tags(ReservedTags::new(ReservedTagAction::Warn))
+                .error_handler(ErrorHandler::new(move |error| {
+                    warnings_r.lock().unwrap().push(error.to_string())
+                }))
+                .build(),
+        );
+        client.incr("requests", ["source:nginx"]).unwrap();
+        assert_eq!(vec!["requests:1|c|#source:nginx"], sink.payloads());
+        assert_eq!(
+            vec!["tag source:nginx sets a key reserved by Datadog".to_string()],
+            *warnings.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_timed_lock() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        let mutex = Mutex::new(1);
+
+        {
+            let mut guard = client.timed_lock(&mutex, "lock", ["tag1"]).unwrap();
+            *guard += 1;
+            assert_eq!(1, sink.payloads().len());
+        }
+        assert_eq!(2, *mutex.lock().unwrap());
+
+        let lock = RwLock::new(1);
+        assert_eq!(1, *client.timed_read(&lock, "rwlock", ["tag1"]).unwrap());
+        *client.timed_write(&lock, "rwlock", ["tag1"]).unwrap() = 2;
+        assert_eq!(2, *lock.read().unwrap());
+
+        let stats: Vec<String> = sink
+            .payloads()
+            .iter()
+            .map(|payload| {
+                assert!(payload.ends_with("|d|#tag1"), "{}", payload);
+                payload.split(':').next().unwrap().to_owned()
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "lock.wait",
+                "lock.hold",
+                "rwlock.wait",
+                "rwlock.hold",
+                "rwlock.wait",
+                "rwlock.hold"
+            ],
+            stats
+        );
+    }
+
+    #[test]
+    fn test_timed_lock_poisoned() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        let mutex = Mutex::new(1);
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = mutex.lock().unwrap();
+            panic!("poison the lock");
+        });
+
+        let guard = client
+            .timed_lock(&mutex, "lock", ["tag1"])
+            .unwrap_err()
+            .into_inner();
+        assert_eq!(1, *guard);
+        drop(guard);
+        assert_eq!(2, sink.payloads().len());
+    }
+
+    #[test]
+    fn test_tag_rate_limit() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .tag_rate_limit(TagRateLimit::new("customer_id", 1, 2))
+                .build(),
+        );
+
+        for _ in 0..5 {
+            client.incr("requests", ["customer_id:noisy"]).unwrap();
+        }
+        client.incr("requests", ["customer_id:quiet"]).unwrap();
+        client.incr("requests", ["tag1"]).unwrap();
+
+        assert_eq!(4, sink.payloads().len());
+        assert_eq!(3, client.stats().dropped(DropReason::RateLimited));
+        assert_eq!(
+            HashMap::from([(String::from("noisy"), 3)]),
+            client.rate_limited_by_tag()
+        );
+    }
+
+    #[test]
+    fn test_adaptive_sampling() {
+        let sink = CaptureSink::new();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .adaptive_sampling(AdaptiveSampling {
+                    interval: Duration::ZERO,
+                    min_sample_rate: 0.5,
+                })
+                .error_handler(ErrorHandler::new(move |error| {
+                    errors_r.lock().unwrap().push(format!("{}", error))
+                }))
+                .build(),
+        );
+        assert_eq!(1.0, client.sample_rate());
+
+        client.writer.stats.record_drop(DropReason::SendFailed, 1);
+        client.gauge("gauge", "1", ["tag1"]).unwrap();
+        assert_eq!(0.5, client.sample_rate());
+        assert_eq!(
+            vec!["sample rate adjusted from 1 to 0.5".to_string()],
+            *errors.lock().unwrap()
+        );
+        assert_eq!(vec!["gauge:1|g|#tag1"], sink.payloads());
+
+        for _ in 0..100 {
+            client.writer.stats.record_drop(DropReason::SendFailed, 1);
+            client.incr("counter", ["tag1"]).unwrap();
+        }
+        let counters = &sink.payloads()[1..];
+        assert!(counters
+            .iter()
+            .all(|payload| payload == "counter:1|c|@0.5|#tag1"));
+        assert_eq!(
+            100,
+            counters.len() as u64 + client.stats().dropped(DropReason::Sampled)
+        );
+    }
+
+    #[test]
+    fn test_send_outcome() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .adaptive_sampling(AdaptiveSampling {
+                    interval: Duration::ZERO,
+                    min_sample_rate: 0.5,
+                })
+                .route_hook(RouteHook::new(|draft| match draft.name() {
+                    "dropped" => RouteDecision::Drop,
+                    _ => RouteDecision::Send,
+                }))
+                .slo_bucket(Duration::from_millis(100))
+                .error_handler(ErrorHandler::new(|_| {}))
+                .build(),
+        );
+
+        let tags = [] as [&str; 0];
+        assert_eq!(
+            SendOutcome::Sent,
+            client.timing_with_outcome("timing", 50, tags).unwrap()
+        );
+        assert_eq!(
+            SendOutcome::Dropped,
+            client.histogram_with_outcome("dropped", "1", tags).unwrap()
+        );
+        assert_eq!(
+            SendOutcome::Dropped,
+            Client::noop()
+                .count_with_outcome("counter", 1, tags)
+                .unwrap()
+        );
+        assert_eq!(
+            SendOutcome::Dropped,
+            client.set_with_outcome("dropped", "member", tags).unwrap()
+        );
+        assert_eq!(
+            SendOutcome::Dropped,
+            Client::noop()
+                .event_with_outcome("title", "text", tags)
+                .unwrap()
+        );
+
+        let rolled_up = sink.client(
+            OptionsBuilder::new()
+                .gauge_rollup(GaugeRollup {
+                    prefixes: vec!["rolled.".into()],
+                    interval: Duration::from_secs(60),
+                })
+                .build(),
+        );
+        assert_eq!(
+            SendOutcome::RolledUp,
+            rolled_up
+                .gauge_with_outcome("rolled.depth", "3", tags)
+                .unwrap()
+        );
+        // Relative gauges can't be rolled up, so they're sent as is.
+        assert_eq!(
+            SendOutcome::Sent,
+            rolled_up
+                .gauge_with_outcome("rolled.depth", "+1", tags)
+                .unwrap()
+        );
+
+        // Every outcome matches what was sent once the sample rate is lowered.
+        let mut sent = 0;
+        for _ in 0..100 {
+            client.writer.stats.record_drop(DropReason::SendFailed, 1);
+            match client.distribution_with_outcome("distribution", "x", tags) {
+                Ok(SendOutcome::Sent) => sent += 1,
+                Ok(SendOutcome::Sampled) => {}
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+        assert_eq!(100 - client.stats().dropped(DropReason::Sampled), sent);
+        assert_eq!(sent as usize + 3, sink.payloads().len());
+    }
+
+    #[test]
+    fn test_normalize_metric_names() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace("my-app".into())
+                .normalize_metric_names(true)
+                .build(),
+        );
+        client.incr("http requests", ["path:/a-b"]).unwrap();
+        client.event("Deploy-1", "text", [] as [&str; 0]).unwrap();
+
+        let truncating = sink.client(
+            OptionsBuilder::new()
+                .normalize_metric_names(true)
+                .max_payload_size(30)
+                .build(),
+        );
+        truncating
+            .incr("--long  name--", ["tag:first", "tag:second"])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "my_app.http_requests:1|c|#path:/a-b",
+                "_e{8,4}:Deploy-1|text",
+                "long_name:1|c|#tag:first",
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_sample_rate_correction() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .adaptive_sampling(AdaptiveSampling {
+                    interval: Duration::ZERO,
+                    min_sample_rate: 0.5,
+                })
+                .sample_rate_correction(true)
+                .error_handler(ErrorHandler::new(|_| {}))
+                .build(),
+        );
+
+        for _ in 0..100 {
+            client.writer.stats.record_drop(DropReason::SendFailed, 1);
+            client.count("counter", 3, ["tag1"]).unwrap();
+            client.writer.stats.record_drop(DropReason::SendFailed, 1);
+            client.timing("timing", 5, ["tag1"]).unwrap();
+        }
+        let payloads = sink.payloads();
+        assert!(!payloads.is_empty());
+        for payload in payloads {
+            assert!(
+                payload == "counter:6|c|#tag1" || payload == "timing:5|ms|@0.5|#tag1",
+                "{}",
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .batching_options(BatchingOptions {
+                    max_buffer_size: 1024,
+                    max_time: Duration::from_secs(60),
+                    max_retry_attempts: 0,
+                    initial_retry_delay: 0,
+                })
+                .build(),
+        );
+        client.incr("first", ["tag1"]).unwrap();
+        client.pause();
+        client.incr("second", ["tag1"]).unwrap();
+
+        let report = client.shutdown();
+        assert_eq!(2, report.flushed_metrics);
+        assert_eq!(33, report.flushed_bytes);
+        assert_eq!(0, report.dropped_metrics);
+        // The batch processor ends with an empty batch, since everything was already flushed.
+        assert_eq!(
+            vec![
+                "first:1|c|#tag1\nsecond:1|c|#tag1\n".to_string(),
+                String::new()
+            ],
+            sink.wait_for_payloads(2, Duration::from_secs(5))
+        );
+
+        let client = sink.client(Options::default());
+        client.incr("third", ["tag1"]).unwrap();
+        assert_eq!(0, client.shutdown().flushed_metrics);
+    }
+
+    #[test]
+    fn test_distribution_sketch() {
+        let sink = CaptureSink::new();
+        let client = sink.client(OptionsBuilder::new().max_payload_size(32).build());
+
+        client
+            .distribution_sketch(
+                "latency",
+                &[(1.5, 4), (2.0, 1), (3.0, 4), (4.0, 0), (5.25, 4), (6.0, 4)],
+                ["tag1"],
+            )
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "latency:2|d|#tag1",
+                "latency:1.5:3:5.25|d|@0.25|#tag1",
+                "latency:6|d|@0.25|#tag1",
+            ],
+            sink.payloads()
+        );
+        assert_eq!(1, client.stats().packed_splits);
+    }
+
+    #[test]
+    fn test_distribution_sketch_keeps_trailing_values() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .max_payload_size(24)
+                .oversized_metric_policy(OversizedMetricPolicy::Reject)
+                .float_precision(17)
+                .build(),
+        );
+
+        let result = client.distribution_sketch(
+            "latency",
+            &[(1.0, 2), (123456789.125, 2), (3.0, 2), (4.0, 2)],
+            [] as [&str; 0],
+        );
+
+        assert!(matches!(
+            result,
+            Err(DogstatsdError::PayloadTooLarge { .. })
+        ));
+        assert_eq!(
+            vec!["latency:1|d|@0.5", "latency:3:4|d|@0.5"],
+            sink.payloads()
+        );
+        assert_eq!(2, client.stats().packed_splits);
+        assert_eq!(1, client.stats().dropped(DropReason::Invalid));
+    }
+
+    #[test]
+    fn test_time_infallible() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .max_payload_size(24)
+                .oversized_metric_policy(OversizedMetricPolicy::Reject)
+                .error_handler(ErrorHandler::new({
+                    let errors = errors.clone();
+                    move |error| errors.lock().unwrap().push(error.to_string())
+                }))
+                .build(),
+        );
+
+        assert_eq!(2, client.time_infallible("timer", ["tag:1"], || 1 + 1));
+        assert_eq!(1, sink.payloads().len());
+        assert!(errors.lock().unwrap().is_empty());
+
+        let output = client.time_infallible("a_much_longer_timer", ["tag:1"], || "done");
+        assert_eq!("done", output);
+        assert_eq!(1, sink.payloads().len());
+        assert_eq!(1, errors.lock().unwrap().len());
+    }
+
+    #[test]
+    fn test_for_namespace() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace(String::from("shop"))
+                .default_tag(String::from("env:test"))
+                .build(),
+        );
+        let billing = client.for_namespace("billing");
+
+        client.incr("orders", ["tag1"]).unwrap();
+        billing.incr("invoices", ["tag1"]).unwrap();
+        drop(client);
+        billing.gauge("balance", "12", ["tag1"]).unwrap();
+
+        assert_eq!(
+            vec![
+                "shop.orders:1|c|#tag1,env:test",
+                "billing.invoices:1|c|#tag1,env:test",
+                "billing.balance:12|g|#tag1,env:test",
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_config_banner() {
+        let options = || {
+            OptionsBuilder::new()
+                .namespace("app".into())
+                .config_banner(true)
+                .build()
+        };
+        let config_hash = config_hash(&options());
+        assert_eq!(8, config_hash.len());
+        assert_eq!(config_hash, super::config_hash(&options()));
+        assert_ne!(config_hash, super::config_hash(&Options::default()));
+
+        let sink = CaptureSink::new();
+        let client = sink.client(options());
+        client.incr("requests", ["env:prod"]).unwrap();
+        assert_eq!(
+            vec![
+                format!(
+                    "datadog.dogstatsd.client.config:1|g|#config_hash:{},client:rust,client_version:{},client_transport:udp",
+                    config_hash, VERSION
+                ),
+                String::from("app.requests:1|c|#env:prod"),
+            ],
+            sink.payloads()
+        );
+
+        // Only sent when it's turned on.
+        sink.client(Options::default());
+        assert_eq!(2, sink.payloads().len());
+    }
+
+    #[test]
+    fn test_thread_tag() {
+        let sink = CaptureSink::new();
+        let client = sink.client(OptionsBuilder::new().thread_tag(true).build());
+        thread::scope(|scope| {
+            thread::Builder::new()
+                .name("worker|1,a".into())
+                .spawn_scoped(scope, || client.incr("jobs", ["env:prod"]).unwrap())
+                .unwrap();
+            scope.spawn(|| client.incr("unnamed", [] as [&str; 0]).unwrap());
+        });
+
+        let mut payloads = sink.payloads();
+        payloads.sort();
+        assert_eq!(
+            vec!["jobs:1|c|#env:prod,thread:worker_1_a", "unnamed:1|c"],
+            payloads
+        );
+    }
+
+    #[test]
+    fn test_host_and_pid_tags() {
+        let client = Client::new(
+            OptionsBuilder::new()
+                .default_tag(String::from("tag1"))
+                .host_tag(true)
+                .pid_tag(true)
+                .build(),
+        )
+        .unwrap();
+
+        let tags = client.default_tags();
+        assert_eq!("tag1", tags[0]);
+        assert_eq!(
+            Some(format!("host:{}", hostname().unwrap()).as_str()),
+            tags.iter().find(|tag| tag.starts_with("host:")).copied()
+        );
+        assert!(tags.contains(&format!("pid:{}", std::process::id()).as_str()));
+
+        let client = Client::new(
+            OptionsBuilder::new()
+                .default_tag(String::from("host:proxy-1"))
+                .host_tag(true)
+                .build(),
+        )
+        .unwrap();
+        assert_eq!(
+            1,
+            client
+                .default_tags()
+                .iter()
+                .filter(|tag| tag.starts_with("host:"))
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_new_default_tags() {
+        let options = Options::new(
+            DEFAULT_FROM_ADDR,
+            DEFAULT_TO_ADDR,
+            "",
+            vec![String::from("tag1:tag1val")],
+            None,
+            None,
+        );
+        let client = Client::new(options).unwrap();
+        let expected_client = Client {
+            writer: default_writer(),
+            from_addr: DEFAULT_FROM_ADDR.into(),
+            to_addr: DEFAULT_TO_ADDR.into(),
+            probe_target: ProbeTarget::Udp,
+            namespace: String::new(),
+            default_tags: String::from("tag1:tag1val").into_bytes().into(),
+            tag_prefix: None,
+            max_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            oversized_metric_policy: OversizedMetricPolicy::TruncateTags,
+            max_tags_bytes: None,
+            thread_tag: false,
+            rollup: None,
+            router: None,
+            slo_buckets: vec![],
+            telemetry_tags: Default::default(),
+            rate_limiter: None,
+            sampler: None,
+            aggregation_key_generator: None,
+            metric_sender: OnceLock::new(),
+            sample_rate_correction: false,
+            normalize_metric_names: false,
+            float_precision: DEFAULT_FLOAT_PRECISION,
+            reserved_tags: None,
+            encoder: None,
+            stats_report_interval: None,
+            last_stats_report: Mutex::new((Instant::now(), ClientStats::default())),
+            keep_alives: KeepAlives::new(DEFAULT_KEEP_ALIVE_INTERVAL),
+        };
 
-        // Apply additional options if provided
-        if let Some(options) = options {
-            if let Some(timestamp) = options.timestamp {
-                event = event.with_timestamp(timestamp);
-            }
-            if let Some(hostname) = options.hostname {
-                event = event.with_hostname(hostname);
-            }
-            if let Some(aggregation_key) = options.aggregation_key {
-                event = event.with_aggregation_key(aggregation_key);
-            }
-            if let Some(priority) = options.priority {
-                event = event.with_priority(priority);
-            }
-            if let Some(source_type_name) = options.source_type_name {
-                event = event.with_source_type_name(source_type_name);
+        assert_eq!(expected_client, client)
+    }
+
+    #[test]
+    fn test_system_tags() {
+        let options = Options::new(
+            DEFAULT_FROM_ADDR,
+            DEFAULT_TO_ADDR,
+            "",
+            vec![String::from("tag1:tag1val"), String::from("version:0.0.2")],
+            None,
+            None,
+        );
+
+        let client = with_default_system_tags(|| Client::new(options).unwrap());
+
+        dbg!(String::from_utf8_lossy(client.default_tags.as_bytes()));
+
+        let expected_client = Client {
+            writer: default_writer(),
+            from_addr: DEFAULT_FROM_ADDR.into(),
+            to_addr: DEFAULT_TO_ADDR.into(),
+            probe_target: ProbeTarget::Udp,
+            namespace: String::new(),
+            default_tags: String::from("tag1:tag1val,version:0.0.2,env:production,service:service")
+                .into_bytes()
+                .into(),
+            tag_prefix: None,
+            max_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            oversized_metric_policy: OversizedMetricPolicy::TruncateTags,
+            max_tags_bytes: None,
+            thread_tag: false,
+            rollup: None,
+            router: None,
+            slo_buckets: vec![],
+            telemetry_tags: Default::default(),
+            rate_limiter: None,
+            sampler: None,
+            aggregation_key_generator: None,
+            metric_sender: OnceLock::new(),
+            sample_rate_correction: false,
+            normalize_metric_names: false,
+            float_precision: DEFAULT_FLOAT_PRECISION,
+            reserved_tags: None,
+            encoder: None,
+            stats_report_interval: None,
+            last_stats_report: Mutex::new((Instant::now(), ClientStats::default())),
+            keep_alives: KeepAlives::new(DEFAULT_KEEP_ALIVE_INTERVAL),
+        };
+
+        assert_eq!(expected_client, client)
+    }
+
+    #[test]
+    fn test_default_tags() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .default_tag(String::from("région:île-de-france"))
+                .default_tag(String::from("city:東京"))
+                .build(),
+        );
+
+        assert_eq!(
+            vec!["région:île-de-france", "city:東京"],
+            client.default_tags()
+        );
+        assert_eq!(
+            "région:île-de-france,city:東京".as_bytes(),
+            client.default_tag_bytes()
+        );
+
+        client.incr("compteur", ["emoji:🚀"]).unwrap();
+        assert_eq!(
+            vec!["compteur:1|c|#emoji:🚀,région:île-de-france,city:東京".to_string()],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_measure_send_latency() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to_addr = server.local_addr().unwrap().to_string();
+
+        let client = Client::new(OptionsBuilder::new().to_addr(to_addr.clone()).build()).unwrap();
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        assert_eq!(None, client.stats().send_latency_p50);
+
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(to_addr)
+                .measure_send_latency(true)
+                .build(),
+        )
+        .unwrap();
+        assert_eq!(None, client.stats().send_latency_p50);
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        let stats = client.stats();
+        assert!(stats.send_latency_p50.is_some());
+        assert!(stats.send_latency_p99 >= stats.send_latency_p50);
+    }
+
+    #[test]
+    fn test_likely_kernel_drops() {
+        // Nothing reads from `_receiver`, so the sender's buffer fills up.
+        let (sender, _receiver) = UnixDatagram::pair().unwrap();
+        sender.set_nonblocking(true).unwrap();
+        let client =
+            Client::with_socket(Options::default(), SocketType::Uds(sender), Clock::System)
+                .unwrap();
+
+        let mut failures = 0;
+        while failures < 3 {
+            if client.incr("counter", ["tag1"]).is_err() {
+                failures += 1;
             }
-            if let Some(alert_type) = options.alert_type {
-                event = event.with_alert_type(alert_type);
+        }
+
+        assert_eq!(3, client.stats().likely_kernel_drops);
+    }
+
+    #[test]
+    fn test_oversized_metric() {
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .max_payload_size(26)
+                .default_tag(String::from("env:prod"))
+                .error_handler(ErrorHandler::new(move |error| {
+                    errors_r.lock().unwrap().push(error.to_string());
+                }))
+                .build(),
+        );
+
+        client.incr("counter", ["tag1", "tag2", "tag3"]).unwrap();
+        assert_eq!(
+            vec!["counter:1|c|#tag1,env:prod".to_string()],
+            sink.payloads()
+        );
+        assert_eq!(
+            vec!["payload for metric counter is 36 bytes, over the limit of 26".to_string()],
+            *errors.lock().unwrap()
+        );
+        assert_eq!(1, client.stats().truncated_oversized);
+
+        match client.incr("a_much_longer_counter_name", ["tag1"]) {
+            Err(DogstatsdError::PayloadTooLarge { metric, .. }) => {
+                assert_eq!("a_much_longer_counter_name", metric)
             }
+            result => panic!("unexpected result {:?}", result),
         }
+        assert_eq!(1, client.stats().dropped(DropReason::Invalid));
+    }
 
-        self.send(&event, tags)
+    #[test]
+    fn test_max_tags_bytes() {
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .max_tags_bytes(20)
+                .default_tag(String::from("env:prod"))
+                .error_handler(ErrorHandler::new(move |error| {
+                    errors_r.lock().unwrap().push(error.to_string());
+                }))
+                .build(),
+        );
+
+        client.incr("counter", ["tag1", "tag2"]).unwrap();
+        client.incr("counter", ["tag1", "tag2", "tag3"]).unwrap();
+        client.incr("counter", ["a_very_long_tag:value"]).unwrap();
+        assert_eq!(
+            vec![
+                "counter:1|c|#tag1,tag2,env:prod".to_string(),
+                "counter:1|c|#tag1,tag2,env:prod".to_string(),
+                "counter:1|c|#env:prod".to_string(),
+            ],
+            sink.payloads()
+        );
+        assert_eq!(
+            vec![
+                "tags for metric counter are 23 bytes, over the limit of 20".to_string(),
+                "tags for metric counter are 30 bytes, over the limit of 20".to_string(),
+            ],
+            *errors.lock().unwrap()
+        );
+        assert_eq!(2, client.stats().truncated_tags);
+
+        // Tags are measured with their prefix, and counted without an error handler.
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .max_tags_bytes(20)
+                .tag_prefix("app".into())
+                .build(),
+        );
+        client
+            .incr("counter", ["tag1", "env:prod", "tag2"])
+            .unwrap();
+        assert_eq!(
+            vec!["counter:1|c|#app.tag1,env:prod".to_string()],
+            sink.payloads()
+        );
+        assert_eq!(1, client.stats().truncated_tags);
     }
 
-    fn send<I, M, S>(&self, metric: &M, tags: I) -> DogstatsdResult
-    where
-        I: IntoIterator<Item = S>,
-        M: Metric,
-        S: AsRef<str>,
-    {
-        let formatted_metric = format_for_send(metric, &self.namespace, tags, &self.default_tags);
-        match &self.socket {
-            SocketType::Udp(socket) => {
-                socket.send_to(formatted_metric.as_slice(), &self.to_addr)?;
-            }
-            SocketType::Uds(socket) => {
-                socket.send(formatted_metric.as_slice())?;
-            }
-            SocketType::BatchableUdp(tx_channel) | SocketType::BatchableUds(tx_channel) => {
-                tx_channel
-                    .lock()
-                    .expect("Mutex poisoned...")
-                    .send(batch_processor::Message::Data(formatted_metric))
-                    .unwrap_or_else(|error| {
-                        println!("Exception occurred when writing to channel: {:?}", error);
-                    });
+    #[test]
+    fn test_oversized_metric_rejected() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .max_payload_size(17)
+                .oversized_metric_policy(OversizedMetricPolicy::Reject)
+                .build(),
+        );
+
+        assert!(client.incr("counter", ["tag1", "tag2"]).is_err());
+        client.incr("counter", ["tag1"]).unwrap();
+        assert_eq!(vec!["counter:1|c|#tag1".to_string()], sink.payloads());
+    }
+
+    #[test]
+    fn test_route_hook() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listener_addr = listener.local_addr().unwrap().to_string();
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .route_hook(RouteHook::new(move |draft| match draft.name() {
+                    "dropped" => RouteDecision::Drop,
+                    "redirected" => RouteDecision::Redirect(listener_addr.clone()),
+                    "duplicated" => RouteDecision::Duplicate(listener_addr.clone()),
+                    _ => RouteDecision::Send,
+                }))
+                .build(),
+        );
+
+        let recv = || {
+            let mut buf = [0; 64];
+            let len = listener.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        };
+
+        client.incr("sent", ["tag1"]).unwrap();
+        client.incr("dropped", ["tag1"]).unwrap();
+        client.incr("redirected", ["tag1"]).unwrap();
+        assert_eq!("redirected:1|c|#tag1", recv());
+        client.incr("duplicated", ["tag1"]).unwrap();
+        assert_eq!("duplicated:1|c|#tag1", recv());
+
+        assert_eq!(
+            vec![
+                "sent:1|c|#tag1".to_string(),
+                "duplicated:1|c|#tag1".to_string()
+            ],
+            sink.payloads()
+        );
+        assert_eq!(1, client.stats().dropped(DropReason::Filtered));
+    }
+
+    #[test]
+    fn test_deploy_event() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        client
+            .deploy_event("checkout", "v1.4.2", ["env:production"])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "_e{24,39}:Deployed checkout v1.4.2|checkout was deployed at version v1.4.2|k:deploy:checkout|p:normal|s:deployment|t:info|#env:production,service:checkout,version:v1.4.2"
+                    .to_string()
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_event_bytes() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .default_tag(String::from("env:prod"))
+                .aggregation_key_generator(AggregationKeyGenerator::new(|title, _| {
+                    Some(format!("trace:{}", title.len()))
+                }))
+                .max_payload_size(70)
+                .build(),
+        );
+        client
+            .event_bytes(b"Worker crashed", b"reset by peer", ["tag:event"])
+            .unwrap();
+        client
+            .event("Worker crashed", "reset by peer", ["tag:event"])
+            .unwrap();
+        client
+            .event_bytes(b"Bad", b"byte \xff", [] as [&str; 0])
+            .unwrap();
+        // Too large with the tag, which is truncated like any other event's.
+        client
+            .event_bytes(b"Worker crashed", b"reset by peer, twice", ["tag:event"])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "_e{14,13}:Worker crashed|reset by peer|k:trace:14|#tag:event,env:prod".to_string(),
+                "_e{14,13}:Worker crashed|reset by peer|k:trace:14|#tag:event,env:prod".to_string(),
+                "_e{3,6}:Bad|byte \u{fffd}|k:trace:3|#env:prod".to_string(),
+                "_e{14,20}:Worker crashed|reset by peer, twice|k:trace:14|#env:prod".to_string(),
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_aggregation_key_generator() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .aggregation_key_generator(AggregationKeyGenerator::new(|title, _| {
+                    Some(format!("trace:{}", title.len()))
+                }))
+                .build(),
+        );
+        client.event("Title", "Text", ["tag"]).unwrap();
+        client
+            .event_with_options(
+                "Title",
+                "Text",
+                ["tag"],
+                Some(EventOptions::new().with_aggregation_key("explicit")),
+            )
+            .unwrap();
+        client
+            .deploy_event("checkout", "v1", [] as [&str; 0])
+            .unwrap();
+
+        let payloads = sink.payloads();
+        assert_eq!("_e{5,4}:Title|Text|k:trace:5|#tag", payloads[0]);
+        assert_eq!("_e{5,4}:Title|Text|k:explicit|#tag", payloads[1]);
+        assert!(payloads[2].contains("|k:deploy:checkout|"));
+    }
+
+    #[cfg(feature = "trace-context")]
+    #[test]
+    fn test_trace_context() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .tag_prefix("app".into())
+                .default_tag("env:test".into())
+                .build(),
+        );
+        {
+            let _guard = TraceContext {
+                trace_id: 12,
+                span_id: 34,
             }
+            .enter();
+            client.incr("traced", ["tag"]).unwrap();
+            client.event("Title", "Text", [] as [&str; 0]).unwrap();
         }
-        Ok(())
+        client.incr("untraced", ["tag"]).unwrap();
+
+        assert_eq!(
+            vec![
+                "traced:1|c|#app.tag,dd.trace_id:12,dd.span_id:34,env:test",
+                "_e{5,4}:Title|Text|#dd.trace_id:12,dd.span_id:34,env:test",
+                "untraced:1|c|#app.tag,env:test",
+            ],
+            sink.payloads()
+        );
     }
-}
 
-/// Configuration options for an `Event`.
-///
-/// `EventOptions` provides additional optional metadata that can be attached
-/// to an event, enabling greater flexibility and contextual information
-/// for event handling and monitoring systems.
-///
-/// # Example
-///
-/// ```rust
-/// use dogstatsd::{EventOptions, EventAlertType, EventPriority};
-///
-/// let options = EventOptions {
-///     timestamp: Some(1638480000),
-///     hostname: Some("localhost"),
-///     aggregation_key: Some("service_down"),
-///     priority: Some(EventPriority::Normal),
-///     source_type_name: Some("monitoring"),
-///     alert_type: Some(EventAlertType::Error),
-/// };
-/// ```
-///
-#[derive(Default, Clone, Copy, Debug)]
-pub struct EventOptions<'a> {
-    /// Optional Unix timestamp representing the event time. The default is the current Unix epoch timestamp.
-    pub timestamp: Option<u64>,
-    /// Optional hostname associated with the event.
-    pub hostname: Option<&'a str>,
-    /// Optional key for grouping related events.
-    pub aggregation_key: Option<&'a str>,
-    /// Optional priority level of the event, e.g., `"low"` or `"normal"`.
-    pub priority: Option<EventPriority>,
-    /// Optional source type name of the event, e.g., `"monitoring"`.
-    pub source_type_name: Option<&'a str>,
-    /// Optional alert type for the event, e.g., `"error"`, `"warning"`,  `"info"`, `"success"`. Default `"info"`.
-    pub alert_type: Option<EventAlertType>,
-}
+    #[test]
+    fn test_error_event() {
+        #[derive(Debug)]
+        struct PaymentError(io::Error);
 
-impl<'a> EventOptions<'a> {
-    /// Creates a new `EventOptions` instance with all fields set to `None`.
-    pub fn new() -> Self {
-        EventOptions {
-            timestamp: None,
-            hostname: None,
-            aggregation_key: None,
-            priority: None,
-            source_type_name: None,
-            alert_type: None,
+        impl fmt::Display for PaymentError {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "card declined")
+            }
+        }
+
+        impl std::error::Error for PaymentError {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
         }
+
+        let sink = CaptureSink::new();
+        let client = sink.client(OptionsBuilder::new().namespace("shop".into()).build());
+        let error = PaymentError(io::Error::other("gateway\ntimed out"));
+        client
+            .error_event("payment failed", &error, ["env:production"])
+            .unwrap();
+
+        let error_type = "error_type:PaymentError";
+        assert_eq!(
+            vec![
+                format!(
+                    "_e{{14,44}}:payment failed|card declined\\ncaused by: gateway\\ntimed out|t:error|#env:production,{}",
+                    error_type
+                ),
+                format!("shop.errors:1|c|#env:production,{}", error_type),
+            ],
+            sink.payloads()
+        );
     }
-    /// Sets the `hostname` for the event.
-    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
-        self.timestamp = Some(timestamp);
-        self
+
+    #[test]
+    fn test_error_event_text() {
+        let error = io::Error::other("x".repeat(5000));
+        let text = error_event_text(&error);
+        assert_eq!(MAX_EVENT_TEXT_LEN, text.len());
+        assert!(text.ends_with("..."));
+
+        let error = io::Error::other("é".repeat(3000));
+        assert!(error_event_text(&error).len() <= MAX_EVENT_TEXT_LEN);
     }
 
-    /// Sets the `hostname` for the event.
-    pub fn with_hostname(mut self, hostname: &'a str) -> Self {
-        self.hostname = Some(hostname);
-        self
+    #[test]
+    fn test_error_type_name() {
+        assert_eq!("Error", error_type_name(std::any::type_name::<io::Error>()));
+        assert_eq!(
+            "Wrapper",
+            error_type_name("app::Wrapper<core::num::ParseIntError, app::Other>")
+        );
+        assert_eq!(
+            "Error",
+            error_type_name(std::any::type_name::<dyn std::error::Error + Send>())
+        );
+        assert_eq!("a_b", error_type_name("a,b"));
     }
 
-    /// Sets the `aggregation_key` for the event.
-    pub fn with_aggregation_key(mut self, aggregation_key: &'a str) -> Self {
-        self.aggregation_key = Some(aggregation_key);
-        self
+    #[test]
+    fn test_slo_buckets() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .slo_bucket(Duration::from_millis(500))
+                .slo_bucket(Duration::from_millis(100))
+                .slo_bucket(Duration::from_secs(2))
+                .build(),
+        );
+
+        client.timing("request", 100, ["tag1"]).unwrap();
+        client.timing("request", 350, ["tag1"]).unwrap();
+        client.timing("request", 2500, ["tag1"]).unwrap();
+        // Distributions aren't necessarily durations, so they aren't bucketed.
+        client.distribution("request", "2500.5", ["tag1"]).unwrap();
+
+        assert_eq!(
+            vec![
+                "request:100|ms|#tag1",
+                "request.slo_bucket:1|c|#tag1,le:100ms",
+                "request:350|ms|#tag1",
+                "request.slo_bucket:1|c|#tag1,le:500ms",
+                "request:2500|ms|#tag1",
+                "request.slo_bucket:1|c|#tag1,le:inf",
+                "request:2500.5|d|#tag1",
+            ],
+            sink.payloads()
+        );
+
+        // Timings that aren't sent aren't counted in a bucket either.
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .slo_bucket(Duration::from_millis(100))
+                .route_hook(RouteHook::new(|_| RouteDecision::Drop))
+                .build(),
+        );
+        assert_eq!(
+            SendOutcome::Dropped,
+            client.timing_with_outcome("request", 50, ["tag1"]).unwrap()
+        );
+        assert!(sink.payloads().is_empty());
+    }
+
+    #[test]
+    fn test_tag_prefix() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .tag_prefix(String::from("custom"))
+                .default_tag(String::from("team:foo"))
+                .default_tag(String::from("env:production"))
+                .build(),
+        );
+        client
+            .incr("counter", ["tag1:val", "service:api", "standalone"])
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "counter:1|c|#custom.tag1:val,service:api,custom.standalone,custom.team:foo,env:production"
+                    .to_string()
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    #[allow(clippy::useless_conversion, clippy::needless_borrows_for_generic_args)]
+    fn test_send() {
+        let options = Options::new("127.0.0.1:9001", "127.0.0.1:9002", "", vec![], None, None);
+        let client = Client::new(options).unwrap();
+        // Shouldn't panic or error
+        client
+            .send(
+                &GaugeMetric::new("gauge".into(), "1234".into()),
+                &["tag1", "tag2"],
+            )
+            .unwrap();
     }
 
-    /// Sets the `priority` for the event.
-    pub fn with_priority(mut self, priority: EventPriority) -> Self {
-        self.priority = Some(priority);
-        self
-    }
+    #[test]
+    fn test_stats_report() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut options = Options::new(
+            DEFAULT_FROM_ADDR,
+            &server.local_addr().unwrap().to_string(),
+            "",
+            vec![],
+            None,
+            None,
+        );
+        options.stats_report_interval = Some(Duration::ZERO);
+        let client = Client::new(options).unwrap();
+        client.writer.stats.record_drop(DropReason::Sampled, 3);
 
-    /// Sets the `source_type_name` for the event.
-    pub fn with_source_type_name(mut self, source_type_name: &'a str) -> Self {
-        self.source_type_name = Some(source_type_name);
-        self
-    }
+        client.incr("counter", ["tag1"]).unwrap();
 
-    /// Sets the `alert_type` for the event.
-    pub fn with_alert_type(mut self, alert_type: EventAlertType) -> Self {
-        self.alert_type = Some(alert_type);
-        self
+        let mut buf = [0; 200];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"counter:1|c|#tag1", &buf[..len]);
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            format!(
+                "datadog.dogstatsd.client.metrics_dropped:3|c|#reason:sampled,client:rust,client_version:{},client_transport:udp",
+                VERSION
+            )
+            .as_bytes(),
+            &buf[..len]
+        );
+        assert_eq!(3, client.stats().total_dropped());
     }
-}
 
-mod batch_processor {
-    use std::sync::mpsc::Receiver;
-    use std::time::SystemTime;
+    #[test]
+    fn test_stats_report_failure() {
+        let path = temp_path(".stats");
+        let _ = std::fs::remove_file(&path);
+        let agent = UnixDatagram::bind(&path).unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .socket_path(Some(path.to_str().unwrap().into()))
+                .stats_report_interval(Duration::ZERO)
+                .reconnect_options(ReconnectOptions {
+                    max_consecutive_failures: 1,
+                    initial_backoff: Duration::ZERO,
+                    max_backoff: Duration::ZERO,
+                })
+                .route_hook(RouteHook::new(|_| RouteDecision::Drop))
+                .error_handler(ErrorHandler::new(move |error| {
+                    errors_r.lock().unwrap().push(error.to_string())
+                }))
+                .build(),
+        )
+        .unwrap();
+        let mut buf = [0; 200];
+        let mut recv = |agent: &UnixDatagram| {
+            let len = agent.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        };
 
-    use retry::{delay::jitter, delay::Exponential, retry};
+        // Drops are reported even though no metric is ever sent.
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        assert!(recv(&agent)
+            .starts_with("datadog.dogstatsd.client.metrics_dropped:1|c|#reason:filtered,"));
 
-    use crate::{BatchingOptions, SocketType};
+        // A failed report doesn't fail the send, and its counts are kept for the next one.
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+        let agent = UnixDatagram::bind(&path).unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        assert_eq!(1, errors.lock().unwrap().len());
 
-    pub(crate) enum Message {
-        Data(Vec<u8>),
-        Shutdown,
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        assert!(recv(&agent)
+            .starts_with("datadog.dogstatsd.client.metrics_dropped:2|c|#reason:filtered,"));
+        let _ = std::fs::remove_file(&path);
     }
 
-    fn send_to_socket_with_retries(
-        batching_options: &BatchingOptions,
-        socket: &SocketType,
-        data: &Vec<u8>,
-        to_addr: &String,
-        socket_path: &Option<String>,
-    ) {
-        retry(
-            Exponential::from_millis(batching_options.initial_retry_delay)
-                .map(jitter)
-                .take(batching_options.max_retry_attempts),
-            || {
-                match socket {
-                    SocketType::Udp(socket) => {
-                        if let Err(error) = socket.send_to(data.as_slice(), to_addr) {
-                            return Err(error);
-                        }
-                    }
-                    SocketType::Uds(socket) => {
-                        if let Err(error) = socket.send(data.as_slice()) {
-                            // Per https://doc.rust-lang.org/stable/std/os/unix/net/struct.UnixDatagram.html#method.send
-                            // If send fails, it is due to a connection issue, so just attempt
-                            // to reconnect
-                            let socket_path_unwrapped = socket_path
-                                .as_ref()
-                                .expect("Only invoked if socket path is defined.");
-                            socket.connect(socket_path_unwrapped)?;
-
-                            return Err(error);
-                        }
-                    }
-                    SocketType::BatchableUdp(_tx_channel)
-                    | SocketType::BatchableUds(_tx_channel) => {
-                        panic!("Logic Error - socket type should not be batchable.");
-                    }
-                }
+    // With `no-panic`, a lock poisoned by a panic on another thread is used as is, rather than
+    // every later send panicking too.
+    #[cfg(feature = "no-panic")]
+    #[test]
+    fn test_no_panic_poisoned_lock() {
+        let sink = CaptureSink::new();
+        let client = Arc::new(
+            sink.client(
+                OptionsBuilder::new()
+                    .stats_report_interval(Duration::ZERO)
+                    .build(),
+            ),
+        );
+        client.writer.stats.record_drop(DropReason::Sampled, 3);
+        let poisoner = client.clone();
+        let _ = thread::spawn(move || {
+            let _last_report = poisoner.last_stats_report.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(client.last_stats_report.is_poisoned());
 
-                Ok(())
-            },
-        )
-        .unwrap_or_else(|error| {
-            println!(
-                "Failed to send within retry policy... Dropping metrics: {:?}",
-                error
-            )
-        });
+        client.incr("counter", [] as [&str; 0]).unwrap();
+        let payloads = sink.payloads();
+        assert_eq!("counter:1|c", payloads[0]);
+        assert!(payloads[1].starts_with("datadog.dogstatsd.client.metrics_dropped:3|c"));
     }
 
-    pub(crate) fn process_events(
-        batching_options: BatchingOptions,
-        to_addr: String,
-        socket: SocketType,
-        socket_path: Option<String>,
-        rx: Receiver<Message>,
-    ) {
-        let mut last_updated = SystemTime::now();
-        let mut buffer: Vec<u8> = vec![];
+    #[test]
+    fn test_pause_and_resume() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut options = Options::new(
+            DEFAULT_FROM_ADDR,
+            &server.local_addr().unwrap().to_string(),
+            "",
+            vec![],
+            None,
+            None,
+        );
+        options.max_paused_metrics = 2;
+        let client = Client::new(options).unwrap();
 
-        loop {
-            match rx.recv() {
-                Ok(Message::Data(data)) => {
-                    for ch in data {
-                        buffer.push(ch);
-                    }
-                    buffer.push(b'\n');
+        client.pause();
+        assert!(client.is_paused());
+        client.incr("first", [] as [&str; 0]).unwrap();
+        client.incr("second", [] as [&str; 0]).unwrap();
+        client.incr("third", [] as [&str; 0]).unwrap();
 
-                    let current_time = SystemTime::now();
-                    if buffer.len() >= batching_options.max_buffer_size
-                        || last_updated + batching_options.max_time < current_time
-                    {
-                        send_to_socket_with_retries(
-                            &batching_options,
-                            &socket,
-                            &buffer,
-                            &to_addr,
-                            &socket_path,
-                        );
-                        buffer.clear();
-                        last_updated = current_time;
-                    }
-                }
-                Ok(Message::Shutdown) => {
-                    send_to_socket_with_retries(
-                        &batching_options,
-                        &socket,
-                        &buffer,
-                        &to_addr,
-                        &socket_path,
-                    );
-                    buffer.clear();
-                }
-                Err(e) => {
-                    println!("Exception occurred when reading from channel: {:?}", e);
-                    break;
-                }
-            }
-        }
+        let mut buf = [0; 100];
+        assert!(server.recv(&mut buf).is_err());
+        assert_eq!(1, client.stats().dropped(DropReason::QueueFull));
+
+        client.resume().unwrap();
+        assert!(!client.is_paused());
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"first:1|c", &buf[..len]);
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"second:1|c", &buf[..len]);
+        assert!(server.recv(&mut buf).is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use metrics::GaugeMetric;
+    #[test]
+    fn test_incr_zero() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        client.incr_zero("counter", ["tag1"]).unwrap();
+        client.incr_by_value("counter", 0, ["tag1"]).unwrap();
 
-    use super::*;
+        assert_eq!(
+            vec![
+                "counter:0|c|#tag1".to_string(),
+                "counter:0|c|#tag1".to_string()
+            ],
+            sink.payloads()
+        );
+    }
 
     #[test]
-    fn test_options_default() {
-        let options = Options::default();
-        let expected_options = Options {
-            from_addr: DEFAULT_FROM_ADDR.into(),
-            to_addr: DEFAULT_TO_ADDR.into(),
-            namespace: String::new(),
-            ..Default::default()
-        };
+    fn test_keep_alive_count() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .keep_alive_interval(Duration::from_millis(10))
+                .build(),
+        );
+        let keep_alive = client.keep_alive_count("counter", ["tag1"]).unwrap();
 
-        assert_eq!(expected_options, options)
+        for _ in 0..100 {
+            if sink.payloads().len() >= 3 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(keep_alive);
+        assert!(sink.payloads().len() >= 3);
+        assert!(sink
+            .payloads()
+            .iter()
+            .all(|payload| payload == "counter:0|c|#tag1"));
+
+        // Nothing is re-sent once the handle is gone.
+        thread::sleep(Duration::from_millis(30));
+        let sent = sink.payloads().len();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(sent, sink.payloads().len());
     }
 
     #[test]
-    fn test_options_builder_none() {
-        let options = OptionsBuilder::new().build();
-        let expected_options = Options {
-            from_addr: DEFAULT_FROM_ADDR.into(),
-            to_addr: DEFAULT_TO_ADDR.into(),
-            namespace: String::new(),
-            ..Default::default()
-        };
+    fn test_persistent_gauge() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace("ns".into())
+                .keep_alive_interval(Duration::from_secs(3600))
+                .build(),
+        );
+        let gauge = client.persistent_gauge("pool.size", ["tag1"]);
 
-        assert_eq!(expected_options, options);
+        // Nothing to re-send before the first value.
+        client.keep_alives.resend(&client.writer);
+        assert!(sink.payloads().is_empty());
+
+        gauge.set("3").unwrap();
+        gauge.set("4").unwrap();
+        client.keep_alives.resend(&client.writer);
+        client.keep_alives.resend(&client.writer);
+        assert_eq!(
+            vec![
+                "ns.pool.size:3|g|#tag1",
+                "ns.pool.size:4|g|#tag1",
+                "ns.pool.size:4|g|#tag1",
+                "ns.pool.size:4|g|#tag1",
+            ],
+            sink.payloads()
+        );
+
+        // Nothing is re-sent once the handle is gone.
+        drop(gauge);
+        client.keep_alives.resend(&client.writer);
+        assert_eq!(4, sink.payloads().len());
     }
 
     #[test]
-    fn teset_options_builder_all() {
-        let options = OptionsBuilder::new()
-            .from_addr("127.0.0.2:0".into())
-            .to_addr("127.0.0.2:8125".into())
-            .namespace("mynamespace".into())
-            .default_tag(String::from("tag1:tag1val"))
-            .build();
-        let expected_options = Options {
-            from_addr: "127.0.0.2:0".into(),
-            to_addr: "127.0.0.2:8125".into(),
-            namespace: "mynamespace".into(),
-            default_tags: vec!["tag1:tag1val".into()].to_vec(),
-            socket_path: None,
-            batching_options: None,
-        };
+    fn test_batch_processor_drains_on_disconnect() {
+        let sink = CaptureSink::new();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let (tx, rx) = mpsc::channel();
+        let processor = thread::spawn(move || {
+            batch_processor::process_events(
+                BatchingOptions {
+                    max_buffer_size: 1024,
+                    max_time: Duration::from_secs(60),
+                    max_retry_attempts: 0,
+                    initial_retry_delay: 0,
+                },
+                Destination::new(DEFAULT_TO_ADDR.into(), None),
+                SocketType::Capture(sink.clone()),
+                None,
+                None,
+                Arc::new(Stats::default()),
+                Some(ErrorHandler::new(move |error| {
+                    errors_r.lock().unwrap().push(format!("{}", error))
+                })),
+                None,
+                None,
+                0,
+                false,
+                batch_processor::Clock::System,
+                rx,
+            );
+            sink
+        });
 
-        assert_eq!(expected_options, options);
+        tx.send(batch_processor::Message::Data(b"counter:1|c".to_vec()))
+            .unwrap();
+        drop(tx);
+        let sink = processor.join().unwrap();
+
+        assert_eq!(vec!["counter:1|c\n".to_string()], sink.payloads());
+        assert_eq!(
+            vec!["batch processor channel closed with 1 metrics buffered".to_string()],
+            *errors.lock().unwrap()
+        );
     }
 
     #[test]
-    fn test_new() {
-        let client = Client::new(Options::default()).unwrap();
-        let expected_client = Client {
-            socket: SocketType::Udp(UdpSocket::bind(DEFAULT_FROM_ADDR).unwrap()),
-            from_addr: DEFAULT_FROM_ADDR.into(),
-            to_addr: DEFAULT_TO_ADDR.into(),
-            namespace: String::new(),
-            default_tags: String::new().into_bytes(),
-        };
+    fn test_batch_processor_sends_queued_buffers_together() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let (tx, rx) = mpsc::channel();
+        // More full buffers than fit in one `sendmmsg` call are already waiting.
+        for i in 0..40 {
+            tx.send(batch_processor::Message::Data(
+                format!("metric{}:1|c", i).into_bytes(),
+            ))
+            .unwrap();
+        }
+        tx.send(batch_processor::Message::Shutdown).unwrap();
 
-        assert_eq!(expected_client, client)
+        let stats = Arc::new(Stats::default());
+        batch_processor::process_events(
+            BatchingOptions {
+                max_buffer_size: 10,
+                max_time: Duration::from_secs(60),
+                max_retry_attempts: 0,
+                initial_retry_delay: 0,
+            },
+            Destination::new(server.local_addr().unwrap().to_string(), None),
+            SocketType::Udp(UdpSocket::bind("127.0.0.1:0").unwrap()),
+            None,
+            None,
+            stats.clone(),
+            None,
+            None,
+            None,
+            0,
+            false,
+            batch_processor::Clock::System,
+            rx,
+        );
+
+        let mut buf = [0; 64];
+        for i in 0..40 {
+            let len = server.recv(&mut buf).unwrap();
+            assert_eq!(format!("metric{}:1|c\n", i).as_bytes(), &buf[..len]);
+        }
+        assert_eq!(0, stats.snapshot().total_dropped());
     }
 
     #[test]
-    fn test_new_default_tags() {
-        let options = Options::new(
-            DEFAULT_FROM_ADDR,
-            DEFAULT_TO_ADDR,
-            "",
-            vec![String::from("tag1:tag1val")],
+    fn test_batch_processor_splits_oversized_batch() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let errors = Arc::new(Mutex::new(vec![]));
+        let errors_r = errors.clone();
+        let (tx, rx) = mpsc::channel();
+        // Together over the largest UDP datagram, so the batch is refused with EMSGSIZE.
+        let metrics: Vec<String> = (0..8)
+            .map(|i| format!("metric{}:{}|s", i, "x".repeat(10_000)))
+            .collect();
+        for metric in &metrics {
+            tx.send(batch_processor::Message::Data(metric.clone().into_bytes()))
+                .unwrap();
+        }
+        tx.send(batch_processor::Message::Shutdown).unwrap();
+
+        let stats = Arc::new(Stats::default());
+        batch_processor::process_events(
+            BatchingOptions {
+                max_buffer_size: 1 << 20,
+                max_time: Duration::from_secs(60),
+                max_retry_attempts: 0,
+                initial_retry_delay: 0,
+            },
+            Destination::new(server.local_addr().unwrap().to_string(), None),
+            SocketType::Udp(UdpSocket::bind("127.0.0.1:0").unwrap()),
             None,
             None,
+            stats.clone(),
+            Some(ErrorHandler::new(move |error| {
+                errors_r.lock().unwrap().push(format!("{}", error))
+            })),
+            None,
+            None,
+            0,
+            cfg!(feature = "io-uring"),
+            batch_processor::Clock::System,
+            rx,
         );
-        let client = Client::new(options).unwrap();
-        let expected_client = Client {
-            socket: SocketType::Udp(UdpSocket::bind(DEFAULT_FROM_ADDR).unwrap()),
-            from_addr: DEFAULT_FROM_ADDR.into(),
-            to_addr: DEFAULT_TO_ADDR.into(),
-            namespace: String::new(),
-            default_tags: String::from("tag1:tag1val").into_bytes(),
-        };
 
-        assert_eq!(expected_client, client)
+        let mut buf = vec![0; 1 << 16];
+        for half in [&metrics[..4], &metrics[4..]] {
+            let len = server.recv(&mut buf).unwrap();
+            assert_eq!(format!("{}\n", half.join("\n")).as_bytes(), &buf[..len]);
+        }
+        let stats = stats.snapshot();
+        assert_eq!(1, stats.batch_splits);
+        assert_eq!(0, stats.total_dropped());
+        assert_eq!(
+            vec!["batch of 80088 bytes was too large for the socket, so it was split".to_string()],
+            *errors.lock().unwrap()
+        );
     }
 
     #[test]
-    fn test_system_tags() {
-        let options = Options::new(
-            DEFAULT_FROM_ADDR,
-            DEFAULT_TO_ADDR,
-            "",
-            vec![String::from("tag1:tag1val"), String::from("version:0.0.2")],
+    fn test_batch_processor_splits_oversized_held_batches() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let (tx, rx) = mpsc::channel();
+        // Buffers are full after 7 of them, over the largest UDP datagram. With more waiting,
+        // they're held and sent together with `sendmmsg` on Linux, or queued with io_uring.
+        let metrics: Vec<String> = (0..16)
+            .map(|i| format!("metric{:02}:{}|s", i, "x".repeat(10_000)))
+            .collect();
+        for metric in &metrics {
+            tx.send(batch_processor::Message::Data(metric.clone().into_bytes()))
+                .unwrap();
+        }
+        tx.send(batch_processor::Message::Shutdown).unwrap();
+
+        let stats = Arc::new(Stats::default());
+        batch_processor::process_events(
+            BatchingOptions {
+                max_buffer_size: 70_000,
+                max_time: Duration::from_secs(60),
+                max_retry_attempts: 0,
+                initial_retry_delay: 0,
+            },
+            Destination::new(server.local_addr().unwrap().to_string(), None),
+            SocketType::Udp(UdpSocket::bind("127.0.0.1:0").unwrap()),
+            None,
+            None,
+            stats.clone(),
+            Some(ErrorHandler::new(|_| {})),
             None,
             None,
+            0,
+            cfg!(feature = "io-uring"),
+            batch_processor::Clock::System,
+            rx,
         );
 
-        let client = with_default_system_tags(|| Client::new(options).unwrap());
+        // Both full buffers are split in two, and the rest is sent as it is.
+        let mut buf = vec![0; 1 << 16];
+        let mut received = vec![];
+        for _ in 0..5 {
+            let len = server.recv(&mut buf).unwrap();
+            received.extend(
+                String::from_utf8_lossy(&buf[..len])
+                    .lines()
+                    .map(str::to_owned),
+            );
+        }
+        received.sort();
+        assert_eq!(metrics, received);
+        let stats = stats.snapshot();
+        assert_eq!(2, stats.batch_splits);
+        assert_eq!(0, stats.total_dropped());
+    }
+
+    #[test]
+    fn test_batch_processor_retains_failed_batches() {
+        let sink = CaptureSink::new();
+        let agent_up = Arc::new(AtomicBool::new(false));
+        // Opening fails until the agent is up.
+        let no_backoff = ConnectRetry {
+            max_retries: 0,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        };
+        let socket = SocketType::Unopened(LazySocket::new(
+            Arc::new({
+                let sink = sink.clone();
+                let agent_up = agent_up.clone();
+                move || match agent_up.load(Ordering::Relaxed) {
+                    true => Ok(SocketType::Capture(sink.clone())),
+                    false => Err(io::Error::from(io::ErrorKind::NotFound)),
+                }
+            }),
+            no_backoff,
+        ));
+        let stats = Arc::new(Stats::default());
+        let (tx, rx) = mpsc::channel();
+        let processor = thread::spawn({
+            let stats = stats.clone();
+            move || {
+                batch_processor::process_events(
+                    BatchingOptions {
+                        max_buffer_size: 1024,
+                        max_time: Duration::from_secs(60),
+                        max_retry_attempts: 0,
+                        initial_retry_delay: 0,
+                    },
+                    Destination::new(DEFAULT_TO_ADDR.into(), None),
+                    socket,
+                    None,
+                    None,
+                    stats,
+                    Some(ErrorHandler::new(|_| {})),
+                    None,
+                    None,
+                    2,
+                    false,
+                    batch_processor::Clock::System,
+                    rx,
+                )
+            }
+        });
+        let flush = |metric: &str| {
+            tx.send(batch_processor::Message::Data(metric.as_bytes().to_vec()))
+                .unwrap();
+            let (reply_tx, reply_rx) = mpsc::channel();
+            tx.send(batch_processor::Message::Flush(reply_tx)).unwrap();
+            reply_rx.recv().unwrap()
+        };
 
-        dbg!(String::from_utf8_lossy(client.default_tags.as_ref()));
+        assert_eq!((0, 0), flush("first:1|c"));
+        assert_eq!((0, 0), flush("second:1|c"));
+        // Only the last 2 are kept.
+        assert_eq!((0, 0), flush("third:1|c"));
+        assert_eq!(1, stats.snapshot().dropped_send_failed);
 
-        let expected_client = Client {
-            socket: SocketType::Udp(UdpSocket::bind(DEFAULT_FROM_ADDR).unwrap()),
-            from_addr: DEFAULT_FROM_ADDR.into(),
-            to_addr: DEFAULT_TO_ADDR.into(),
-            namespace: String::new(),
-            default_tags: String::from("tag1:tag1val,version:0.0.2,env:production,service:service")
-                .into_bytes(),
+        agent_up.store(true, Ordering::Relaxed);
+        assert_eq!((11, 1), flush("fourth:1|c"));
+        tx.send(batch_processor::Message::Shutdown).unwrap();
+        processor.join().unwrap();
+        assert_eq!(
+            vec!["second:1|c\n", "third:1|c\n", "fourth:1|c\n", ""],
+            sink.payloads()
+        );
+        assert_eq!(1, stats.snapshot().dropped_send_failed);
+    }
+
+    #[test]
+    fn test_batch_processor_retains_batches_sent_together() {
+        let stats = Arc::new(Stats::default());
+        let (tx, rx) = mpsc::channel();
+        let processor = thread::spawn({
+            let stats = stats.clone();
+            move || {
+                batch_processor::process_events(
+                    BatchingOptions {
+                        max_buffer_size: 1,
+                        max_time: Duration::from_secs(60),
+                        max_retry_attempts: 1,
+                        initial_retry_delay: 0,
+                    },
+                    // An IPv4 socket can't send to an IPv6 address, however many times it tries.
+                    Destination::new("[::1]:8125".into(), None),
+                    SocketType::Udp(UdpSocket::bind("127.0.0.1:0").unwrap()),
+                    None,
+                    None,
+                    stats,
+                    Some(ErrorHandler::new(|_| {})),
+                    None,
+                    None,
+                    2,
+                    cfg!(feature = "io-uring"),
+                    batch_processor::Clock::System,
+                    rx,
+                )
+            }
+        });
+        let flush = |metric: &str| {
+            tx.send(batch_processor::Message::Data(metric.as_bytes().to_vec()))
+                .unwrap();
+            let (reply_tx, reply_rx) = mpsc::channel();
+            tx.send(batch_processor::Message::Flush(reply_tx)).unwrap();
+            reply_rx.recv().unwrap()
         };
 
-        assert_eq!(expected_client, client)
+        assert_eq!((0, 0), flush("first:1|c"));
+        assert_eq!((0, 0), flush("second:1|c"));
+        assert_eq!(0, stats.snapshot().dropped_send_failed);
+        // Only the last 2 are kept.
+        assert_eq!((0, 0), flush("third:1|c"));
+        assert_eq!(1, stats.snapshot().dropped_send_failed);
+        tx.send(batch_processor::Message::Shutdown).unwrap();
+        processor.join().unwrap();
+        assert_eq!(3, stats.snapshot().dropped_send_failed);
     }
 
     #[test]
-    fn test_send() {
-        let options = Options::new("127.0.0.1:9001", "127.0.0.1:9002", "", vec![], None, None);
-        let client = Client::new(options).unwrap();
-        // Shouldn't panic or error
+    fn test_watchdog_restarts_stalled_batch_processor() {
+        let (socket, peer) = UnixDatagram::pair().unwrap();
+        drop(peer);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Mutex::new(release_rx);
+        let (stalled_tx, stalled_rx) = mpsc::channel();
+        let stalled_tx = Mutex::new(stalled_tx);
+
+        let options = OptionsBuilder::new()
+            .batching_options(BatchingOptions {
+                max_buffer_size: 1,
+                max_time: Duration::from_millis(10),
+                max_retry_attempts: 0,
+                initial_retry_delay: 0,
+            })
+            .watchdog(WatchdogOptions {
+                stall_intervals: 3,
+                restart: true,
+            })
+            .error_handler(ErrorHandler::new(move |error| match error {
+                // Wedges the batch processor on its first failed send.
+                DogstatsdError::IoError(_) => {
+                    let _ = release_rx
+                        .lock()
+                        .unwrap()
+                        .recv_timeout(Duration::from_secs(5));
+                }
+                DogstatsdError::BatchProcessorStalled { restarted, .. } => {
+                    let _ = stalled_tx.lock().unwrap().send(*restarted);
+                }
+                _ => {}
+            }))
+            .build();
+        let client = Client::with_socket(options, SocketType::Uds(socket), Clock::System).unwrap();
+        client.incr("wedged", ["tag"]).unwrap();
+
+        assert_eq!(Ok(true), stalled_rx.recv_timeout(Duration::from_secs(5)));
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_restarted_batch_processor_sends_to_current_destination() {
+        let first = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let second = UdpSocket::bind("127.0.0.1:0").unwrap();
+        second
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(first.local_addr().unwrap().to_string())
+                .batching_options(BatchingOptions::default())
+                .watchdog(WatchdogOptions {
+                    stall_intervals: 1000,
+                    restart: true,
+                })
+                .build(),
+        )
+        .unwrap();
+        client.rebind().unwrap();
         client
-            .send(
-                &GaugeMetric::new("gauge".into(), "1234".into()),
-                &["tag1", "tag2"],
-            )
+            .set_destination(&second.local_addr().unwrap().to_string())
             .unwrap();
+        client.writer.restart_processor().unwrap();
+        client.incr("restarted", [] as [&str; 0]).unwrap();
+        client.writer.flush();
+
+        let mut buf = [0; 64];
+        let len = second.recv(&mut buf).unwrap();
+        assert_eq!(b"restarted:1|c\n", &buf[..len]);
+    }
+
+    #[test]
+    fn test_watchdog_ignores_idle_batch_processor() {
+        let stalls = Arc::new(Mutex::new(0));
+        let stalls_r = stalls.clone();
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .batching_options(BatchingOptions {
+                    max_time: Duration::from_millis(5),
+                    ..Default::default()
+                })
+                .watchdog(WatchdogOptions {
+                    stall_intervals: 2,
+                    restart: false,
+                })
+                .error_handler(ErrorHandler::new(move |_| *stalls_r.lock().unwrap() += 1))
+                .build(),
+        );
+
+        thread::sleep(Duration::from_millis(100));
+        drop(client);
+        assert_eq!(0, *stalls.lock().unwrap());
+    }
+
+    fn default_writer() -> Arc<Writer> {
+        Arc::new(Writer::new(
+            SocketType::Udp(UdpSocket::bind(DEFAULT_FROM_ADDR).unwrap()),
+            Destination::new(DEFAULT_TO_ADDR.into(), None),
+            Arc::new(Stats::default()),
+            DEFAULT_MAX_PAUSED_METRICS,
+            None,
+            None,
+        ))
     }
 
     fn with_default_system_tags<T, F: FnOnce() -> T>(f: F) -> T {
@@ -0,0 +1,239 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Client;
+
+// How often the generator wakes up to catch up with its rate.
+const TICK: Duration = Duration::from_millis(10);
+
+/// The kinds of metric a `LoadProfile` can generate.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LoadMetricType {
+    /// `Client::incr`.
+    Count,
+    /// `Client::gauge`.
+    Gauge,
+    /// `Client::timing`.
+    Timing,
+    /// `Client::histogram`.
+    Histogram,
+    /// `Client::distribution`.
+    Distribution,
+    /// `Client::set`.
+    Set,
+}
+
+impl LoadMetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LoadMetricType::Count => "count",
+            LoadMetricType::Gauge => "gauge",
+            LoadMetricType::Timing => "timing",
+            LoadMetricType::Histogram => "histogram",
+            LoadMetricType::Distribution => "distribution",
+            LoadMetricType::Set => "set",
+        }
+    }
+}
+
+/// The traffic `run_load` generates. Only available with the `loadgen` feature.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LoadProfile {
+    /// The metric types to send, each with a relative weight.
+    pub metric_types: Vec<(LoadMetricType, u32)>,
+    /// The number of distinct metric names per type, named `loadgen.<type>.<n>`.
+    pub metric_names: usize,
+    /// The number of distinct values of the `loadgen_context` tag, which with `metric_names`
+    /// sets the number of contexts the agent has to aggregate.
+    pub tag_cardinality: usize,
+    /// Metrics sent per second.
+    pub rate: u32,
+    /// How long to send for.
+    pub duration: Duration,
+    /// The seed for choosing metrics, so runs can be repeated exactly.
+    pub seed: u64,
+}
+
+impl Default for LoadProfile {
+    /// An even mix of every metric type, over 10 names and 100 tag values, at 1000 metrics
+    /// per second for 10 seconds.
+    fn default() -> Self {
+        LoadProfile {
+            metric_types: vec![
+                (LoadMetricType::Count, 1),
+                (LoadMetricType::Gauge, 1),
+                (LoadMetricType::Timing, 1),
+                (LoadMetricType::Histogram, 1),
+                (LoadMetricType::Distribution, 1),
+                (LoadMetricType::Set, 1),
+            ],
+            metric_names: 10,
+            tag_cardinality: 100,
+            rate: 1000,
+            duration: Duration::from_secs(10),
+            seed: 1,
+        }
+    }
+}
+
+/// What happened during a `run_load`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct LoadReport {
+    /// Metrics the client accepted.
+    pub sent: u64,
+    /// Metrics the client returned an error for.
+    pub failed: u64,
+    /// How long the run took.
+    pub elapsed: Duration,
+}
+
+/// Send the traffic described by `profile` through `client`, blocking until it's done. Only
+/// available with the `loadgen` feature.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{run_load, Client, LoadProfile, Options};
+///   use std::time::Duration;
+///
+///   let client = Client::new(Options::default()).unwrap();
+///   let profile = LoadProfile { rate: 100, duration: Duration::from_millis(50), ..Default::default() };
+///   let report = run_load(&client, &profile);
+///   println!("sent {} metrics in {:?}", report.sent, report.elapsed);
+/// ```
+pub fn run_load(client: &Client, profile: &LoadProfile) -> LoadReport {
+    let total = (f64::from(profile.rate) * profile.duration.as_secs_f64()).round() as u64;
+    let total_weight: u64 = profile
+        .metric_types
+        .iter()
+        .map(|(_, weight)| u64::from(*weight))
+        .sum();
+    let mut report = LoadReport::default();
+    if total_weight == 0 {
+        return report;
+    }
+
+    let mut rng = XorShift(profile.seed.max(1));
+    let start = Instant::now();
+    let mut generated = 0;
+
+    while generated < total {
+        let elapsed = start.elapsed();
+        let due = if elapsed >= profile.duration {
+            total
+        } else {
+            (f64::from(profile.rate) * elapsed.as_secs_f64()) as u64
+        };
+
+        while generated < due.min(total) {
+            let metric_type = choose(&profile.metric_types, rng.next() % total_weight);
+            let name = format!(
+                "loadgen.{}.{}",
+                metric_type.as_str(),
+                rng.next() % profile.metric_names.max(1) as u64
+            );
+            let tag = format!(
+                "loadgen_context:{}",
+                rng.next() % profile.tag_cardinality.max(1) as u64
+            );
+            let value = rng.next() % 1000;
+
+            match send(client, metric_type, &name, value, [tag]) {
+                Ok(()) => report.sent += 1,
+                Err(_) => report.failed += 1,
+            }
+            generated += 1;
+        }
+
+        if generated < total {
+            thread::sleep(TICK);
+        }
+    }
+
+    report.elapsed = start.elapsed();
+    report
+}
+
+fn choose(metric_types: &[(LoadMetricType, u32)], mut point: u64) -> LoadMetricType {
+    for (metric_type, weight) in metric_types {
+        if point < u64::from(*weight) {
+            return *metric_type;
+        }
+        point -= u64::from(*weight);
+    }
+    metric_types[metric_types.len() - 1].0
+}
+
+fn send(
+    client: &Client,
+    metric_type: LoadMetricType,
+    name: &str,
+    value: u64,
+    tags: [String; 1],
+) -> crate::DogstatsdResult {
+    match metric_type {
+        LoadMetricType::Count => client.incr(name, tags),
+        LoadMetricType::Gauge => client.gauge(name, value.to_string(), tags),
+        LoadMetricType::Timing => client.timing(name, value as i64, tags),
+        LoadMetricType::Histogram => client.histogram(name, value.to_string(), tags),
+        LoadMetricType::Distribution => client.distribution(name, value.to_string(), tags),
+        LoadMetricType::Set => client.set(name, value.to_string(), tags),
+    }
+}
+
+// Good enough randomness for picking metrics, without a dependency.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureSink, Options};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_run_load() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        let profile = LoadProfile {
+            metric_types: vec![(LoadMetricType::Count, 3), (LoadMetricType::Gauge, 1)],
+            metric_names: 2,
+            tag_cardinality: 5,
+            rate: 2000,
+            duration: Duration::from_millis(50),
+            seed: 42,
+        };
+
+        let report = run_load(&client, &profile);
+        assert_eq!(100, report.sent);
+        assert_eq!(0, report.failed);
+        assert!(report.elapsed >= Duration::from_millis(40));
+
+        let payloads = sink.payloads();
+        assert_eq!(100, payloads.len());
+        assert!(payloads.iter().all(|payload| {
+            payload.starts_with("loadgen.count.") || payload.starts_with("loadgen.gauge.")
+        }));
+        let contexts: HashSet<&str> = payloads
+            .iter()
+            .filter_map(|payload| payload.split("|#").nth(1))
+            .collect();
+        assert!(contexts.len() <= 5);
+    }
+
+    #[test]
+    fn test_choose() {
+        let metric_types = [(LoadMetricType::Count, 2), (LoadMetricType::Set, 1)];
+        assert_eq!(LoadMetricType::Count, choose(&metric_types, 0));
+        assert_eq!(LoadMetricType::Count, choose(&metric_types, 1));
+        assert_eq!(LoadMetricType::Set, choose(&metric_types, 2));
+    }
+}
@@ -1,58 +1,312 @@
 use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+
+use crate::encoder::{Encoder, MetricParts};
+
+// Formats a value with at most `significant_digits`, so float noise like
+// `0.30000000000000004` doesn't make it into payloads. Digits before the point are never
+// rounded away, so whole numbers are printed exactly. Rounding through the exponent form and
+// printing the result keeps the shortest representation of the rounded value.
+pub(crate) fn format_float(value: f64, significant_digits: u32) -> String {
+    if !value.is_finite() || value.fract() == 0.0 {
+        return value.to_string();
+    }
+    let whole_digits = value.abs().log10().floor().max(0.0) as usize;
+    let precision = (significant_digits.clamp(1, 17) as usize - 1).max(whole_digits);
+    format!("{:.*e}", precision, value)
+        .parse::<f64>()
+        .unwrap_or(value)
+        .to_string()
+}
 
 pub fn format_for_send<M, I, S>(
     in_metric: &M,
     in_namespace: &str,
     tags: I,
-    default_tags: &Vec<u8>,
+    default_tags: &[u8],
 ) -> Vec<u8>
 where
     M: Metric,
     I: IntoIterator<Item = S>,
     S: AsRef<str>,
 {
+    let mut buf = format_untagged(in_metric, in_namespace).into_bytes();
+    append_tags(&mut buf, tags, default_tags);
+    buf
+}
+
+// Formats the metric like `format_for_send`, but with `encoder` if there is one.
+pub fn format_with_encoder<M, I, S>(
+    in_metric: &M,
+    in_namespace: &str,
+    tags: I,
+    encoder: Option<&dyn Encoder>,
+    default_tags: &DefaultTags,
+) -> Vec<u8>
+where
+    M: Metric,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    match encoder {
+        Some(encoder) => encode_for_send(
+            Some(encoder),
+            format_untagged(in_metric, in_namespace),
+            in_metric.uses_namespace(),
+            tags,
+            default_tags,
+        ),
+        None => format_for_send(in_metric, in_namespace, tags, default_tags.as_bytes()),
+    }
+}
+
+// The metric in the DogStatsD format without any tags, with the namespace if it uses one.
+pub fn format_untagged<M: Metric>(in_metric: &M, in_namespace: &str) -> String {
     let metric = in_metric.metric_type_format();
-    let namespace = if in_metric.uses_namespace() {
-        in_namespace
-    } else {
-        ""
+    if !in_metric.uses_namespace() || in_namespace.is_empty() {
+        return metric;
+    }
+    let mut untagged = String::with_capacity(in_namespace.len() + 1 + metric.len());
+    untagged.push_str(in_namespace);
+    untagged.push('.');
+    untagged.push_str(&metric);
+    untagged
+}
+
+// Writes `untagged`, from `format_untagged`, with `encoder`, followed by its tags then
+// `default_tags`. Without an encoder it's written in the DogStatsD format, reusing `untagged`'s
+// buffer.
+pub fn encode_for_send<I, S>(
+    encoder: Option<&dyn Encoder>,
+    untagged: String,
+    is_metric: bool,
+    tags: I,
+    default_tags: &DefaultTags,
+) -> Vec<u8>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let Some(encoder) = encoder else {
+        let mut buf = untagged.into_bytes();
+        append_tags(&mut buf, tags, default_tags.as_bytes());
+        return buf;
     };
-    let mut buf = Vec::with_capacity(metric.len() + namespace.len());
 
-    if !namespace.is_empty() {
-        buf.extend_from_slice(namespace.as_bytes());
-        buf.extend_from_slice(b".");
+    let metric = &MetricParts::new(&untagged, is_metric);
+    let mut buf = Vec::with_capacity(untagged.len() + default_tags.as_bytes().len() + 2);
+    encoder.begin(metric, &mut buf);
+
+    let mut first = true;
+    for tag in tags.into_iter() {
+        encoder.tag(metric, tag.as_ref(), first, &mut buf);
+        first = false;
+    }
+    for tag in default_tags.iter() {
+        encoder.tag(metric, tag, first, &mut buf);
+        first = false;
     }
 
-    buf.extend_from_slice(metric.as_bytes());
+    encoder.end(metric, &mut buf);
+    buf
+}
 
-    let mut tags_iter = tags.into_iter();
-    let mut next_tag = tags_iter.next();
-    let has_tags = next_tag.is_some();
+// Appends the tags then `default_tags`, a comma-separated list, in the DogStatsD format.
+fn append_tags<I, S>(buf: &mut Vec<u8>, tags: I, default_tags: &[u8])
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    buf.reserve(default_tags.len() + 2);
+    let mut first = true;
+    for tag in tags {
+        buf.extend_from_slice(if first { b"|#" } else { b"," });
+        buf.extend_from_slice(tag.as_ref().as_bytes());
+        first = false;
+    }
+    if !default_tags.is_empty() {
+        buf.extend_from_slice(if first { b"|#" } else { b"," });
+        buf.extend_from_slice(default_tags);
+    }
+}
 
-    if next_tag.is_some() || !default_tags.is_empty() {
-        buf.extend_from_slice(b"|#");
+// A tag the caller passed, or one the client added to them, such as the thread tag, so both can
+// be formatted together without copying the caller's.
+pub enum Tag<S> {
+    Given(S),
+    Added(String),
+}
+
+impl<S: AsRef<str>> AsRef<str> for Tag<S> {
+    fn as_ref(&self) -> &str {
+        match self {
+            Tag::Given(tag) => tag.as_ref(),
+            Tag::Added(tag) => tag,
+        }
+    }
+}
+
+// A client's default tags, joined with `,` the way they're appended to every payload, and
+// split once for encoders, which are given them one at a time.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct DefaultTags {
+    joined: Vec<u8>,
+    tags: Vec<String>,
+}
+
+impl DefaultTags {
+    pub(crate) fn new(tags: Vec<String>) -> Self {
+        DefaultTags {
+            joined: tags.join(",").into_bytes(),
+            tags,
+        }
     }
 
-    while next_tag.is_some() {
-        buf.extend_from_slice(next_tag.unwrap().as_ref().as_bytes());
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.joined
+    }
 
-        next_tag = tags_iter.next();
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &str> {
+        self.tags.iter().map(String::as_str)
+    }
+}
 
-        if next_tag.is_some() {
-            buf.extend_from_slice(b",");
+impl From<Vec<u8>> for DefaultTags {
+    // From tags already joined with `,`.
+    fn from(joined: Vec<u8>) -> Self {
+        if joined.is_empty() {
+            return DefaultTags::default();
         }
+        let tags = String::from_utf8_lossy(&joined)
+            .split(',')
+            .map(str::to_owned)
+            .collect();
+        DefaultTags { joined, tags }
+    }
+}
+
+// Drops per-call tags from the end of `formatted` until it's at most `max_size` bytes, keeping
+// every default tag. `untagged_size` is the size of the same payload without any tags. Returns
+// `None` if it can't be made small enough.
+pub fn truncate_tags(
+    formatted: &[u8],
+    untagged_size: usize,
+    default_tags: &[u8],
+    max_size: usize,
+) -> Option<Vec<u8>> {
+    if formatted.len() <= untagged_size {
+        return None;
+    }
+
+    let tags = &formatted[untagged_size + 2..];
+    let tags = if default_tags.is_empty() {
+        tags
+    } else {
+        &tags[..tags.len().saturating_sub(default_tags.len() + 1)]
+    };
+    let mut kept: Vec<&[u8]> = tags
+        .split(|b| *b == b',')
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    // Every tag but the first costs a ',' on top of its length, and any tag at all costs "|#".
+    let size_with = |kept: &[&[u8]]| {
+        let tags_size: usize = kept.iter().map(|tag| tag.len()).sum::<usize>()
+            + (kept.len() + usize::from(!default_tags.is_empty())).saturating_sub(1)
+            + default_tags.len();
+        untagged_size + if tags_size > 0 { 2 + tags_size } else { 0 }
+    };
+
+    let mut size = formatted.len();
+    while size > max_size {
+        kept.pop()?;
+        size = size_with(&kept);
     }
 
+    let mut buf = Vec::with_capacity(size);
+    buf.extend_from_slice(&formatted[..untagged_size]);
+    if !kept.is_empty() || !default_tags.is_empty() {
+        buf.extend_from_slice(b"|#");
+    }
+    for (i, tag) in kept.iter().enumerate() {
+        if i > 0 {
+            buf.push(b',');
+        }
+        buf.extend_from_slice(tag);
+    }
     if !default_tags.is_empty() {
-        if has_tags {
-            buf.extend_from_slice(b",")
+        if !kept.is_empty() {
+            buf.push(b',');
         }
-
         buf.extend_from_slice(default_tags);
     }
+    Some(buf)
+}
+
+// Counts the size of a metric's tags as they're sent, so whole ones can be dropped from the end
+// once they'd take more than `max_size` bytes along with the default tags, with the commas
+// between them. No tag after the first that doesn't fit is kept.
+#[derive(Debug)]
+pub struct TagBudget {
+    size: usize,
+    max_size: usize,
+    count: usize,
+    full: bool,
+}
+
+impl TagBudget {
+    pub fn new(default_tags: &[u8], max_size: usize) -> Self {
+        TagBudget {
+            size: default_tags.len(),
+            max_size,
+            count: usize::from(!default_tags.is_empty()),
+            full: false,
+        }
+    }
 
-    buf
+    // Adds a tag of `len` bytes, returning whether it's kept.
+    pub fn fits(&mut self, len: usize) -> bool {
+        self.size += len + usize::from(self.count > 0);
+        self.count += 1;
+        self.full |= self.size > self.max_size;
+        !self.full
+    }
+
+    // The size all of the tags would have taken, if it was over the limit.
+    pub fn exceeded(&self) -> Option<usize> {
+        (self.size > self.max_size).then_some(self.size)
+    }
+}
+// The metric's name, for errors about its payload.
+pub fn metric_name(formatted: &[u8]) -> String {
+    let end = formatted
+        .iter()
+        .position(|b| *b == b':' || *b == b'|')
+        .unwrap_or(formatted.len());
+    String::from_utf8_lossy(&formatted[..end]).into_owned()
+}
+
+// Tags that keep their name when `Options::tag_prefix` is set, since Datadog gives them meaning.
+const UNPREFIXED_TAGS: [&str; 5] = ["env", "service", "version", "dd.trace_id", "dd.span_id"];
+
+pub fn prefix_tag<'a>(prefix: Option<&str>, tag: &'a str) -> Cow<'a, str> {
+    match prefix.filter(|_| is_prefixed(tag)) {
+        Some(prefix) => Cow::Owned(format!("{}.{}", prefix, tag)),
+        None => Cow::Borrowed(tag),
+    }
+}
+
+// The length `prefix_tag` would return, without building it.
+pub fn prefixed_len(prefix: Option<&str>, tag: &str) -> usize {
+    match prefix.filter(|_| is_prefixed(tag)) {
+        Some(prefix) => prefix.len() + 1 + tag.len(),
+        None => tag.len(),
+    }
+}
+
+fn is_prefixed(tag: &str) -> bool {
+    let name = tag.split_once(':').map_or(tag, |(name, _)| name);
+    !UNPREFIXED_TAGS.contains(&name)
 }
 
 pub trait Metric {
@@ -67,6 +321,8 @@ pub enum CountMetric<'a> {
     Incr(&'a str, i64),
     Decr(&'a str, i64),
     Arbitrary(&'a str, i64),
+    // Stands for `amount` times the weight, which is sent as a sample rate of 1 / weight.
+    Weighted(&'a str, i64, f64),
 }
 
 impl<'a> Metric for CountMetric<'a> {
@@ -83,7 +339,8 @@ impl<'a> Metric for CountMetric<'a> {
             CountMetric::Decr(stat, amount) => {
                 let mut buf = String::with_capacity(3 + stat.len() + amount.to_string().len() + 4);
                 buf.push_str(stat);
-                buf.push_str(&format!(":{}|c", -amount));
+                // Widened so decrementing by `i64::MIN` can't overflow.
+                buf.push_str(&format!(":{}|c", -i128::from(amount)));
                 buf
             }
             CountMetric::Arbitrary(stat, amount) => {
@@ -94,6 +351,17 @@ impl<'a> Metric for CountMetric<'a> {
                 buf.push_str("|c");
                 buf
             }
+            CountMetric::Weighted(stat, amount, weight) => {
+                let mut buf = String::with_capacity(3 + stat.len() + 23);
+                buf.push_str(stat);
+                buf.push(':');
+                buf.push_str(&amount.to_string());
+                buf.push_str("|c");
+                if weight > 1.0 {
+                    buf.push_str(&format!("|@{}", 1.0 / weight));
+                }
+                buf
+            }
         }
     }
 }
@@ -220,6 +488,40 @@ impl<'a> DistributionMetric<'a> {
     }
 }
 
+// Several values of a distribution in one message, each standing for `1 / sample_rate`
+// values.
+pub struct PackedDistributionMetric<'a> {
+    stat: &'a str,
+    vals: &'a str,
+    sample_rate: f64,
+}
+
+impl<'a> Metric for PackedDistributionMetric<'a> {
+    // my_distribution:1:2.5:3|d|@0.5
+    fn metric_type_format(&self) -> String {
+        let mut buf = String::with_capacity(3 + self.stat.len() + self.vals.len());
+        buf.push_str(self.stat);
+        buf.push(':');
+        buf.push_str(self.vals);
+        buf.push_str("|d");
+        if self.sample_rate < 1.0 {
+            buf.push_str(&format!("|@{}", self.sample_rate));
+        }
+        buf
+    }
+}
+
+impl<'a> PackedDistributionMetric<'a> {
+    // `vals` are joined with ':'.
+    pub fn new(stat: &'a str, vals: &'a str, sample_rate: f64) -> Self {
+        PackedDistributionMetric {
+            stat,
+            vals,
+            sample_rate,
+        }
+    }
+}
+
 pub struct SetMetric<'a> {
     stat: &'a str,
     val: &'a str,
@@ -307,19 +609,19 @@ impl<'a> Metric for ServiceCheck<'a> {
         buf.push('|');
         buf.push_str(&format!("{}", self.val.to_int()));
 
-        if self.options.timestamp.is_some() {
+        if let Some(timestamp) = self.options.timestamp {
             buf.push_str("|d:");
-            buf.push_str(&format!("{}", self.options.timestamp.unwrap()));
+            buf.push_str(&format!("{}", timestamp));
         }
 
-        if self.options.hostname.is_some() {
+        if let Some(hostname) = self.options.hostname {
             buf.push_str("|h:");
-            buf.push_str(self.options.hostname.unwrap());
+            buf.push_str(hostname);
         }
 
-        if self.options.message.is_some() {
+        if let Some(message) = self.options.message {
             buf.push_str("|m:");
-            buf.push_str(self.options.message.unwrap());
+            buf.push_str(message);
         }
 
         buf
@@ -487,10 +789,113 @@ impl<'a> Event<'a> {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_conversion, clippy::needless_borrows_for_generic_args)]
 mod tests {
     use super::*;
     use chrono::{TimeZone, Utc};
 
+    #[test]
+    fn test_format_float() {
+        assert_eq!("0.3", format_float(0.1 + 0.2, 6));
+        assert_eq!("0.30000000000000004", format_float(0.1 + 0.2, 17));
+        assert_eq!("3.14159", format_float(std::f64::consts::PI, 6));
+        assert_eq!("3", format_float(std::f64::consts::PI, 0));
+        assert_eq!("123456789", format_float(123_456_789.0, 6));
+        assert_eq!("123456789", format_float(123_456_789.125, 6));
+        assert_eq!("123456789.13", format_float(123_456_789.126, 11));
+        assert_eq!("9007199254740994", format_float(9_007_199_254_740_994.0, 0));
+        assert_eq!("-0.000123457", format_float(-0.000_123_456_789, 6));
+        assert_eq!("-0.0000001", format_float(-0.000_000_1, 6));
+        assert_eq!("12.5", format_float(12.5, 6));
+        assert_eq!("0", format_float(0.0, 6));
+        assert_eq!("NaN", format_float(f64::NAN, 6));
+    }
+
+    #[test]
+    fn test_truncate_tags() {
+        let untagged = b"stat:1|c";
+        let formatted = b"stat:1|c|#tag1,tag2,tag3,default1,default2";
+
+        assert_eq!(
+            Some(b"stat:1|c|#tag1,default1,default2".to_vec()),
+            truncate_tags(formatted, untagged.len(), b"default1,default2", 32)
+        );
+        assert_eq!(
+            Some(b"stat:1|c|#default1,default2".to_vec()),
+            truncate_tags(formatted, untagged.len(), b"default1,default2", 27)
+        );
+        assert_eq!(
+            None,
+            truncate_tags(formatted, untagged.len(), b"default1,default2", 26)
+        );
+
+        let formatted = b"stat:1|c|#tag1,tag2";
+        assert_eq!(
+            Some(b"stat:1|c|#tag1".to_vec()),
+            truncate_tags(formatted, untagged.len(), b"", 18)
+        );
+        assert_eq!(
+            Some(b"stat:1|c".to_vec()),
+            truncate_tags(formatted, untagged.len(), b"", 13)
+        );
+        assert_eq!(None, truncate_tags(untagged, untagged.len(), b"", 4));
+    }
+
+    #[test]
+    fn test_tag_budget() {
+        let kept = |default_tags: &[u8], max_size| {
+            let mut budget = TagBudget::new(default_tags, max_size);
+            let kept = ["tag1", "tag2", "tag3"]
+                .iter()
+                .filter(|tag| budget.fits(tag.len()))
+                .count();
+            (kept, budget.exceeded())
+        };
+        assert_eq!((3, None), kept(b"", 14));
+        assert_eq!((2, Some(14)), kept(b"", 13));
+        assert_eq!((1, Some(23)), kept(b"default1", 13));
+        assert_eq!((0, Some(23)), kept(b"default1", 12));
+        assert_eq!((0, Some(14)), kept(b"", 3));
+
+        // Nothing after the first tag that doesn't fit is kept, even if it would.
+        let mut budget = TagBudget::new(b"", 6);
+        assert!(!budget.fits(7));
+        assert!(!budget.fits(1));
+        assert_eq!(Some(9), budget.exceeded());
+    }
+
+    #[test]
+    fn test_prefixed_len() {
+        for tag in ["env:prod", "role:web", "service", "key"] {
+            assert_eq!(
+                prefix_tag(Some("app"), tag).len(),
+                prefixed_len(Some("app"), tag)
+            );
+            assert_eq!(tag.len(), prefixed_len(None, tag));
+        }
+    }
+
+    #[test]
+    fn test_metric_name() {
+        assert_eq!("ns.stat", metric_name(b"ns.stat:1|c|#tag"));
+        assert_eq!("_sc", metric_name(b"_sc|redis|0"));
+        assert_eq!("bare", metric_name(b"bare"));
+    }
+
+    #[test]
+    fn test_prefix_tag() {
+        assert_eq!("tag:val", prefix_tag(None, "tag:val"));
+        assert_eq!("custom.tag:val", prefix_tag(Some("custom"), "tag:val"));
+        assert_eq!("custom.tag", prefix_tag(Some("custom"), "tag"));
+        assert_eq!("env:prod", prefix_tag(Some("custom"), "env:prod"));
+        assert_eq!("version", prefix_tag(Some("custom"), "version"));
+        assert_eq!("dd.trace_id:1", prefix_tag(Some("custom"), "dd.trace_id:1"));
+        assert_eq!(
+            "custom.environment:prod",
+            prefix_tag(Some("custom"), "environment:prod")
+        );
+    }
+
     #[test]
     fn test_format_for_send_no_tags() {
         assert_eq!(
@@ -603,6 +1008,13 @@ mod tests {
         assert_eq!("decr:-35|c", metric.metric_type_format())
     }
 
+    #[test]
+    fn test_count_decr_by_min_value_metric() {
+        let metric = CountMetric::Decr("decr", i64::MIN);
+
+        assert_eq!("decr:9223372036854775808|c", metric.metric_type_format())
+    }
+
     #[test]
     fn test_count_metric() {
         let metric = CountMetric::Arbitrary("arb".into(), 54321);
@@ -613,6 +1025,14 @@ mod tests {
         assert_eq!("arb:0|c", metric.metric_type_format());
     }
 
+    #[test]
+    fn test_weighted_count_metric() {
+        let metric = CountMetric::Weighted("weighted", 3, 4.0);
+        assert_eq!("weighted:3|c|@0.25", metric.metric_type_format());
+        let metric = CountMetric::Weighted("weighted", 3, 1.0);
+        assert_eq!("weighted:3|c", metric.metric_type_format());
+    }
+
     #[test]
     fn test_time_metric() {
         let start_time = Utc.with_ymd_and_hms(2016, 4, 24, 0, 0, 0).unwrap();
@@ -652,6 +1072,15 @@ mod tests {
         assert_eq!("distribution:67890|d", metric.metric_type_format())
     }
 
+    #[test]
+    fn test_packed_distribution_metric() {
+        let metric = PackedDistributionMetric::new("distribution", "1:2.5", 0.25);
+        assert_eq!("distribution:1:2.5|d|@0.25", metric.metric_type_format());
+
+        let metric = PackedDistributionMetric::new("distribution", "1", 1.0);
+        assert_eq!("distribution:1|d", metric.metric_type_format());
+    }
+
     #[test]
     fn test_set_metric() {
         let metric = SetMetric::new("set".into(), "13579".into());
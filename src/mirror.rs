@@ -0,0 +1,172 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::sampling::Random;
+use crate::transport::FileSink;
+
+type HandlerFn = dyn Fn(&[u8]) + Send + Sync;
+
+/// Copies a fraction of the payloads a client sends to a second sink, from
+/// `Options::debug_mirror`, to see what's really being sent in production without sending
+/// everything to a file. Payloads are copied as they're handed to the socket, before
+/// batching, whether or not the send succeeds.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{DebugMirror, MirrorSink, OptionsBuilder};
+///
+///   let options = OptionsBuilder::new()
+///       .debug_mirror(DebugMirror {
+///           fraction: 0.001,
+///           sink: MirrorSink::File(String::from("/tmp/dogstatsd-mirror.dsd")),
+///       })
+///       .build();
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct DebugMirror {
+    /// The fraction of payloads to copy, between 0 and 1.
+    pub fraction: f64,
+    /// Where to copy them to.
+    pub sink: MirrorSink,
+}
+
+/// Where `DebugMirror` copies payloads to.
+#[derive(Debug, PartialEq, Clone)]
+pub enum MirrorSink {
+    /// Appended to the file at this path, one per line, like with `Options::file_path`.
+    File(String),
+    /// Handed to this handler, such as to log them or keep them in a buffer.
+    Handler(MirrorHandler),
+}
+
+/// A closure that's handed each payload `DebugMirror` copies.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::MirrorHandler;
+///
+///   let handler = MirrorHandler::new(|payload| eprintln!("{}", String::from_utf8_lossy(payload)));
+/// ```
+#[derive(Clone)]
+pub struct MirrorHandler(Arc<HandlerFn>);
+
+impl MirrorHandler {
+    /// Create a new `MirrorHandler` from a closure.
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&[u8]) + Send + Sync + 'static,
+    {
+        MirrorHandler(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for MirrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MirrorHandler")
+    }
+}
+
+impl PartialEq for MirrorHandler {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug)]
+enum Sink {
+    File(FileSink),
+    Handler(MirrorHandler),
+}
+
+// Picks which payloads to copy, and copies them to the sink.
+#[derive(Debug)]
+pub(crate) struct Mirror {
+    fraction: f64,
+    sink: Sink,
+    random: Random,
+}
+
+impl Mirror {
+    pub(crate) fn open(mirror: DebugMirror) -> io::Result<Self> {
+        Ok(Mirror {
+            fraction: mirror.fraction,
+            sink: match mirror.sink {
+                MirrorSink::File(path) => Sink::File(FileSink::open(&path)?),
+                MirrorSink::Handler(handler) => Sink::Handler(handler),
+            },
+            random: Random::new(),
+        })
+    }
+
+    // Copies `payload` if it's picked, returning any error writing it.
+    pub(crate) fn copy(&self, payload: &[u8]) -> io::Result<()> {
+        if self.fraction <= 0.0 || self.random.next() >= self.fraction {
+            return Ok(());
+        }
+        match &self.sink {
+            Sink::File(file) => {
+                let mut line = Vec::with_capacity(payload.len() + 1);
+                line.extend_from_slice(payload);
+                line.push(b'\n');
+                file.send(&line)
+            }
+            Sink::Handler(handler) => {
+                (handler.0)(payload);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureSink, OptionsBuilder};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_debug_mirror() {
+        let mirrored = Arc::new(Mutex::new(Vec::new()));
+        let mirror = |fraction| DebugMirror {
+            fraction,
+            sink: MirrorSink::Handler(MirrorHandler::new({
+                let mirrored = mirrored.clone();
+                move |payload| {
+                    mirrored
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8_lossy(payload).into_owned())
+                }
+            })),
+        };
+
+        let sink = CaptureSink::new();
+        let client = sink.client(OptionsBuilder::new().debug_mirror(mirror(1.0)).build());
+        client.incr("mirrored", ["env:prod"]).unwrap();
+        assert_eq!(vec!["mirrored:1|c|#env:prod"], *mirrored.lock().unwrap());
+        assert_eq!(vec!["mirrored:1|c|#env:prod"], sink.payloads());
+
+        let client = sink.client(OptionsBuilder::new().debug_mirror(mirror(0.0)).build());
+        client.incr("not_mirrored", ["env:prod"]).unwrap();
+        assert_eq!(1, mirrored.lock().unwrap().len());
+        assert_eq!(2, sink.payloads().len());
+
+        let path = crate::temp_path(".mirror");
+        let _ = std::fs::remove_file(&path);
+        let mirror = Mirror::open(DebugMirror {
+            fraction: 1.0,
+            sink: MirrorSink::File(path.to_str().unwrap().into()),
+        })
+        .unwrap();
+        mirror.copy(b"first:1|c").unwrap();
+        mirror.copy(b"second:1|c").unwrap();
+        assert_eq!(
+            "first:1|c\nsecond:1|c\n",
+            std::fs::read_to_string(&path).unwrap()
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,145 @@
+use std::io;
+use std::mem::{size_of, zeroed};
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+// The most datagrams the batch processor sends with one `sendmmsg` call.
+pub(crate) const MAX_DATAGRAMS_PER_SEND: usize = 32;
+
+pub(crate) fn iovec(buf: &[u8]) -> libc::iovec {
+    libc::iovec {
+        iov_base: buf.as_ptr().cast_mut().cast(),
+        iov_len: buf.len(),
+    }
+}
+
+// A header for sending the one buffer `iov` points to to `addr`, or to the address the socket
+// is connected to without one.
+pub(crate) fn msghdr(addr: Option<&RawAddr>, iov: *const libc::iovec) -> libc::msghdr {
+    let (name, namelen) = addr.map_or((ptr::null(), 0), RawAddr::as_ptr);
+    // SAFETY: `msghdr` is plain data, and some libcs have private padding fields, so it's
+    // zeroed rather than built field by field.
+    let mut hdr: libc::msghdr = unsafe { zeroed() };
+    hdr.msg_name = name.cast_mut();
+    hdr.msg_namelen = namelen;
+    hdr.msg_iov = iov.cast_mut();
+    hdr.msg_iovlen = 1;
+    hdr
+}
+
+pub(crate) enum RawAddr {
+    V4(libc::sockaddr_in),
+    V6(libc::sockaddr_in6),
+}
+
+impl RawAddr {
+    pub(crate) fn new(addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: `sockaddr_in` is plain data, zeroed for `sin_zero`.
+                let mut raw: libc::sockaddr_in = unsafe { zeroed() };
+                raw.sin_family = libc::AF_INET as libc::sa_family_t;
+                raw.sin_port = addr.port().to_be();
+                raw.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+                RawAddr::V4(raw)
+            }
+            SocketAddr::V6(addr) => {
+                // SAFETY: `sockaddr_in6` is plain data.
+                let mut raw: libc::sockaddr_in6 = unsafe { zeroed() };
+                raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                raw.sin6_port = addr.port().to_be();
+                raw.sin6_flowinfo = addr.flowinfo();
+                raw.sin6_addr.s6_addr = addr.ip().octets();
+                raw.sin6_scope_id = addr.scope_id();
+                RawAddr::V6(raw)
+            }
+        }
+    }
+
+    fn as_ptr(&self) -> (*const libc::c_void, libc::socklen_t) {
+        match self {
+            RawAddr::V4(addr) => (
+                (addr as *const libc::sockaddr_in).cast(),
+                size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            ),
+            RawAddr::V6(addr) => (
+                (addr as *const libc::sockaddr_in6).cast(),
+                size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+            ),
+        }
+    }
+}
+
+// Sends up to `MAX_DATAGRAMS_PER_SEND` of `datagrams` to `addr`, or to the address the socket
+// is connected to without one, in a single `sendmmsg` call, returning how many were sent. Like
+// `sendmmsg`, it only fails if none were.
+pub(crate) fn send_many(
+    socket: &UdpSocket,
+    datagrams: &[&[u8]],
+    addr: Option<SocketAddr>,
+) -> io::Result<usize> {
+    let datagrams = &datagrams[..datagrams.len().min(MAX_DATAGRAMS_PER_SEND)];
+    if datagrams.is_empty() {
+        return Ok(0);
+    }
+    let raw_addr = addr.map(RawAddr::new);
+    let iovecs: Vec<libc::iovec> = datagrams.iter().map(|datagram| iovec(datagram)).collect();
+    let mut messages: Vec<libc::mmsghdr> = iovecs
+        .iter()
+        .map(|iov| libc::mmsghdr {
+            msg_hdr: msghdr(raw_addr.as_ref(), iov),
+            msg_len: 0,
+        })
+        .collect();
+
+    loop {
+        // SAFETY: every pointer in `messages` points into `raw_addr`, `iovecs` or `datagrams`,
+        // which outlive the call, and `vlen` is the length of `messages`.
+        let sent = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                messages.as_mut_ptr(),
+                messages.len() as libc::c_uint,
+                0,
+            )
+        };
+        if sent >= 0 {
+            return Ok(sent as usize);
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_send_many() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        let datagrams: [&[u8]; 2] = [b"first:1|c", b"second:1|c"];
+        let sent = send_many(&socket, &datagrams, Some(server.local_addr().unwrap())).unwrap();
+        assert_eq!(2, sent);
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"first:1|c", &buf[..len]);
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"second:1|c", &buf[..len]);
+
+        socket.connect(server.local_addr().unwrap()).unwrap();
+        assert_eq!(1, send_many(&socket, &datagrams[..1], None).unwrap());
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"first:1|c", &buf[..len]);
+    }
+}
@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+
+// The longest metric name Datadog keeps.
+const MAX_METRIC_NAME_LEN: usize = 200;
+
+// Put in front of a name with no letters, so it doesn't normalize to nothing.
+const NO_LETTERS_PREFIX: &str = "metric_";
+
+/// The name Datadog will show for a metric sent as `name`. Every character other than an ASCII
+/// letter, digit, `_` or `.` becomes `_`, runs of `_` collapse into one, `_` next to a `.` is
+/// dropped, the name is trimmed to start with a letter and not end with `_`, and cut to 200
+/// characters. A name with no letters at all, which would otherwise come out empty, is
+/// prefixed with `metric_` first.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::normalize_metric_name;
+///
+///   assert_eq!("http.request_time", normalize_metric_name("http.request-time"));
+///   assert_eq!("queue.depth_ms", normalize_metric_name("__queue._depth (ms)_"));
+///   assert_eq!("cache.hits", normalize_metric_name("cache.hits"));
+///   assert_eq!("metric_404", normalize_metric_name("404"));
+/// ```
+pub fn normalize_metric_name(name: &str) -> Cow<'_, str> {
+    if is_normalized(name) {
+        return Cow::Borrowed(name);
+    }
+
+    let mut normalized = String::with_capacity(NO_LETTERS_PREFIX.len() + name.len());
+    if !name.bytes().any(|b| b.is_ascii_alphabetic()) {
+        normalized.push_str(NO_LETTERS_PREFIX);
+    }
+    for c in name.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '.' {
+            c
+        } else {
+            '_'
+        };
+        match (normalized.chars().last(), c) {
+            (None, c) if !c.is_ascii_alphabetic() => {}
+            (Some('_'), '_') | (Some('.'), '_') => {}
+            (Some('_'), '.') => {
+                normalized.pop();
+                normalized.push('.');
+            }
+            _ => normalized.push(c),
+        }
+    }
+    while normalized.ends_with('_') {
+        normalized.pop();
+    }
+    normalized.truncate(MAX_METRIC_NAME_LEN);
+    Cow::Owned(normalized)
+}
+
+fn is_normalized(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() <= MAX_METRIC_NAME_LEN
+        && bytes.first().is_some_and(u8::is_ascii_alphabetic)
+        && bytes.last() != Some(&b'_')
+        && bytes
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'.')
+        && !bytes
+            .windows(2)
+            .any(|pair| matches!(pair, [b'_', b'_'] | [b'_', b'.'] | [b'.', b'_']))
+}
+
+// Normalizes the name at the start of a formatted metric. Events and service checks, which
+// don't have metric names, are returned unchanged.
+pub(crate) fn normalize_formatted(formatted_metric: String) -> String {
+    if formatted_metric.starts_with("_e{") || formatted_metric.starts_with("_sc|") {
+        return formatted_metric;
+    }
+    let end = formatted_metric
+        .find([':', '|'])
+        .unwrap_or(formatted_metric.len());
+    match normalize_metric_name(&formatted_metric[..end]) {
+        Cow::Borrowed(_) => formatted_metric,
+        Cow::Owned(mut normalized) => {
+            normalized.push_str(&formatted_metric[end..]);
+            normalized
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_metric_name() {
+        for (name, normalized) in [
+            ("requests", "requests"),
+            ("http.requests_total", "http.requests_total"),
+            ("http requests", "http_requests"),
+            ("http--requests", "http_requests"),
+            ("http._requests", "http.requests"),
+            ("http_.requests", "http.requests"),
+            ("9lives.count", "lives.count"),
+            ("_private", "private"),
+            ("trailing_", "trailing"),
+            ("unicodé", "unicod"),
+            ("123", "metric_123"),
+            ("1.5", "metric_1.5"),
+            ("-", "metric"),
+        ] {
+            assert_eq!(normalized, normalize_metric_name(name), "{}", name);
+            assert_eq!(
+                normalize_metric_name(normalized),
+                Cow::Borrowed(normalized),
+                "{}",
+                normalized
+            );
+        }
+        assert_eq!(200, normalize_metric_name(&"a".repeat(300)).len());
+    }
+
+    #[test]
+    fn test_normalize_formatted() {
+        assert_eq!(
+            "my_app.latency:1|ms|#tag-1",
+            normalize_formatted("my-app.latency:1|ms|#tag-1".to_owned())
+        );
+        assert_eq!(
+            "_e{3,4}:t-t|text",
+            normalize_formatted("_e{3,4}:t-t|text".to_owned())
+        );
+        assert_eq!("_sc|a-b|0", normalize_formatted("_sc|a-b|0".to_owned()));
+    }
+}
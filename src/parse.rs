@@ -0,0 +1,230 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a line couldn't be parsed as a DogStatsD metric.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseError {
+    line: String,
+    reason: &'static str,
+}
+
+impl ParseError {
+    fn new(line: &str, reason: &'static str) -> Self {
+        ParseError {
+            line: line.to_owned(),
+            reason,
+        }
+    }
+
+    /// The line that couldn't be parsed.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// What's wrong with it.
+    pub fn reason(&self) -> &'static str {
+        self.reason
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {:?}", self.reason, self.line)
+    }
+}
+
+impl Error for ParseError {}
+
+/// A metric in the DogStatsD format,
+/// `<name>:<value>[:<value>...]|<type>[|@<rate>][|#<tags>][|c:<container>][|T<timestamp>]`,
+/// split into its fields. Only the layout is checked: the type, values, sample rate and
+/// timestamp are returned as they were sent.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::parse_metric;
+///
+///   let metric = parse_metric("requests:1|c|@0.5|#route:index").unwrap();
+///   assert_eq!("requests", metric.name());
+///   assert_eq!("c", metric.metric_type());
+///   assert_eq!(Some("0.5"), metric.sample_rate());
+///   assert_eq!(vec!["route:index"], metric.tags().collect::<Vec<_>>());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MetricLine<'a> {
+    name: &'a str,
+    value: &'a str,
+    metric_type: &'a str,
+    sample_rate: Option<&'a str>,
+    tags: Option<&'a str>,
+    container_id: Option<&'a str>,
+    timestamp: Option<&'a str>,
+}
+
+impl<'a> MetricLine<'a> {
+    /// The metric's name, including any namespace.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The metric's value, or values separated by `:` when several were packed together.
+    pub fn value(&self) -> &'a str {
+        self.value
+    }
+
+    /// The metric's type, such as `c` or `ms`.
+    pub fn metric_type(&self) -> &'a str {
+        self.metric_type
+    }
+
+    /// The sample rate after the `@`, if there is one.
+    pub fn sample_rate(&self) -> Option<&'a str> {
+        self.sample_rate
+    }
+
+    /// The metric's tags, in the order they were sent.
+    pub fn tags(&self) -> impl Iterator<Item = &'a str> {
+        self.tags.into_iter().flat_map(|tags| tags.split(','))
+    }
+
+    /// The container id after the `c:`, if there is one.
+    pub fn container_id(&self) -> Option<&'a str> {
+        self.container_id
+    }
+
+    /// The unix timestamp after the `T`, if there is one.
+    pub fn timestamp(&self) -> Option<&'a str> {
+        self.timestamp
+    }
+}
+
+/// Split a single metric line into its fields. Events and service checks aren't metrics, and
+/// are rejected.
+pub fn parse_metric(line: &str) -> Result<MetricLine<'_>, ParseError> {
+    let error = |reason| ParseError::new(line, reason);
+
+    let mut fields = line.split('|');
+    let (name, value) = fields
+        .next()
+        .and_then(|field| field.split_once(':'))
+        .ok_or_else(|| error("missing ':' between the name and value"))?;
+    if name.is_empty() {
+        return Err(error("empty name"));
+    }
+    if value.is_empty() {
+        return Err(error("empty value"));
+    }
+    let metric_type = fields.next().ok_or_else(|| error("missing metric type"))?;
+
+    let mut metric = MetricLine {
+        name,
+        value,
+        metric_type,
+        sample_rate: None,
+        tags: None,
+        container_id: None,
+        timestamp: None,
+    };
+    for field in fields {
+        let (slot, rest) = if let Some(rate) = field.strip_prefix('@') {
+            (&mut metric.sample_rate, rate)
+        } else if let Some(tags) = field.strip_prefix('#') {
+            validate_tags(tags).map_err(error)?;
+            (&mut metric.tags, tags)
+        } else if let Some(container) = field.strip_prefix("c:") {
+            (&mut metric.container_id, container)
+        } else if let Some(timestamp) = field.strip_prefix('T') {
+            (&mut metric.timestamp, timestamp)
+        } else {
+            return Err(error("unknown field"));
+        };
+        if slot.replace(rest).is_some() {
+            return Err(error("repeated field"));
+        }
+    }
+
+    Ok(metric)
+}
+
+pub(crate) fn validate_tags(tags: &str) -> Result<(), &'static str> {
+    if tags.split(',').any(str::is_empty) {
+        return Err("empty tag");
+    }
+    Ok(())
+}
+
+// What any message starts with: a metric's name, or `_e{..}` or `_sc` for events and service
+// checks.
+pub(crate) fn message_name(message: &str) -> &str {
+    let end = message.find([':', '|']).unwrap_or(message.len());
+    &message[..end]
+}
+
+// The tags of a metric, event or service check, skipping empty ones rather than rejecting
+// the message.
+pub(crate) fn message_tags(message: &str) -> impl Iterator<Item = &str> {
+    let tags = message
+        .find("|#")
+        .map(|start| &message[start + 2..])
+        .unwrap_or_default();
+    let tags = tags.split('|').next().unwrap_or_default();
+    tags.split(',').filter(|tag| !tag.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metric() {
+        let metric = parse_metric("ns.stat:1:2|d|@0.5|#tag1,env:prod|c:abc|T1700000000").unwrap();
+        assert_eq!("ns.stat", metric.name());
+        assert_eq!("1:2", metric.value());
+        assert_eq!("d", metric.metric_type());
+        assert_eq!(Some("0.5"), metric.sample_rate());
+        assert_eq!(vec!["tag1", "env:prod"], metric.tags().collect::<Vec<_>>());
+        assert_eq!(Some("abc"), metric.container_id());
+        assert_eq!(Some("1700000000"), metric.timestamp());
+
+        let metric = parse_metric("queue.depth:12.5|g").unwrap();
+        assert_eq!(0, metric.tags().count());
+        assert_eq!(None, metric.sample_rate());
+    }
+
+    #[test]
+    fn test_parse_metric_invalid() {
+        let reason = |line| parse_metric(line).unwrap_err().reason();
+
+        assert_eq!("missing ':' between the name and value", reason("stat"));
+        assert_eq!(
+            "missing ':' between the name and value",
+            reason("_sc|redis|0")
+        );
+        assert_eq!("empty name", reason(":1|c"));
+        assert_eq!("empty value", reason("stat:|c"));
+        assert_eq!("missing metric type", reason("stat:1"));
+        assert_eq!("empty tag", reason("stat:1|c|#a,,b"));
+        assert_eq!("unknown field", reason("stat:1|c|x"));
+        assert_eq!("repeated field", reason("stat:1|c|#a|#b"));
+        assert_eq!(
+            "empty value in \"stat:|c\"",
+            parse_metric("stat:|c").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_message_name_and_tags() {
+        assert_eq!("ns.stat", message_name("ns.stat:1|c|#tag1,env:prod"));
+        assert_eq!(
+            vec!["tag1", "env:prod"],
+            message_tags("ns.stat:1|c|#tag1,env:prod").collect::<Vec<_>>()
+        );
+        assert_eq!("_sc", message_name("_sc|redis|0|#tag1|m:message"));
+        assert_eq!(
+            vec!["tag1"],
+            message_tags("_sc|redis|0|#tag1|m:message").collect::<Vec<_>>()
+        );
+        assert_eq!(0, message_tags("stat:1|c").count());
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Client, DogstatsdResult};
+
+/// The state of a connection pool, for `PoolMetrics` to report. Implement it for a pool from
+/// r2d2, bb8, deadpool or any other crate by reading its own status.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::PoolStats;
+///
+///   struct Pool {
+///       connections: u64,
+///       idle: u64,
+///   }
+///
+///   impl PoolStats for Pool {
+///       fn size(&self) -> u64 {
+///           self.connections
+///       }
+///
+///       fn idle(&self) -> u64 {
+///           self.idle
+///       }
+///   }
+/// ```
+pub trait PoolStats: Send + Sync {
+    /// The number of connections open, idle or in use.
+    fn size(&self) -> u64;
+
+    /// The number of connections waiting to be used.
+    fn idle(&self) -> u64;
+
+    /// The most connections the pool will open, if it has a limit.
+    fn max_size(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Reports a connection pool's state every interval, from a thread that stops when it's
+/// dropped, as the gauges `pool.size`, `pool.idle`, `pool.in_use` and `pool.max_size`, and how
+/// long callers waited for a connection as the distribution `pool.wait_time` in ms. Every
+/// metric is tagged `pool:<name>`.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, Options, PoolStats};
+///   use std::sync::Arc;
+///   use std::time::Duration;
+///
+///   struct Pool;
+///
+///   impl PoolStats for Pool {
+///       fn size(&self) -> u64 { 4 }
+///       fn idle(&self) -> u64 { 1 }
+///   }
+///
+///   let client = Client::new(Options::default()).unwrap();
+///   let pool = Arc::new(Pool);
+///   let metrics = client.pool_metrics("db", pool.clone(), Duration::from_secs(10), &["tag:pool"]).unwrap();
+///   let _connection = metrics.time_wait(|| "a connection from the pool");
+/// ```
+#[derive(Debug)]
+pub struct PoolMetrics {
+    state: Arc<State>,
+}
+
+struct State {
+    client: Client,
+    pool: Arc<dyn PoolStats>,
+    tags: Vec<String>,
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("State")
+            .field("client", &self.client)
+            .field("tags", &self.tags)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PoolMetrics {
+    pub(crate) fn new(
+        client: Client,
+        name: &str,
+        pool: Arc<dyn PoolStats>,
+        interval: Duration,
+        mut tags: Vec<String>,
+    ) -> io::Result<Self> {
+        tags.push(format!("pool:{}", name));
+        let state = Arc::new(State { client, pool, tags });
+        let weak = Arc::downgrade(&state);
+        thread::Builder::new()
+            .name("dogstatsd-pool".into())
+            .spawn(move || report_every(interval, weak))?;
+        Ok(PoolMetrics { state })
+    }
+
+    /// Send the pool's state now, returning the first error.
+    pub fn report(&self) -> DogstatsdResult {
+        self.state.report()
+    }
+
+    /// Send how long a caller waited for a connection.
+    pub fn record_wait(&self, wait: Duration) -> DogstatsdResult {
+        self.state.client.distribution(
+            "pool.wait_time",
+            (wait.as_secs_f64() * 1000.0).to_string(),
+            &self.state.tags,
+        )
+    }
+
+    /// Call `get`, such as to check a connection out of the pool, and send how long it took.
+    pub fn time_wait<T>(&self, get: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let connection = get();
+        if let Err(error) = self.record_wait(start.elapsed()) {
+            self.state
+                .client
+                .writer
+                .report_error("Failed to send pool wait time", error);
+        }
+        connection
+    }
+}
+
+impl State {
+    fn report(&self) -> DogstatsdResult {
+        let (size, idle) = (self.pool.size(), self.pool.idle());
+        let mut gauges = vec![
+            ("pool.size", size),
+            ("pool.idle", idle),
+            ("pool.in_use", size.saturating_sub(idle)),
+        ];
+        if let Some(max_size) = self.pool.max_size() {
+            gauges.push(("pool.max_size", max_size));
+        }
+
+        let mut result = Ok(());
+        for (stat, value) in gauges {
+            let sent = self.client.gauge(stat, value.to_string(), &self.tags);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+}
+
+fn report_every(interval: Duration, state: Weak<State>) {
+    loop {
+        thread::sleep(interval);
+
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => return,
+        };
+        if let Err(error) = state.report() {
+            state
+                .client
+                .writer
+                .report_error("Failed to send pool metrics", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureSink, Options};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    struct Pool {
+        in_use: AtomicU64,
+    }
+
+    impl PoolStats for Pool {
+        fn size(&self) -> u64 {
+            4
+        }
+
+        fn idle(&self) -> u64 {
+            4 - self.in_use.load(Ordering::Relaxed)
+        }
+
+        fn max_size(&self) -> Option<u64> {
+            Some(8)
+        }
+    }
+
+    #[test]
+    fn test_pool_metrics() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        let pool = Arc::new(Pool {
+            in_use: AtomicU64::new(0),
+        });
+        let metrics = client
+            .pool_metrics("db", pool.clone(), Duration::from_secs(60), ["env:prod"])
+            .unwrap();
+
+        let in_use = metrics.time_wait(|| pool.in_use.fetch_add(1, Ordering::Relaxed) + 1);
+        assert_eq!(1, in_use);
+        metrics.report().unwrap();
+
+        let payloads = sink.payloads();
+        assert!(payloads[0].starts_with("pool.wait_time:"));
+        assert!(payloads[0].ends_with("|d|#env:prod,pool:db"));
+        assert_eq!(
+            vec![
+                "pool.size:4|g|#env:prod,pool:db",
+                "pool.idle:3|g|#env:prod,pool:db",
+                "pool.in_use:1|g|#env:prod,pool:db",
+                "pool.max_size:8|g|#env:prod,pool:db",
+            ],
+            payloads[1..]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::transport::connect_uds;
+use crate::{DogstatsdResult, Options, SocketType, Transport};
+
+// How long a UDP probe waits for the agent's host to refuse the canary.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// How long a TCP probe waits to connect to the agent.
+const PROBE_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether the agent could be reached, from `Client::probe`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AgentHealth {
+    /// The canary metric was sent. Over UDP this only means nothing refused it.
+    Reachable,
+    /// The UDS socket path doesn't exist, such as while the agent is starting.
+    SocketMissing,
+    /// Connecting to the agent or sending the canary failed with this kind of error, such as
+    /// `ConnectionRefused` when nothing is listening.
+    Unreachable(io::ErrorKind),
+    /// The client doesn't send to an agent, such as one from `Client::noop` or with
+    /// `Options::file_path`.
+    NotApplicable,
+}
+
+/// What `Client::self_test` sent, for checking against the agent's `dogstatsd-stats` output.
+#[derive(Debug)]
+pub struct SelfTestReport {
+    /// The `self_test:<id>` tag every payload was sent with, unique to this run.
+    pub marker: String,
+    /// The result of sending each payload, by its kind, such as `"count"` or `"event"`.
+    pub results: Vec<(&'static str, DogstatsdResult)>,
+    /// With batching, the result of sending the batch the payloads were buffered in.
+    pub flushed: DogstatsdResult,
+}
+
+impl SelfTestReport {
+    /// Whether every payload was sent.
+    pub fn passed(&self) -> bool {
+        self.flushed.is_ok() && self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+// Where `Client::probe` checks the agent can be reached. Over UDP and TCP that's the client's
+// current destination, so it follows `Client::set_destination`.
+#[derive(Debug, Clone)]
+pub(crate) enum ProbeTarget {
+    Udp,
+    Uds {
+        path: String,
+        connect_timeout: Option<Duration>,
+        seqpacket: bool,
+    },
+    Tcp,
+    None,
+}
+
+impl ProbeTarget {
+    pub(crate) fn new(socket: &SocketType, options: &Options) -> Self {
+        let uds = |socket_path: &String| ProbeTarget::Uds {
+            path: socket_path.clone(),
+            connect_timeout: options.uds_connect_timeout,
+            seqpacket: options.transport == Some(Transport::UdsSeqPacket),
+        };
+        match (socket, &options.socket_path) {
+            (SocketType::Udp(_), _) => ProbeTarget::Udp,
+            (SocketType::Uds(_) | SocketType::UdsFallback(_), Some(socket_path)) => {
+                uds(socket_path)
+            }
+            (SocketType::Tcp(_), _) => ProbeTarget::Tcp,
+            (SocketType::Unopened(_), Some(socket_path)) => uds(socket_path),
+            (SocketType::Unopened(_), None) => ProbeTarget::Udp,
+            _ => ProbeTarget::None,
+        }
+    }
+
+    // Sends `canary` to the agent from a new socket, so it's sent right away even with
+    // batching, and an error doesn't affect the client's own socket. `primary_addr` is the
+    // destination's address, for UDP and TCP.
+    pub(crate) fn probe(
+        &self,
+        canary: &[u8],
+        primary_addr: impl FnOnce() -> io::Result<SocketAddr>,
+    ) -> AgentHealth {
+        let result = match self {
+            ProbeTarget::Udp => primary_addr().and_then(|addr| probe_udp(addr, canary)),
+            ProbeTarget::Uds {
+                path,
+                connect_timeout,
+                seqpacket,
+            } => {
+                if !Path::new(path).exists() {
+                    return AgentHealth::SocketMissing;
+                }
+                probe_uds(path, *connect_timeout, *seqpacket, canary)
+            }
+            ProbeTarget::Tcp => primary_addr().and_then(|addr| probe_tcp(addr, canary)),
+            ProbeTarget::None => return AgentHealth::NotApplicable,
+        };
+        match result {
+            Ok(()) => AgentHealth::Reachable,
+            Err(error) => AgentHealth::Unreachable(error.kind()),
+        }
+    }
+}
+
+fn probe_udp(addr: SocketAddr, canary: &[u8]) -> io::Result<()> {
+    let socket = UdpSocket::bind(match addr {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })?;
+    socket.connect(addr)?;
+    socket.send(canary)?;
+    // A connected socket sees the ICMP error if the port is closed. The agent never replies,
+    // so a timeout means nothing refused it.
+    socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    match socket.recv(&mut [0; 1]) {
+        Err(error)
+            if !matches!(
+                error.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Err(error)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn probe_uds(
+    path: &str,
+    connect_timeout: Option<Duration>,
+    seqpacket: bool,
+    canary: &[u8],
+) -> io::Result<()> {
+    let socket = connect_uds(path, connect_timeout, seqpacket)?;
+    socket.send(canary)?;
+    Ok(())
+}
+
+fn probe_tcp(addr: SocketAddr, canary: &[u8]) -> io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&addr, PROBE_CONNECT_TIMEOUT)?;
+    stream.write_all(canary)?;
+    stream.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_probe() {
+        let canary = b"probe:1|c";
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        assert_eq!(
+            AgentHealth::Reachable,
+            ProbeTarget::Udp.probe(canary, || Ok(addr))
+        );
+        let mut buf = [0; 16];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(canary, &buf[..len]);
+
+        drop(server);
+        assert_eq!(
+            AgentHealth::Unreachable(io::ErrorKind::ConnectionRefused),
+            ProbeTarget::Udp.probe(canary, || Ok(addr))
+        );
+        assert_eq!(
+            AgentHealth::Unreachable(io::ErrorKind::NotFound),
+            ProbeTarget::Udp.probe(canary, || Err(io::ErrorKind::NotFound.into()))
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(
+            AgentHealth::Reachable,
+            ProbeTarget::Tcp.probe(canary, || Ok(addr))
+        );
+        let mut line = String::new();
+        BufReader::new(listener.accept().unwrap().0)
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!("probe:1|c\n", line);
+        drop(listener);
+        assert_eq!(
+            AgentHealth::Unreachable(io::ErrorKind::ConnectionRefused),
+            ProbeTarget::Tcp.probe(canary, || Ok(addr))
+        );
+
+        let path = crate::temp_path(".probe");
+        let _ = std::fs::remove_file(&path);
+        let target = |seqpacket| ProbeTarget::Uds {
+            path: path.to_str().unwrap().into(),
+            connect_timeout: Some(Duration::from_secs(1)),
+            seqpacket,
+        };
+        let no_addr = || Err(io::ErrorKind::Unsupported.into());
+        let target_seqpacket = target(true);
+        let target = target(false);
+        assert_eq!(AgentHealth::SocketMissing, target.probe(canary, no_addr));
+        drop(UnixDatagram::bind(&path).unwrap());
+        assert_eq!(
+            AgentHealth::Unreachable(io::ErrorKind::ConnectionRefused),
+            target.probe(canary, no_addr)
+        );
+        std::fs::remove_file(&path).unwrap();
+        let _agent = UnixDatagram::bind(&path).unwrap();
+        assert_eq!(AgentHealth::Reachable, target.probe(canary, no_addr));
+        // A `SOCK_SEQPACKET` socket can't connect to a datagram one.
+        assert!(matches!(
+            target_seqpacket.probe(canary, no_addr),
+            AgentHealth::Unreachable(_)
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            AgentHealth::NotApplicable,
+            ProbeTarget::None.probe(canary, no_addr)
+        );
+    }
+}
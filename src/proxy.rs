@@ -0,0 +1,254 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{Client, DogstatsdError, DogstatsdResult, Options};
+
+// How often the listening thread checks whether the proxy was stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// The largest datagram the proxy reads.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// Where a `Proxy` listens for metrics from other processes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ProxyListen {
+    /// A UDP address, such as `127.0.0.1:8125`. Port 0 picks a free one, which
+    /// `Proxy::local_addr` returns.
+    Udp(String),
+    /// The path of a Unix datagram socket, created when the proxy starts and removed when it
+    /// stops.
+    Uds(String),
+}
+
+/// Listens for metrics from other processes, and forwards them to the agent through a
+/// `Client`, so many small processes can share one client's batching, and gauges matching
+/// `Options::gauge_rollup` are rolled up across all of them, without running a separate
+/// sidecar. Metrics are forwarded as they were received, so the client's namespace and
+/// default tags aren't added. Stops listening when it's dropped. Only available with the
+/// `proxy` feature.
+///
+/// # Examples
+///
+/// ```no_run
+///   use dogstatsd::{BatchingOptions, OptionsBuilder, Proxy, ProxyListen};
+///
+///   let proxy = Proxy::start(
+///       ProxyListen::Uds(String::from("/tmp/dogstatsd-proxy.socket")),
+///       OptionsBuilder::new()
+///           .batching_options(BatchingOptions::default())
+///           .build(),
+///   )
+///   .unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Proxy {
+    client: Arc<Client>,
+    local_addr: Option<SocketAddr>,
+    uds_path: Option<String>,
+    stopped: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+enum Listener {
+    Udp(UdpSocket),
+    Uds(UnixDatagram),
+}
+
+impl Listener {
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Listener::Udp(socket) => socket.recv(buf),
+            Listener::Uds(socket) => socket.recv(buf),
+        }
+    }
+}
+
+impl Proxy {
+    /// Start listening on `listen`, forwarding what's received through a `Client` created
+    /// from `options`.
+    pub fn start(listen: ProxyListen, options: Options) -> Result<Self, DogstatsdError> {
+        Self::with_client(listen, Client::new(options)?)
+    }
+
+    pub(crate) fn with_client(listen: ProxyListen, client: Client) -> Result<Self, DogstatsdError> {
+        let (listener, local_addr, uds_path) = match listen {
+            ProxyListen::Udp(addr) => {
+                let socket = UdpSocket::bind(addr)?;
+                socket.set_read_timeout(Some(STOP_POLL_INTERVAL))?;
+                let local_addr = socket.local_addr()?;
+                (Listener::Udp(socket), Some(local_addr), None)
+            }
+            ProxyListen::Uds(path) => {
+                let socket = UnixDatagram::bind(&path)?;
+                socket.set_read_timeout(Some(STOP_POLL_INTERVAL))?;
+                (Listener::Uds(socket), None, Some(path))
+            }
+        };
+
+        let client = Arc::new(client);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let listener = thread::Builder::new()
+            .name("dogstatsd-proxy".into())
+            .spawn({
+                let client = client.clone();
+                let stopped = stopped.clone();
+                move || listen_until_stopped(listener, &client, &stopped)
+            })?;
+        Ok(Proxy {
+            client,
+            local_addr,
+            uds_path,
+            stopped,
+            listener: Some(listener),
+        })
+    }
+
+    /// The address the proxy is listening on, when listening over UDP.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// The client metrics are forwarded through, such as to check its `stats`.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Stop listening, and wait for what's already been received to be forwarded.
+    pub fn stop(mut self) {
+        self.stop_listening();
+    }
+
+    fn stop_listening(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+        if let Some(path) = self.uds_path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.stop_listening();
+    }
+}
+
+fn listen_until_stopped(listener: Listener, client: &Client, stopped: &AtomicBool) {
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    while !stopped.load(Ordering::Relaxed) {
+        let len = match listener.recv(&mut buf) {
+            Ok(len) => len,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            Err(error) => {
+                client
+                    .writer
+                    .report_error("Failed to receive metrics to forward", error.into());
+                continue;
+            }
+        };
+        if let Err(error) = forward(client, &buf[..len]) {
+            client
+                .writer
+                .report_error("Failed to forward metrics", error);
+        }
+    }
+}
+
+// Sends each metric in a datagram through the client, rolling up the gauges it's set to.
+fn forward(client: &Client, datagram: &[u8]) -> DogstatsdResult {
+    let lines = datagram.split(|b| *b == b'\n').filter(|line| {
+        !client
+            .rollup
+            .as_ref()
+            .is_some_and(|rollup| rollup.record_relayed(line))
+    });
+    client.send_batch_raw(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureSink, GaugeRollup, OptionsBuilder};
+    use std::time::Instant;
+
+    fn wait_for(sink: &CaptureSink, count: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while sink.payloads().len() < count && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        sink.payloads()
+    }
+
+    #[test]
+    fn test_proxy_udp() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace("ignored".into())
+                .gauge_rollup(GaugeRollup {
+                    prefixes: vec!["queue.".into()],
+                    interval: Duration::from_secs(60),
+                })
+                .build(),
+        );
+        let proxy = Proxy::with_client(ProxyListen::Udp("127.0.0.1:0".into()), client).unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = proxy.local_addr().unwrap();
+        sender
+            .send_to(b"requests:1|c|#app:a\nqueue.depth:3|g", addr)
+            .unwrap();
+        sender.send_to(b"requests:2|c|#app:b", addr).unwrap();
+        assert_eq!(
+            vec!["requests:1|c|#app:a", "requests:2|c|#app:b"],
+            wait_for(&sink, 2)
+        );
+
+        // The client, and so its rollup, is dropped with the proxy.
+        proxy.stop();
+        assert_eq!(
+            vec![
+                "queue.depth:3|g",
+                "queue.depth.min:3|g",
+                "queue.depth.max:3|g",
+                "queue.depth.avg:3|g",
+            ],
+            sink.payloads()[2..]
+        );
+    }
+
+    #[test]
+    fn test_proxy_uds() {
+        let path = crate::temp_path(".proxy");
+        let _ = std::fs::remove_file(&path);
+        let sink = CaptureSink::new();
+        let proxy = Proxy::with_client(
+            ProxyListen::Uds(path.to_str().unwrap().into()),
+            sink.client(Options::default()),
+        )
+        .unwrap();
+
+        let sender = UnixDatagram::unbound().unwrap();
+        sender.send_to(b"requests:1|c", &path).unwrap();
+        assert_eq!(vec!["requests:1|c"], wait_for(&sink, 1));
+        drop(proxy);
+        assert!(!path.exists());
+    }
+}
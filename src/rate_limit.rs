@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::route::MetricDraft;
+use crate::sync::lock;
+
+// Above this many tracked tag values, buckets that have refilled are forgotten, since they
+// behave the same as new ones.
+const MAX_TRACKED_VALUES: usize = 10_000;
+
+// The most tag values whose drops are counted separately.
+const MAX_COUNTED_VALUES: usize = 10_000;
+
+/// Limits how many metrics are sent per second for each value of a tag, so that a single
+/// noisy value, such as one customer, can't use up the whole metric budget. Metrics over the
+/// limit are dropped, and counted as `DropReason::RateLimited` and per tag value. Metrics
+/// without the tag aren't limited.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::TagRateLimit;
+///
+///   // Up to 100 metrics per second per customer, with bursts of up to 500.
+///   let limit = TagRateLimit::new("customer_id", 100, 500);
+/// ```
+#[derive(Debug, PartialEq, Clone)]
+pub struct TagRateLimit {
+    /// The name of the tag whose values are limited separately, such as `customer_id`.
+    pub tag: String,
+    /// The number of metrics per second each value can send once its burst is used up.
+    pub per_second: u32,
+    /// The number of metrics each value can send at once.
+    pub burst: u32,
+}
+
+impl TagRateLimit {
+    /// Create a new `TagRateLimit`.
+    pub fn new<T: Into<String>>(tag: T, per_second: u32, burst: u32) -> Self {
+        TagRateLimit {
+            tag: tag.into(),
+            per_second,
+            burst,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// A token bucket for every value of the limited tag.
+#[derive(Debug)]
+pub(crate) struct TagRateLimiter {
+    prefix: String,
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    dropped: Mutex<HashMap<String, u64>>,
+}
+
+impl TagRateLimiter {
+    pub(crate) fn new(limit: TagRateLimit) -> Self {
+        TagRateLimiter {
+            prefix: format!("{}:", limit.tag),
+            per_second: f64::from(limit.per_second),
+            burst: f64::from(limit.burst),
+            buckets: Mutex::new(HashMap::new()),
+            dropped: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Whether the formatted metric is within its tag value's limit.
+    pub(crate) fn allow(&self, payload: &[u8]) -> bool {
+        self.allow_at(payload, Instant::now())
+    }
+
+    fn allow_at(&self, payload: &[u8], now: Instant) -> bool {
+        let value = match MetricDraft::new(payload)
+            .tags()
+            .find_map(|tag| tag.strip_prefix(self.prefix.as_str()))
+        {
+            Some(value) => value,
+            None => return true,
+        };
+
+        let mut buckets = lock(&self.buckets);
+        if buckets.len() >= MAX_TRACKED_VALUES && !buckets.contains_key(value) {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+        let bucket = buckets.entry(value.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        if self.refill(bucket, now) >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+        drop(buckets);
+
+        let mut dropped = lock(&self.dropped);
+        if let Some(count) = dropped.get_mut(value) {
+            *count += 1;
+        } else if dropped.len() < MAX_COUNTED_VALUES {
+            dropped.insert(value.to_owned(), 1);
+        }
+        false
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens
+    }
+
+    pub(crate) fn dropped(&self) -> HashMap<String, u64> {
+        lock(&self.dropped).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_allow() {
+        let limiter = TagRateLimiter::new(TagRateLimit::new("customer_id", 2, 2));
+        let now = Instant::now();
+
+        assert!(limiter.allow_at(b"stat:1|c|#customer_id:a", now));
+        assert!(limiter.allow_at(b"stat:1|c|#env:prod,customer_id:a", now));
+        assert!(!limiter.allow_at(b"stat:1|c|#customer_id:a", now));
+        assert!(limiter.allow_at(b"stat:1|c|#customer_id:b", now));
+        assert!(limiter.allow_at(b"stat:1|c|#other_id:a", now));
+        assert!(limiter.allow_at(b"stat:1|c", now));
+
+        // Half a second refills one token at 2 per second.
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.allow_at(b"stat:1|c|#customer_id:a", later));
+        assert!(!limiter.allow_at(b"stat:1|c|#customer_id:a", later));
+
+        assert_eq!(HashMap::from([(String::from("a"), 2)]), limiter.dropped());
+    }
+
+    #[test]
+    fn test_dropped_is_bounded() {
+        let limiter = TagRateLimiter::new(TagRateLimit::new("customer_id", 1, 0));
+        let now = Instant::now();
+
+        for value in 0..MAX_COUNTED_VALUES + 10 {
+            let payload = format!("stat:1|c|#customer_id:{}", value);
+            assert!(!limiter.allow_at(payload.as_bytes(), now));
+        }
+        assert!(!limiter.allow_at(b"stat:1|c|#customer_id:0", now));
+
+        let dropped = limiter.dropped();
+        assert_eq!(MAX_COUNTED_VALUES, dropped.len());
+        assert_eq!(Some(&2), dropped.get("0"));
+        assert_eq!(None, dropped.get(MAX_COUNTED_VALUES.to_string().as_str()));
+    }
+}
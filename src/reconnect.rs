@@ -0,0 +1,300 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::report_error_once;
+use crate::sync::lock;
+use crate::writer::Reopen;
+use crate::{DogstatsdError, ErrorHandler, SocketType};
+
+/// Re-creates a client's UDP or UDS socket when sends keep failing, such as when the agent
+/// restarts and its socket path is replaced, backing off between attempts until a send
+/// succeeds again. Used by both batched and unbatched clients, except those created with
+/// `from_socket` or `BindMode::Inherit`, whose socket can't be opened again.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{OptionsBuilder, ReconnectOptions};
+///
+///   let options = OptionsBuilder::new()
+///       .socket_path(Some(String::from("/var/run/datadog/dsd.socket")))
+///       .reconnect_options(ReconnectOptions::default())
+///       .build();
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ReconnectOptions {
+    /// The number of consecutive failed sends before the socket is re-created. At least 1.
+    pub max_consecutive_failures: u32,
+    /// How long to wait after a reconnect before trying again if sends still fail. Doubled
+    /// after each attempt.
+    pub initial_backoff: Duration,
+    /// The longest to wait between reconnects.
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectOptions {
+    /// Reconnects after 3 failures, backing off from 100ms to 30s.
+    fn default() -> Self {
+        ReconnectOptions {
+            max_consecutive_failures: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Retries opening a client's socket in `Client::new`, backing off between attempts, for when
+/// the agent's socket or port isn't there yet, such as a UDS socket mounted into a container a
+/// little after the app starts.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{ConnectRetry, OptionsBuilder};
+///
+///   let options = OptionsBuilder::new()
+///       .socket_path(Some(String::from("/var/run/datadog/dsd.socket")))
+///       .connect_retry(ConnectRetry::default())
+///       .build();
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ConnectRetry {
+    /// The number of times to try again after the first attempt fails.
+    pub max_retries: u32,
+    /// How long to wait before the first retry. Doubled after each one.
+    pub initial_backoff: Duration,
+    /// The longest to wait between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for ConnectRetry {
+    /// Retries 5 times, backing off from 100ms to 2s.
+    fn default() -> Self {
+        ConnectRetry {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl ConnectRetry {
+    // Calls `connect` until it succeeds or there are no retries left, returning the last error.
+    pub(crate) fn run<T>(&self, mut connect: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut delay = self.initial_backoff;
+        let mut retries = 0;
+        loop {
+            match connect() {
+                Err(_) if retries < self.max_retries => {
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(self.max_backoff);
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+// When to next try something that failed, doubling the wait after each attempt.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    next_attempt: Option<Instant>,
+    delay: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(initial: Duration) -> Self {
+        Backoff {
+            next_attempt: None,
+            delay: initial,
+        }
+    }
+
+    pub(crate) fn ready(&self, now: Instant) -> bool {
+        self.next_attempt
+            .is_none_or(|next_attempt| now >= next_attempt)
+    }
+
+    // Records an attempt at `now`, waiting twice as long before the next one, up to `max`.
+    pub(crate) fn attempted(&mut self, now: Instant, max: Duration) {
+        self.next_attempt = Some(now + self.delay);
+        self.delay = (self.delay * 2).min(max);
+    }
+}
+
+// Counts failed sends, and re-creates the socket once there have been enough of them. Whoever
+// sends from the socket reports each send, and swaps in the socket `reconnect` returns.
+pub(crate) struct Reconnector {
+    options: ReconnectOptions,
+    reopen: Reopen,
+    consecutive_failures: AtomicU32,
+    backoff: Mutex<Backoff>,
+    // Whether a failed reconnect has been printed since sends last succeeded.
+    printed: AtomicBool,
+}
+
+impl fmt::Debug for Reconnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reconnector")
+            .field("options", &self.options)
+            .field("consecutive_failures", &self.consecutive_failures)
+            .field("backoff", &self.backoff)
+            .finish()
+    }
+}
+
+impl Reconnector {
+    pub(crate) fn new(options: ReconnectOptions, reopen: Reopen) -> Self {
+        Reconnector {
+            options,
+            reopen,
+            consecutive_failures: AtomicU32::new(0),
+            backoff: Mutex::new(Backoff::new(options.initial_backoff)),
+            printed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn succeeded(&self) {
+        if self.consecutive_failures.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *lock(&self.backoff) = Backoff::new(self.options.initial_backoff);
+        self.printed.store(false, Ordering::Relaxed);
+    }
+
+    pub(crate) fn failed(&self) {
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Opens a new socket if enough sends have failed in a row and the backoff has passed since
+    // the last attempt.
+    pub(crate) fn reconnect(&self) -> Option<io::Result<SocketType>> {
+        let failures = self.consecutive_failures.load(Ordering::Relaxed);
+        if failures < self.options.max_consecutive_failures.max(1) {
+            return None;
+        }
+        let now = Instant::now();
+        let mut backoff = lock(&self.backoff);
+        if !backoff.ready(now) {
+            return None;
+        }
+        backoff.attempted(now, self.options.max_backoff);
+        drop(backoff);
+        Some((self.reopen)())
+    }
+
+    // Reports a socket `reconnect` failed to open. Without an error handler, only the first
+    // failure until sends succeed again is printed, however long the agent stays away.
+    pub(crate) fn report_failure(&self, handler: &Option<ErrorHandler>, error: io::Error) {
+        report_error_once(
+            handler,
+            &self.printed,
+            "Failed to reconnect",
+            &DogstatsdError::IoError(error),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reconnector_backs_off() {
+        let reopened = Arc::new(AtomicUsize::new(0));
+        let reconnector = Reconnector::new(
+            ReconnectOptions {
+                max_consecutive_failures: 2,
+                initial_backoff: Duration::from_secs(60),
+                max_backoff: Duration::from_secs(60),
+            },
+            Arc::new({
+                let reopened = reopened.clone();
+                move || {
+                    reopened.fetch_add(1, Ordering::Relaxed);
+                    Ok(SocketType::Null)
+                }
+            }),
+        );
+
+        reconnector.failed();
+        assert!(reconnector.reconnect().is_none());
+        reconnector.failed();
+        assert!(reconnector.reconnect().is_some());
+        // Still failing, but within the backoff.
+        reconnector.failed();
+        assert!(reconnector.reconnect().is_none());
+        assert_eq!(1, reopened.load(Ordering::Relaxed));
+
+        // A successful send starts over.
+        reconnector.succeeded();
+        reconnector.failed();
+        reconnector.failed();
+        assert!(reconnector.reconnect().is_some());
+        assert_eq!(2, reopened.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_reconnector_prints_once() {
+        let reconnector = Reconnector::new(
+            ReconnectOptions::default(),
+            Arc::new(|| Ok(SocketType::Null)),
+        );
+        let failure = || io::Error::from(io::ErrorKind::NotFound);
+
+        reconnector.report_failure(&None, failure());
+        assert!(reconnector.printed.load(Ordering::Relaxed));
+
+        // A handler gets every failure.
+        let handled = Arc::new(AtomicUsize::new(0));
+        let handler = Some(ErrorHandler::new({
+            let handled = handled.clone();
+            move |_| {
+                handled.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+        reconnector.report_failure(&handler, failure());
+        reconnector.report_failure(&handler, failure());
+        assert_eq!(2, handled.load(Ordering::Relaxed));
+
+        // Sends succeeding again lets the next failure be printed.
+        reconnector.failed();
+        reconnector.succeeded();
+        assert!(!reconnector.printed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_connect_retry() {
+        let connect_retry = ConnectRetry {
+            max_retries: 2,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        };
+
+        let mut attempts = 0;
+        let result = connect_retry.run(|| {
+            attempts += 1;
+            match attempts {
+                3 => Ok(attempts),
+                _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+            }
+        });
+        assert_eq!(3, result.unwrap());
+
+        let mut attempts = 0;
+        let result: io::Result<()> = connect_retry.run(|| {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(io::ErrorKind::NotFound, result.unwrap_err().kind());
+        assert_eq!(3, attempts);
+    }
+}
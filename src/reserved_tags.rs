@@ -0,0 +1,73 @@
+// Tag keys Datadog gives a meaning of its own, which break ingestion when set per metric.
+const RESERVED_TAG_KEYS: [&str; 3] = ["host", "device", "source"];
+
+/// What to do when a metric's tags set a key Datadog reserves, `host`, `device` or `source`,
+/// which override where Datadog thinks the metric came from. Only the tags passed with each
+/// metric are checked, not the default tags.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{ReservedTagAction, ReservedTags};
+///
+///   // Reject reserved tags, except for `device`, which this service really means to set.
+///   let reserved_tags = ReservedTags::new(ReservedTagAction::Reject).allow("device");
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReservedTags {
+    /// What happens to a metric with a reserved tag.
+    pub action: ReservedTagAction,
+    /// Reserved keys that are allowed anyway.
+    pub allowed: Vec<String>,
+}
+
+/// What `ReservedTags` does with a metric that sets a reserved tag.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReservedTagAction {
+    /// Drop the metric, counted as `DropReason::Invalid`, and return
+    /// `DogstatsdError::ReservedTag`.
+    Reject,
+    /// Send the metric, and report `DogstatsdError::ReservedTag` to `Options::error_handler`.
+    Warn,
+}
+
+impl ReservedTags {
+    /// Create a new `ReservedTags` that allows none of the reserved keys.
+    pub fn new(action: ReservedTagAction) -> Self {
+        ReservedTags {
+            action,
+            allowed: vec![],
+        }
+    }
+
+    /// Allow a reserved key anyway.
+    pub fn allow<T: Into<String>>(mut self, key: T) -> Self {
+        self.allowed.push(key.into());
+        self
+    }
+
+    // Whether the tag sets a reserved key that isn't allowed.
+    pub(crate) fn is_reserved(&self, tag: &str) -> bool {
+        let key = tag.split_once(':').map_or(tag, |(key, _)| key);
+        RESERVED_TAG_KEYS.contains(&key) && !self.allowed.iter().any(|allowed| allowed == key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_reserved() {
+        let reserved_tags = ReservedTags::new(ReservedTagAction::Warn);
+
+        assert!(!reserved_tags.is_reserved("env:prod"));
+        assert!(!reserved_tags.is_reserved("hostname:a"));
+        assert!(reserved_tags.is_reserved("host:a"));
+        assert!(reserved_tags.is_reserved("source"));
+
+        let reserved_tags = reserved_tags.allow("device");
+        assert!(!reserved_tags.is_reserved("device:sda1"));
+        assert!(reserved_tags.is_reserved("host:a"));
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::metrics::format_float;
+use crate::sync::lock;
+use crate::writer::Writer;
+use crate::DogstatsdResult;
+
+/// Gauges to roll up locally over a window instead of sending every value, from
+/// `Options::gauge_rollup`. At the end of each window, the last value of each series is sent,
+/// along with its `.min`, `.max` and `.avg` over the window as gauges with the same tags.
+/// Relative gauges, such as `+5`, are sent right away.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{GaugeRollup, OptionsBuilder};
+///   use std::time::Duration;
+///
+///   let options = OptionsBuilder::new()
+///       .gauge_rollup(GaugeRollup {
+///           prefixes: vec![String::from("queue.")],
+///           interval: Duration::from_secs(10),
+///       })
+///       .build();
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GaugeRollup {
+    /// Gauges whose name, before the namespace is added, starts with one of these are rolled
+    /// up. An empty prefix matches every gauge.
+    pub prefixes: Vec<String>,
+    /// How long each window lasts.
+    pub interval: Duration,
+}
+
+#[derive(Debug)]
+struct Window {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+    last: String,
+}
+
+// The gauges seen this window, by name and the `|g|#tags` that follows the value.
+type Windows = HashMap<(String, Vec<u8>), Window>;
+
+// Collects a client's matching gauges, and sends each series' rollup from its own thread once
+// per interval, and what's left when the last client sharing it is dropped.
+pub(crate) struct Rollup {
+    writer: Arc<Writer>,
+    prefixes: Vec<String>,
+    float_precision: u32,
+    windows: Mutex<Windows>,
+}
+
+impl fmt::Debug for Rollup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rollup")
+            .field("prefixes", &self.prefixes)
+            .field("float_precision", &self.float_precision)
+            .field("windows", &lock(&self.windows).len())
+            .finish()
+    }
+}
+
+impl Rollup {
+    pub(crate) fn new(
+        rollup: GaugeRollup,
+        writer: Arc<Writer>,
+        float_precision: u32,
+    ) -> io::Result<Arc<Self>> {
+        let state = Arc::new(Rollup {
+            writer,
+            prefixes: rollup.prefixes,
+            float_precision,
+            windows: Mutex::new(HashMap::new()),
+        });
+        let weak = Arc::downgrade(&state);
+        thread::Builder::new()
+            .name("dogstatsd-rollup".into())
+            .spawn(move || flush_every(rollup.interval, weak))?;
+        Ok(state)
+    }
+
+    pub(crate) fn matches(&self, stat: &str) -> bool {
+        self.prefixes.iter().any(|prefix| stat.starts_with(prefix))
+    }
+
+    // Adds a formatted gauge to its series' window, returning it if it can't be rolled up,
+    // such as a relative gauge, so it can be sent as is.
+    pub(crate) fn record(&self, formatted_metric: Vec<u8>) -> Option<Vec<u8>> {
+        let parsed = std::str::from_utf8(&formatted_metric)
+            .ok()
+            .and_then(|metric| {
+                let (name, rest) = metric.split_once(':')?;
+                let value_len = rest.find('|')?;
+                let value = &rest[..value_len];
+                if value.starts_with(['+', '-']) {
+                    return None;
+                }
+                let parsed = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.is_finite())?;
+                Some((name, value, parsed, &rest[value_len..]))
+            });
+        let (name, value, parsed, suffix) = match parsed {
+            Some(parsed) => parsed,
+            None => return Some(formatted_metric),
+        };
+
+        let mut windows = lock(&self.windows);
+        let window = windows
+            .entry((name.to_owned(), suffix.as_bytes().to_vec()))
+            .or_insert(Window {
+                min: parsed,
+                max: parsed,
+                sum: 0.0,
+                count: 0,
+                last: String::new(),
+            });
+        window.min = window.min.min(parsed);
+        window.max = window.max.max(parsed);
+        window.sum += parsed;
+        window.count += 1;
+        window.last = value.to_owned();
+        None
+    }
+
+    // Adds a metric relayed from another process, as by `Proxy`, if it's a gauge matching a
+    // prefix, returning whether it was.
+    #[cfg(feature = "proxy")]
+    pub(crate) fn record_relayed(&self, line: &[u8]) -> bool {
+        let Ok(line) = std::str::from_utf8(line) else {
+            return false;
+        };
+        let is_gauge = line.split('|').nth(1) == Some("g");
+        let matches = line
+            .split_once(':')
+            .is_some_and(|(name, _)| self.matches(name));
+        is_gauge && matches && self.record(line.as_bytes().to_vec()).is_none()
+    }
+
+    // Sends the rollup of every series seen since the last flush, returning the first error.
+    pub(crate) fn flush(&self) -> DogstatsdResult {
+        let windows = std::mem::take(&mut *lock(&self.windows));
+        let mut result = Ok(());
+        for ((name, suffix), window) in windows {
+            let avg = window.sum / window.count as f64;
+            let gauges = [
+                (String::new(), window.last),
+                (
+                    ".min".into(),
+                    format_float(window.min, self.float_precision),
+                ),
+                (
+                    ".max".into(),
+                    format_float(window.max, self.float_precision),
+                ),
+                (".avg".into(), format_float(avg, self.float_precision)),
+            ];
+            for (companion, value) in gauges {
+                let mut payload = format!("{}{}:{}", name, companion, value).into_bytes();
+                payload.extend_from_slice(&suffix);
+                let sent = self.writer.send(payload);
+                if result.is_ok() {
+                    result = sent;
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Rollup {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush() {
+            self.writer
+                .report_error("Failed to send gauge rollups", error);
+        }
+    }
+}
+
+fn flush_every(interval: Duration, state: Weak<Rollup>) {
+    loop {
+        thread::sleep(interval);
+
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => return,
+        };
+        if let Err(error) = state.flush() {
+            state
+                .writer
+                .report_error("Failed to send gauge rollups", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CaptureSink, GaugeRollup, OptionsBuilder};
+    use std::time::Duration;
+
+    #[test]
+    fn test_gauge_rollup() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .namespace("app".into())
+                .gauge_rollup(GaugeRollup {
+                    prefixes: vec!["queue.".into()],
+                    interval: Duration::from_secs(60),
+                })
+                .build(),
+        );
+
+        client.gauge("queue.depth", "4", ["env:prod"]).unwrap();
+        client.gauge("queue.depth", "1", ["env:prod"]).unwrap();
+        client.gauge("queue.depth", "2.5", ["env:prod"]).unwrap();
+        client.gauge("queue.depth", "+1", ["env:prod"]).unwrap();
+        client.gauge("pool.size", "8", ["env:prod"]).unwrap();
+        assert_eq!(
+            vec![
+                "app.queue.depth:+1|g|#env:prod",
+                "app.pool.size:8|g|#env:prod"
+            ],
+            sink.payloads()
+        );
+
+        client.rollup.as_ref().unwrap().flush().unwrap();
+        assert_eq!(
+            vec![
+                "app.queue.depth:2.5|g|#env:prod",
+                "app.queue.depth.min:1|g|#env:prod",
+                "app.queue.depth.max:4|g|#env:prod",
+                "app.queue.depth.avg:2.5|g|#env:prod",
+            ],
+            sink.payloads()[2..]
+        );
+
+        // What's left is sent once the client is dropped.
+        client.gauge("queue.depth", "3", [] as [&str; 0]).unwrap();
+        drop(client);
+        assert_eq!("app.queue.depth:3|g", sink.payloads()[6]);
+        assert_eq!(10, sink.payloads().len());
+    }
+}
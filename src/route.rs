@@ -0,0 +1,163 @@
+use std::fmt;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+
+use crate::parse::{message_name, message_tags};
+use crate::sync::lock;
+
+/// A formatted metric about to be sent, as seen by a `RouteHook`.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricDraft<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> MetricDraft<'a> {
+    pub(crate) fn new(payload: &'a [u8]) -> Self {
+        MetricDraft { payload }
+    }
+
+    /// The metric's name, including the namespace.
+    pub fn name(&self) -> &'a str {
+        message_name(std::str::from_utf8(self.payload).unwrap_or_default())
+    }
+
+    /// Every tag the metric will be sent with, including default tags.
+    pub fn tags(&self) -> impl Iterator<Item = &'a str> {
+        message_tags(std::str::from_utf8(self.payload).unwrap_or_default())
+    }
+
+    /// The payload exactly as it will be sent.
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+}
+
+/// Where a `RouteHook` sends a metric.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RouteDecision {
+    /// Send the metric as usual.
+    Send,
+    /// Don't send the metric at all. It's counted as `DropReason::Filtered`.
+    Drop,
+    /// Send the metric to this UDP address instead.
+    Redirect(String),
+    /// Send the metric as usual, and also to this UDP address.
+    Duplicate(String),
+}
+
+/// A callback that decides where each metric is sent, for sending some metrics to a debug
+/// listener, duplicating or dropping them without changing instrumentation.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{RouteDecision, RouteHook};
+///
+///   let hook = RouteHook::new(|draft| {
+///       if draft.tags().any(|tag| tag == "experiment:b") {
+///           RouteDecision::Duplicate(String::from("127.0.0.1:8200"))
+///       } else {
+///           RouteDecision::Send
+///       }
+///   });
+/// ```
+#[derive(Clone)]
+pub struct RouteHook(Arc<dyn Fn(&MetricDraft) -> RouteDecision + Send + Sync>);
+
+impl RouteHook {
+    /// Create a new `RouteHook` from a closure.
+    pub fn new<F>(hook: F) -> Self
+    where
+        F: Fn(&MetricDraft) -> RouteDecision + Send + Sync + 'static,
+    {
+        RouteHook(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for RouteHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RouteHook")
+    }
+}
+
+impl PartialEq for RouteHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// Applies the hook, and owns the socket used for redirected and duplicated metrics, which is
+// only bound the first time one is sent.
+#[derive(Debug)]
+pub(crate) struct Router {
+    hook: RouteHook,
+    socket: Mutex<Option<UdpSocket>>,
+}
+
+impl Router {
+    pub(crate) fn new(hook: RouteHook) -> Self {
+        Router {
+            hook,
+            socket: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn hook(&self) -> &RouteHook {
+        &self.hook
+    }
+
+    pub(crate) fn route(&self, payload: &[u8]) -> RouteDecision {
+        (self.hook.0)(&MetricDraft { payload })
+    }
+
+    pub(crate) fn send_to(&self, addr: &str, payload: &[u8]) -> io::Result<()> {
+        let mut socket = lock(&self.socket);
+        if socket.is_none() {
+            *socket = Some(UdpSocket::bind("0.0.0.0:0")?);
+        }
+        if let Some(socket) = socket.as_ref() {
+            socket.send_to(payload, addr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric_draft() {
+        let draft = MetricDraft {
+            payload: b"ns.stat:1|c|#tag1,env:prod",
+        };
+        assert_eq!("ns.stat", draft.name());
+        assert_eq!(vec!["tag1", "env:prod"], draft.tags().collect::<Vec<_>>());
+
+        let draft = MetricDraft {
+            payload: b"_sc|redis|0|#tag1|m:message",
+        };
+        assert_eq!("_sc", draft.name());
+        assert_eq!(vec!["tag1"], draft.tags().collect::<Vec<_>>());
+
+        let draft = MetricDraft {
+            payload: b"stat:1|c",
+        };
+        assert_eq!(0, draft.tags().count());
+    }
+
+    #[test]
+    fn test_router_send_to() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let router = Router::new(RouteHook::new(|_| RouteDecision::Send));
+
+        router
+            .send_to(&listener.local_addr().unwrap().to_string(), b"stat:1|c")
+            .unwrap();
+
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(b"stat:1|c", &buf[..len]);
+    }
+}
@@ -0,0 +1,296 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::stats::{ClientStats, Stats};
+use crate::sync::lock;
+use crate::DogstatsdError;
+
+// The metric types that can be sampled, and scaled back up by the agent.
+const SAMPLED_TYPES: [&[u8]; 4] = [b"c", b"ms", b"h", b"d"];
+
+/// Lowers the client's sample rate when sends are failing, and raises it again once they
+/// stop. Only counts, timings, histograms and distributions are sampled, and they're sent with
+/// their sample rate so the agent scales them back up. Every change is reported to
+/// `Options::error_handler` as `DogstatsdError::SampleRateAdjusted`.
+///
+/// The agent doesn't reply to the client, so failures are detected from the client's own
+/// stats: sends the kernel refused because the socket's buffer was full, which is how an
+/// agent that can't keep up over UDS shows up, and any other failed sends.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::AdaptiveSampling;
+///
+///   let sampling = AdaptiveSampling { min_sample_rate: 0.25, ..Default::default() };
+/// ```
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AdaptiveSampling {
+    /// How often the sample rate is adjusted.
+    pub interval: Duration,
+    /// The lowest the sample rate will go.
+    pub min_sample_rate: f64,
+}
+
+impl Default for AdaptiveSampling {
+    /// Adjusts every 10 seconds, down to a sample rate of 0.1.
+    fn default() -> Self {
+        AdaptiveSampling {
+            interval: Duration::from_secs(10),
+            min_sample_rate: 0.1,
+        }
+    }
+}
+
+// The current sample rate, and what's needed to adjust it. The rate halves after every
+// interval with failures, and recovers by 0.1 after every interval without.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    options: AdaptiveSampling,
+    rate: AtomicU64,
+    random: Random,
+    last_adjusted: Mutex<(Instant, u64)>,
+}
+
+impl Sampler {
+    pub(crate) fn new(options: AdaptiveSampling) -> Self {
+        Sampler {
+            options,
+            rate: AtomicU64::new(1.0f64.to_bits()),
+            random: Random::new(),
+            last_adjusted: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    pub(crate) fn rate(&self) -> f64 {
+        f64::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+
+    // Returns `None` if the formatted metric was sampled out, or the metric to send, with its
+    // sample rate added if it's been sampled.
+    pub(crate) fn sample(&self, formatted_metric: Vec<u8>) -> Option<Vec<u8>> {
+        let rate = self.rate();
+        if rate >= 1.0 {
+            return Some(formatted_metric);
+        }
+        let type_end = match sampled_type_end(&formatted_metric) {
+            Some(type_end) => type_end,
+            None => return Some(formatted_metric),
+        };
+        if self.random.next() >= rate {
+            return None;
+        }
+
+        let rate = format!("|@{}", rate);
+        let mut sampled = Vec::with_capacity(formatted_metric.len() + rate.len());
+        sampled.extend_from_slice(&formatted_metric[..type_end]);
+        sampled.extend_from_slice(rate.as_bytes());
+        sampled.extend_from_slice(&formatted_metric[type_end..]);
+        Some(sampled)
+    }
+
+    // Adjusts the sample rate if an interval has passed, returning the change to report.
+    pub(crate) fn adjust_if_due(&self, stats: &Stats) -> Option<DogstatsdError> {
+        let failures = failures(&stats.snapshot());
+        let previous_failures = {
+            let mut last_adjusted = lock(&self.last_adjusted);
+            if last_adjusted.0.elapsed() < self.options.interval {
+                return None;
+            }
+            let previous_failures = last_adjusted.1;
+            *last_adjusted = (Instant::now(), failures);
+            previous_failures
+        };
+
+        let from = self.rate();
+        let to = next_rate(
+            from,
+            failures > previous_failures,
+            self.options.min_sample_rate,
+        );
+        if to == from {
+            return None;
+        }
+        self.rate.store(to.to_bits(), Ordering::Relaxed);
+        Some(DogstatsdError::SampleRateAdjusted { from, to })
+    }
+}
+
+// A xorshift generator, seeded from the time, that can be shared between threads.
+#[derive(Debug)]
+pub(crate) struct Random(AtomicU64);
+
+impl Random {
+    pub(crate) fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |now| now.as_nanos() as u64)
+            | 1;
+        Random(AtomicU64::new(seed))
+    }
+
+    // A uniformly distributed number in [0, 1).
+    pub(crate) fn next(&self) -> f64 {
+        let mut next = 0;
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |mut x| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                next = x;
+                Some(x)
+            });
+        (next >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn failures(stats: &ClientStats) -> u64 {
+    stats.likely_kernel_drops + stats.dropped_send_failed + stats.dropped_queue_full
+}
+
+// Scales a sampled counter's values up by its sample rate and removes the rate, for servers
+// that ignore it. Anything else is returned unchanged.
+pub(crate) fn correct_sample_rate(formatted_metric: Vec<u8>) -> Vec<u8> {
+    let corrected = std::str::from_utf8(&formatted_metric)
+        .ok()
+        .and_then(corrected_counter);
+    match corrected {
+        Some(corrected) => corrected.into_bytes(),
+        None => formatted_metric,
+    }
+}
+
+fn corrected_counter(metric: &str) -> Option<String> {
+    let (name_and_values, rest) = metric.split_once('|')?;
+    let rest = rest.strip_prefix("c|@")?;
+    let (rate, rest) = rest.split_at(rest.find('|').unwrap_or(rest.len()));
+    let rate = rate
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| *rate > 0.0 && *rate <= 1.0)?;
+    let (name, values) = name_and_values.split_once(':')?;
+
+    let mut corrected = String::with_capacity(metric.len());
+    corrected.push_str(name);
+    for value in values.split(':') {
+        // Rounded so that rates like 0.3 don't leave a long tail of digits.
+        let scaled = (value.parse::<f64>().ok()? / rate * 1e6).round() / 1e6;
+        corrected.push(':');
+        corrected.push_str(&scaled.to_string());
+    }
+    corrected.push_str("|c");
+    corrected.push_str(rest);
+    Some(corrected)
+}
+
+fn next_rate(rate: f64, failing: bool, min_sample_rate: f64) -> f64 {
+    let next = if failing {
+        rate / 2.0
+    } else {
+        // Rounded so that repeated steps land back on exactly 1.
+        ((rate + 0.1) * 100.0).round() / 100.0
+    };
+    next.clamp(min_sample_rate.min(1.0), 1.0)
+}
+
+// Where the type field of a formatted metric ends, if it's a type that can be sampled and
+// doesn't already have a sample rate.
+fn sampled_type_end(formatted_metric: &[u8]) -> Option<usize> {
+    let type_start = formatted_metric.iter().position(|b| *b == b'|')? + 1;
+    let type_end = formatted_metric[type_start..]
+        .iter()
+        .position(|b| *b == b'|')
+        .map_or(formatted_metric.len(), |end| type_start + end);
+    if !SAMPLED_TYPES.contains(&&formatted_metric[type_start..type_end])
+        || formatted_metric[type_end..].starts_with(b"|@")
+    {
+        return None;
+    }
+    Some(type_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DropReason;
+
+    #[test]
+    fn test_next_rate() {
+        assert_eq!(0.5, next_rate(1.0, true, 0.1));
+        assert_eq!(0.1, next_rate(0.15, true, 0.1));
+        assert_eq!(0.6, next_rate(0.5, false, 0.1));
+        assert_eq!(1.0, next_rate(0.95, false, 0.1));
+        assert_eq!(1.0, next_rate(1.0, false, 0.1));
+    }
+
+    #[test]
+    fn test_sampled_type_end() {
+        assert_eq!(Some(8), sampled_type_end(b"stat:1|c|#tag1"));
+        assert_eq!(Some(10), sampled_type_end(b"stat:12|ms"));
+        assert_eq!(None, sampled_type_end(b"stat:1|g|#tag1"));
+        assert_eq!(None, sampled_type_end(b"stat:a|s"));
+        assert_eq!(None, sampled_type_end(b"stat:1|c|@0.5"));
+        assert_eq!(None, sampled_type_end(b"_sc|check|0"));
+    }
+
+    #[test]
+    fn test_correct_sample_rate() {
+        let correct = |metric: &str| {
+            String::from_utf8(correct_sample_rate(metric.as_bytes().to_vec())).unwrap()
+        };
+        assert_eq!("stat:2|c|#tag1", correct("stat:1|c|@0.5|#tag1"));
+        assert_eq!("stat:10", &correct("stat:3|c|@0.3")[..7]);
+        assert_eq!("stat:-4:8|c", correct("stat:-1:2|c|@0.25"));
+        assert_eq!("stat:1|c|#tag1", correct("stat:1|c|#tag1"));
+        assert_eq!("stat:1|ms|@0.5", correct("stat:1|ms|@0.5"));
+        assert_eq!("stat:1|c|@0", correct("stat:1|c|@0"));
+        assert_eq!("stat:x|c|@0.5", correct("stat:x|c|@0.5"));
+    }
+
+    #[test]
+    fn test_sample() {
+        let sampler = Sampler::new(AdaptiveSampling::default());
+        assert_eq!(
+            Some(b"stat:1|c|#tag1".to_vec()),
+            sampler.sample(b"stat:1|c|#tag1".to_vec())
+        );
+
+        sampler.rate.store(0.5f64.to_bits(), Ordering::Relaxed);
+        let sent: Vec<Vec<u8>> = (0..1000)
+            .filter_map(|_| sampler.sample(b"stat:1|c|#tag1".to_vec()))
+            .collect();
+        assert!(sent.len() > 400 && sent.len() < 600, "{}", sent.len());
+        assert!(sent.iter().all(|metric| metric == b"stat:1|c|@0.5|#tag1"));
+        assert_eq!(
+            Some(b"stat:1|g".to_vec()),
+            sampler.sample(b"stat:1|g".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_adjust_if_due() {
+        let sampler = Sampler::new(AdaptiveSampling {
+            interval: Duration::ZERO,
+            min_sample_rate: 0.25,
+        });
+        let stats = Stats::default();
+
+        assert!(sampler.adjust_if_due(&stats).is_none());
+
+        stats.record_drop(DropReason::SendFailed, 1);
+        assert!(matches!(
+            sampler.adjust_if_due(&stats),
+            Some(DogstatsdError::SampleRateAdjusted { from, to }) if from == 1.0 && to == 0.5
+        ));
+        stats.record_drop(DropReason::SendFailed, 1);
+        sampler.adjust_if_due(&stats);
+        stats.record_drop(DropReason::SendFailed, 1);
+        sampler.adjust_if_due(&stats);
+        assert_eq!(0.25, sampler.rate());
+
+        sampler.adjust_if_due(&stats);
+        assert_eq!(0.35, sampler.rate());
+    }
+}
@@ -0,0 +1,177 @@
+use std::io;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use crate::{Client, DogstatsdResult};
+
+/// An owned metric, for sending through a `MetricSender`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MetricPayload {
+    /// As sent by `Client::count`.
+    Count {
+        /// The metric's name.
+        stat: String,
+        /// The amount to count.
+        value: i64,
+        /// The metric's tags.
+        tags: Vec<String>,
+    },
+    /// As sent by `Client::gauge`.
+    Gauge {
+        /// The metric's name.
+        stat: String,
+        /// The gauge's value.
+        value: String,
+        /// The metric's tags.
+        tags: Vec<String>,
+    },
+    /// As sent by `Client::timing`.
+    Timing {
+        /// The metric's name.
+        stat: String,
+        /// The timing in milliseconds.
+        ms: i64,
+        /// The metric's tags.
+        tags: Vec<String>,
+    },
+    /// As sent by `Client::histogram`.
+    Histogram {
+        /// The metric's name.
+        stat: String,
+        /// The value to record.
+        value: String,
+        /// The metric's tags.
+        tags: Vec<String>,
+    },
+    /// As sent by `Client::distribution`.
+    Distribution {
+        /// The metric's name.
+        stat: String,
+        /// The value to record.
+        value: String,
+        /// The metric's tags.
+        tags: Vec<String>,
+    },
+    /// As sent by `Client::set`.
+    Set {
+        /// The metric's name.
+        stat: String,
+        /// The set member.
+        value: String,
+        /// The metric's tags.
+        tags: Vec<String>,
+    },
+}
+
+impl MetricPayload {
+    fn send(self, client: &Client) -> DogstatsdResult {
+        match self {
+            MetricPayload::Count { stat, value, tags } => client.count(stat, value, tags),
+            MetricPayload::Gauge { stat, value, tags } => client.gauge(stat, value, tags),
+            MetricPayload::Timing { stat, ms, tags } => client.timing(stat, ms, tags),
+            MetricPayload::Histogram { stat, value, tags } => client.histogram(stat, value, tags),
+            MetricPayload::Distribution { stat, value, tags } => {
+                client.distribution(stat, value, tags)
+            }
+            MetricPayload::Set { stat, value, tags } => client.set(stat, value, tags),
+        }
+    }
+}
+
+/// A cheap, clonable handle for sending metrics from any thread, created by
+/// `Client::sender`. Metrics are handed to a background thread that sends them through the
+/// client's socket, so they're still sent after the client is dropped, until every sender is.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, MetricPayload, Options};
+///   use std::thread;
+///
+///   let client = Client::new(Options::default()).unwrap();
+///   let sender = client.sender().unwrap();
+///   thread::spawn(move || {
+///       sender.send(MetricPayload::Count {
+///           stat: "jobs.processed".into(),
+///           value: 1,
+///           tags: vec!["queue:default".into()],
+///       })
+///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+///   });
+/// ```
+#[derive(Debug, Clone)]
+pub struct MetricSender {
+    tx: Sender<MetricPayload>,
+}
+
+impl MetricSender {
+    /// Queue `payload` to be sent. Errors from sending it are passed to the client's error
+    /// handler, since they happen after this returns.
+    pub fn send(&self, payload: MetricPayload) -> DogstatsdResult {
+        self.tx.send(payload).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "MetricSender's thread has stopped",
+            )
+            .into()
+        })
+    }
+}
+
+// Starts the thread that sends metrics queued through the returned sender with `client`,
+// until every sender is dropped.
+pub(crate) fn spawn(client: Client) -> io::Result<MetricSender> {
+    let (tx, rx) = mpsc::channel::<MetricPayload>();
+    thread::Builder::new()
+        .name("dogstatsd-sender".into())
+        .spawn(move || {
+            for payload in rx {
+                if let Err(error) = payload.send(&client) {
+                    client
+                        .writer
+                        .report_error("Failed to send metric from MetricSender", error);
+                }
+            }
+        })?;
+    Ok(MetricSender { tx })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureSink, Options};
+    use std::time::Duration;
+
+    #[test]
+    fn test_metric_sender() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        let sender = client.sender().unwrap();
+        let other_sender = client.sender().unwrap();
+        drop(client);
+
+        thread::spawn(move || {
+            sender
+                .send(MetricPayload::Count {
+                    stat: "count".into(),
+                    value: 2,
+                    tags: vec!["tag:a".into()],
+                })
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+        other_sender
+            .send(MetricPayload::Gauge {
+                stat: "gauge".into(),
+                value: "1.5".into(),
+                tags: vec![],
+            })
+            .unwrap();
+
+        assert_eq!(
+            vec!["count:2|c|#tag:a".to_string(), "gauge:1.5|g".to_string()],
+            sink.wait_for_payloads(2, Duration::from_secs(5))
+        );
+    }
+}
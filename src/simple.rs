@@ -0,0 +1,302 @@
+use std::borrow::Cow;
+use std::io;
+
+use crate::destination::Destination;
+use crate::encoder::SharedEncoder;
+use crate::metrics::{
+    format_with_encoder, CountMetric, DefaultTags, DistributionMetric, GaugeMetric,
+    HistogramMetric, Metric, SetMetric, TimingMetric,
+};
+use crate::stats::Stats;
+use crate::transport::{drops_would_block, send_uds};
+use crate::{
+    open_datagram_socket, validate_options, ClientStats, DogstatsdError, DogstatsdResult,
+    DropReason, Options, SocketType, WouldBlockPolicy,
+};
+
+/// A client that sends each metric straight to the agent from the calling thread, over UDP or
+/// UDS. Unlike `Client` it never starts a thread or creates a channel, for processes that
+/// can't, such as before forking or under a seccomp filter.
+///
+/// Only `from_addr`, `to_addr`, `additional_to_addrs`, `connect_udp`, `bind_mode`,
+/// `failover_options`, `socket_path`, `udp_fallback`, `uds_connect_timeout`,
+/// `would_block_policy`, `namespace`, `default_tags` and `encoder` are used.
+/// `uds_connect_timeout` polls the connecting socket on the calling thread. Options that would
+/// need a thread, such as batching, are rejected by `SimpleClient::new`. Metrics dropped by
+/// `would_block_policy` are counted in `SimpleClient::stats`.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Options, SimpleClient};
+///
+///   let client = SimpleClient::new(Options::default()).unwrap();
+///   client.incr("counter", &["tag:counter"])
+///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+/// ```
+#[derive(Debug)]
+pub struct SimpleClient {
+    socket: SocketType,
+    destination: Destination,
+    namespace: String,
+    default_tags: DefaultTags,
+    encoder: Option<SharedEncoder>,
+    would_block_policy: Option<WouldBlockPolicy>,
+    stats: Stats,
+}
+
+impl SimpleClient {
+    /// Create a new simple client from an options struct.
+    pub fn new(options: Options) -> Result<Self, DogstatsdError> {
+        if options.batching_options.is_some()
+            || options
+                .transport
+                .is_some_and(|transport| transport == crate::Transport::Tcp)
+            || options.file_path.is_some()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SimpleClient only sends unbatched over UDP or UDS",
+            )
+            .into());
+        }
+
+        validate_options(&options)?;
+        Ok(SimpleClient {
+            socket: open_datagram_socket(&options)?,
+            destination: Destination::new(options.to_addr, options.failover_options)
+                .with_address_family(options.address_family)
+                .with_additional_addrs(options.additional_to_addrs)
+                .with_connected(options.connect_udp),
+            would_block_policy: options.would_block_policy,
+            namespace: options.namespace,
+            default_tags: DefaultTags::new(Options::merge_with_system_tags(options.default_tags)),
+            encoder: options.encoder,
+            stats: Stats::default(),
+        })
+    }
+
+    /// Get a snapshot of the client's internal counters. Only metrics dropped because a send
+    /// would block, and those that failed because a kernel buffer was full, are counted.
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
+    }
+
+    /// Increment a StatsD counter.
+    pub fn incr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Incr(stat.into().as_ref(), 1), tags)
+    }
+
+    /// Decrement a StatsD counter.
+    pub fn decr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Decr(stat.into().as_ref(), 1), tags)
+    }
+
+    /// Make an arbitrary change to a StatsD counter.
+    pub fn count<'a, I, S, T>(&self, stat: S, count: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&CountMetric::Arbitrary(stat.into().as_ref(), count), tags)
+    }
+
+    /// Send your own timing metric in milliseconds.
+    pub fn timing<'a, I, S, T>(&self, stat: S, ms: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(&TimingMetric::new(stat.into().as_ref(), ms), tags)
+    }
+
+    /// Report an arbitrary value as a gauge.
+    pub fn gauge<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(
+            &GaugeMetric::new(stat.into().as_ref(), val.into().as_ref()),
+            tags,
+        )
+    }
+
+    /// Report a value in a histogram.
+    pub fn histogram<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(
+            &HistogramMetric::new(stat.into().as_ref(), val.into().as_ref()),
+            tags,
+        )
+    }
+
+    /// Report a value in a distribution.
+    pub fn distribution<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(
+            &DistributionMetric::new(stat.into().as_ref(), val.into().as_ref()),
+            tags,
+        )
+    }
+
+    /// Report a value in a set.
+    pub fn set<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.send(
+            &SetMetric::new(stat.into().as_ref(), val.into().as_ref()),
+            tags,
+        )
+    }
+
+    fn send<I, M, S>(&self, metric: &M, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = S>,
+        M: Metric,
+        S: AsRef<str>,
+    {
+        let formatted_metric = format_with_encoder(
+            metric,
+            &self.namespace,
+            tags,
+            self.encoder.as_ref().map(SharedEncoder::get),
+            &self.default_tags,
+        );
+        match &self.socket {
+            SocketType::Udp(socket) => {
+                self.destination.send_to(socket, &formatted_metric)?;
+            }
+            SocketType::Uds(socket) => self.record_uds_result(
+                send_uds(socket, &formatted_metric, self.would_block_policy).map(|_| ()),
+            )?,
+            SocketType::UdsFallback(socket) => {
+                self.record_uds_result(socket.send(&formatted_metric))?
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "SimpleClient only sends over UDP or UDS",
+                )
+                .into())
+            }
+        };
+        Ok(())
+    }
+
+    // Drops the metric on a full buffer if `would_block_policy` says to, and returns any other
+    // error.
+    fn record_uds_result(&self, result: io::Result<()>) -> io::Result<()> {
+        match result {
+            Err(error) if drops_would_block(&error, self.would_block_policy) => {
+                self.stats.record_send_error(&error, 1);
+                self.stats.record_drop(DropReason::SendFailed, 1);
+                Ok(())
+            }
+            result => result.inspect_err(|error| self.stats.record_send_error(error, 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BatchingOptions, OptionsBuilder};
+    use std::net::UdpSocket;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_simple_client() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = SimpleClient::new(
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .namespace("app".into())
+                .default_tag("env:prod".into())
+                .build(),
+        )
+        .unwrap();
+
+        client.count("requests", 3, ["route:index"]).unwrap();
+        client.gauge("queue.depth", "12", [] as [&str; 0]).unwrap();
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"app.requests:3|c|#route:index,env:prod", &buf[..len]);
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"app.queue.depth:12|g|#env:prod", &buf[..len]);
+
+        let client = SimpleClient::new(
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .default_tag("env:prod".into())
+                .encoder(SharedEncoder::new(crate::TelegrafEncoder))
+                .build(),
+        )
+        .unwrap();
+        client.count("requests", 3, ["route:index"]).unwrap();
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"requests,route=index,env=prod:3|c", &buf[..len]);
+    }
+
+    #[test]
+    fn test_simple_client_counts_dropped() {
+        let path = crate::temp_path(".simple");
+        let _ = std::fs::remove_file(&path);
+        // Never read from, so its buffer fills up.
+        let _agent = UnixDatagram::bind(&path).unwrap();
+        let client = SimpleClient::new(
+            OptionsBuilder::new()
+                .socket_path(Some(path.to_str().unwrap().into()))
+                .would_block_policy(WouldBlockPolicy::Drop)
+                .build(),
+        )
+        .unwrap();
+
+        for _ in 0..10_000 {
+            client.incr("counter", [] as [&str; 0]).unwrap();
+            if client.stats().dropped(DropReason::SendFailed) > 0 {
+                break;
+            }
+        }
+        assert!(client.stats().dropped(DropReason::SendFailed) > 0);
+        assert!(client.stats().likely_kernel_drops > 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_simple_client_rejects_batching() {
+        let options = OptionsBuilder::new()
+            .batching_options(BatchingOptions::default())
+            .build();
+        assert!(SimpleClient::new(options).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::sync::lock;
+use crate::{Client, DogstatsdResult, Options};
+
+// A metric sent before promotion, replayed through the client once there is one.
+type Queued = Box<dyn FnOnce(&Client) -> DogstatsdResult + Send>;
+
+/// A client for the start of a process, before its configuration has been loaded, that holds
+/// metrics in memory until `promote` is called with the real `Options`, then sends them all
+/// through a `Client` created from them. After that it sends through that client directly,
+/// without copying the name, value or tags. Metrics beyond `max_queued` are dropped, and
+/// replayed metrics are sent with the namespace and default tags of the new client.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{BufferedStartupClient, Options};
+///
+///   let client = BufferedStartupClient::new(1000);
+///   client.incr("boot.started", &["tag:boot"]).unwrap();
+///
+///   // Once the configuration is loaded.
+///   client.promote(Options::default()).unwrap();
+/// ```
+pub struct BufferedStartupClient {
+    client: OnceLock<Client>,
+    queued: Mutex<Vec<Queued>>,
+    max_queued: usize,
+    dropped: AtomicU64,
+}
+
+impl fmt::Debug for BufferedStartupClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferedStartupClient")
+            .field("client", &self.client)
+            .field("queued", &lock(&self.queued).len())
+            .field("max_queued", &self.max_queued)
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+impl BufferedStartupClient {
+    /// Create a client that holds up to `max_queued` metrics until it's promoted.
+    pub fn new(max_queued: usize) -> Self {
+        BufferedStartupClient {
+            client: OnceLock::new(),
+            queued: Mutex::new(Vec::new()),
+            max_queued,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a `Client` from `options`, and send every metric held so far through it,
+    /// returning the first error sending them. If the client can't be created, metrics are
+    /// still held, and it can be promoted again. Promoting a second time returns an error.
+    pub fn promote(&self, options: Options) -> DogstatsdResult {
+        let mut queued = lock(&self.queued);
+        if self.client.get().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the startup client was already promoted",
+            )
+            .into());
+        }
+        let client = Client::new(options)?;
+        let client = self.client.get_or_init(|| client);
+        let mut result = Ok(());
+        for send in queued.drain(..) {
+            let sent = send(client);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+
+    /// The client metrics are sent through, once promoted.
+    pub fn client(&self) -> Option<&Client> {
+        self.client.get()
+    }
+
+    /// The number of metrics dropped because `max_queued` were already held.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Increment a StatsD counter.
+    pub fn incr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.count(stat, 1, tags)
+    }
+
+    /// Decrement a StatsD counter.
+    pub fn decr<'a, I, S, T>(&self, stat: S, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        self.count(stat, -1, tags)
+    }
+
+    /// Make an arbitrary change to a StatsD counter.
+    pub fn count<'a, I, S, T>(&self, stat: S, count: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        if let Some(client) = self.client.get() {
+            return client.count(stat, count, tags);
+        }
+        let stat = stat.into().into_owned();
+        self.hold(tags, move |client, tags| client.count(stat, count, tags))
+    }
+
+    /// Send your own timing metric in milliseconds.
+    pub fn timing<'a, I, S, T>(&self, stat: S, ms: i64, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        if let Some(client) = self.client.get() {
+            return client.timing(stat, ms, tags);
+        }
+        let stat = stat.into().into_owned();
+        self.hold(tags, move |client, tags| client.timing(stat, ms, tags))
+    }
+
+    /// Report an arbitrary value as a gauge.
+    pub fn gauge<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        if let Some(client) = self.client.get() {
+            return client.gauge(stat, val, tags);
+        }
+        let (stat, val) = (stat.into().into_owned(), val.into().into_owned());
+        self.hold(tags, move |client, tags| client.gauge(stat, val, tags))
+    }
+
+    /// Report a value in a histogram.
+    pub fn histogram<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        if let Some(client) = self.client.get() {
+            return client.histogram(stat, val, tags);
+        }
+        let (stat, val) = (stat.into().into_owned(), val.into().into_owned());
+        self.hold(tags, move |client, tags| client.histogram(stat, val, tags))
+    }
+
+    /// Report a value in a distribution.
+    pub fn distribution<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        if let Some(client) = self.client.get() {
+            return client.distribution(stat, val, tags);
+        }
+        let (stat, val) = (stat.into().into_owned(), val.into().into_owned());
+        self.hold(tags, move |client, tags| {
+            client.distribution(stat, val, tags)
+        })
+    }
+
+    /// Report a value in a set.
+    pub fn set<'a, I, S, SS, T>(&self, stat: S, val: SS, tags: I) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        S: Into<Cow<'a, str>>,
+        SS: Into<Cow<'a, str>>,
+        T: AsRef<str>,
+    {
+        if let Some(client) = self.client.get() {
+            return client.set(stat, val, tags);
+        }
+        let (stat, val) = (stat.into().into_owned(), val.into().into_owned());
+        self.hold(tags, move |client, tags| client.set(stat, val, tags))
+    }
+
+    // Holds a metric sent before promotion, with its own copy of the tags. Each method sends
+    // straight through the client once promoted, so nothing is copied after that.
+    fn hold<I, T, F>(&self, tags: I, send: F) -> DogstatsdResult
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+        F: FnOnce(&Client, Vec<String>) -> DogstatsdResult + Send + 'static,
+    {
+        let tags: Vec<String> = tags
+            .into_iter()
+            .map(|tag| tag.as_ref().to_owned())
+            .collect();
+        let mut queued = lock(&self.queued);
+        // Promoted since the check above.
+        if let Some(client) = self.client.get() {
+            drop(queued);
+            return send(client, tags);
+        }
+        if queued.len() < self.max_queued {
+            queued.push(Box::new(move |client| send(client, tags)));
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OptionsBuilder;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    #[test]
+    fn test_buffered_startup_client() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let client = BufferedStartupClient::new(2);
+        client.incr("boot.started", ["phase:early"]).unwrap();
+        client.gauge("boot.modules", "4", [] as [&str; 0]).unwrap();
+        client.incr("boot.overflow", [] as [&str; 0]).unwrap();
+        assert_eq!(1, client.dropped());
+        assert!(client.client().is_none());
+
+        let options = || {
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().unwrap().to_string())
+                .namespace("app".into())
+                .build()
+        };
+        client.promote(options()).unwrap();
+        client.incr("boot.done", [] as [&str; 0]).unwrap();
+        assert!(client.promote(options()).is_err());
+
+        let mut buf = [0; 64];
+        let mut recv = || {
+            let len = server.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        };
+        assert_eq!("app.boot.started:1|c|#phase:early", recv());
+        assert_eq!("app.boot.modules:4|g", recv());
+        assert_eq!("app.boot.done:1|c", recv());
+    }
+}
@@ -0,0 +1,348 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::sync::lock;
+
+// How many of the most recent socket sends the latency percentiles are computed from.
+const SEND_LATENCY_SAMPLES: usize = 1024;
+
+// ENOBUFS, which Linux returns instead of blocking when a UDP socket's send queue is full.
+#[cfg(target_os = "linux")]
+const ENOBUFS: i32 = 105;
+
+/// The reasons the client can drop a metric instead of sending it.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[non_exhaustive]
+pub enum DropReason {
+    /// The metric failed validation.
+    Invalid,
+    /// The metric was removed by a filter.
+    Filtered,
+    /// The metric exceeded a rate limit.
+    RateLimited,
+    /// The metric was not selected by sampling.
+    Sampled,
+    /// The metric couldn't be written to the socket.
+    SendFailed,
+    /// The metric didn't fit in a bounded in-memory queue.
+    QueueFull,
+}
+
+impl DropReason {
+    const ALL: [DropReason; 6] = [
+        DropReason::Invalid,
+        DropReason::Filtered,
+        DropReason::RateLimited,
+        DropReason::Sampled,
+        DropReason::SendFailed,
+        DropReason::QueueFull,
+    ];
+
+    /// The value of the `reason` tag used when reporting drops.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::Invalid => "invalid",
+            DropReason::Filtered => "filtered",
+            DropReason::RateLimited => "rate_limited",
+            DropReason::Sampled => "sampled",
+            DropReason::SendFailed => "send_failed",
+            DropReason::QueueFull => "queue_full",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A snapshot of the client's internal counters, as returned by `Client::stats`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ClientStats {
+    /// Metrics dropped because they failed validation.
+    pub dropped_invalid: u64,
+    /// Metrics dropped by a filter.
+    pub dropped_filtered: u64,
+    /// Metrics dropped by a rate limit.
+    pub dropped_rate_limited: u64,
+    /// Metrics dropped by sampling.
+    pub dropped_sampled: u64,
+    /// Metrics dropped because they couldn't be written to the socket.
+    pub dropped_send_failed: u64,
+    /// Metrics dropped because a bounded in-memory queue was full.
+    pub dropped_queue_full: u64,
+    /// Metrics that failed to send because a kernel socket buffer was full, rather than
+    /// because of anything the client did. These are also counted as `dropped_send_failed`
+    /// when the client gives up on them, so they aren't included in `total_dropped`.
+    pub likely_kernel_drops: u64,
+    /// The median time a recent socket send took, with `Options::measure_send_latency`.
+    /// `None` when it's off or nothing has been sent yet.
+    pub send_latency_p50: Option<Duration>,
+    /// The 99th percentile time a recent socket send took, as for `send_latency_p50`.
+    pub send_latency_p99: Option<Duration>,
+    /// Times `Client::distribution_sketch` had to start another message because the values
+    /// sharing a count didn't fit in one. Lots of these suggest sending fewer distinct values
+    /// per call, or a larger `Options::max_payload_size`.
+    pub packed_splits: u64,
+    /// Batches the socket refused as too large, with `EMSGSIZE`, that were split in half and
+    /// sent again. Any at all suggest `BatchingOptions::max_buffer_size` is larger than the
+    /// path MTU allows.
+    pub batch_splits: u64,
+    /// Metrics sent with some of their tags dropped to fit in `Options::max_tags_bytes`.
+    pub truncated_tags: u64,
+    /// Metrics sent with some of their per-call tags dropped to fit in
+    /// `Options::max_payload_size`, with `OversizedMetricPolicy::TruncateTags`.
+    pub truncated_oversized: u64,
+}
+
+impl ClientStats {
+    /// The number of metrics dropped for the given reason.
+    pub fn dropped(&self, reason: DropReason) -> u64 {
+        match reason {
+            DropReason::Invalid => self.dropped_invalid,
+            DropReason::Filtered => self.dropped_filtered,
+            DropReason::RateLimited => self.dropped_rate_limited,
+            DropReason::Sampled => self.dropped_sampled,
+            DropReason::SendFailed => self.dropped_send_failed,
+            DropReason::QueueFull => self.dropped_queue_full,
+        }
+    }
+
+    /// The number of metrics dropped for any reason.
+    pub fn total_dropped(&self) -> u64 {
+        DropReason::ALL
+            .iter()
+            .map(|reason| self.dropped(*reason))
+            .sum()
+    }
+
+    pub(crate) fn dropped_mut(&mut self, reason: DropReason) -> &mut u64 {
+        match reason {
+            DropReason::Invalid => &mut self.dropped_invalid,
+            DropReason::Filtered => &mut self.dropped_filtered,
+            DropReason::RateLimited => &mut self.dropped_rate_limited,
+            DropReason::Sampled => &mut self.dropped_sampled,
+            DropReason::SendFailed => &mut self.dropped_send_failed,
+            DropReason::QueueFull => &mut self.dropped_queue_full,
+        }
+    }
+
+    // The drop counts that have accumulated since `previous` was taken.
+    pub(crate) fn dropped_since(&self, previous: &ClientStats) -> Vec<(DropReason, u64)> {
+        DropReason::ALL
+            .iter()
+            .map(|reason| (*reason, self.dropped(*reason) - previous.dropped(*reason)))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+/// What happened when a client was shut down, as returned by `Client::shutdown`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ShutdownReport {
+    /// The size of the final batch sent, in bytes. Always 0 without batching.
+    pub flushed_bytes: usize,
+    /// The number of metrics in the final batch sent, including any held by `Client::pause`.
+    /// Always 0 without batching.
+    pub flushed_metrics: u64,
+    /// The number of metrics dropped while shutting down, such as because the final batch
+    /// couldn't be sent.
+    pub dropped_metrics: u64,
+    /// How long shutting down took.
+    pub duration: Duration,
+}
+
+// Shared between the client and the batch processor, so everything is atomic.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    dropped: [AtomicU64; 6],
+    likely_kernel_drops: AtomicU64,
+    packed_splits: AtomicU64,
+    batch_splits: AtomicU64,
+    truncated_tags: AtomicU64,
+    truncated_oversized: AtomicU64,
+    send_latency: Option<Mutex<LatencySamples>>,
+}
+
+impl Stats {
+    pub(crate) fn new(measure_send_latency: bool) -> Self {
+        Stats {
+            send_latency: measure_send_latency.then(Mutex::default),
+            ..Default::default()
+        }
+    }
+
+    // Runs a socket send, recording how long it took if send latency is being measured.
+    pub(crate) fn time_send<T>(&self, send: impl FnOnce() -> T) -> T {
+        let Some(send_latency) = &self.send_latency else {
+            return send();
+        };
+        let started = Instant::now();
+        let result = send();
+        lock(send_latency).record(started.elapsed());
+        result
+    }
+
+    pub(crate) fn record_drop(&self, reason: DropReason, count: u64) {
+        self.dropped[reason.index()].fetch_add(count, Ordering::Relaxed);
+    }
+
+    // Called with every error from writing `count` metrics to a socket, to tell the ones the
+    // kernel refused for lack of buffer space apart from the rest.
+    pub(crate) fn record_send_error(&self, error: &io::Error, count: u64) {
+        if is_buffer_full(error) {
+            self.likely_kernel_drops.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_packed_split(&self) {
+        self.packed_splits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_batch_split(&self) {
+        self.batch_splits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_tag_truncation(&self) {
+        self.truncated_tags.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_oversized_truncation(&self) {
+        self.truncated_oversized.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ClientStats {
+        let dropped = |reason: DropReason| self.dropped[reason.index()].load(Ordering::Relaxed);
+        let (send_latency_p50, send_latency_p99) = match &self.send_latency {
+            Some(send_latency) => {
+                let mut samples = lock(send_latency).samples.clone();
+                samples.sort_unstable();
+                (percentile(&samples, 50), percentile(&samples, 99))
+            }
+            None => (None, None),
+        };
+
+        ClientStats {
+            dropped_invalid: dropped(DropReason::Invalid),
+            dropped_filtered: dropped(DropReason::Filtered),
+            dropped_rate_limited: dropped(DropReason::RateLimited),
+            dropped_sampled: dropped(DropReason::Sampled),
+            dropped_send_failed: dropped(DropReason::SendFailed),
+            dropped_queue_full: dropped(DropReason::QueueFull),
+            likely_kernel_drops: self.likely_kernel_drops.load(Ordering::Relaxed),
+            send_latency_p50,
+            send_latency_p99,
+            packed_splits: self.packed_splits.load(Ordering::Relaxed),
+            batch_splits: self.batch_splits.load(Ordering::Relaxed),
+            truncated_tags: self.truncated_tags.load(Ordering::Relaxed),
+            truncated_oversized: self.truncated_oversized.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// A ring buffer of the most recent send latencies.
+#[derive(Debug, Default)]
+struct LatencySamples {
+    samples: Vec<Duration>,
+    next: usize,
+}
+
+impl LatencySamples {
+    fn record(&mut self, latency: Duration) {
+        if self.samples.len() < SEND_LATENCY_SAMPLES {
+            self.samples.push(latency);
+        } else {
+            self.samples[self.next] = latency;
+        }
+        self.next = (self.next + 1) % SEND_LATENCY_SAMPLES;
+    }
+}
+
+// The nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[Duration], percent: usize) -> Option<Duration> {
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+fn is_buffer_full(error: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if error.raw_os_error() == Some(ENOBUFS) {
+        return true;
+    }
+    error.kind() == io::ErrorKind::WouldBlock
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_drop() {
+        let stats = Stats::default();
+        stats.record_drop(DropReason::Sampled, 2);
+        stats.record_drop(DropReason::SendFailed, 3);
+        stats.record_drop(DropReason::Sampled, 1);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(3, snapshot.dropped(DropReason::Sampled));
+        assert_eq!(3, snapshot.dropped(DropReason::SendFailed));
+        assert_eq!(0, snapshot.dropped(DropReason::Invalid));
+        assert_eq!(6, snapshot.total_dropped());
+    }
+
+    #[test]
+    fn test_record_send_error() {
+        let stats = Stats::default();
+        stats.record_send_error(&io::Error::from(io::ErrorKind::WouldBlock), 3);
+        stats.record_send_error(&io::Error::from(io::ErrorKind::ConnectionRefused), 2);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(3, snapshot.likely_kernel_drops);
+        assert_eq!(0, snapshot.total_dropped());
+    }
+
+    #[test]
+    fn test_send_latency() {
+        let stats = Stats::default();
+        stats.time_send(|| ());
+        assert_eq!(None, stats.snapshot().send_latency_p50);
+
+        let stats = Stats::new(true);
+        assert_eq!(None, stats.snapshot().send_latency_p99);
+        for millis in 1..=100 {
+            lock(stats.send_latency.as_ref().unwrap()).record(Duration::from_millis(millis));
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(Some(Duration::from_millis(50)), snapshot.send_latency_p50);
+        assert_eq!(Some(Duration::from_millis(99)), snapshot.send_latency_p99);
+        assert_eq!(7, stats.time_send(|| 7));
+    }
+
+    #[test]
+    fn test_latency_samples_wrap() {
+        let mut samples = LatencySamples::default();
+        for _ in 0..SEND_LATENCY_SAMPLES {
+            samples.record(Duration::from_secs(1));
+        }
+        samples.record(Duration::from_millis(1));
+
+        assert_eq!(SEND_LATENCY_SAMPLES, samples.samples.len());
+        assert_eq!(Duration::from_millis(1), samples.samples[0]);
+        assert_eq!(1, samples.next);
+    }
+
+    #[test]
+    fn test_dropped_since() {
+        let stats = Stats::default();
+        stats.record_drop(DropReason::Filtered, 4);
+        let previous = stats.snapshot();
+        stats.record_drop(DropReason::Filtered, 1);
+        stats.record_drop(DropReason::RateLimited, 7);
+
+        assert_eq!(
+            vec![(DropReason::Filtered, 1), (DropReason::RateLimited, 7)],
+            stats.snapshot().dropped_since(&previous)
+        );
+    }
+}
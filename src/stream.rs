@@ -0,0 +1,219 @@
+use std::ops::Deref;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures_core::Stream;
+use pin_project_lite::pin_project;
+
+use crate::metrics::{CountMetric, TimingMetric};
+use crate::Client;
+
+/// Adapters that report metrics about the items of a stream as it's polled. Only available
+/// with the `stream` feature.
+///
+/// The client can be anything that derefs to a `Client`, such as `&Client` or `Arc<Client>`.
+/// Errors sending metrics never affect the stream, they go to `Options::error_handler`.
+pub trait InstrumentStream: Stream + Sized {
+    /// Increment a counter for every item the stream yields.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{Client, InstrumentStream, Options};
+    ///   # fn messages() -> impl futures_core::Stream<Item = u32> {
+    ///   #     struct Empty;
+    ///   #     impl futures_core::Stream for Empty {
+    ///   #         type Item = u32;
+    ///   #         fn poll_next(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<Option<u32>> {
+    ///   #             std::task::Poll::Ready(None)
+    ///   #         }
+    ///   #     }
+    ///   #     Empty
+    ///   # }
+    ///
+    ///   let client = Client::new(Options::default()).unwrap();
+    ///   let messages = messages().count_items(&client, "messages.consumed", &["tag:stream"]);
+    /// ```
+    fn count_items<C, S, I, T>(self, client: C, stat: S, tags: I) -> CountItems<Self, C>
+    where
+        C: Deref<Target = Client>,
+        S: Into<String>,
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        CountItems {
+            stream: self,
+            client,
+            stat: stat.into(),
+            tags: collect_tags(tags),
+        }
+    }
+
+    /// Report how long the stream took to yield each item, in ms, measured from the first poll
+    /// after the previous item.
+    fn time_items<C, S, I, T>(self, client: C, stat: S, tags: I) -> TimeItems<Self, C>
+    where
+        C: Deref<Target = Client>,
+        S: Into<String>,
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        TimeItems {
+            stream: self,
+            client,
+            stat: stat.into(),
+            tags: collect_tags(tags),
+            waiting_since: None,
+        }
+    }
+}
+
+impl<St: Stream> InstrumentStream for St {}
+
+fn collect_tags<I, T>(tags: I) -> Vec<String>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    tags.into_iter()
+        .map(|tag| tag.as_ref().to_owned())
+        .collect()
+}
+
+pin_project! {
+    /// Stream for the `InstrumentStream::count_items` method.
+    #[derive(Debug)]
+    pub struct CountItems<St, C> {
+        #[pin]
+        stream: St,
+        client: C,
+        stat: String,
+        tags: Vec<String>,
+    }
+}
+
+impl<St, C> Stream for CountItems<St, C>
+where
+    St: Stream,
+    C: Deref<Target = Client>,
+{
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let item = this.stream.poll_next(cx);
+
+        if let Poll::Ready(Some(_)) = item {
+            if let Err(error) = this
+                .client
+                .send(&CountMetric::Incr(this.stat, 1), this.tags.iter())
+            {
+                this.client
+                    .writer
+                    .report_error("Failed to count stream item", error);
+            }
+        }
+
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+pin_project! {
+    /// Stream for the `InstrumentStream::time_items` method.
+    #[derive(Debug)]
+    pub struct TimeItems<St, C> {
+        #[pin]
+        stream: St,
+        client: C,
+        stat: String,
+        tags: Vec<String>,
+        waiting_since: Option<Instant>,
+    }
+}
+
+impl<St, C> Stream for TimeItems<St, C>
+where
+    St: Stream,
+    C: Deref<Target = Client>,
+{
+    type Item = St::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let waiting_since = *this.waiting_since.get_or_insert_with(Instant::now);
+        let item = this.stream.poll_next(cx);
+
+        if let Poll::Ready(ready) = &item {
+            *this.waiting_since = None;
+
+            if ready.is_some() {
+                let ms = waiting_since.elapsed().as_millis() as i64;
+                if let Err(error) = this
+                    .client
+                    .send(&TimingMetric::new(this.stat, ms), this.tags.iter())
+                {
+                    this.client
+                        .writer
+                        .report_error("Failed to time stream item", error);
+                }
+            }
+        }
+
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CaptureSink, Options};
+    use std::collections::VecDeque;
+    use std::future::poll_fn;
+
+    struct Items(VecDeque<u32>);
+
+    impl Stream for Items {
+        type Item = u32;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<u32>> {
+            Poll::Ready(self.0.pop_front())
+        }
+    }
+
+    async fn collect<St: Stream + Unpin>(mut stream: St) -> Vec<St::Item> {
+        let mut items = vec![];
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn test_count_items() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        let stream = Items(vec![1, 2, 3].into()).count_items(&client, "consumed", ["tag1"]);
+
+        assert_eq!(vec![1, 2, 3], collect(stream).await);
+        assert_eq!(vec!["consumed:1|c|#tag1".to_string(); 3], sink.payloads());
+    }
+
+    #[tokio::test]
+    async fn test_time_items() {
+        let sink = CaptureSink::new();
+        let client = std::sync::Arc::new(sink.client(Options::default()));
+        let stream = Items(vec![1, 2].into()).time_items(client, "latency", ["tag1"]);
+
+        assert_eq!(vec![1, 2], collect(stream).await);
+        assert_eq!(vec!["latency:0|ms|#tag1".to_string(); 2], sink.payloads());
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// Locks a mutex shared with other threads. A poisoned lock means another thread panicked while
+// holding it, which is a bug, so it panics here too. With the `no-panic` feature the data is
+// used as is instead, since everything behind these locks stays consistent between
+// statements anyway.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    #[cfg(feature = "no-panic")]
+    {
+        mutex
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+    #[cfg(not(feature = "no-panic"))]
+    {
+        mutex.lock().expect("Mutex poisoned...")
+    }
+}
+
+// Read locks an `RwLock` shared with other threads, handling poisoning like `lock`.
+pub(crate) fn read<T>(rw_lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    #[cfg(feature = "no-panic")]
+    {
+        rw_lock
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+    #[cfg(not(feature = "no-panic"))]
+    {
+        rw_lock.read().expect("RwLock poisoned...")
+    }
+}
+
+// Write locks an `RwLock` shared with other threads, handling poisoning like `lock`.
+pub(crate) fn write<T>(rw_lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    #[cfg(feature = "no-panic")]
+    {
+        rw_lock
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+    #[cfg(not(feature = "no-panic"))]
+    {
+        rw_lock.write().expect("RwLock poisoned...")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock() {
+        let mutex = Mutex::new(1);
+        *lock(&mutex) += 1;
+        assert_eq!(2, *lock(&mutex));
+    }
+
+    #[cfg(feature = "no-panic")]
+    #[test]
+    fn test_lock_poisoned() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let mutex = Arc::new(Mutex::new(1));
+        let poisoner = mutex.clone();
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+
+        assert!(mutex.is_poisoned());
+        assert_eq!(1, *lock(&mutex));
+    }
+}
@@ -0,0 +1,111 @@
+/// Where a default tag set on `OptionsBuilder` came from. When sources set tags with the same
+/// key, only the tags from the source that comes last here are kept, so `Code` overrides
+/// `File`, which overrides `Env`.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{OptionsBuilder, TagSource};
+///
+///   let options = OptionsBuilder::new()
+///       .tag_source(TagSource::File, vec![String::from("team:platform"), String::from("env:staging")])
+///       .default_tag(String::from("env:prod"))
+///       .build();
+///   assert_eq!(vec!["env:prod", "team:platform"], options.default_tags);
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum TagSource {
+    /// Read from an environment variable, as by `OptionsBuilder::env_tags`.
+    Env,
+    /// Loaded from a configuration file.
+    File,
+    /// Set in code, as by `OptionsBuilder::default_tag`.
+    Code,
+}
+
+// The part of a tag before the first `:`, or the whole tag.
+fn key(tag: &str) -> &str {
+    tag.split_once(':').map_or(tag, |(key, _)| key)
+}
+
+// Keeps each tag whose key isn't set by a later source, from the latest source first, keeping
+// the order they were added in within a source.
+pub(crate) fn merge(tags: &[(TagSource, String)]) -> Vec<(String, TagSource)> {
+    let mut merged: Vec<(String, TagSource)> = Vec::new();
+    for source in [TagSource::Code, TagSource::File, TagSource::Env] {
+        for (_, tag) in tags.iter().filter(|(tag_source, _)| *tag_source == source) {
+            let overridden = merged.iter().any(|(merged_tag, merged_source)| {
+                *merged_source != source && key(merged_tag) == key(tag)
+            });
+            if !overridden {
+                merged.push((tag.clone(), source));
+            }
+        }
+    }
+    merged
+}
+
+// Splits the value of a variable like `DD_TAGS` into tags, on commas and whitespace.
+pub(crate) fn parse_env(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OptionsBuilder;
+
+    #[test]
+    fn test_merge_tag_sources() {
+        let tags = [
+            (TagSource::Env, "env:dev".into()),
+            (TagSource::Env, "region:us-east-1".into()),
+            (TagSource::Code, "env:prod".into()),
+            (TagSource::File, "team:a".into()),
+            (TagSource::File, "team:b".into()),
+            (TagSource::File, "env:staging".into()),
+            (TagSource::Env, "team:c".into()),
+        ];
+        assert_eq!(
+            vec![
+                ("env:prod".into(), TagSource::Code),
+                ("team:a".into(), TagSource::File),
+                ("team:b".into(), TagSource::File),
+                ("region:us-east-1".into(), TagSource::Env),
+            ],
+            merge(&tags)
+        );
+
+        assert_eq!(
+            vec!["a:1", "b", "c:3"],
+            parse_env(" a:1, b\tc:3,,").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_options_builder_tag_sources() {
+        std::env::set_var("DOGSTATSD_TEST_TAG_SOURCES", "env:dev,host:a region:eu");
+        let mut builder = OptionsBuilder::new();
+        builder
+            .default_tag("env:prod".into())
+            .env_tags("DOGSTATSD_TEST_TAG_SOURCES")
+            .env_tags("DOGSTATSD_TEST_TAG_SOURCES_UNSET")
+            .tag_source(TagSource::File, vec!["region:us".into()]);
+
+        assert_eq!(
+            vec![
+                ("env:prod".into(), TagSource::Code),
+                ("region:us".into(), TagSource::File),
+                ("host:a".into(), TagSource::Env),
+            ],
+            builder.merged_tags()
+        );
+        assert_eq!(
+            vec!["env:prod", "region:us", "host:a"],
+            builder.build().default_tags
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::time::{timeout_at, Instant};
+
+/// A UDP server that records the metrics it receives, for end-to-end tests of async
+/// applications against real datagram traffic. Only available with the `test-server` feature.
+///
+/// # Examples
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+///   use dogstatsd::{Client, OptionsBuilder, TestServer};
+///   use std::time::Duration;
+///
+///   let mut server = TestServer::bind("127.0.0.1:0").await.unwrap();
+///   let client = Client::new(
+///       OptionsBuilder::new()
+///           .to_addr(server.local_addr().to_string())
+///           .build(),
+///   )
+///   .unwrap();
+///
+///   client.incr("checkout.started", &["env:test"]).unwrap();
+///   server.expect_metric("checkout.started:1|c|#*", Duration::from_secs(1)).await;
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TestServer {
+    socket: UdpSocket,
+    received: VecDeque<String>,
+}
+
+impl TestServer {
+    /// Bind a server to `addr`. Bind port 0 and use `local_addr` to avoid clashing with other
+    /// tests.
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(TestServer {
+            socket: UdpSocket::bind(addr).await?,
+            received: VecDeque::new(),
+        })
+    }
+
+    /// The address the server is bound to, for `Options::to_addr`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket
+            .local_addr()
+            .expect("unable to get the test server's address")
+    }
+
+    /// Wait up to `timeout` for the next metric, which is removed from the server. Batched
+    /// datagrams are split, so each metric is returned on its own.
+    pub async fn next_metric(&mut self, timeout: Duration) -> Option<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(metric) = self.received.pop_front() {
+                return Some(metric);
+            }
+            if !self.receive_until(deadline).await {
+                return None;
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for a metric matching `pattern`, in which `*` matches any run of
+    /// characters, and return it. The matching metric is removed from the server, and metrics
+    /// received before it are kept for later calls.
+    ///
+    /// # Panics
+    ///
+    /// If no matching metric arrives in time, listing the metrics that did.
+    pub async fn expect_metric(&mut self, pattern: &str, timeout: Duration) -> String {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(index) = self.received.iter().position(|m| matches(pattern, m)) {
+                return self.received.remove(index).unwrap_or_default();
+            }
+            if !self.receive_until(deadline).await {
+                panic!(
+                    "no metric matching {:?} within {:?}, received {:?}",
+                    pattern, timeout, self.received
+                );
+            }
+        }
+    }
+
+    /// Wait for `duration`, and panic if any metric matching `pattern` arrives.
+    pub async fn expect_no_metric(&mut self, pattern: &str, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while self.receive_until(deadline).await {}
+
+        if let Some(metric) = self.received.iter().find(|m| matches(pattern, m)) {
+            panic!("unexpected metric matching {:?}: {:?}", pattern, metric);
+        }
+    }
+
+    // Receives one datagram into `received`, returning false if the deadline passed first.
+    async fn receive_until(&mut self, deadline: Instant) -> bool {
+        let mut buf = vec![0; 65_535];
+        let len = match timeout_at(deadline, self.socket.recv(&mut buf)).await {
+            Ok(result) => result.expect("unable to read from the test server's socket"),
+            Err(_) => return false,
+        };
+
+        let datagram = String::from_utf8_lossy(&buf[..len]);
+        self.received.extend(
+            datagram
+                .split('\n')
+                .filter(|metric| !metric.is_empty())
+                .map(String::from),
+        );
+        true
+    }
+}
+
+// Whether `metric` matches `pattern`, where `*` matches any run of characters.
+fn matches(pattern: &str, metric: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match metric.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let mut parts: Vec<&str> = parts.collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BatchingOptions, Client, OptionsBuilder};
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("stat:1|c", "stat:1|c"));
+        assert!(!matches("stat:1|c", "stat:1|c|#tag"));
+        assert!(matches("stat:1|c*", "stat:1|c|#tag"));
+        assert!(matches("stat:*|c|#*env:test*", "stat:4|c|#a,env:test,b"));
+        assert!(matches("*", ""));
+        assert!(!matches("stat:*|g", "stat:1|c"));
+        assert!(!matches("ab*ba", "aba"));
+    }
+
+    #[tokio::test]
+    async fn test_expect_metric() {
+        let mut server = TestServer::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(
+            OptionsBuilder::new()
+                .to_addr(server.local_addr().to_string())
+                .batching_options(BatchingOptions::default())
+                .build(),
+        )
+        .unwrap();
+
+        client.incr("first", ["tag:a"]).unwrap();
+        client.gauge("second", "5", ["tag:b"]).unwrap();
+        drop(client);
+
+        assert_eq!(
+            "second:5|g|#tag:b",
+            server
+                .expect_metric("second:*", Duration::from_secs(5))
+                .await
+        );
+        assert_eq!(
+            Some("first:1|c|#tag:a".to_string()),
+            server.next_metric(Duration::from_secs(1)).await
+        );
+        assert_eq!(None, server.next_metric(Duration::from_millis(10)).await);
+        server
+            .expect_no_metric("*", Duration::from_millis(10))
+            .await;
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "no metric matching \"missing:*\"")]
+    async fn test_expect_metric_timeout() {
+        let mut server = TestServer::bind("127.0.0.1:0").await.unwrap();
+        server
+            .expect_metric("missing:*", Duration::from_millis(10))
+            .await;
+    }
+}
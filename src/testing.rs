@@ -0,0 +1,450 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::batch_processor::Message;
+use crate::sync::lock;
+use crate::{Client, Clock, Options, SocketType};
+
+// Set this environment variable to rewrite snapshot files instead of comparing against them.
+const UPDATE_SNAPSHOTS_ENV: &str = "DOGSTATSD_UPDATE_SNAPSHOTS";
+
+/// A sink that records every payload a client would have sent, for asserting on metrics in
+/// tests. Only available with the `testing` feature.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{CaptureSink, Options};
+///
+///   let sink = CaptureSink::new();
+///   let client = sink.client(Options::default());
+///   client.incr("counter", &["tag:counter"]).unwrap();
+///
+///   assert_eq!(vec!["counter:1|c|#tag:counter".to_string()], sink.payloads());
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CaptureSink {
+    records: Arc<Mutex<Vec<CapturedPayload>>>,
+    // The clock payloads are timed by, for a client from `client_with_clock`.
+    clock: Option<MockClock>,
+}
+
+/// A payload captured by a `CaptureSink`, with when and from which thread it was sent, for
+/// asserting on ordering, batching windows and concurrency. A batching client sends from its
+/// batch processor's thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedPayload {
+    /// The payload, as it would have been sent.
+    pub payload: String,
+    /// When the payload was sent. For a client from `CaptureSink::client_with_clock`, that's
+    /// by its `MockClock`: when the clock was created, plus however far it had been advanced.
+    pub sent_at: Instant,
+    /// The thread the payload was sent from.
+    pub thread_id: ThreadId,
+}
+
+impl CaptureSink {
+    /// Create a new, empty `CaptureSink`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a client that sends to this sink instead of a socket. Every other option,
+    /// including batching, still applies.
+    ///
+    /// # Panics
+    ///
+    /// If the client's background threads can't be started, as with `thread::spawn`.
+    pub fn client(&self, options: Options) -> Client {
+        Client::with_socket(options, SocketType::Capture(self.clone()), Clock::System)
+            .expect("failed to start the client's threads")
+    }
+
+    /// Like `client`, but the batch processor's time only moves when `clock` is advanced, so
+    /// tests can check when batches are flushed without sleeping. As with a real clock, a batch
+    /// older than `max_time` is flushed when the next metric arrives. Panics as `client` does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{BatchingOptions, CaptureSink, MockClock, OptionsBuilder};
+    ///   use std::time::Duration;
+    ///
+    ///   let sink = CaptureSink::new();
+    ///   let clock = MockClock::new();
+    ///   let client = sink.client_with_clock(
+    ///       OptionsBuilder::new()
+    ///           .batching_options(BatchingOptions { max_time: Duration::from_secs(1), ..Default::default() })
+    ///           .build(),
+    ///       &clock,
+    ///   );
+    ///
+    ///   client.incr("first", &["tag:a"]).unwrap();
+    ///   clock.advance(Duration::from_secs(2));
+    ///   client.incr("second", &["tag:a"]).unwrap();
+    ///
+    ///   assert_eq!(
+    ///       vec!["first:1|c|#tag:a\nsecond:1|c|#tag:a\n".to_string()],
+    ///       sink.wait_for_payloads(1, Duration::from_secs(5))
+    ///   );
+    /// ```
+    pub fn client_with_clock(&self, options: Options, clock: &MockClock) -> Client {
+        let sink = CaptureSink {
+            records: self.records.clone(),
+            clock: Some(clock.clone()),
+        };
+        let client = Client::with_socket(
+            options,
+            SocketType::Capture(sink),
+            Clock::Manual(SystemTime::UNIX_EPOCH),
+        )
+        .expect("failed to start the client's threads");
+        if let Some(tx) = client.writer.batch_sender() {
+            lock(&clock.batch_processors).push(tx);
+        }
+        client
+    }
+
+    /// Every payload captured so far, in the order they were sent.
+    pub fn payloads(&self) -> Vec<String> {
+        lock(&self.records)
+            .iter()
+            .map(|record| record.payload.clone())
+            .collect()
+    }
+
+    /// Every payload captured so far, with when and from which thread it was sent, in the order
+    /// they were sent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///   use dogstatsd::{CaptureSink, Options};
+    ///
+    ///   let sink = CaptureSink::new();
+    ///   let client = sink.client(Options::default());
+    ///   client.incr("counter", &["tag:counter"]).unwrap();
+    ///
+    ///   let records = sink.records();
+    ///   assert_eq!("counter:1|c|#tag:counter", records[0].payload);
+    ///   assert_eq!(std::thread::current().id(), records[0].thread_id);
+    /// ```
+    pub fn records(&self) -> Vec<CapturedPayload> {
+        lock(&self.records).clone()
+    }
+
+    /// Wait until at least `count` payloads have been captured, or `timeout` has passed, and
+    /// return every payload captured so far. For batching clients, whose batches are sent from
+    /// another thread.
+    pub fn wait_for_payloads(&self, count: usize, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let payloads = self.payloads();
+            if payloads.len() >= count || Instant::now() >= deadline {
+                return payloads;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Forget every payload captured so far.
+    pub fn clear(&self) {
+        lock(&self.records).clear();
+    }
+
+    /// Write every payload captured so far to `path`, one per line, with timestamps normalized.
+    pub fn write_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.snapshot())
+    }
+
+    /// Assert that the payloads captured so far match the snapshot at `path`, ignoring
+    /// timestamps. If the file doesn't exist yet, or `DOGSTATSD_UPDATE_SNAPSHOTS` is set, the
+    /// snapshot is written instead.
+    ///
+    /// # Panics
+    ///
+    /// If the payloads don't match the snapshot, or the snapshot can't be read or written.
+    pub fn assert_snapshot<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+        let actual = self.snapshot();
+
+        if !path.exists() || std::env::var_os(UPDATE_SNAPSHOTS_ENV).is_some() {
+            fs::write(path, actual)
+                .unwrap_or_else(|e| panic!("unable to write snapshot {:?}: {}", path, e));
+            return;
+        }
+
+        let expected = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("unable to read snapshot {:?}: {}", path, e));
+        assert!(
+            expected == actual,
+            "metrics don't match snapshot {:?} (set {} to update it)\n--- expected\n{}--- actual\n{}",
+            path,
+            UPDATE_SNAPSHOTS_ENV,
+            expected,
+            actual
+        );
+    }
+
+    pub(crate) fn capture(&self, payload: Vec<u8>) {
+        let record = CapturedPayload {
+            payload: String::from_utf8_lossy(&payload).into_owned(),
+            sent_at: self
+                .clock
+                .as_ref()
+                .map_or_else(Instant::now, MockClock::now),
+            thread_id: thread::current().id(),
+        };
+        lock(&self.records).push(record);
+    }
+
+    fn snapshot(&self) -> String {
+        let mut snapshot = String::new();
+        for payload in self.payloads() {
+            for line in payload.lines() {
+                snapshot.push_str(&normalize_timestamps(line));
+                snapshot.push('\n');
+            }
+        }
+        snapshot
+    }
+}
+
+/// A clock that only moves when told to, for driving a batching client's `max_time` in tests.
+/// Only available with the `testing` feature.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    batch_processors: Arc<Mutex<Vec<Sender<Message>>>>,
+    // When the clock was created, and how far it's been advanced since.
+    created: Instant,
+    advanced: Arc<Mutex<Duration>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock {
+            batch_processors: Arc::default(),
+            created: Instant::now(),
+            advanced: Arc::default(),
+        }
+    }
+}
+
+impl MockClock {
+    /// Create a new `MockClock`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `duration`, for every client created with it. Metrics sent
+    /// before the call see the old time, and metrics sent after it the new one.
+    pub fn advance(&self, duration: Duration) {
+        *lock(&self.advanced) += duration;
+        lock(&self.batch_processors).retain(|tx| tx.send(Message::Advance(duration)).is_ok());
+    }
+
+    /// The clock's current time: when it was created, plus however far it's been advanced.
+    pub fn now(&self) -> Instant {
+        self.created + *lock(&self.advanced)
+    }
+}
+
+// Replaces the value of every `|d:` field (event and service check timestamps), which would
+// otherwise change from run to run.
+fn normalize_timestamps(line: &str) -> String {
+    let mut normalized = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(index) = rest.find("|d:") {
+        normalized.push_str(&rest[..index + 3]);
+        rest = &rest[index + 3..];
+
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits > 0 {
+            normalized.push_str("<timestamp>");
+            rest = &rest[digits..];
+        }
+    }
+    normalized.push_str(rest);
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BatchingOptions, EventOptions, OptionsBuilder, ServiceCheckOptions, ServiceStatus,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_capture() {
+        let sink = CaptureSink::new();
+        let client = sink.client(Options::default());
+        client.gauge("gauge", "1234", ["tag1"]).unwrap();
+        client.incr("counter", [] as [&str; 0]).unwrap();
+
+        assert_eq!(
+            vec!["gauge:1234|g|#tag1".to_string(), "counter:1|c".to_string()],
+            sink.payloads()
+        );
+
+        sink.clear();
+        assert!(sink.payloads().is_empty());
+    }
+
+    #[test]
+    fn test_capture_records() {
+        let sink = CaptureSink::new();
+        let client = Arc::new(sink.client(Options::default()));
+        client.incr("main", [] as [&str; 0]).unwrap();
+        let spawned = thread::spawn({
+            let client = client.clone();
+            move || {
+                client.incr("spawned", [] as [&str; 0]).unwrap();
+                thread::current().id()
+            }
+        })
+        .join()
+        .unwrap();
+
+        let records = sink.records();
+        assert_eq!(2, records.len());
+        assert_eq!("main:1|c", records[0].payload);
+        assert_eq!(thread::current().id(), records[0].thread_id);
+        assert_eq!("spawned:1|c", records[1].payload);
+        assert_eq!(spawned, records[1].thread_id);
+        assert!(records[0].sent_at <= records[1].sent_at);
+    }
+
+    #[test]
+    fn test_capture_batched() {
+        let sink = CaptureSink::new();
+        let client = sink.client(
+            OptionsBuilder::new()
+                .batching_options(BatchingOptions {
+                    max_buffer_size: 1024,
+                    max_time: Duration::from_secs(60),
+                    max_retry_attempts: 0,
+                    initial_retry_delay: 0,
+                })
+                .build(),
+        );
+        client.incr("first", [] as [&str; 0]).unwrap();
+        client.incr("second", [] as [&str; 0]).unwrap();
+        drop(client);
+
+        // The batch processor flushes on its own thread.
+        for _ in 0..100 {
+            if !sink.payloads().is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(vec!["first:1|c\nsecond:1|c\n".to_string()], sink.payloads());
+    }
+
+    #[test]
+    fn test_capture_batched_mock_clock() {
+        let sink = CaptureSink::new();
+        let clock = MockClock::new();
+        let client = sink.client_with_clock(
+            OptionsBuilder::new()
+                .batching_options(BatchingOptions {
+                    max_buffer_size: 1024,
+                    max_time: Duration::from_secs(10),
+                    max_retry_attempts: 0,
+                    initial_retry_delay: 0,
+                })
+                .build(),
+            &clock,
+        );
+
+        client.incr("first", [] as [&str; 0]).unwrap();
+        clock.advance(Duration::from_secs(10));
+        client.incr("second", [] as [&str; 0]).unwrap();
+        clock.advance(Duration::from_secs(1));
+        client.incr("third", [] as [&str; 0]).unwrap();
+
+        assert_eq!(
+            vec!["first:1|c\nsecond:1|c\nthird:1|c\n".to_string()],
+            sink.wait_for_payloads(1, Duration::from_secs(5))
+        );
+        // Payloads are timed by the clock, not by how long the test took.
+        assert_eq!(clock.now(), sink.records()[0].sent_at);
+
+        clock.advance(Duration::from_secs(5));
+        client.incr("fourth", [] as [&str; 0]).unwrap();
+        drop(client);
+        assert_eq!(
+            vec![
+                "first:1|c\nsecond:1|c\nthird:1|c\n".to_string(),
+                "fourth:1|c\n".to_string()
+            ],
+            sink.wait_for_payloads(2, Duration::from_secs(5))
+        );
+        let records = sink.records();
+        assert_eq!(
+            Duration::from_secs(5),
+            records[1].sent_at - records[0].sent_at
+        );
+    }
+
+    #[test]
+    fn test_normalize_timestamps() {
+        assert_eq!(
+            "_sc|redis|0|d:<timestamp>|h:host",
+            normalize_timestamps("_sc|redis|0|d:1510326433|h:host")
+        );
+        assert_eq!("gauge:1|g", normalize_timestamps("gauge:1|g"));
+        assert_eq!("odd|d:", normalize_timestamps("odd|d:"));
+    }
+
+    #[test]
+    fn test_assert_snapshot() {
+        let path = crate::temp_path(&format!("-snapshot-{:?}.txt", std::thread::current().id()));
+        let _ = fs::remove_file(&path);
+
+        let run = |timestamp| {
+            let sink = CaptureSink::new();
+            let client = sink.client(Options::default());
+            client.incr("counter", ["tag1"]).unwrap();
+            client
+                .service_check(
+                    "redis.can_connect",
+                    ServiceStatus::OK,
+                    [] as [&str; 0],
+                    Some(ServiceCheckOptions {
+                        timestamp: Some(timestamp),
+                        ..Default::default()
+                    }),
+                )
+                .unwrap();
+            client
+                .event_with_options(
+                    "title",
+                    "text",
+                    [] as [&str; 0],
+                    Some(EventOptions::new().with_timestamp(timestamp as u64)),
+                )
+                .unwrap();
+            sink
+        };
+
+        // The first run writes the snapshot, the second compares against it.
+        run(1510326433).assert_snapshot(&path);
+        run(1638480000).assert_snapshot(&path);
+
+        assert_eq!(
+            "counter:1|c|#tag1\n_sc|redis.can_connect|0|d:<timestamp>\n_e{5,4}:title|text|d:<timestamp>\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{LockResult, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::Client;
+
+/// A lock guard that reports how long the lock was held when it's dropped. Returned by
+/// `Client::timed_lock`, `Client::timed_read` and `Client::timed_write`.
+pub struct TimedGuard<'a, G> {
+    guard: G,
+    client: &'a Client,
+    stat: String,
+    tags: Vec<String>,
+    acquired: Instant,
+}
+
+impl<G> fmt::Debug for TimedGuard<'_, G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TimedGuard")
+            .field("stat", &self.stat)
+            .field("tags", &self.tags)
+            .field("acquired", &self.acquired)
+            .finish()
+    }
+}
+
+impl<G: Deref> Deref for TimedGuard<'_, G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for TimedGuard<'_, G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for TimedGuard<'_, G> {
+    fn drop(&mut self) {
+        report(
+            self.client,
+            &format!("{}.hold", self.stat),
+            self.acquired.elapsed(),
+            &self.tags,
+        );
+    }
+}
+
+// Acquires a lock with `acquire`, reporting how long that took as `<stat>.wait`. A poisoned
+// lock is still timed, and its guard is returned in the `PoisonError` as usual.
+pub(crate) fn timed<'a, G, I, T>(
+    client: &'a Client,
+    stat: &str,
+    tags: I,
+    acquire: impl FnOnce() -> LockResult<G>,
+) -> LockResult<TimedGuard<'a, G>>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<str>,
+{
+    let tags: Vec<String> = tags
+        .into_iter()
+        .map(|tag| tag.as_ref().to_owned())
+        .collect();
+    let start = Instant::now();
+    let result = acquire();
+    let acquired = Instant::now();
+    report(client, &format!("{}.wait", stat), acquired - start, &tags);
+
+    let wrap = |guard| TimedGuard {
+        guard,
+        client,
+        stat: stat.to_owned(),
+        tags,
+        acquired,
+    };
+    match result {
+        Ok(guard) => Ok(wrap(guard)),
+        Err(poisoned) => Err(PoisonError::new(wrap(poisoned.into_inner()))),
+    }
+}
+
+fn report(client: &Client, stat: &str, duration: Duration, tags: &[String]) {
+    let ms = format!("{:.3}", duration.as_secs_f64() * 1000.0);
+    if let Err(error) = client.distribution(stat, ms, tags) {
+        client
+            .writer
+            .report_error("Failed to report lock timing", error);
+    }
+}
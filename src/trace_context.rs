@@ -0,0 +1,301 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::marker::PhantomData;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+thread_local! {
+    static CURRENT: Cell<Option<TraceContext>> = const { Cell::new(None) };
+    // The contexts that were current before each span `TraceContextLayer` entered, innermost
+    // last.
+    static ENTERED: RefCell<Vec<Option<TraceContext>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The trace and span a thread is working on. While one is entered, every metric and event
+/// the thread sends is tagged `dd.trace_id:<trace_id>` and `dd.span_id:<span_id>`, so they
+/// can be correlated with the trace in Datadog. Only available with the `trace-context`
+/// feature.
+///
+/// With `tracing`, add `TraceContextLayer` to the subscriber and the context is entered along
+/// with each span. Otherwise enter it wherever the active span is known, such as in an
+/// OpenTelemetry propagator. OpenTelemetry trace IDs are 128 bits, of which Datadog uses the
+/// lower 64.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, Options, TraceContext};
+///
+///   let client = Client::new(Options::default()).unwrap();
+///   let _guard = TraceContext { trace_id: 1234, span_id: 5678 }.enter();
+///   // Sent as `checkout.started:1|c|#dd.trace_id:1234,dd.span_id:5678`
+///   client.incr("checkout.started", [] as [&str; 0])
+///       .unwrap_or_else(|e| println!("Encountered error: {}", e));
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TraceContext {
+    /// The trace's ID, sent as `dd.trace_id`.
+    pub trace_id: u64,
+    /// The active span's ID, sent as `dd.span_id`.
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// Make this the current thread's context until the returned guard is dropped, which
+    /// restores the context that was current before.
+    pub fn enter(self) -> TraceContextGuard {
+        TraceContextGuard {
+            previous: CURRENT.with(|current| current.replace(Some(self))),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The current thread's context, if one is entered.
+    pub fn current() -> Option<TraceContext> {
+        CURRENT.with(Cell::get)
+    }
+
+    pub(crate) fn tags(&self) -> [String; 2] {
+        [
+            format!("dd.trace_id:{}", self.trace_id),
+            format!("dd.span_id:{}", self.span_id),
+        ]
+    }
+}
+
+/// Restores the previous `TraceContext` when dropped. It can't be sent to another thread,
+/// since the context it restores belongs to the thread it was entered on.
+#[derive(Debug)]
+#[must_use = "the context is only entered until the guard is dropped"]
+pub struct TraceContextGuard {
+    previous: Option<TraceContext>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for TraceContextGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// A `tracing_subscriber` layer that enters each span's `TraceContext` while the span is
+/// entered, so metrics sent inside it are tagged with its trace. A span's context comes from
+/// its `dd.trace_id` and `dd.span_id` fields, such as those of a span for an incoming request
+/// with propagated trace headers, and is otherwise inherited from its parent. Spans outside
+/// any trace don't tag metrics.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, Options, TraceContextLayer};
+///   use tracing_subscriber::layer::SubscriberExt;
+///
+///   let subscriber = tracing_subscriber::registry().with(TraceContextLayer);
+///   tracing::subscriber::with_default(subscriber, || {
+///       let client = Client::new(Options::default()).unwrap();
+///       let _span = tracing::info_span!("checkout", dd.trace_id = 1234u64, dd.span_id = 5678u64)
+///           .entered();
+///       // Sent as `checkout.started:1|c|#dd.trace_id:1234,dd.span_id:5678`
+///       client.incr("checkout.started", [] as [&str; 0])
+///           .unwrap_or_else(|e| println!("Encountered error: {}", e));
+///   });
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraceContextLayer;
+
+impl<S> Layer<S> for TraceContextLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut fields = TraceFields::default();
+        attrs.record(&mut fields);
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<TraceContext>().copied());
+        if let Some(context) = fields.apply(parent) {
+            span.extensions_mut().insert(context);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut fields = TraceFields::default();
+        values.record(&mut fields);
+        let mut extensions = span.extensions_mut();
+        let recorded = extensions.get_mut::<TraceContext>().copied();
+        if let Some(context) = fields.apply(recorded) {
+            extensions.replace(context);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let context = ctx
+            .span(id)
+            .and_then(|span| span.extensions().get::<TraceContext>().copied());
+        // Spans outside a trace leave the current context alone, so one entered by hand stays.
+        let previous = CURRENT.with(|current| match context {
+            Some(context) => current.replace(Some(context)),
+            None => current.get(),
+        });
+        ENTERED.with(|entered| entered.borrow_mut().push(previous));
+    }
+
+    fn on_exit(&self, _id: &Id, _ctx: Context<'_, S>) {
+        if let Some(previous) = ENTERED.with(|entered| entered.borrow_mut().pop()) {
+            CURRENT.with(|current| current.set(previous));
+        }
+    }
+}
+
+// The `dd.trace_id` and `dd.span_id` fields of a span, as numbers or strings.
+#[derive(Default)]
+struct TraceFields {
+    trace_id: Option<u64>,
+    span_id: Option<u64>,
+}
+
+impl TraceFields {
+    // The context of a span with these fields, given the one it would have otherwise. A span
+    // that sets only `dd.trace_id` starts a trace without a Datadog span, so it's sent as 0.
+    fn apply(&self, context: Option<TraceContext>) -> Option<TraceContext> {
+        match (self.trace_id, context) {
+            (Some(trace_id), _) => Some(TraceContext {
+                trace_id,
+                span_id: self.span_id.unwrap_or_default(),
+            }),
+            (None, Some(context)) => Some(TraceContext {
+                span_id: self.span_id.unwrap_or(context.span_id),
+                ..context
+            }),
+            (None, None) => None,
+        }
+    }
+
+    fn set(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "dd.trace_id" => self.trace_id = Some(value),
+            "dd.span_id" => self.span_id = Some(value),
+            _ => {}
+        }
+    }
+}
+
+impl Visit for TraceFields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if let Ok(value) = u64::try_from(value) {
+            self.set(field, value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if let Ok(value) = value.parse() {
+            self.set(field, value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceContext, TraceContextLayer};
+    use std::thread;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_enter() {
+        let outer = TraceContext {
+            trace_id: 1,
+            span_id: 2,
+        };
+        let inner = TraceContext {
+            trace_id: 1,
+            span_id: 3,
+        };
+
+        assert_eq!(None, TraceContext::current());
+        let outer_guard = outer.enter();
+        {
+            let _inner_guard = inner.enter();
+            assert_eq!(Some(inner), TraceContext::current());
+            thread::spawn(|| assert_eq!(None, TraceContext::current()))
+                .join()
+                .unwrap();
+        }
+        assert_eq!(Some(outer), TraceContext::current());
+        drop(outer_guard);
+        assert_eq!(None, TraceContext::current());
+    }
+
+    #[test]
+    fn test_layer() {
+        let subscriber = tracing_subscriber::registry().with(TraceContextLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            let untraced = tracing::info_span!("untraced").entered();
+            assert_eq!(None, TraceContext::current());
+
+            let request = tracing::info_span!("request", dd.trace_id = "12", dd.span_id = 34u64);
+            {
+                let _request = request.enter();
+                assert_eq!(
+                    Some(TraceContext {
+                        trace_id: 12,
+                        span_id: 34
+                    }),
+                    TraceContext::current()
+                );
+
+                // Children inherit the trace, and can set their own Datadog span.
+                let child = tracing::info_span!("child", dd.span_id = tracing::field::Empty);
+                {
+                    let _child = child.enter();
+                    assert_eq!(
+                        Some(TraceContext {
+                            trace_id: 12,
+                            span_id: 34
+                        }),
+                        TraceContext::current()
+                    );
+                }
+                child.record("dd.span_id", 56u64);
+                let _child = child.enter();
+                assert_eq!(
+                    Some(TraceContext {
+                        trace_id: 12,
+                        span_id: 56
+                    }),
+                    TraceContext::current()
+                );
+            }
+            assert_eq!(None, TraceContext::current());
+            drop(untraced);
+
+            // A context entered by hand is kept inside spans outside any trace.
+            let manual = TraceContext {
+                trace_id: 7,
+                span_id: 8,
+            };
+            let _guard = manual.enter();
+            let inner = tracing::info_span!("inner").entered();
+            assert_eq!(Some(manual), TraceContext::current());
+            drop(inner);
+            assert_eq!(Some(manual), TraceContext::current());
+        });
+    }
+}
@@ -0,0 +1,932 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::sync::{lock, read, write};
+
+/// The MTU of a regular Ethernet network.
+pub const DEFAULT_MTU: usize = 1500;
+
+/// The largest UDP payload that's never fragmented on a network with the default MTU.
+pub const OPTIMAL_UDP_PAYLOAD_SIZE: usize = DEFAULT_MTU - UDP_OVERHEAD;
+
+/// The largest payload a UDP datagram can carry, with the largest possible IP header.
+pub const MAX_UDP_PAYLOAD_SIZE: usize = 65_535 - UDP_OVERHEAD;
+
+/// The payload size the Datadog Agent reads from its UDS socket by default.
+pub const DEFAULT_UDS_PAYLOAD_SIZE: usize = 8192;
+
+// The largest IPv4 header, with options, plus the UDP header.
+const UDP_OVERHEAD: usize = 60 + 8;
+
+// How long a write to a TCP collector can block the sending thread before it fails.
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How long connecting to each of a TCP collector's addresses can block the sending thread,
+// including when a send reconnects.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The ways the client can send to the agent, for computing payload sizes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Transport {
+    /// UDP over a network with this MTU.
+    Udp {
+        /// The network's MTU, such as `DEFAULT_MTU`, 9000 with jumbo frames, or less behind a
+        /// VPN.
+        mtu: usize,
+    },
+    /// A Unix domain socket.
+    Uds,
+    /// A Unix domain socket of type `SOCK_SEQPACKET` at `Options::socket_path`, for relays
+    /// that deliver each payload whole, in order and without dropping it.
+    UdsSeqPacket,
+    /// A TCP connection to a collector that accepts StatsD over TCP, with one metric per line.
+    Tcp,
+}
+
+/// How metrics sent over `Transport::Tcp` are compressed, for bandwidth-constrained links to
+/// collectors that read a compressed stream. The stream is flushed after every send, so each
+/// batch arrives without waiting for the next, and a reconnected connection starts a new
+/// stream. It needs the `compression` feature, and can't be used with the agent's UDP and
+/// UDS sockets.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
+    /// A zlib stream.
+    Zlib,
+    /// A zstd stream.
+    Zstd,
+}
+
+/// What a send over UDS does when the socket's buffer is full, so the send would block, such
+/// as when the agent falls behind under load. Without one, the send fails with the
+/// `WouldBlock` error.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WouldBlockPolicy {
+    /// Try again up to `attempts` more times, sleeping for `backoff` before each.
+    Retry {
+        /// The most times to try again.
+        attempts: u32,
+        /// How long to wait before each try.
+        backoff: Duration,
+    },
+    /// Block the sending thread until the metric is sent, failing with `WouldBlock` if that
+    /// takes longer than `deadline`, which can't be zero.
+    Block {
+        /// The longest a send can block for.
+        deadline: Duration,
+    },
+    /// Drop the metric without an error, counted as `DropReason::SendFailed`.
+    Drop,
+}
+
+/// The IP version to send over when `Options::to_addr` is a hostname with both IPv4 and IPv6
+/// addresses.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AddressFamily {
+    /// Prefer an IPv4 address.
+    Ipv4,
+    /// Prefer an IPv6 address.
+    Ipv6,
+}
+
+/// How the client gets the socket it sends from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BindMode {
+    /// Bind a UDP socket to a port the OS chooses.
+    Ephemeral,
+    /// Bind a UDP socket to this address, failing if it's already in use.
+    Fixed(String),
+    /// Use a socket that's already open, such as one passed down by a parent process. It's a
+    /// Unix datagram socket if `Options::socket_path` is set, and a UDP socket otherwise.
+    Inherit(InheritedFd),
+}
+
+/// An open socket for `BindMode::Inherit`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct InheritedFd(RawFd);
+
+impl InheritedFd {
+    /// Wrap an open socket's file descriptor.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open datagram socket that nothing else will use or close. The client
+    /// created with it takes ownership and closes it when dropped, so only one client can be
+    /// created from options holding it.
+    pub unsafe fn new(fd: RawFd) -> Self {
+        InheritedFd(fd)
+    }
+}
+
+// Gets the UDP socket for `bind_mode`, or binds `from_addr` without one.
+pub(crate) fn bind_udp(bind_mode: Option<&BindMode>, from_addr: &str) -> io::Result<UdpSocket> {
+    match bind_mode {
+        None => UdpSocket::bind(from_addr),
+        Some(BindMode::Ephemeral) => UdpSocket::bind("0.0.0.0:0"),
+        Some(BindMode::Fixed(addr)) => UdpSocket::bind(addr).map_err(|error| {
+            if error.kind() == io::ErrorKind::AddrInUse {
+                io::Error::new(
+                    error.kind(),
+                    format!(
+                        "can't bind {}, it's already in use. Use BindMode::Ephemeral to let the OS choose a port",
+                        addr
+                    ),
+                )
+            } else {
+                error
+            }
+        }),
+        // SAFETY: `InheritedFd::new` requires the caller to hand over ownership of an open
+        // socket.
+        Some(BindMode::Inherit(fd)) => Ok(unsafe { UdpSocket::from_raw_fd(fd.0) }),
+    }
+}
+
+// Takes over the inherited socket. Only done once for each `InheritedFd`, which can't be
+// closed again.
+pub(crate) fn take_inherited(fd: &InheritedFd) -> OwnedFd {
+    // SAFETY: as for `bind_udp`.
+    unsafe { OwnedFd::from_raw_fd(fd.0) }
+}
+
+// Resolves `to_addr` once, to an address of the preferred family if it has one, and to its
+// first address otherwise.
+pub(crate) fn resolve_to_addr(
+    to_addr: &str,
+    address_family: Option<AddressFamily>,
+) -> io::Result<SocketAddr> {
+    let addrs: Vec<SocketAddr> = to_addr
+        .to_socket_addrs()
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("can't resolve to_addr {:?}: {}", to_addr, error),
+            )
+        })?
+        .collect();
+    let preferred = addrs.iter().find(|addr| match address_family {
+        Some(AddressFamily::Ipv4) => addr.is_ipv4(),
+        Some(AddressFamily::Ipv6) => addr.is_ipv6(),
+        None => false,
+    });
+    preferred.or(addrs.first()).copied().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("to_addr {:?} didn't resolve to any addresses", to_addr),
+        )
+    })
+}
+
+// Blocks sends with a timeout for `WouldBlockPolicy::Block`, and doesn't block them otherwise.
+pub(crate) fn set_uds_blocking(
+    socket: &UnixDatagram,
+    would_block_policy: Option<WouldBlockPolicy>,
+) -> io::Result<()> {
+    match would_block_policy {
+        Some(WouldBlockPolicy::Block { deadline }) => {
+            socket.set_nonblocking(false)?;
+            socket.set_write_timeout(Some(deadline))
+        }
+        _ => socket.set_nonblocking(true),
+    }
+}
+
+// Sends over UDS, retrying for `WouldBlockPolicy::Retry`. Callers count the drops for
+// `WouldBlockPolicy::Drop`.
+pub(crate) fn send_uds(
+    socket: &UnixDatagram,
+    data: &[u8],
+    would_block_policy: Option<WouldBlockPolicy>,
+) -> io::Result<usize> {
+    let Some(WouldBlockPolicy::Retry { attempts, backoff }) = would_block_policy else {
+        return socket.send(data);
+    };
+    let mut attempt = 0;
+    loop {
+        match socket.send(data) {
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock && attempt < attempts => {
+                attempt += 1;
+                thread::sleep(backoff);
+            }
+            result => return result,
+        }
+    }
+}
+
+// Whether `error` is a full buffer that `would_block_policy` says to drop the metrics for.
+pub(crate) fn drops_would_block(
+    error: &io::Error,
+    would_block_policy: Option<WouldBlockPolicy>,
+) -> bool {
+    would_block_policy == Some(WouldBlockPolicy::Drop) && error.kind() == io::ErrorKind::WouldBlock
+}
+
+// Whether `error` is the socket refusing a payload as too large to send at once, such as a UDP
+// datagram over the path MTU.
+pub(crate) fn is_message_too_long(error: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    const EMSGSIZE: i32 = 90;
+    #[cfg(not(target_os = "linux"))]
+    const EMSGSIZE: i32 = 40;
+
+    error.raw_os_error() == Some(EMSGSIZE)
+}
+
+// A connection to a TCP collector, which is reconnected when a write to it fails.
+#[derive(Debug)]
+pub(crate) struct TcpConnection {
+    addr: String,
+    compression: Option<Compression>,
+    stream: Mutex<Option<TcpWriter>>,
+}
+
+impl TcpConnection {
+    pub(crate) fn connect(addr: &str, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(TcpConnection {
+            addr: addr.to_owned(),
+            compression,
+            stream: Mutex::new(Some(TcpWriter::new(connect_tcp(addr)?, compression)?)),
+        })
+    }
+
+    // Writes newline separated metrics, reconnecting and writing them again once if the
+    // connection was lost.
+    pub(crate) fn send(&self, payload: &[u8]) -> io::Result<()> {
+        let mut stream = lock(&self.stream);
+        if let Some(connected) = stream.as_mut() {
+            if connected.write(payload).is_ok() {
+                return Ok(());
+            }
+            *stream = None;
+        }
+
+        let mut reconnected = TcpWriter::new(connect_tcp(&self.addr)?, self.compression)?;
+        reconnected.write(payload)?;
+        *stream = Some(reconnected);
+        Ok(())
+    }
+
+    // A compressed stream can't be shared, so a clone of a compressed connection makes its own
+    // on its first send.
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        let stream = match &*lock(&self.stream) {
+            Some(TcpWriter::Plain(stream)) => Some(TcpWriter::Plain(stream.try_clone()?)),
+            _ => None,
+        };
+        Ok(TcpConnection {
+            addr: self.addr.clone(),
+            compression: self.compression,
+            stream: Mutex::new(stream),
+        })
+    }
+}
+
+// A TCP stream, compressed if the connection is.
+enum TcpWriter {
+    Plain(TcpStream),
+    #[cfg(feature = "compression")]
+    Zlib(flate2::write::ZlibEncoder<TcpStream>),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::stream::write::Encoder<'static, TcpStream>),
+}
+
+impl TcpWriter {
+    fn new(stream: TcpStream, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(match compression {
+            None => TcpWriter::Plain(stream),
+            #[cfg(feature = "compression")]
+            Some(Compression::Zlib) => TcpWriter::Zlib(flate2::write::ZlibEncoder::new(
+                stream,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "compression")]
+            Some(Compression::Zstd) => TcpWriter::Zstd(zstd::stream::write::Encoder::new(
+                stream,
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )?),
+            #[cfg(not(feature = "compression"))]
+            Some(_) => return Err(compression_unavailable()),
+        })
+    }
+
+    // Writes `payload`, flushing a compressed stream so it's sent now.
+    fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        match self {
+            TcpWriter::Plain(stream) => stream.write_all(payload),
+            #[cfg(feature = "compression")]
+            TcpWriter::Zlib(encoder) => {
+                encoder.write_all(payload)?;
+                encoder.flush()
+            }
+            #[cfg(feature = "compression")]
+            TcpWriter::Zstd(encoder) => {
+                encoder.write_all(payload)?;
+                encoder.flush()
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TcpWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TcpWriter::Plain(stream) => f.debug_tuple("Plain").field(stream).finish(),
+            #[cfg(feature = "compression")]
+            TcpWriter::Zlib(encoder) => f.debug_tuple("Zlib").field(encoder.get_ref()).finish(),
+            #[cfg(feature = "compression")]
+            TcpWriter::Zstd(encoder) => f.debug_tuple("Zstd").field(encoder.get_ref()).finish(),
+        }
+    }
+}
+
+// Checks `Options::compression` is only set for TCP, and only when it can be used.
+pub(crate) fn validate_compression(
+    compression: Option<Compression>,
+    transport: Option<Transport>,
+) -> io::Result<()> {
+    if compression.is_none() {
+        return Ok(());
+    }
+    if transport != Some(Transport::Tcp) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "compression can only be used with Transport::Tcp",
+        ));
+    }
+    #[cfg(not(feature = "compression"))]
+    return Err(compression_unavailable());
+    #[cfg(feature = "compression")]
+    Ok(())
+}
+
+#[cfg(not(feature = "compression"))]
+fn compression_unavailable() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "compression needs the `compression` feature",
+    )
+}
+
+// A UDS socket that's sent to over UDP instead while its path is missing or refuses
+// connections, such as while the agent isn't running in UDS mode, trying the path again every
+// `retry_interval`.
+#[derive(Debug)]
+pub(crate) struct UdsFallback {
+    path: String,
+    connect_timeout: Option<Duration>,
+    seqpacket: bool,
+    would_block_policy: Option<WouldBlockPolicy>,
+    retry_interval: Duration,
+    // The connected socket, or when it was last tried while falling back.
+    uds: RwLock<Result<UnixDatagram, Instant>>,
+    udp: UdpSocket,
+    to_addr: SocketAddr,
+}
+
+impl UdsFallback {
+    pub(crate) fn open(
+        path: &str,
+        udp: UdpSocket,
+        to_addr: SocketAddr,
+        retry_interval: Duration,
+        connect_timeout: Option<Duration>,
+        seqpacket: bool,
+        would_block_policy: Option<WouldBlockPolicy>,
+    ) -> Self {
+        let uds = open_uds(path, connect_timeout, seqpacket, would_block_policy);
+        UdsFallback {
+            path: path.to_owned(),
+            connect_timeout,
+            seqpacket,
+            would_block_policy,
+            retry_interval,
+            uds: RwLock::new(uds.map_err(|_| Instant::now())),
+            udp,
+            to_addr,
+        }
+    }
+
+    pub(crate) fn send(&self, payload: &[u8]) -> io::Result<()> {
+        if let Ok(socket) = &*read(&self.uds) {
+            match send_uds(socket, payload, self.would_block_policy) {
+                // A full buffer means the agent is there, just behind.
+                Err(error) if error.kind() != io::ErrorKind::WouldBlock => {}
+                result => return result.map(|_| ()),
+            }
+        }
+
+        let mut uds = write(&self.uds);
+        let reconnect = match &*uds {
+            // Either it just failed, or another thread reconnected since.
+            Ok(socket) => match send_uds(socket, payload, self.would_block_policy) {
+                Err(error) if error.kind() != io::ErrorKind::WouldBlock => true,
+                result => return result.map(|_| ()),
+            },
+            Err(tried) => tried.elapsed() >= self.retry_interval,
+        };
+        if reconnect {
+            // A socket that failed stays connected to an agent that's gone, so it's replaced.
+            *uds = Err(Instant::now());
+            if let Ok(socket) = open_uds(
+                &self.path,
+                self.connect_timeout,
+                self.seqpacket,
+                self.would_block_policy,
+            ) {
+                match send_uds(&socket, payload, self.would_block_policy) {
+                    Err(error) if error.kind() != io::ErrorKind::WouldBlock => {}
+                    result => {
+                        *uds = Ok(socket);
+                        return result.map(|_| ());
+                    }
+                }
+            }
+        }
+        drop(uds);
+        self.udp.send_to(payload, self.to_addr).map(|_| ())
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        Ok(UdsFallback {
+            path: self.path.clone(),
+            connect_timeout: self.connect_timeout,
+            seqpacket: self.seqpacket,
+            would_block_policy: self.would_block_policy,
+            retry_interval: self.retry_interval,
+            uds: RwLock::new(match &*read(&self.uds) {
+                Ok(socket) => Ok(socket.try_clone()?),
+                Err(tried) => Err(*tried),
+            }),
+            udp: self.udp.try_clone()?,
+            to_addr: self.to_addr,
+        })
+    }
+}
+
+fn open_uds(
+    path: &str,
+    connect_timeout: Option<Duration>,
+    seqpacket: bool,
+    would_block_policy: Option<WouldBlockPolicy>,
+) -> io::Result<UnixDatagram> {
+    let socket = connect_uds(path, connect_timeout, seqpacket)?;
+    set_uds_blocking(&socket, would_block_policy)?;
+    Ok(socket)
+}
+
+// Connects a new UDS socket, a `SOCK_SEQPACKET` one if `seqpacket` is set, to `path`. With a
+// timeout it connects without blocking and polls for the connection to finish, giving up after
+// `timeout`. The socket is left blocking either way.
+pub(crate) fn connect_uds(
+    path: &str,
+    timeout: Option<Duration>,
+    seqpacket: bool,
+) -> io::Result<UnixDatagram> {
+    let socket = if seqpacket {
+        unbound_seqpacket()?
+    } else {
+        UnixDatagram::unbound()?
+    };
+    let Some(timeout) = timeout else {
+        socket.connect(path)?;
+        return Ok(socket);
+    };
+
+    socket.set_nonblocking(true)?;
+    match socket.connect(path) {
+        Ok(()) => {}
+        Err(error) if error.raw_os_error() == Some(libc::EINPROGRESS) => {
+            let mut pollfd = libc::pollfd {
+                fd: socket.as_raw_fd(),
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            let timeout_ms = timeout.as_millis().clamp(1, libc::c_int::MAX as u128);
+            // SAFETY: `pollfd` is valid for reads and writes, and its length is 1.
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms as libc::c_int) } {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("connecting to {} took longer than {:?}", path, timeout),
+                    ))
+                }
+                _ => {
+                    if let Some(error) = socket.take_error()? {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+        Err(error) => return Err(error),
+    }
+    socket.set_nonblocking(false)?;
+    Ok(socket)
+}
+
+// A new `SOCK_SEQPACKET` Unix socket. It's held as a `UnixDatagram`, whose `connect` and `send`
+// work the same on it, sending each payload as one message.
+fn unbound_seqpacket() -> io::Result<UnixDatagram> {
+    // Where the socket can't be created close-on-exec, it's set afterwards.
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    let ty = libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC;
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    let ty = libc::SOCK_SEQPACKET;
+
+    // SAFETY: `socket` takes no pointers, and returns a new descriptor or -1.
+    let fd = unsafe { libc::socket(libc::AF_UNIX, ty, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just opened, and nothing else owns it.
+    let socket = unsafe { UnixDatagram::from_raw_fd(fd) };
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    // SAFETY: `fd` is open, and owned by `socket`.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+// A file metrics are appended to, one per line, for replaying later.
+#[derive(Debug)]
+pub(crate) struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub(crate) fn open(path: &str) -> io::Result<Self> {
+        Ok(FileSink {
+            file: Mutex::new(OpenOptions::new().create(true).append(true).open(path)?),
+        })
+    }
+
+    // Appends newline separated metrics.
+    pub(crate) fn send(&self, payload: &[u8]) -> io::Result<()> {
+        lock(&self.file).write_all(payload)
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        Ok(FileSink {
+            file: Mutex::new(lock(&self.file).try_clone()?),
+        })
+    }
+}
+
+// Connects to the first of the addresses `addr` resolves to that accepts within the timeout,
+// returning the last error if none do.
+fn connect_tcp(addr: &str) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TCP_CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} resolved to no addresses", addr),
+        )
+    }))
+}
+
+/// The largest payload, and so batch, that's safe to send over `transport`. For UDP that's
+/// the largest that won't be fragmented, since losing any fragment loses the whole datagram.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{recommended_max_payload, Transport, DEFAULT_MTU};
+///
+///   assert_eq!(1432, recommended_max_payload(Transport::Udp { mtu: DEFAULT_MTU }));
+///   assert_eq!(8932, recommended_max_payload(Transport::Udp { mtu: 9000 }));
+///   assert_eq!(8192, recommended_max_payload(Transport::Uds));
+/// ```
+///
+/// TCP has no limit, so it gets the same as UDS.
+pub fn recommended_max_payload(transport: Transport) -> usize {
+    match transport {
+        Transport::Udp { mtu } => mtu.saturating_sub(UDP_OVERHEAD).min(MAX_UDP_PAYLOAD_SIZE),
+        Transport::Uds | Transport::UdsSeqPacket | Transport::Tcp => DEFAULT_UDS_PAYLOAD_SIZE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_udp() {
+        let fixed = bind_udp(Some(&BindMode::Fixed("127.0.0.1:0".into())), "").unwrap();
+        let addr = fixed.local_addr().unwrap().to_string();
+
+        let error = bind_udp(Some(&BindMode::Fixed(addr.clone())), "").unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, error.kind());
+        assert!(error.to_string().contains(&addr));
+
+        assert_ne!(
+            0,
+            bind_udp(Some(&BindMode::Ephemeral), "")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port()
+        );
+        assert!(bind_udp(None, "127.0.0.1:0").is_ok());
+    }
+
+    #[test]
+    fn test_inherit() {
+        use std::os::unix::io::IntoRawFd;
+
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fd = UdpSocket::bind("127.0.0.1:0").unwrap().into_raw_fd();
+        let socket = bind_udp(
+            Some(&BindMode::Inherit(unsafe { InheritedFd::new(fd) })),
+            "",
+        )
+        .unwrap();
+        socket
+            .send_to(b"stat:1|c", listener.local_addr().unwrap())
+            .unwrap();
+
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(b"stat:1|c", &buf[..len]);
+    }
+
+    #[test]
+    fn test_resolve_to_addr() {
+        assert_eq!(
+            "[::1]:8125",
+            resolve_to_addr("[::1]:8125", Some(AddressFamily::Ipv4))
+                .unwrap()
+                .to_string()
+        );
+        // Falls back to an address of the other family.
+        assert!(resolve_to_addr("localhost:8125", Some(AddressFamily::Ipv6)).is_ok());
+        assert!(resolve_to_addr("localhost:8125", Some(AddressFamily::Ipv4))
+            .unwrap()
+            .is_ipv4());
+
+        let error = resolve_to_addr("localhost", None).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        assert!(error
+            .to_string()
+            .starts_with("can't resolve to_addr \"localhost\": "));
+    }
+
+    #[test]
+    fn test_would_block_policy() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        set_uds_blocking(&sender, None).unwrap();
+        // Fill the socket's buffer.
+        while sender.send(&[0; 1024]).is_ok() {}
+
+        let policy = Some(WouldBlockPolicy::Retry {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+        });
+        let error = send_uds(&sender, b"stat:1|c", policy).unwrap_err();
+        assert!(!drops_would_block(&error, policy));
+        assert!(drops_would_block(&error, Some(WouldBlockPolicy::Drop)));
+
+        // Reading from the other end makes room for the retry.
+        let reader = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            receiver.recv(&mut [0; 1024]).unwrap();
+            receiver
+        });
+        let policy = Some(WouldBlockPolicy::Retry {
+            attempts: 1000,
+            backoff: Duration::from_millis(1),
+        });
+        send_uds(&sender, b"stat:1|c", policy).unwrap();
+        drop(reader.join().unwrap());
+
+        let (sender, _receiver) = UnixDatagram::pair().unwrap();
+        let policy = Some(WouldBlockPolicy::Block {
+            deadline: Duration::from_millis(10),
+        });
+        set_uds_blocking(&sender, policy).unwrap();
+        let error = loop {
+            if let Err(error) = send_uds(&sender, &[0; 1024], policy) {
+                break error;
+            }
+        };
+        assert_eq!(io::ErrorKind::WouldBlock, error.kind());
+    }
+
+    #[test]
+    fn test_connect_uds() {
+        let path = crate::temp_path(".connect");
+        let _ = std::fs::remove_file(&path);
+        let timeout = Some(Duration::from_secs(1));
+        let error = connect_uds(path.to_str().unwrap(), timeout, false).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, error.kind());
+
+        let agent = UnixDatagram::bind(&path).unwrap();
+        let socket = connect_uds(path.to_str().unwrap(), timeout, false).unwrap();
+        // SAFETY: `F_GETFL` takes no pointers.
+        let flags = unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(0, flags & libc::O_NONBLOCK);
+        socket.send(b"connected:1|c").unwrap();
+        let mut buf = [0; 32];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"connected:1|c", &buf[..len]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_connect_uds_seqpacket() {
+        use std::io::Read;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::{AsRawFd, OwnedFd};
+        use std::os::unix::net::UnixListener;
+
+        let path = crate::temp_path(".seqpacket");
+        let _ = std::fs::remove_file(&path);
+        // A `struct sockaddr_un` for `path`, for a relay listening on a `SOCK_SEQPACKET` socket.
+        let mut addr = [0u8; 110];
+        addr[..2].copy_from_slice(&1u16.to_ne_bytes());
+        let path_bytes = path.as_os_str().as_bytes();
+        addr[2..2 + path_bytes.len()].copy_from_slice(path_bytes);
+        let relay = unbound_seqpacket().unwrap();
+        // SAFETY: `addr` is valid for reads of its length.
+        unsafe {
+            let len = addr.len() as libc::socklen_t;
+            assert_eq!(0, libc::bind(relay.as_raw_fd(), addr.as_ptr().cast(), len));
+            assert_eq!(0, libc::listen(relay.as_raw_fd(), 1));
+        }
+        let relay = UnixListener::from(OwnedFd::from(relay));
+
+        let timeout = Some(Duration::from_secs(1));
+        let socket = connect_uds(path.to_str().unwrap(), timeout, true).unwrap();
+        socket.send(b"first:1|c").unwrap();
+        socket.send(b"second:1|c").unwrap();
+        let (mut connection, _) = relay.accept().unwrap();
+        let mut buf = [0; 32];
+        // Each payload arrives as a message of its own.
+        let len = connection.read(&mut buf).unwrap();
+        assert_eq!(b"first:1|c", &buf[..len]);
+        let len = connection.read(&mut buf).unwrap();
+        assert_eq!(b"second:1|c", &buf[..len]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_uds_fallback() {
+        let path = crate::temp_path(".fallback");
+        let _ = std::fs::remove_file(&path);
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fallback = UdsFallback::open(
+            path.to_str().unwrap(),
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            server.local_addr().unwrap(),
+            Duration::ZERO,
+            None,
+            false,
+            None,
+        );
+        let mut buf = [0; 64];
+
+        fallback.send(b"missing:1|c").unwrap();
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"missing:1|c", &buf[..len]);
+
+        // The socket is tried again once the agent is up.
+        let agent = UnixDatagram::bind(&path).unwrap();
+        fallback.send(b"uds:1|c").unwrap();
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"uds:1|c", &buf[..len]);
+
+        // A restarted agent gets a fresh socket, rather than the one connected to the old agent.
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+        let agent = UnixDatagram::bind(&path).unwrap();
+        fallback.send(b"restarted:1|c").unwrap();
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(b"restarted:1|c", &buf[..len]);
+
+        drop(agent);
+        std::fs::remove_file(&path).unwrap();
+        fallback.send(b"stopped:1|c").unwrap();
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"stopped:1|c", &buf[..len]);
+    }
+
+    #[test]
+    fn test_tcp_connection_reconnects() {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connection =
+            TcpConnection::connect(&listener.local_addr().unwrap().to_string(), None).unwrap();
+        let (first, _) = listener.accept().unwrap();
+        connection.send(b"first:1|c\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(&first).read_line(&mut line).unwrap();
+        assert_eq!("first:1|c\n", line);
+
+        // The first write after the collector closes the connection usually still succeeds,
+        // and only a later one finds out it's gone.
+        drop(first);
+        listener.set_nonblocking(true).unwrap();
+        let second = loop {
+            connection.send(b"second:1|c\n").unwrap();
+            if let Ok((second, _)) = listener.accept() {
+                break second;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        second.set_nonblocking(false).unwrap();
+        line.clear();
+        BufReader::new(&second).read_line(&mut line).unwrap();
+        assert_eq!("second:1|c\n", line);
+
+        // With the collector gone, reconnecting fails rather than the send succeeding.
+        drop((listener, second));
+        let error = loop {
+            if let Err(error) = connection.send(b"third:1|c\n") {
+                break error;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(io::ErrorKind::ConnectionRefused, error.kind());
+        assert!(connect_tcp("127.0.0.1:0").is_err());
+    }
+
+    #[test]
+    fn test_validate_compression() {
+        assert!(validate_compression(None, None).is_ok());
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            validate_compression(Some(Compression::Zlib), Some(Transport::Uds))
+                .unwrap_err()
+                .kind()
+        );
+        assert_eq!(
+            cfg!(feature = "compression"),
+            validate_compression(Some(Compression::Zstd), Some(Transport::Tcp)).is_ok()
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_tcp_compression() {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        for compression in [Compression::Zlib, Compression::Zstd] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let connection = TcpConnection::connect(&addr, Some(compression)).unwrap();
+            let (collector, _) = listener.accept().unwrap();
+
+            // Each send is flushed, so it can be read while the connection stays open.
+            let expected = b"first:1|c\nsecond:1|c\n";
+            connection.send(b"first:1|c\n").unwrap();
+            connection.send(b"second:1|c\n").unwrap();
+            let mut decoder: Box<dyn Read> = match compression {
+                Compression::Zlib => Box::new(flate2::read::ZlibDecoder::new(collector)),
+                Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(collector).unwrap()),
+            };
+            let mut received = vec![0; expected.len()];
+            decoder.read_exact(&mut received).unwrap();
+            assert_eq!(&expected[..], received);
+        }
+    }
+
+    #[test]
+    fn test_recommended_max_payload() {
+        assert_eq!(
+            OPTIMAL_UDP_PAYLOAD_SIZE,
+            recommended_max_payload(Transport::Udp { mtu: DEFAULT_MTU })
+        );
+        assert_eq!(1332, recommended_max_payload(Transport::Udp { mtu: 1400 }));
+        assert_eq!(0, recommended_max_payload(Transport::Udp { mtu: 40 }));
+        assert_eq!(
+            MAX_UDP_PAYLOAD_SIZE,
+            recommended_max_payload(Transport::Udp { mtu: 100_000 })
+        );
+        assert_eq!(
+            DEFAULT_UDS_PAYLOAD_SIZE,
+            recommended_max_payload(Transport::Uds)
+        );
+        assert_eq!(
+            DEFAULT_UDS_PAYLOAD_SIZE,
+            recommended_max_payload(Transport::Tcp)
+        );
+    }
+}
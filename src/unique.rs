@@ -0,0 +1,147 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::sync::lock;
+use crate::{Client, DogstatsdError};
+
+// How many members a tracker remembers per interval unless `with_max_members` says otherwise,
+// 800KB of hashes.
+const DEFAULT_MAX_MEMBERS: usize = 100_000;
+
+/// A set metric whose members are sent once each per interval no matter how often they're
+/// seen, for sets with members that repeat a lot, such as user ids. A member is sent through
+/// the client, batched like any other metric, the first time it's seen in an interval. Only a
+/// 64-bit hash of each member is kept, so a member whose hash collides with another's is taken
+/// as already seen.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{Client, Options};
+///   use std::time::Duration;
+///
+///   let client = Client::new(Options::default()).unwrap();
+///   let users = client
+///       .unique_tracker("active_users", Duration::from_secs(10), &["tag:set"])
+///       .with_max_members(10_000);
+///   users.insert("user-1").unwrap();
+///   users.insert("user-1").unwrap();
+/// ```
+#[derive(Debug)]
+pub struct UniqueTracker {
+    client: Client,
+    stat: String,
+    tags: Vec<String>,
+    interval: Duration,
+    max_members: usize,
+    // When the current interval started, and the hashes of the members seen in it.
+    seen: Mutex<(Instant, HashSet<u64>)>,
+}
+
+impl UniqueTracker {
+    pub(crate) fn new(client: Client, stat: String, interval: Duration, tags: Vec<String>) -> Self {
+        UniqueTracker {
+            client,
+            stat,
+            tags,
+            interval,
+            max_members: DEFAULT_MAX_MEMBERS,
+            seen: Mutex::new((Instant::now(), HashSet::new())),
+        }
+    }
+
+    /// Remember at most `max_members` members per interval, 100,000 by default. Once that
+    /// many have been seen, new members are sent every time they're inserted until the
+    /// interval ends.
+    pub fn with_max_members(mut self, max_members: usize) -> Self {
+        self.max_members = max_members;
+        self
+    }
+
+    /// Add a member to the set, sending it if it hasn't been seen in this interval, and
+    /// returning whether it was sent.
+    pub fn insert<S: AsRef<str>>(&self, member: S) -> Result<bool, DogstatsdError> {
+        let member = member.as_ref();
+        let mut hasher = DefaultHasher::new();
+        member.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        {
+            let mut seen = lock(&self.seen);
+            let (started, members) = &mut *seen;
+            if started.elapsed() >= self.interval {
+                *started = Instant::now();
+                members.clear();
+            }
+            if members.contains(&hash) {
+                return Ok(false);
+            }
+            if members.len() < self.max_members {
+                members.insert(hash);
+            }
+        }
+        self.client
+            .set(self.stat.as_str(), member, &self.tags)
+            .map(|()| true)
+    }
+
+    /// The number of members remembered in this interval.
+    pub fn len(&self) -> usize {
+        lock(&self.seen).1.len()
+    }
+
+    /// Whether no members have been remembered in this interval.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Start a new interval now, so every member is sent again the next time it's inserted.
+    pub fn reset(&self) {
+        *lock(&self.seen) = (Instant::now(), HashSet::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CaptureSink, OptionsBuilder};
+    use std::time::Duration;
+
+    #[test]
+    fn test_unique_tracker() {
+        let sink = CaptureSink::new();
+        let client = sink.client(OptionsBuilder::new().namespace("app".into()).build());
+        let tracker = client.unique_tracker("users", Duration::from_secs(60), ["env:prod"]);
+
+        assert!(tracker.insert("alice").unwrap());
+        assert!(tracker.insert("bob").unwrap());
+        assert!(!tracker.insert("alice").unwrap());
+        assert_eq!(2, tracker.len());
+        assert_eq!(
+            vec!["app.users:alice|s|#env:prod", "app.users:bob|s|#env:prod"],
+            sink.payloads()
+        );
+
+        // A new interval sends every member again.
+        tracker.reset();
+        assert!(tracker.is_empty());
+        assert!(tracker.insert("alice").unwrap());
+        assert_eq!(3, sink.payloads().len());
+
+        // Each insert starts a new interval once the last one has ended.
+        let tracker = client.unique_tracker("users", Duration::ZERO, ["env:prod"]);
+        assert!(tracker.insert("alice").unwrap());
+        assert!(tracker.insert("alice").unwrap());
+
+        // Past the cap, members are sent every time rather than remembered.
+        let tracker = client
+            .unique_tracker("users", Duration::from_secs(60), ["env:prod"])
+            .with_max_members(1);
+        assert!(tracker.insert("alice").unwrap());
+        assert!(tracker.insert("bob").unwrap());
+        assert!(tracker.insert("bob").unwrap());
+        assert_eq!(1, tracker.len());
+    }
+}
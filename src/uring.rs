@@ -0,0 +1,428 @@
+use std::io;
+use std::mem::size_of;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::raw::{c_int, c_long, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::mmsg::{iovec, msghdr, RawAddr};
+
+// How many sends the batch processor can have queued with the kernel at once. Queueing another
+// waits for one of them to finish.
+pub(crate) const QUEUE_DEPTH: u32 = 64;
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_ENTER_GETEVENTS: c_long = 1;
+const IORING_OP_SENDMSG: u8 = 9;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    msg_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+// A region of the ring shared with the kernel, unmapped when dropped.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize, offset: libc::off_t) -> io::Result<Mapping> {
+        // SAFETY: mapping a fresh region has no effect on existing memory.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the region was mapped by `Mapping::new` and nothing points into it anymore.
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+struct Ring {
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    _mappings: Vec<Mapping>,
+    fd: OwnedFd,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params::default();
+        // SAFETY: `params` is a valid `io_uring_params` for the kernel to fill in.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries as c_long,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the fd was just created and nothing else owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as c_int) };
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let single_mmap = params.features & IORING_FEAT_SINGLE_MMAP != 0;
+        let sq = Mapping::new(
+            &fd,
+            if single_mmap {
+                sq_len.max(cq_len)
+            } else {
+                sq_len
+            },
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq = match single_mmap {
+            true => None,
+            false => Some(Mapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?),
+        };
+        let sqes = Mapping::new(
+            &fd,
+            params.sq_entries as usize * size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+
+        let sq_ptr = sq.ptr;
+        let cq_ptr = cq.as_ref().map_or(sq.ptr, |cq| cq.ptr);
+        // SAFETY: the kernel gave the offsets into the rings, which are mapped as long as the
+        // `Ring` is.
+        unsafe {
+            let at = |base: *mut u8, offset: u32| base.add(offset as usize);
+            Ok(Ring {
+                sq_head: at(sq_ptr, params.sq_off.head).cast(),
+                sq_tail: at(sq_ptr, params.sq_off.tail).cast(),
+                sq_mask: *at(sq_ptr, params.sq_off.ring_mask).cast::<u32>(),
+                sq_entries: params.sq_entries,
+                sq_array: at(sq_ptr, params.sq_off.array).cast(),
+                sqes: sqes.ptr.cast(),
+                cq_head: at(cq_ptr, params.cq_off.head).cast(),
+                cq_tail: at(cq_ptr, params.cq_off.tail).cast(),
+                cq_mask: *at(cq_ptr, params.cq_off.ring_mask).cast::<u32>(),
+                cqes: at(cq_ptr, params.cq_off.cqes).cast(),
+                _mappings: [Some(sq), cq, Some(sqes)].into_iter().flatten().collect(),
+                fd,
+            })
+        }
+    }
+
+    // Queues `sqe` for the next `enter`. There must be fewer than `sq_entries` sends in flight,
+    // so the submission queue has room.
+    fn push(&mut self, sqe: Sqe) {
+        // SAFETY: the pointers are into the mapped submission queue, and the index is masked to
+        // its size.
+        unsafe {
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            let head = (*self.sq_head).load(Ordering::Acquire);
+            debug_assert!(tail.wrapping_sub(head) < self.sq_entries);
+            let index = tail & self.sq_mask;
+            self.sqes.add(index as usize).write(sqe);
+            self.sq_array.add(index as usize).write(index);
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Cqe> {
+        // SAFETY: the pointers are into the mapped completion queue, and the index is masked to
+        // its size.
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = self.cqes.add((head & self.cq_mask) as usize).read();
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(cqe)
+        }
+    }
+
+    // Submits everything queued, then waits for at least `min_complete` sends to finish.
+    fn enter(&self, min_complete: u32) -> io::Result<()> {
+        let flags = if min_complete > 0 {
+            IORING_ENTER_GETEVENTS
+        } else {
+            0
+        };
+        loop {
+            // SAFETY: no signal mask is passed, and every queued entry points to a live `Slot`.
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd() as c_long,
+                    self.sq_entries as c_long,
+                    min_complete as c_long,
+                    flags,
+                    ptr::null::<c_void>(),
+                    0 as c_long,
+                )
+            };
+            if result >= 0 {
+                return Ok(());
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+}
+
+// A queued send. It's boxed so the pointers the kernel reads stay put until the send finishes.
+struct Slot {
+    buffer: Vec<u8>,
+    metrics: u64,
+    addr: Option<RawAddr>,
+    iov: libc::iovec,
+    hdr: libc::msghdr,
+}
+
+// A buffer whose send failed, with the number of metrics in it.
+pub(crate) type FailedSend = (Vec<u8>, u64, io::Error);
+
+// Sends buffers over UDP with io_uring, which queues them with the kernel instead of waiting
+// for each `sendmsg`. Buffers whose send failed are kept until `take_failed` is called, for
+// the caller to handle.
+pub(crate) struct UringSender {
+    ring: Ring,
+    // Indexed by each send's `user_data`.
+    slots: Vec<Option<Box<Slot>>>,
+    spare: Vec<Vec<u8>>,
+    failed: Vec<FailedSend>,
+}
+
+impl UringSender {
+    pub(crate) fn new() -> io::Result<Self> {
+        let ring = Ring::new(QUEUE_DEPTH)?;
+        Ok(UringSender {
+            slots: (0..ring.sq_entries).map(|_| None).collect(),
+            spare: Vec::new(),
+            failed: Vec::new(),
+            ring,
+        })
+    }
+
+    // Queues `buffer`, with `metrics` in it, to be sent to `addr`, or to the address the
+    // socket is connected to without one, replacing it with an empty buffer. It's left as it
+    // is if it couldn't be queued, with the error.
+    pub(crate) fn submit(
+        &mut self,
+        socket: &UdpSocket,
+        buffer: &mut Vec<u8>,
+        metrics: u64,
+        addr: Option<SocketAddr>,
+    ) -> io::Result<()> {
+        self.reap();
+        let index = loop {
+            if let Some(index) = self.slots.iter().position(Option::is_none) {
+                break index;
+            }
+            self.ring.enter(1)?;
+            self.reap();
+        };
+
+        let empty = self
+            .spare
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(buffer.capacity()));
+        let mut slot = Box::new(Slot {
+            buffer: std::mem::replace(buffer, empty),
+            metrics,
+            addr: addr.map(RawAddr::new),
+            iov: iovec(&[]),
+            hdr: msghdr(None, ptr::null()),
+        });
+        slot.iov = iovec(&slot.buffer);
+        slot.hdr = msghdr(slot.addr.as_ref(), &slot.iov);
+        self.ring.push(Sqe {
+            opcode: IORING_OP_SENDMSG,
+            fd: socket.as_raw_fd(),
+            addr: &slot.hdr as *const libc::msghdr as u64,
+            len: 1,
+            user_data: index as u64,
+            ..Sqe::default()
+        });
+        self.slots[index] = Some(slot);
+        // If it can't be submitted now, it's submitted with the next call that waits.
+        let _ = self.ring.enter(0);
+        Ok(())
+    }
+
+    // Waits for every queued send to finish.
+    pub(crate) fn wait_all(&mut self) -> io::Result<()> {
+        self.reap();
+        while self.slots.iter().any(Option::is_some) {
+            self.ring.enter(1)?;
+            self.reap();
+        }
+        Ok(())
+    }
+
+    // The buffers whose send failed since the last call.
+    pub(crate) fn take_failed(&mut self) -> Vec<FailedSend> {
+        std::mem::take(&mut self.failed)
+    }
+
+    fn reap(&mut self) {
+        while let Some(cqe) = self.ring.pop() {
+            let Some(slot) = self
+                .slots
+                .get_mut(cqe.user_data as usize)
+                .and_then(Option::take)
+            else {
+                continue;
+            };
+            let Slot {
+                mut buffer,
+                metrics,
+                ..
+            } = *slot;
+            if cqe.res < 0 {
+                let error = io::Error::from_raw_os_error(-cqe.res);
+                self.failed.push((buffer, metrics, error));
+                continue;
+            }
+            buffer.clear();
+            self.spare.push(buffer);
+        }
+    }
+}
+
+impl Drop for UringSender {
+    fn drop(&mut self) {
+        let _ = self.wait_all();
+        // The kernel could still read what it couldn't be waited for, so that's leaked.
+        for slot in self.slots.drain(..).flatten() {
+            std::mem::forget(slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_uring_sender() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut sender = UringSender::new().unwrap();
+
+        let addr = Some(server.local_addr().unwrap());
+        for i in 0..QUEUE_DEPTH + 1 {
+            let mut buffer = format!("metric:{}|c", i).into_bytes();
+            sender.submit(&socket, &mut buffer, 1, addr).unwrap();
+            assert!(buffer.is_empty());
+        }
+        sender.wait_all().unwrap();
+        assert!(sender.take_failed().is_empty());
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(b"metric:0|c", &buf[..len]);
+
+        // Sending to an IPv6 address from an IPv4 socket fails once the kernel gets to it.
+        let mut buffer = b"metric:1|c".to_vec();
+        let ipv6 = Some("[::1]:8125".parse().unwrap());
+        sender.submit(&socket, &mut buffer, 3, ipv6).unwrap();
+        sender.wait_all().unwrap();
+        let failed = sender.take_failed();
+        assert_eq!(1, failed.len());
+        assert_eq!((&b"metric:1|c"[..], 3), (&failed[0].0[..], failed[0].1));
+        assert!(sender.take_failed().is_empty());
+    }
+}
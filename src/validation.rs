@@ -0,0 +1,148 @@
+use std::fmt;
+
+use crate::{Options, DEFAULT_FROM_ADDR};
+
+// The Datadog Agent's default `dogstatsd_buffer_size`. Larger UDP payloads get truncated by the
+// agent, which silently loses whatever metrics didn't fit.
+const MAX_UDP_BUFFER_SIZE: usize = 8192;
+
+/// A suspicious setting found by `Options::validate`. None of these stop a client from being
+/// created, but each is likely to lose metrics without any error.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConfigWarning {
+    /// The namespace contains characters that don't belong in a metric name, such as a tag
+    /// that was passed as the namespace.
+    InvalidNamespace(String),
+    /// A default tag is empty, has an empty name or value around its `:`, or contains a
+    /// character that would break the payload.
+    MalformedDefaultTag(String),
+    /// Both `socket_path` and a non-default `from_addr` are set. UDS is used, and `from_addr`
+    /// is ignored.
+    SocketPathWithFromAddr,
+    /// Batches over UDP are allowed to grow larger than the agent will read by default.
+    MaxBufferSizeTooLargeForUdp(usize),
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigWarning::InvalidNamespace(namespace) => {
+                write!(f, "namespace {:?} isn't a valid metric name prefix", namespace)
+            }
+            ConfigWarning::MalformedDefaultTag(tag) => {
+                write!(f, "default tag {:?} is malformed", tag)
+            }
+            ConfigWarning::SocketPathWithFromAddr => {
+                write!(f, "from_addr is ignored because socket_path is set")
+            }
+            ConfigWarning::MaxBufferSizeTooLargeForUdp(max_buffer_size) => write!(
+                f,
+                "max_buffer_size of {} is larger than the {} bytes the agent reads from UDP by default",
+                max_buffer_size, MAX_UDP_BUFFER_SIZE
+            ),
+        }
+    }
+}
+
+pub(crate) fn validate(options: &Options) -> Vec<ConfigWarning> {
+    let mut warnings = vec![];
+
+    if options.namespace.contains(is_reserved) {
+        warnings.push(ConfigWarning::InvalidNamespace(options.namespace.clone()));
+    }
+
+    for tag in &options.default_tags {
+        if is_malformed_tag(tag) {
+            warnings.push(ConfigWarning::MalformedDefaultTag(tag.clone()));
+        }
+    }
+
+    if options.socket_path.is_some() && options.from_addr != DEFAULT_FROM_ADDR {
+        warnings.push(ConfigWarning::SocketPathWithFromAddr);
+    }
+
+    if let Some(batching_options) = &options.batching_options {
+        if options.socket_path.is_none() && batching_options.max_buffer_size > MAX_UDP_BUFFER_SIZE {
+            warnings.push(ConfigWarning::MaxBufferSizeTooLargeForUdp(
+                batching_options.max_buffer_size,
+            ));
+        }
+    }
+
+    warnings
+}
+
+// Characters that separate the parts of a payload, so they can't appear in names or tags.
+fn is_reserved(c: char) -> bool {
+    matches!(c, ':' | '|' | '@' | '#' | ',') || c.is_whitespace()
+}
+
+fn is_malformed_tag(tag: &str) -> bool {
+    let (name, value) = match tag.split_once(':') {
+        Some((name, value)) => (name, Some(value)),
+        None => (tag, None),
+    };
+
+    name.is_empty() || value == Some("") || tag.contains(|c: char| c != ':' && is_reserved(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BatchingOptions, OptionsBuilder};
+    use std::time::Duration;
+
+    #[test]
+    fn test_validate_default() {
+        assert!(validate(&Options::default()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_tags() {
+        let options = OptionsBuilder::new()
+            .namespace("env:prod".into())
+            .default_tag("env:prod".into())
+            .default_tag("standalone".into())
+            .default_tag("version:".into())
+            .default_tag(":prod".into())
+            .default_tag("region:us east".into())
+            .default_tag(String::new())
+            .build();
+
+        assert_eq!(
+            vec![
+                ConfigWarning::InvalidNamespace("env:prod".into()),
+                ConfigWarning::MalformedDefaultTag("version:".into()),
+                ConfigWarning::MalformedDefaultTag(":prod".into()),
+                ConfigWarning::MalformedDefaultTag("region:us east".into()),
+                ConfigWarning::MalformedDefaultTag(String::new()),
+            ],
+            validate(&options)
+        );
+    }
+
+    #[test]
+    fn test_validate_transport() {
+        let batching_options = BatchingOptions {
+            max_buffer_size: 65_000,
+            max_time: Duration::from_millis(100),
+            max_retry_attempts: 0,
+            initial_retry_delay: 0,
+        };
+
+        let udp = OptionsBuilder::new()
+            .batching_options(batching_options)
+            .build();
+        assert_eq!(
+            vec![ConfigWarning::MaxBufferSizeTooLargeForUdp(65_000)],
+            validate(&udp)
+        );
+
+        let uds = OptionsBuilder::new()
+            .from_addr("127.0.0.1:9000".into())
+            .socket_path(Some("/tmp/dsd.socket".into()))
+            .batching_options(batching_options)
+            .build();
+        assert_eq!(vec![ConfigWarning::SocketPathWithFromAddr], validate(&uds));
+    }
+}
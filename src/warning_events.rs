@@ -0,0 +1,227 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+use crate::encoder::SharedEncoder;
+use crate::error::report_error_once;
+use crate::metrics::{format_with_encoder, DefaultTags, Event};
+use crate::sync::lock;
+use crate::writer::Writer;
+use crate::{DogstatsdError, ErrorHandler, EventAlertType};
+
+/// Sends a Datadog event through the client when something goes wrong inside it that loses
+/// metrics, such as the batch processor stalling or batches failing to send, so it shows up in
+/// Datadog and not only in local logs. Each kind of problem is sent at most once per
+/// `min_interval`, and everything is still passed to `Options::error_handler`.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{BatchingOptions, OptionsBuilder, WarningEvents};
+///
+///   let options = OptionsBuilder::new()
+///       .batching_options(BatchingOptions::default())
+///       .warning_events(WarningEvents::default())
+///       .build();
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WarningEvents {
+    /// The shortest time between two events about the same kind of problem.
+    pub min_interval: Duration,
+}
+
+impl Default for WarningEvents {
+    /// At most one event every 5 minutes for each kind of problem.
+    fn default() -> Self {
+        WarningEvents {
+            min_interval: Duration::from_secs(300),
+        }
+    }
+}
+
+// The problems that are sent as events, each limited separately.
+#[derive(Debug, Clone, Copy)]
+enum Warning {
+    Stalled,
+    ChannelClosed,
+    SendFailed,
+}
+
+impl Warning {
+    fn of(error: &DogstatsdError) -> Option<Warning> {
+        match error {
+            DogstatsdError::BatchProcessorStalled { .. } => Some(Warning::Stalled),
+            DogstatsdError::ChannelClosed(_) => Some(Warning::ChannelClosed),
+            DogstatsdError::IoError(_) => Some(Warning::SendFailed),
+            DogstatsdError::PayloadTooLarge { .. }
+            | DogstatsdError::SampleRateAdjusted { .. }
+            | DogstatsdError::InvalidBatchingOptions(_)
+            | DogstatsdError::ReservedTag(_)
+            | DogstatsdError::TagsTooLarge { .. }
+            | DogstatsdError::BatchTooLarge { .. }
+            | DogstatsdError::CompletionDropped => None,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Warning::Stalled => "DogStatsD batch processor stalled",
+            Warning::ChannelClosed => "DogStatsD batch processor stopped",
+            Warning::SendFailed => "DogStatsD client failed to send metrics",
+        }
+    }
+
+    fn alert_type(self) -> EventAlertType {
+        match self {
+            Warning::Stalled | Warning::SendFailed => EventAlertType::Warning,
+            Warning::ChannelClosed => EventAlertType::Error,
+        }
+    }
+}
+
+// Turns errors reported to the error handler into events. The writer only exists after the
+// handler has been given to it and the batch processor, so it's attached afterwards.
+#[derive(Debug)]
+pub(crate) struct WarningEventSender {
+    options: WarningEvents,
+    client: OnceLock<(Weak<Writer>, DefaultTags, Option<SharedEncoder>)>,
+    last_sent: Mutex<[Option<Instant>; 3]>,
+    // Whether an error has been printed, without a handler to pass errors on to.
+    printed: AtomicBool,
+}
+
+impl WarningEventSender {
+    pub(crate) fn new(options: WarningEvents) -> Self {
+        WarningEventSender {
+            options,
+            client: OnceLock::new(),
+            last_sent: Mutex::new([None; 3]),
+            printed: AtomicBool::new(false),
+        }
+    }
+
+    // Sends events with the client's default tags and encoder through `writer` from now on.
+    pub(crate) fn attach(
+        &self,
+        writer: Weak<Writer>,
+        default_tags: DefaultTags,
+        encoder: Option<SharedEncoder>,
+    ) {
+        let _ = self.client.set((writer, default_tags, encoder));
+    }
+
+    // An error handler that sends events for `handler`'s errors before passing them on.
+    // Without a handler, only the first error is printed, since the events report the rest.
+    pub(crate) fn wrap(self: &Arc<Self>, handler: Option<ErrorHandler>) -> ErrorHandler {
+        let events = self.clone();
+        ErrorHandler::new(move |error| {
+            events.report(error);
+            report_error_once(&handler, &events.printed, "dogstatsd error", error);
+        })
+    }
+
+    fn report(&self, error: &DogstatsdError) {
+        let Some(warning) = Warning::of(error) else {
+            return;
+        };
+        let Some((writer, default_tags, encoder)) = self.client.get() else {
+            return;
+        };
+        let Some(writer) = writer.upgrade() else {
+            return;
+        };
+
+        {
+            let mut last_sent = lock(&self.last_sent);
+            let last_sent = &mut last_sent[warning as usize];
+            if last_sent.is_some_and(|sent| sent.elapsed() < self.options.min_interval) {
+                return;
+            }
+            *last_sent = Some(Instant::now());
+        }
+
+        let text = error.to_string();
+        let event = Event::new(warning.title(), &text)
+            .with_aggregation_key("dogstatsd_client")
+            .with_alert_type(warning.alert_type());
+        // Failing to send the event is the same problem again, so it's not reported.
+        let _ = writer.send(format_with_encoder(
+            &event,
+            "",
+            [] as [&str; 0],
+            encoder.as_ref().map(SharedEncoder::get),
+            default_tags,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::destination::Destination;
+    use crate::stats::Stats;
+    use crate::{CaptureSink, SocketType};
+    use std::io;
+
+    #[test]
+    fn test_warning_events() {
+        let sink = CaptureSink::new();
+        let writer = Arc::new(Writer::new(
+            SocketType::Capture(sink.clone()),
+            Destination::new("127.0.0.1:8125".into(), None),
+            Arc::new(Stats::default()),
+            0,
+            None,
+            None,
+        ));
+        let handled = Arc::new(Mutex::new(0));
+        let handled_r = handled.clone();
+        let events = Arc::new(WarningEventSender::new(WarningEvents::default()));
+        let handler = events.wrap(Some(ErrorHandler::new(move |_| {
+            *handled_r.lock().unwrap() += 1
+        })));
+
+        // Nothing is sent before the writer is attached.
+        handler.handle(&DogstatsdError::ChannelClosed(1));
+        events.attach(Arc::downgrade(&writer), b"env:prod".to_vec().into(), None);
+        handler.handle(&DogstatsdError::ChannelClosed(3));
+        handler.handle(&DogstatsdError::ChannelClosed(4));
+        handler.handle(&io::Error::other("refused").into());
+        handler.handle(&DogstatsdError::SampleRateAdjusted { from: 1.0, to: 0.5 });
+
+        assert_eq!(5, *handled.lock().unwrap());
+        assert_eq!(
+            vec![
+                "_e{33,54}:DogStatsD batch processor stopped|batch processor channel closed with 3 metrics buffered|k:dogstatsd_client|t:error|#env:prod",
+                "_e{39,7}:DogStatsD client failed to send metrics|refused|k:dogstatsd_client|t:warning|#env:prod",
+            ],
+            sink.payloads()
+        );
+    }
+
+    #[test]
+    fn test_warning_events_min_interval() {
+        let sink = CaptureSink::new();
+        let writer = Arc::new(Writer::new(
+            SocketType::Capture(sink.clone()),
+            Destination::new("127.0.0.1:8125".into(), None),
+            Arc::new(Stats::default()),
+            0,
+            None,
+            None,
+        ));
+        let events = Arc::new(WarningEventSender::new(WarningEvents {
+            min_interval: Duration::ZERO,
+        }));
+        events.attach(Arc::downgrade(&writer), DefaultTags::default(), None);
+        let handler = events.wrap(Some(ErrorHandler::new(|_| {})));
+
+        handler.handle(&DogstatsdError::ChannelClosed(1));
+        handler.handle(&DogstatsdError::ChannelClosed(1));
+        assert_eq!(2, sink.payloads().len());
+
+        drop(writer);
+        handler.handle(&DogstatsdError::ChannelClosed(1));
+        assert_eq!(2, sink.payloads().len());
+    }
+}
@@ -0,0 +1,140 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::writer::Writer;
+use crate::DogstatsdError;
+
+/// Watches a batching client's batch processor, and reports
+/// `DogstatsdError::BatchProcessorStalled` to `Options::error_handler` when it hasn't handled
+/// a message in `stall_intervals` times `BatchingOptions::max_time`, such as when a send is
+/// blocked. Only used with batching.
+///
+/// # Examples
+///
+/// ```
+///   use dogstatsd::{BatchingOptions, OptionsBuilder, WatchdogOptions};
+///
+///   let options = OptionsBuilder::new()
+///       .batching_options(BatchingOptions::default())
+///       .watchdog(WatchdogOptions { restart: true, ..Default::default() })
+///       .build();
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WatchdogOptions {
+    /// How many `max_time` intervals the batch processor can go without handling a message
+    /// before it's considered stalled. At least 2.
+    pub stall_intervals: u32,
+    /// If true, a stalled batch processor is replaced by a new one sending from the client's
+    /// current socket. Metrics the stalled one holds are only sent if it recovers.
+    pub restart: bool,
+}
+
+impl Default for WatchdogOptions {
+    /// Stalled after 10 intervals, without restarting.
+    fn default() -> Self {
+        WatchdogOptions {
+            stall_intervals: 10,
+            restart: false,
+        }
+    }
+}
+
+// When the batch processor last handled a message.
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    started: Instant,
+    last_beat_ms: AtomicU64,
+}
+
+impl Heartbeat {
+    pub(crate) fn new() -> Self {
+        Heartbeat {
+            started: Instant::now(),
+            last_beat_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn beat(&self) {
+        self.last_beat_ms
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn since_last_beat(&self) -> Duration {
+        let last_beat = Duration::from_millis(self.last_beat_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last_beat)
+    }
+}
+
+// Checks the heartbeat every `interval` until the writer is dropped. Each check also pings the
+// batch processor, so an idle one keeps beating.
+pub(crate) fn spawn(
+    options: WatchdogOptions,
+    interval: Duration,
+    heartbeat: Arc<Heartbeat>,
+    writer: Weak<Writer>,
+) -> io::Result<()> {
+    let interval = interval.max(Duration::from_millis(1));
+    let threshold = interval * options.stall_intervals.max(2);
+
+    thread::Builder::new()
+        .name("dogstatsd-watchdog".into())
+        .spawn(move || {
+            let mut reported = false;
+            loop {
+                thread::sleep(interval);
+                let writer = match writer.upgrade() {
+                    Some(writer) => writer,
+                    None => return,
+                };
+
+                let stalled_for = heartbeat.since_last_beat();
+                if stalled_for < threshold {
+                    reported = false;
+                } else if !reported {
+                    let restarted = options.restart
+                        && match writer.restart_processor() {
+                            Ok(()) => {
+                                heartbeat.beat();
+                                true
+                            }
+                            Err(error) => {
+                                writer.report_error(
+                                    "Failed to restart the stalled batch processor",
+                                    error.into(),
+                                );
+                                false
+                            }
+                        };
+                    writer.report_error(
+                        "Batch processor stalled",
+                        DogstatsdError::BatchProcessorStalled {
+                            stalled_for,
+                            restarted,
+                        },
+                    );
+                    reported = !restarted;
+                }
+
+                writer.ping();
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat() {
+        let heartbeat = Heartbeat::new();
+        thread::sleep(Duration::from_millis(20));
+        assert!(heartbeat.since_last_beat() >= Duration::from_millis(20));
+
+        heartbeat.beat();
+        assert!(heartbeat.since_last_beat() < Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,516 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::completion::Completion;
+use crate::destination::Destination;
+use crate::error::report_error;
+use crate::mirror::Mirror;
+use crate::reconnect::{Backoff, Reconnector};
+use crate::stats::Stats;
+use crate::sync::{lock, read, write};
+use crate::transport::{drops_would_block, send_uds};
+use crate::{
+    batch_processor, ConnectRetry, DogstatsdError, DogstatsdResult, DropReason, ErrorHandler,
+    SocketType, WouldBlockPolicy,
+};
+
+// Opens a new socket like the one the client was created with.
+pub(crate) type Reopen = Arc<dyn Fn() -> io::Result<SocketType> + Send + Sync>;
+
+// A socket that's opened on the first send, for `Options::lazy_socket`, backing off between
+// attempts as `retry` says to. Shared with the copies made for restarting the batch processor.
+#[derive(Clone)]
+pub(crate) struct LazySocket(Arc<Lazy>);
+
+struct Lazy {
+    open: Reopen,
+    retry: ConnectRetry,
+    backoff: Mutex<Backoff>,
+}
+
+impl fmt::Debug for LazySocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LazySocket")
+            .field("retry", &self.0.retry)
+            .field("backoff", &self.0.backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LazySocket {
+    pub(crate) fn new(open: Reopen, retry: ConnectRetry) -> Self {
+        LazySocket(Arc::new(Lazy {
+            open,
+            retry,
+            backoff: Mutex::new(Backoff::new(retry.initial_backoff)),
+        }))
+    }
+
+    // Whether it's time to try opening the socket again.
+    pub(crate) fn ready(&self) -> bool {
+        lock(&self.0.backoff).ready(Instant::now())
+    }
+
+    pub(crate) fn open(&self) -> io::Result<SocketType> {
+        let now = Instant::now();
+        let mut backoff = lock(&self.0.backoff);
+        if !backoff.ready(now) {
+            return Err(backing_off());
+        }
+        (self.0.open)().inspect_err(|_| backoff.attempted(now, self.0.retry.max_backoff))
+    }
+}
+
+// The error for a send while the socket is waiting to be opened again.
+fn backing_off() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotConnected,
+        "the socket couldn't be opened, waiting to try again",
+    )
+}
+
+// Starts a batch processor sending from a socket to a destination.
+pub(crate) type SpawnProcessor = Arc<
+    dyn Fn(
+            SocketType,
+            Destination,
+        ) -> io::Result<(mpsc::Sender<batch_processor::Message>, JoinHandle<()>)>
+        + Send
+        + Sync,
+>;
+
+// The socket, and where it sends udp payloads, behind one lock so a send only takes it once.
+#[derive(Debug)]
+struct Socket {
+    socket: SocketType,
+    destination: Destination,
+}
+
+// What `Writer::rebind` needs to replace the socket, when the client's socket can be reopened.
+#[derive(Default)]
+struct Rebind {
+    reopen: Option<Reopen>,
+    spawn_processor: Option<SpawnProcessor>,
+    // Another handle to the socket the batch processor sends from, for the watchdog to restart
+    // it. Only kept with `WatchdogOptions::restart`.
+    spare_socket: Option<SocketType>,
+}
+
+impl fmt::Debug for Rebind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rebind")
+            .field("reopen", &self.reopen.is_some())
+            .field("spawn_processor", &self.spawn_processor.is_some())
+            .field("spare_socket", &self.spare_socket.is_some())
+            .finish()
+    }
+}
+
+// Writes formatted payloads to the socket. Shared between the client and anything that sends
+// on its behalf from another thread, so the batch processor is only shut down once the last
+// of them is gone.
+#[derive(Debug)]
+pub(crate) struct Writer {
+    socket: RwLock<Socket>,
+    pub(crate) stats: Arc<Stats>,
+    paused: Mutex<Option<Vec<Vec<u8>>>>,
+    max_paused_metrics: usize,
+    would_block_policy: Option<WouldBlockPolicy>,
+    error_handler: Option<ErrorHandler>,
+    processor: Mutex<Option<JoinHandle<()>>>,
+    join_on_drop: AtomicBool,
+    rebind: Mutex<Rebind>,
+    // Only set without batching, otherwise the batch processor reconnects.
+    reconnector: OnceLock<Arc<Reconnector>>,
+    mirror: OnceLock<Mirror>,
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let SocketType::Batched(tx_channel) = &read(&self.socket).socket {
+            // Destructing Client... If fails, ignore and keep going...
+            let _ = lock(tx_channel).send(batch_processor::Message::Shutdown);
+
+            if self.join_on_drop.load(Ordering::Relaxed) {
+                // The last client can be dropped by the batch processor itself, such as from an
+                // error handler, which can't wait for itself.
+                if let Some(processor) = lock(&self.processor)
+                    .take()
+                    .filter(|processor| processor.thread().id() != thread::current().id())
+                {
+                    let _ = processor.join();
+                }
+            }
+        }
+    }
+}
+
+impl Writer {
+    pub(crate) fn new(
+        socket: SocketType,
+        destination: Destination,
+        stats: Arc<Stats>,
+        max_paused_metrics: usize,
+        would_block_policy: Option<WouldBlockPolicy>,
+        error_handler: Option<ErrorHandler>,
+    ) -> Self {
+        Writer {
+            socket: RwLock::new(Socket {
+                socket,
+                destination,
+            }),
+            stats,
+            paused: Mutex::new(None),
+            max_paused_metrics,
+            would_block_policy,
+            error_handler,
+            processor: Mutex::new(None),
+            join_on_drop: AtomicBool::new(false),
+            rebind: Mutex::default(),
+            reconnector: OnceLock::new(),
+            mirror: OnceLock::new(),
+        }
+    }
+
+    // The batch processor's thread, for joining on drop.
+    pub(crate) fn set_processor(&self, processor: JoinHandle<()>) {
+        *lock(&self.processor) = Some(processor);
+    }
+
+    pub(crate) fn join_on_drop(&self, join: bool) {
+        self.join_on_drop.store(join, Ordering::Relaxed);
+    }
+
+    // Forgets the batch processor's thread, so it's never joined.
+    pub(crate) fn detach(&self) {
+        self.join_on_drop(false);
+        lock(&self.processor).take();
+    }
+
+    pub(crate) fn is_null(&self) -> bool {
+        matches!(read(&self.socket).socket, SocketType::Null)
+    }
+
+    // The address the destination sends to, as `Client::set_destination` last set it.
+    pub(crate) fn primary_addr(&self) -> io::Result<SocketAddr> {
+        read(&self.socket).destination.primary_addr()
+    }
+
+    pub(crate) fn is_batched(&self) -> bool {
+        matches!(read(&self.socket).socket, SocketType::Batched(_))
+    }
+
+    // The channel to the batch processor, if the client batches.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn batch_sender(&self) -> Option<std::sync::mpsc::Sender<batch_processor::Message>> {
+        match &read(&self.socket).socket {
+            SocketType::Batched(tx_channel) => Some(lock(tx_channel).clone()),
+            _ => None,
+        }
+    }
+
+    // Has the batch processor show it's still handling messages, for the watchdog.
+    pub(crate) fn ping(&self) {
+        if let SocketType::Batched(tx_channel) = &read(&self.socket).socket {
+            let _ = lock(tx_channel).send(batch_processor::Message::Ping);
+        }
+    }
+
+    // Sends to a new batch processor from now on, sending from the current socket to the
+    // current destination, leaving the old one to finish what it has and stop once it sees its
+    // channel is closed. Only the new one is joined on drop, since the old one may never finish.
+    pub(crate) fn restart_processor(&self) -> io::Result<()> {
+        let rebind = lock(&self.rebind);
+        let (Some(spawn_processor), Some(spare_socket)) =
+            (&rebind.spawn_processor, &rebind.spare_socket)
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this client's batch processor can't be restarted",
+            ));
+        };
+        let socket = spare_socket.try_clone()?;
+
+        let current = read(&self.socket);
+        if let SocketType::Batched(tx_channel) = &current.socket {
+            let (tx, processor) = spawn_processor(socket, current.destination.clone())?;
+            *lock(tx_channel) = tx;
+            self.set_processor(processor);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_reopen(&self, reopen: Reopen) {
+        lock(&self.rebind).reopen = Some(reopen);
+    }
+
+    pub(crate) fn set_spawn_processor(&self, spawn_processor: SpawnProcessor) {
+        lock(&self.rebind).spawn_processor = Some(spawn_processor);
+    }
+
+    pub(crate) fn set_spare_socket(&self, spare_socket: SocketType) {
+        lock(&self.rebind).spare_socket = Some(spare_socket);
+    }
+
+    pub(crate) fn set_reconnector(&self, reconnector: Arc<Reconnector>) {
+        let _ = self.reconnector.set(reconnector);
+    }
+
+    pub(crate) fn set_mirror(&self, mirror: Mirror) {
+        let _ = self.mirror.set(mirror);
+    }
+
+    // Opens a new socket and sends from it from now on. With batching, a new batch processor
+    // sends from it, and the old one sends what it has buffered and stops.
+    pub(crate) fn rebind(&self) -> io::Result<()> {
+        let mut rebind = lock(&self.rebind);
+        let Some(reopen) = &rebind.reopen else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this client's socket can't be reopened",
+            ));
+        };
+        let socket = reopen()?;
+
+        if rebind.spawn_processor.is_some() && rebind.spare_socket.is_some() {
+            rebind.spare_socket = socket.try_clone().ok();
+        }
+        let mut current = write(&self.socket);
+        current.destination.reopened();
+        match &rebind.spawn_processor {
+            Some(spawn_processor) => {
+                let (tx, processor) = spawn_processor(socket, current.destination.clone())?;
+                if let SocketType::Batched(tx_channel) = &current.socket {
+                    let old_tx = std::mem::replace(&mut *lock(tx_channel), tx);
+                    let _ = old_tx.send(batch_processor::Message::Shutdown);
+                    self.set_processor(processor);
+                }
+            }
+            None => current.socket = socket,
+        }
+        Ok(())
+    }
+
+    // Sends to `addr` instead of `to_addr` from now on, including what's already batched.
+    pub(crate) fn set_destination(&self, addr: &str) -> io::Result<()> {
+        // Resolved before taking the lock every send takes.
+        let destination = read(&self.socket).destination.clone();
+        let resolved = destination.resolve_new_primary(addr)?;
+
+        let mut socket = write(&self.socket);
+        socket.destination.set_primary_addr(addr, resolved);
+        if let SocketType::Batched(tx_channel) = &socket.socket {
+            let _ = lock(tx_channel).send(batch_processor::Message::SetDestination(
+                addr.to_owned(),
+                resolved,
+            ));
+        }
+        Ok(())
+    }
+
+    // For errors from sends that happen away from the caller.
+    pub(crate) fn report_error(&self, context: &str, error: DogstatsdError) {
+        report_error(&self.error_handler, context, error)
+    }
+
+    // Hands an error to the handler only if there is one, for errors that are counted in the
+    // stats rather than printed for every metric. The error is only built when it's handled.
+    pub(crate) fn report_to_handler(&self, error: impl FnOnce() -> DogstatsdError) {
+        if let Some(handler) = &self.error_handler {
+            handler.handle(&error());
+        }
+    }
+
+    pub(crate) fn pause(&self) {
+        let mut paused = lock(&self.paused);
+        if paused.is_none() {
+            *paused = Some(vec![]);
+        }
+    }
+
+    pub(crate) fn resume(&self) -> DogstatsdResult {
+        let mut paused = lock(&self.paused);
+        let mut result = Ok(());
+        for formatted_metric in paused.take().unwrap_or_default() {
+            if let Err(error) = self.write(formatted_metric) {
+                self.stats.record_drop(DropReason::SendFailed, 1);
+                if result.is_ok() {
+                    result = Err(error);
+                }
+            }
+        }
+        result
+    }
+
+    // Has the batch processor send what it's buffered, and waits for it to, returning the
+    // number of bytes and metrics sent.
+    pub(crate) fn flush(&self) -> (usize, u64) {
+        if let SocketType::Batched(tx_channel) = &read(&self.socket).socket {
+            let (tx, rx) = mpsc::channel();
+            if lock(tx_channel)
+                .send(batch_processor::Message::Flush(tx))
+                .is_ok()
+            {
+                return rx.recv().unwrap_or_default();
+            }
+        }
+        (0, 0)
+    }
+
+    // Has the batch processor send what it's buffered, without waiting for it to.
+    pub(crate) fn completion(&self) -> Completion {
+        match &read(&self.socket).socket {
+            SocketType::Batched(tx_channel) => {
+                let (completer, completion) = Completion::new();
+                // If the batch processor has stopped, dropping the completer resolves it.
+                let _ = lock(tx_channel).send(batch_processor::Message::Completion(completer));
+                completion
+            }
+            _ => Completion::resolved(Ok(())),
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        lock(&self.paused).is_some()
+    }
+
+    pub(crate) fn send(&self, formatted_metric: Vec<u8>) -> DogstatsdResult {
+        if let Some(mirror) = self.mirror.get() {
+            if let Err(error) = mirror.copy(&formatted_metric) {
+                self.report_error("Failed to mirror payload", error.into());
+            }
+        }
+        let mut paused = lock(&self.paused);
+        if let Some(held_metrics) = paused.as_mut() {
+            if held_metrics.len() < self.max_paused_metrics {
+                held_metrics.push(formatted_metric);
+            } else {
+                self.stats.record_drop(DropReason::QueueFull, 1);
+            }
+            return Ok(());
+        }
+        drop(paused);
+
+        self.write(formatted_metric)
+    }
+
+    fn write(&self, formatted_metric: Vec<u8>) -> DogstatsdResult {
+        let result = self.write_to_socket(formatted_metric);
+        if let Some(reconnector) = self.reconnector.get() {
+            match &result {
+                Ok(()) => reconnector.succeeded(),
+                Err(_) => self.reconnect(reconnector),
+            }
+        }
+        result
+    }
+
+    // Swaps in a new socket if enough sends have failed.
+    fn reconnect(&self, reconnector: &Reconnector) {
+        reconnector.failed();
+        match reconnector.reconnect() {
+            Some(Ok(reconnected)) => {
+                let mut socket = write(&self.socket);
+                socket.destination.reopened();
+                socket.socket = reconnected;
+            }
+            Some(Err(error)) => reconnector.report_failure(&self.error_handler, error),
+            None => {}
+        }
+    }
+
+    // Drops the metric on a full buffer if `would_block_policy` says to, and returns any other
+    // error.
+    fn record_uds_result(&self, result: io::Result<()>) -> io::Result<()> {
+        match result {
+            Err(error) if drops_would_block(&error, self.would_block_policy) => {
+                self.stats.record_send_error(&error, 1);
+                self.stats.record_drop(DropReason::SendFailed, 1);
+                Ok(())
+            }
+            result => result.inspect_err(|error| self.stats.record_send_error(error, 1)),
+        }
+    }
+
+    fn write_to_socket(&self, formatted_metric: Vec<u8>) -> DogstatsdResult {
+        {
+            let socket = read(&self.socket);
+            match &socket.socket {
+                SocketType::Unopened(lazy) => {
+                    // Only takes the write lock to open the socket once the backoff since the
+                    // last attempt has passed.
+                    if !lazy.ready() {
+                        let error = backing_off();
+                        self.stats.record_send_error(&error, 1);
+                        return Err(error.into());
+                    }
+                }
+                _ => return self.write_to(&socket, formatted_metric),
+            }
+        }
+        write(&self.socket)
+            .socket
+            .open_lazily()
+            .inspect_err(|error| self.stats.record_send_error(error, 1))?;
+        self.write_to(&read(&self.socket), formatted_metric)
+    }
+
+    fn write_to(&self, socket: &Socket, formatted_metric: Vec<u8>) -> DogstatsdResult {
+        let destination = &socket.destination;
+        match &socket.socket {
+            SocketType::Udp(socket) => {
+                self.stats
+                    .time_send(|| destination.send_to(socket, formatted_metric.as_slice()))
+                    .inspect_err(|error| self.stats.record_send_error(error, 1))?;
+            }
+            SocketType::Uds(socket) => {
+                let result = self.stats.time_send(|| {
+                    send_uds(socket, formatted_metric.as_slice(), self.would_block_policy)
+                });
+                self.record_uds_result(result.map(|_| ()))?;
+            }
+            SocketType::UdsFallback(socket) => {
+                let result = self
+                    .stats
+                    .time_send(|| socket.send(formatted_metric.as_slice()));
+                self.record_uds_result(result)?;
+            }
+            SocketType::Tcp(connection) => {
+                let mut framed = formatted_metric;
+                framed.push(b'\n');
+                self.stats
+                    .time_send(|| connection.send(&framed))
+                    .inspect_err(|error| self.stats.record_send_error(error, 1))?;
+            }
+            SocketType::File(sink) => {
+                let mut framed = formatted_metric;
+                framed.push(b'\n');
+                sink.send(&framed)
+                    .inspect_err(|error| self.stats.record_send_error(error, 1))?;
+            }
+            SocketType::Null => {}
+            // Opened above, and never closed again.
+            SocketType::Unopened(_) => {
+                return Err(io::Error::from(io::ErrorKind::NotConnected).into())
+            }
+            #[cfg(any(test, feature = "testing"))]
+            SocketType::Capture(sink) => {
+                sink.capture(formatted_metric);
+            }
+            SocketType::Batched(tx_channel) => {
+                lock(tx_channel)
+                    .send(batch_processor::Message::Data(formatted_metric))
+                    .unwrap_or_else(|error| {
+                        self.stats.record_drop(DropReason::SendFailed, 1);
+                        println!("Exception occurred when writing to channel: {:?}", error);
+                    });
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,46 +1,34 @@
-mod support;
+#![allow(clippy::needless_borrows_for_generic_args)]
 
 use std::{thread, time::Duration};
 
-use dogstatsd::{BatchingOptions, Client, OptionsBuilder};
-use tokio::{sync::mpsc::Receiver, time::timeout};
-
-use crate::support::TestServer;
+use dogstatsd::{BatchingOptions, Client, OptionsBuilder, TestServer};
+use tokio::{net::UdpSocket, time::timeout};
 
 #[tokio::test(flavor = "multi_thread")]
 async fn simple_metric_test() {
-    let server = TestServer::new("127.0.0.1:8126".into()).await;
+    let mut server = TestServer::bind("127.0.0.1:0").await.unwrap();
     let opts = OptionsBuilder::new()
-        .to_addr("127.0.0.1:8126".into())
+        .to_addr(server.local_addr().to_string())
         .build();
     let client = Client::new(opts).unwrap();
 
-    let mut promise: Receiver<()>;
-    {
-        let mut shared = server.lock().unwrap();
-        promise = shared.next_message_received();
-    }
     client
         .gauge("my_stat", "7", &["tag1:value1"])
         .expect("unable to send stat");
 
-    if let Err(_) = timeout(Duration::from_secs(1), promise.recv()).await {
-        assert!(false, "Didn't receive next message within a second");
-    }
-
-    {
-        assert_eq!(
-            server.lock().unwrap().last_metric().unwrap(),
-            "my_stat:7|g|#tag1:value1"
-        );
-    }
+    server
+        .expect_metric("my_stat:7|g|#tag1:value1", Duration::from_secs(1))
+        .await;
 }
 
 #[tokio::test(flavor = "multi_thread")]
 async fn batching_test() {
-    let server = TestServer::new("127.0.0.1:8127".into()).await;
+    // A plain socket rather than a `TestServer`, which splits batches into metrics, to check
+    // that they arrive in one datagram.
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
     let opts = OptionsBuilder::new()
-        .to_addr("127.0.0.1:8127".into())
+        .to_addr(server.local_addr().unwrap().to_string())
         .batching_options(BatchingOptions {
             max_time: Duration::from_secs(2),
             max_buffer_size: 1024,
@@ -50,11 +38,6 @@ async fn batching_test() {
         .build();
     let client = Client::new(opts).unwrap();
 
-    let mut promise: Receiver<()>;
-    {
-        let mut shared = server.lock().unwrap();
-        promise = shared.next_message_received();
-    }
     client
         .gauge("my_stat", "7", &["tag1:value1"])
         .expect("unable to send stat");
@@ -71,14 +54,34 @@ async fn batching_test() {
         .timing("my_timing", 311, &["tag1:value1"])
         .expect("unable to send stat");
 
-    if let Err(_) = timeout(Duration::from_secs(5), promise.recv()).await {
-        assert!(false, "Didn't receive next batch within 5 seconds");
-    }
+    let mut buf = [0; 1024];
+    let len = timeout(Duration::from_secs(5), server.recv(&mut buf))
+        .await
+        .expect("Didn't receive next batch within 5 seconds")
+        .unwrap();
+    assert_eq!(
+        "my_stat:7|g|#tag1:value1\nmy_count:29|c|#tag1:value1\nmy_timing:311|ms|#tag1:value1\n",
+        String::from_utf8_lossy(&buf[..len])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_byte_tags_test() {
+    let mut server = TestServer::bind("127.0.0.1:0").await.unwrap();
+    let opts = OptionsBuilder::new()
+        .to_addr(server.local_addr().to_string())
+        .default_tag("city:東京".into())
+        .build();
+    let client = Client::new(opts).unwrap();
+
+    client
+        .incr("compteur", ["région:île-de-france"])
+        .expect("unable to send stat");
 
-    {
-        assert_eq!(
-            server.lock().unwrap().last_metric().unwrap(),
-            "my_stat:7|g|#tag1:value1\nmy_count:29|c|#tag1:value1\nmy_timing:311|ms|#tag1:value1\n"
-        );
-    }
+    server
+        .expect_metric(
+            "compteur:1|c|#région:île-de-france,city:東京",
+            Duration::from_secs(1),
+        )
+        .await;
 }